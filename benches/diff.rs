@@ -0,0 +1,96 @@
+// Benchmarks `sisyphus diff` end to end against synthetic monitor directories. Only
+// KubernetesYaml resources are generated so no registry or cluster access is needed: the run
+// exercises file parsing, rendering, the database load, and the diff itself.
+//
+// Run with `cargo bench`. Pass `--timings` to the binary by hand to see the per-phase breakdown.
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use std::{
+    fs,
+    process::{Command, Stdio},
+};
+use tempfile::TempDir;
+
+struct SyntheticRepo {
+    _directory: TempDir,
+    database_url: String,
+    monitor_directory: String,
+}
+
+// Lays out `namespaces` folders, each with an index.yaml holding `objects_per_namespace`
+// ConfigMaps pushed to two clusters.
+fn make_repo(namespaces: usize, objects_per_namespace: usize) -> SyntheticRepo {
+    let directory = TempDir::new().unwrap();
+    let monitor_directory = directory.path().join("production");
+    for n in 0..namespaces {
+        let namespace = monitor_directory.join(format!("namespace-{}", n));
+        fs::create_dir_all(&namespace).unwrap();
+        let mut index = String::new();
+        index.push_str(
+            "apiVersion: sisyphus/v1\n\
+             kind: KubernetesYaml\n\
+             metadata:\n  name: objects\n\
+             clusters:\n  - cluster-a\n  - cluster-b\n\
+             objects:\n",
+        );
+        for o in 0..objects_per_namespace {
+            index.push_str(&format!(
+                "  - apiVersion: v1\n    kind: ConfigMap\n    metadata:\n      name: config-{}\n    data:\n      key: value-{}\n",
+                o, o
+            ));
+        }
+        fs::write(namespace.join("index.yaml"), index).unwrap();
+    }
+
+    let database_path = directory.path().join("sisyphus.db");
+    let database_url = format!("sqlite://{}?mode=rwc", database_path.display());
+    create_database(&database_url);
+
+    SyntheticRepo {
+        monitor_directory: monitor_directory.to_string_lossy().to_string(),
+        database_url,
+        _directory: directory,
+    }
+}
+
+fn create_database(database_url: &str) {
+    let status = Command::new(env!("CARGO_BIN_EXE_sisyphus"))
+        .args(["db", "migrate", "--database-url", database_url])
+        .stdout(Stdio::null())
+        .status()
+        .unwrap();
+    assert!(status.success());
+}
+
+fn run_diff(repo: &SyntheticRepo) {
+    let status = Command::new(env!("CARGO_BIN_EXE_sisyphus"))
+        .arg("diff")
+        .arg("--database-url")
+        .arg(&repo.database_url)
+        .arg("--monitor-directory")
+        .arg(&repo.monitor_directory)
+        .stdout(Stdio::null())
+        .status()
+        .unwrap();
+    assert!(status.success());
+}
+
+fn bench_diff(c: &mut Criterion) {
+    let mut group = c.benchmark_group("diff");
+    group.sample_size(10);
+    for (namespaces, objects_per_namespace) in [(10, 10), (50, 20), (100, 50)] {
+        let repo = make_repo(namespaces, objects_per_namespace);
+        group.bench_with_input(
+            BenchmarkId::from_parameter(format!(
+                "{}x{}",
+                namespaces, objects_per_namespace
+            )),
+            &repo,
+            |b, repo| b.iter(|| run_diff(repo)),
+        );
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_diff);
+criterion_main!(benches);
@@ -0,0 +1,3 @@
+-- the Sisyphus version, lockfile hash, and flags that last wrote each row, which the audit table
+-- copies into its history
+ALTER TABLE kubernetes_objects ADD COLUMN IF NOT EXISTS provenance TEXT;
@@ -0,0 +1,14 @@
+-- temporary environment variables and arguments added by `sisyphus debug-overlay`
+CREATE TABLE IF NOT EXISTS debug_overlays
+(
+    api_version TEXT NOT NULL,
+    cluster TEXT NOT NULL,
+    created TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    -- RFC 3339 in UTC, so it sorts and compares the same way in every database
+    expires TEXT NOT NULL,
+    kind TEXT NOT NULL,
+    name TEXT NOT NULL,
+    namespace TEXT NOT NULL,
+    -- JSON describing the variables and arguments
+    overlay TEXT NOT NULL
+);
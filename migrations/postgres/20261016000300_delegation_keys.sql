@@ -0,0 +1,8 @@
+-- public keys whose signed delegations let a team push to some namespaces. Once any key is here,
+-- every push needs a delegation signed by one of them.
+CREATE TABLE IF NOT EXISTS delegation_keys
+(
+    name TEXT NOT NULL PRIMARY KEY,
+    -- a hex-encoded Ed25519 public key
+    public_key TEXT NOT NULL
+);
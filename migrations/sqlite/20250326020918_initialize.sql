@@ -0,0 +1,56 @@
+CREATE TABLE IF NOT EXISTS kubernetes_objects
+(
+    api_version TEXT NOT NULL,
+    cluster TEXT NOT NULL,
+    created TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    kind TEXT NOT NULL,
+    last_updated TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    name TEXT NOT NULL,
+    -- set un-namespaced objects to namespace "" to avoid composite primary key issues
+    namespace TEXT NOT NULL,
+    yaml TEXT NOT NULL,
+    PRIMARY KEY (name, namespace, kind, api_version, cluster)
+);
+
+-- the same history that create_audit_table keeps in PostgreSQL, minus the session details SQLite
+-- doesn't have
+CREATE TABLE IF NOT EXISTS kubernetes_objects_audit
+(
+    action TEXT NOT NULL,
+    action_time TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    old_data TEXT,
+    new_data TEXT
+);
+
+CREATE TRIGGER IF NOT EXISTS kubernetes_objects_record_insert
+AFTER INSERT ON kubernetes_objects
+BEGIN
+    INSERT INTO kubernetes_objects_audit (action, new_data)
+    VALUES ('INSERT', json_object(
+        'api_version', NEW.api_version, 'cluster', NEW.cluster, 'created', NEW.created,
+        'kind', NEW.kind, 'last_updated', NEW.last_updated, 'name', NEW.name,
+        'namespace', NEW.namespace, 'yaml', NEW.yaml));
+END;
+
+CREATE TRIGGER IF NOT EXISTS kubernetes_objects_record_update
+AFTER UPDATE ON kubernetes_objects
+BEGIN
+    INSERT INTO kubernetes_objects_audit (action, old_data, new_data)
+    VALUES ('UPDATE', json_object(
+        'api_version', OLD.api_version, 'cluster', OLD.cluster, 'created', OLD.created,
+        'kind', OLD.kind, 'last_updated', OLD.last_updated, 'name', OLD.name,
+        'namespace', OLD.namespace, 'yaml', OLD.yaml), json_object(
+        'api_version', NEW.api_version, 'cluster', NEW.cluster, 'created', NEW.created,
+        'kind', NEW.kind, 'last_updated', NEW.last_updated, 'name', NEW.name,
+        'namespace', NEW.namespace, 'yaml', NEW.yaml));
+END;
+
+CREATE TRIGGER IF NOT EXISTS kubernetes_objects_record_delete
+AFTER DELETE ON kubernetes_objects
+BEGIN
+    INSERT INTO kubernetes_objects_audit (action, old_data)
+    VALUES ('DELETE', json_object(
+        'api_version', OLD.api_version, 'cluster', OLD.cluster, 'created', OLD.created,
+        'kind', OLD.kind, 'last_updated', OLD.last_updated, 'name', OLD.name,
+        'namespace', OLD.namespace, 'yaml', OLD.yaml));
+END;
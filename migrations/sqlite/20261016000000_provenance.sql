@@ -0,0 +1,40 @@
+-- the Sisyphus version, lockfile hash, and flags that last wrote each row
+ALTER TABLE kubernetes_objects ADD COLUMN provenance TEXT;
+
+-- record the new column in the history like PostgreSQL's row_to_json does
+DROP TRIGGER kubernetes_objects_record_insert;
+DROP TRIGGER kubernetes_objects_record_update;
+DROP TRIGGER kubernetes_objects_record_delete;
+
+CREATE TRIGGER kubernetes_objects_record_insert
+AFTER INSERT ON kubernetes_objects
+BEGIN
+    INSERT INTO kubernetes_objects_audit (action, new_data)
+    VALUES ('INSERT', json_object(
+        'api_version', NEW.api_version, 'cluster', NEW.cluster, 'created', NEW.created,
+        'kind', NEW.kind, 'last_updated', NEW.last_updated, 'name', NEW.name,
+        'namespace', NEW.namespace, 'yaml', NEW.yaml, 'provenance', NEW.provenance));
+END;
+
+CREATE TRIGGER kubernetes_objects_record_update
+AFTER UPDATE ON kubernetes_objects
+BEGIN
+    INSERT INTO kubernetes_objects_audit (action, old_data, new_data)
+    VALUES ('UPDATE', json_object(
+        'api_version', OLD.api_version, 'cluster', OLD.cluster, 'created', OLD.created,
+        'kind', OLD.kind, 'last_updated', OLD.last_updated, 'name', OLD.name,
+        'namespace', OLD.namespace, 'yaml', OLD.yaml, 'provenance', OLD.provenance), json_object(
+        'api_version', NEW.api_version, 'cluster', NEW.cluster, 'created', NEW.created,
+        'kind', NEW.kind, 'last_updated', NEW.last_updated, 'name', NEW.name,
+        'namespace', NEW.namespace, 'yaml', NEW.yaml, 'provenance', NEW.provenance));
+END;
+
+CREATE TRIGGER kubernetes_objects_record_delete
+AFTER DELETE ON kubernetes_objects
+BEGIN
+    INSERT INTO kubernetes_objects_audit (action, old_data)
+    VALUES ('DELETE', json_object(
+        'api_version', OLD.api_version, 'cluster', OLD.cluster, 'created', OLD.created,
+        'kind', OLD.kind, 'last_updated', OLD.last_updated, 'name', OLD.name,
+        'namespace', OLD.namespace, 'yaml', OLD.yaml, 'provenance', OLD.provenance));
+END;
@@ -0,0 +1,13 @@
+-- held while `push`, `refresh`, or `import` changes clusters, so two of them never interleave
+CREATE TABLE IF NOT EXISTS locks
+(
+    name TEXT NOT NULL PRIMARY KEY,
+    -- who took the lock, like alice@laptop (pid 1234, push)
+    holder TEXT NOT NULL,
+    -- random, so only the process that took the lock extends or releases it
+    token TEXT NOT NULL,
+    -- RFC 3339 in UTC like debug_overlays.expires
+    acquired TEXT NOT NULL,
+    heartbeat TEXT NOT NULL,
+    expires TEXT NOT NULL
+);
@@ -0,0 +1,180 @@
+use anyhow::{bail, Context, Result};
+use serde_yaml::{Mapping, Value};
+use std::{collections::BTreeMap, fs, path::Path};
+
+use crate::{generate_diff::DiffAction, globs::match_name, kubernetes_io::KubernetesKey};
+
+// Recorded answers to push's per-change prompts, so that scripts can approve some changes
+// without approving everything. A file looks like:
+//
+//   changes:
+//     "Deployment echo/* (prod)": y
+//     "Namespace *": n
+//   verbs:
+//     create: y
+//     patch: y
+//
+// Change patterns match against how the prompt names the object and are tried in order before
+// falling back to the answer for the change's verb.
+#[derive(Debug, Default)]
+pub(crate) struct Answers {
+    changes: Vec<(String, bool)>,
+    verbs: BTreeMap<String, bool>,
+}
+
+const VERBS: [&str; 4] = ["create", "delete", "patch", "recreate"];
+
+impl Answers {
+    pub(crate) fn load(path: &Path) -> Result<Answers> {
+        let content =
+            fs::read_to_string(path).with_context(|| format!("while reading {:?}", path))?;
+        Answers::parse(&content).with_context(|| format!("while parsing {:?}", path))
+    }
+
+    fn parse(content: &str) -> Result<Answers> {
+        let mut answers = Answers::default();
+        let document: Mapping = match serde_yaml::from_str(content)? {
+            Value::Null => return Ok(answers),
+            Value::Mapping(m) => m,
+            _ => bail!("Expected a mapping with changes and verbs"),
+        };
+        for (section, entries) in document {
+            let section = section.as_str().unwrap_or_default().to_string();
+            let entries = match entries {
+                Value::Null => Mapping::new(),
+                Value::Mapping(m) => m,
+                _ => bail!("Expected {} to be a mapping", section),
+            };
+            for (pattern, answer) in entries {
+                let Some(pattern) = pattern.as_str() else {
+                    bail!("Expected the keys in {} to be strings", section);
+                };
+                let answer = parse_answer(&answer)
+                    .with_context(|| format!("while reading the answer for {}", pattern))?;
+                match section.as_str() {
+                    "changes" => answers.changes.push((pattern.to_string(), answer)),
+                    "verbs" => {
+                        if !VERBS.contains(&pattern) {
+                            bail!(
+                                "Unknown verb {}, expected one of {}",
+                                pattern,
+                                VERBS.join(", ")
+                            );
+                        }
+                        answers.verbs.insert(pattern.to_string(), answer);
+                    }
+                    _ => bail!("Unknown section {}, expected changes or verbs", section),
+                }
+            }
+        }
+        Ok(answers)
+    }
+
+    // The recorded answer to applying this change, or None if nothing in the file covers it
+    pub(crate) fn for_change(&self, key: &KubernetesKey, action: &DiffAction) -> Option<bool> {
+        let name = key.to_string();
+        self.changes
+            .iter()
+            .find(|(pattern, _)| match_name(pattern, &name))
+            .map(|(_, answer)| *answer)
+            .or_else(|| self.verbs.get(verb(action)).copied())
+    }
+}
+
+fn verb(action: &DiffAction) -> &'static str {
+    match action {
+        DiffAction::Create(_) => "create",
+        DiffAction::Delete => "delete",
+        DiffAction::Patch { .. } => "patch",
+        DiffAction::Recreate(_) => "recreate",
+    }
+}
+
+// YAML reads true and false as bools but y and n as strings, so accept either
+fn parse_answer(value: &Value) -> Result<bool> {
+    match value {
+        Value::Bool(b) => Ok(*b),
+        Value::String(s) => match s.to_lowercase().as_str() {
+            "y" | "yes" => Ok(true),
+            "n" | "no" => Ok(false),
+            _ => bail!("Expected y or n but got {}", s),
+        },
+        _ => bail!("Expected y or n"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::kubernetes_io::test_key;
+
+    #[test]
+    fn test_changes_before_verbs() -> Result<()> {
+        let answers = Answers::parse(
+            r#"
+            changes:
+              "ConfigMap echo/keep (prod)": y
+              "ConfigMap echo/* (prod)": n
+              "Namespace *": no
+            verbs:
+              create: yes
+              delete: "n"
+            "#,
+        )?;
+        let create = DiffAction::Create(serde_yaml::from_str(
+            "apiVersion: v1\nkind: ConfigMap\nmetadata:\n  name: echo\n",
+        )?);
+        assert_eq!(
+            answers.for_change(
+                &test_key("prod", "v1", "ConfigMap", Some("echo"), "keep"),
+                &create
+            ),
+            Some(true)
+        );
+        assert_eq!(
+            answers.for_change(
+                &test_key("prod", "v1", "ConfigMap", Some("echo"), "other"),
+                &create
+            ),
+            Some(false)
+        );
+        assert_eq!(
+            answers.for_change(
+                &test_key("prod", "v1", "Secret", Some("echo"), "other"),
+                &create
+            ),
+            Some(true)
+        );
+        assert_eq!(
+            answers.for_change(
+                &test_key("prod", "v1", "Secret", Some("echo"), "other"),
+                &DiffAction::Delete
+            ),
+            Some(false)
+        );
+        assert_eq!(
+            answers.for_change(
+                &test_key("prod", "v1", "Namespace", None, "echo"),
+                &DiffAction::Delete
+            ),
+            Some(false)
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_unmatched_and_invalid() -> Result<()> {
+        let answers = Answers::parse("verbs:\n  create: y\n")?;
+        assert_eq!(
+            answers.for_change(
+                &test_key("prod", "v1", "Secret", Some("echo"), "other"),
+                &DiffAction::Delete
+            ),
+            None
+        );
+        assert!(Answers::parse("verbs:\n  restart: y\n").is_err());
+        assert!(Answers::parse("verbs:\n  create: maybe\n").is_err());
+        assert!(Answers::parse("prompts:\n  create: y\n").is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,234 @@
+use anyhow::{anyhow, bail, Context, Result};
+use clap::Args;
+use kube::api::{DynamicObject, Patch, PatchParams};
+use serde::Deserialize;
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::{Path, PathBuf},
+};
+use tracing::info;
+
+use crate::{
+    kubernetes_io::{get_kubernetes_api, get_kubernetes_clients, KubernetesKey, MANAGER},
+    kubernetes_rendering::render_sisyphus_resource,
+    registry_clients::{resolve_image_tag, RegistryClients},
+    sisyphus_yaml::{HasConfigImage, SisyphusDeployment, SisyphusResource},
+};
+
+// Contexts that kind, k3d, minikube, and the desktop Kubernetes distributions create, so a typo
+// can't send a smoke test to a real cluster
+const LOCAL_CONTEXTS: [&str; 6] = [
+    "kind-*",
+    "k3d-*",
+    "minikube",
+    "docker-desktop",
+    "rancher-desktop",
+    "orbstack",
+];
+
+#[derive(Args, Debug)]
+pub(crate) struct DeployLocalArgs {
+    // The file defining the Deployment, like production/echo/index.yaml
+    #[arg(long)]
+    pub file: PathBuf,
+
+    // Which Deployment to deploy when the file defines several
+    #[arg(long)]
+    pub name: Option<String>,
+
+    // The local cluster's kubeconfig context
+    #[arg(long, default_value = "kind-kind")]
+    pub context: String,
+
+    // Which footprint entry's replicas and variables to use, defaulting to the first
+    #[arg(long)]
+    pub footprint: Option<String>,
+
+    // Defaults to the name of the file's folder, like a push would use
+    #[arg(long)]
+    pub namespace: Option<String>,
+}
+
+// Renders one Deployment for a local cluster and applies it there directly. Nothing is recorded
+// in a database, so objects it leaves behind are the developer's to clean up.
+pub(crate) async fn deploy_local(args: DeployLocalArgs) -> Result<()> {
+    if !LOCAL_CONTEXTS
+        .iter()
+        .any(|p| crate::globs::match_name(p, &args.context))
+    {
+        bail!(
+            "Refusing to deploy to {}, which doesn't look like a kind, k3d, minikube, or desktop \
+             cluster",
+            args.context
+        );
+    }
+    let namespace = match &args.namespace {
+        Some(namespace) => namespace.clone(),
+        None => folder_name(&args.file)?,
+    };
+    let mut deployment = read_deployment(&args.file, args.name.as_deref())?;
+    retarget(&mut deployment, &args.context, args.footprint.as_deref())?;
+
+    let registries = RegistryClients::new();
+    let reference = resolve_image_tag(deployment.config_image(), &registries).await?;
+    deployment.set_config_image(reference.to_string());
+    let mut by_key = BTreeMap::new();
+    render_sisyphus_resource(
+        &SisyphusResource::SisyphusDeployment(deployment),
+        /* allow_any_namespace= */ false,
+        &Some(namespace.clone()),
+        &mut by_key,
+        &registries,
+    )
+    .await?;
+    let namespace_key = KubernetesKey {
+        api_version: "v1".to_string(),
+        cluster: args.context.clone(),
+        kind: "Namespace".to_string(),
+        name: namespace.clone(),
+        namespace: None,
+    };
+    let namespace_object: DynamicObject = serde_json::from_value(serde_json::json!({
+        "apiVersion": "v1",
+        "kind": "Namespace",
+        "metadata": {"name": namespace},
+    }))?;
+
+    let (clients, types) =
+        get_kubernetes_clients(std::iter::once(&namespace_key).chain(by_key.keys())).await?;
+    let apply = PatchParams::apply(MANAGER).force();
+    // The namespace has to exist before anything goes in it
+    for (key, object) in std::iter::once((&namespace_key, &namespace_object)).chain(&by_key) {
+        let api = get_kubernetes_api(key, &clients, &types)?;
+        api.patch(&key.name, &apply, &Patch::Apply(object))
+            .await
+            .with_context(|| format!("while applying {}", key))?;
+        info!(object = %key, "Applied {}", key);
+    }
+    Ok(())
+}
+
+fn folder_name(file: &Path) -> Result<String> {
+    let folder = fs::canonicalize(file)
+        .with_context(|| format!("while reading {:?}", file))?
+        .parent()
+        .and_then(|p| p.file_name())
+        .map(|n| n.to_string_lossy().into_owned());
+    folder.ok_or_else(|| {
+        anyhow!(
+            "Unable to tell the namespace of {:?}, pass --namespace",
+            file
+        )
+    })
+}
+
+fn read_deployment(file: &Path, name: Option<&str>) -> Result<SisyphusDeployment> {
+    let content = fs::read_to_string(file).with_context(|| format!("while reading {:?}", file))?;
+    let mut deployments = Vec::new();
+    for document in serde_yaml::Deserializer::from_str(&content) {
+        let resource =
+            SisyphusResource::deserialize(document).with_context(|| format!("in {:?}", file))?;
+        if let SisyphusResource::SisyphusDeployment(mut deployment) = resource {
+            deployment.defined_in = Some(file.to_path_buf());
+            deployments.push(deployment);
+        }
+    }
+    let names: Vec<_> = deployments
+        .iter()
+        .map(|d| d.metadata.name.clone())
+        .collect();
+    let mut matching: Vec<_> = deployments
+        .into_iter()
+        .filter(|d| name.map_or(true, |n| d.metadata.name == n))
+        .collect();
+    match (matching.len(), name) {
+        (1, _) => Ok(matching.remove(0)),
+        (0, Some(name)) => bail!(
+            "{:?} doesn't define Deployment {}, only {}",
+            file,
+            name,
+            names.join(", ")
+        ),
+        (0, None) => bail!("{:?} doesn't define any Deployments", file),
+        (_, _) => bail!(
+            "{:?} defines several Deployments, pick one with --name: {}",
+            file,
+            names.join(", ")
+        ),
+    }
+}
+
+// Points the footprint at only the local cluster, keeping the replicas and variables of one of
+// the real clusters
+fn retarget(deployment: &mut SisyphusDeployment, context: &str, from: Option<&str>) -> Result<()> {
+    let entry = match from {
+        Some(cluster) => deployment.footprint.remove(cluster).ok_or_else(|| {
+            anyhow!(
+                "Deployment {} has no footprint entry for {}",
+                deployment.metadata.name,
+                cluster
+            )
+        })?,
+        None => match deployment.footprint.pop_first() {
+            Some((_, entry)) => entry,
+            None => bail!(
+                "Deployment {} has an empty footprint",
+                deployment.metadata.name
+            ),
+        },
+    };
+    deployment.footprint = BTreeMap::from([(context.to_string(), entry)]);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_read_and_retarget() -> Result<()> {
+        let directory = tempfile::TempDir::new()?;
+        let file = directory.path().join("index.yaml");
+        fs::write(
+            &file,
+            r#"
+apiVersion: sisyphus/v1
+kind: Deployment
+metadata:
+  name: echo
+config:
+  env: prod
+  image: registry.test/echo_config:latest
+footprint:
+  prod-east:
+    replicas: 3
+  prod-west:
+    replicas: 2
+---
+apiVersion: sisyphus/v1
+kind: Deployment
+metadata:
+  name: other
+config:
+  env: prod
+  image: registry.test/other_config:latest
+footprint: {}
+"#,
+        )?;
+        assert!(read_deployment(&file, None).is_err());
+        assert!(read_deployment(&file, Some("missing")).is_err());
+
+        let mut deployment = read_deployment(&file, Some("echo"))?;
+        retarget(&mut deployment, "kind-kind", Some("prod-west"))?;
+        assert_eq!(
+            deployment.footprint.keys().collect::<Vec<_>>(),
+            vec!["kind-kind"]
+        );
+        assert_eq!(deployment.footprint["kind-kind"].replicas, 2);
+
+        let mut other = read_deployment(&file, Some("other"))?;
+        assert!(retarget(&mut other, "kind-kind", None).is_err());
+        Ok(())
+    }
+}
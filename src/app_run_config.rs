@@ -1,6 +1,6 @@
 use crate::{
-    config_image::{assign_ports, Application, Argument, ArgumentValues},
-    starlark::load_starlark_config,
+    config_image::{Application, Argument, ArgumentValues},
+    starlark::{load_starlark_config, Context as ConfigContext},
 };
 use anyhow::{anyhow, Context, Result};
 use clap::Args;
@@ -23,6 +23,14 @@ pub(crate) struct RunConfigArgs {
     #[arg(long)]
     pub config: PathBuf,
 
+    // The cluster reported to the config by ctx.cluster()
+    #[arg(long)]
+    pub cluster: Option<String>,
+
+    // Which container to run when the config defines several
+    #[arg(long)]
+    pub container: Option<String>,
+
     #[arg(long)]
     pub environment: String,
 
@@ -31,34 +39,39 @@ pub(crate) struct RunConfigArgs {
 }
 
 pub(crate) async fn run_config(args: RunConfigArgs) -> Result<()> {
-    let application = load_starlark_config(
+    let applications = load_starlark_config(
         &args.config_root,
         &args.config,
-        "local-app",
-        args.namespace.as_deref(),
+        ConfigContext {
+            name: "local-app".to_string(),
+            namespace: args.namespace.clone(),
+            environment: args.environment.clone(),
+            cluster: args.cluster.clone(),
+        },
     )
     .await
     .with_context(|| format!("Failed to load config from {}", args.config.display()))?;
-    let (cmd_args, env_vars) = build_config_local(&application, &args.environment)?;
+    let application = applications.select(args.container.as_deref())?;
+    let port_numbers = applications.assign_ports(&args.environment)?;
+    let (cmd_args, env_vars) = build_config_local(application, &port_numbers, &args.environment)?;
     run_binary_local(&args.binary, cmd_args, env_vars).await
 }
 
 fn build_config_local(
     app: &Application,
+    port_numbers: &BTreeMap<String, u16>,
     environment: &str,
 ) -> Result<(Vec<String>, HashMap<String, String>)> {
-    let port_numbers = assign_ports(app, environment)?;
-
     let mut args = Vec::new();
     for arg_val in &app.args {
-        if let Some((_, resolved)) = resolve_argument_local(arg_val, environment, &port_numbers)? {
+        if let Some((_, resolved)) = resolve_argument_local(arg_val, environment, port_numbers)? {
             args.push(resolved);
         }
     }
 
     let mut env = HashMap::new();
     for (key, arg_val) in &app.env {
-        if let Some((_, resolved)) = resolve_argument_local(arg_val, environment, &port_numbers)? {
+        if let Some((_, resolved)) = resolve_argument_local(arg_val, environment, port_numbers)? {
             env.insert(key.clone(), resolved);
         }
     }
@@ -83,6 +96,17 @@ pub(crate) fn resolve_argument_local<'a>(
         single,
         match single {
             Argument::String(s) => s.clone(),
+            Argument::ConfigMapVariable(v) => {
+                // Holds the value, or with a path, the path of a file holding the value
+                let key = as_env_key(&format!("{}-{}", v.name, v.key)).replace(".", "_");
+                std::env::var(&key)
+                    .with_context(|| format!("Environment config map variable {} not set", key))?
+            }
+            Argument::FieldVariable(v) => {
+                let key = format!("FIELD_{}", as_env_key(&v.path).replace(".", "_"));
+                std::env::var(&key)
+                    .with_context(|| format!("Environment field variable {} not set", key))?
+            }
             Argument::FileVariable(v) => {
                 let key = as_env_key(&v.name);
                 std::env::var(&key)
@@ -98,6 +122,11 @@ pub(crate) fn resolve_argument_local<'a>(
                         .to_string(),
                 }
             }
+            Argument::ResourceFieldVariable(v) => {
+                let key = format!("RESOURCE_{}", as_env_key(&v.resource).replace(".", "_"));
+                std::env::var(&key)
+                    .with_context(|| format!("Environment resource variable {} not set", key))?
+            }
             Argument::StringVariable(v) => {
                 let key = as_env_key(&v.name);
                 std::env::var(&key)
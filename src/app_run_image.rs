@@ -1,16 +1,26 @@
 use crate::{
     app_run_config::resolve_argument_local,
-    config_image::{assign_ports, Application, Argument, ArgumentValues},
+    cli::parse_env_var,
+    config_image::{Application, Applications, Argument, ArgumentValues, ConfigImageIndex},
     kubernetes_rendering::prepare_image_config,
     registry_clients::{resolve_image_tag, RegistryClients},
+    starlark::Context as ConfigContext,
 };
-use anyhow::{Context, Result};
-use clap::Args;
+use anyhow::{bail, Context, Result};
+use clap::{Args, ValueEnum};
 use std::collections::{BTreeMap, HashMap};
 use tokio::process::Command;
 
 #[derive(Args, Debug)]
 pub(crate) struct RunImageArgs {
+    // The cluster reported to the config by ctx.cluster()
+    #[arg(long)]
+    pub cluster: Option<String>,
+
+    // Which container to run when the config defines several
+    #[arg(long)]
+    pub container: Option<String>,
+
     #[arg(long)]
     pub environment: String,
 
@@ -19,6 +29,39 @@ pub(crate) struct RunImageArgs {
 
     #[arg(long)]
     pub namespace: Option<String>,
+
+    // Publish a container port on a different host port, as HOST:CONTAINER. Ports that aren't
+    // remapped are published on the same host port.
+    #[arg(long, value_parser = parse_publish)]
+    pub publish: Vec<(String, String)>,
+
+    // An environment variable to set in the container, as NAME=VALUE, overriding the config's
+    #[arg(long, value_parser = parse_env_var)]
+    pub env: Vec<(String, String)>,
+
+    // The network to attach the container to, like host or a user-defined network
+    #[arg(long)]
+    pub network: Option<String>,
+
+    #[arg(long, value_enum, default_value = "podman")]
+    pub engine: ContainerEngine,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq, ValueEnum)]
+pub(crate) enum ContainerEngine {
+    Docker,
+    Podman,
+}
+
+fn parse_publish(value: &str) -> Result<(String, String)> {
+    // The host side may include an address, like 127.0.0.1:9090:8080
+    let Some((host, container)) = value.rsplit_once(':') else {
+        bail!("{:?} must look like HOST:CONTAINER", value);
+    };
+    if host.is_empty() || container.parse::<u16>().is_err() {
+        bail!("{:?} must look like HOST:CONTAINER", value);
+    }
+    Ok((host.to_string(), container.to_string()))
 }
 
 #[derive(Debug)]
@@ -40,23 +83,62 @@ enum ResolvedArgument {
 }
 
 pub async fn run_image(args: RunImageArgs) -> Result<()> {
-    let mut registries = RegistryClients::new();
-    let (binary_image, application) =
-        load_config_from_image(&args.image, &mut registries, args.namespace.as_deref())
-            .await
-            .with_context(|| format!("Failed to load config from image: {}", args.image))?;
-    let config = build_config_container(&application, &args.environment)?;
-    run_container_podman(&binary_image, config).await
+    let registries = RegistryClients::new();
+    let context = ConfigContext {
+        name: "local-app".to_string(),
+        namespace: args.namespace.clone(),
+        environment: args.environment.clone(),
+        cluster: args.cluster.clone(),
+    };
+    let (index, applications) = load_config_from_image(&args.image, &registries, context)
+        .await
+        .with_context(|| format!("Failed to load config from image: {}", args.image))?;
+    let application = applications.select(args.container.as_deref())?;
+    let binary_image = match &args.container {
+        Some(container) => index.image_for(container),
+        None => format!("{}@{}", index.binary_repository, index.binary_digest),
+    };
+    let port_numbers = applications.assign_ports(&args.environment)?;
+    let mut config = build_config_container(application, &port_numbers, &args.environment)?;
+    config.env.extend(args.env.iter().cloned());
+    let options = EngineOptions {
+        engine: args.engine,
+        network: args.network.clone(),
+        publish: publish_flags(&config.ports, &args.publish),
+    };
+    run_container(&binary_image, config, options).await
+}
+
+struct EngineOptions {
+    engine: ContainerEngine,
+    network: Option<String>,
+    publish: Vec<String>,
 }
 
-fn build_config_container(app: &Application, environment: &str) -> Result<ContainerConfig> {
-    let port_numbers = assign_ports(app, environment)?;
+// Publishes every port the config declares on the same host port unless remapped, plus any other
+// remapped ports
+fn publish_flags(ports: &[String], remapped: &[(String, String)]) -> Vec<String> {
+    let mut published: BTreeMap<&str, &str> =
+        ports.iter().map(|p| (p.as_str(), p.as_str())).collect();
+    for (host, container) in remapped {
+        published.insert(container, host);
+    }
+    published
+        .into_iter()
+        .map(|(container, host)| format!("{}:{}", host, container))
+        .collect()
+}
 
+fn build_config_container(
+    app: &Application,
+    port_numbers: &BTreeMap<String, u16>,
+    environment: &str,
+) -> Result<ContainerConfig> {
     let mut mounts = Vec::new();
     let mut ports = Vec::new();
     let mut cmd_args = Vec::new();
     for arg_val in &app.args {
-        if let Some(resolved) = resolve_argument_container(arg_val, environment, &port_numbers)? {
+        if let Some(resolved) = resolve_argument_container(arg_val, environment, port_numbers)? {
             match resolved {
                 ResolvedArgument::Port(s) => {
                     cmd_args.push(s.clone());
@@ -76,7 +158,7 @@ fn build_config_container(app: &Application, environment: &str) -> Result<Contai
 
     let mut env_vars = HashMap::new();
     for (key, arg_val) in &app.env {
-        if let Some(resolved) = resolve_argument_container(arg_val, environment, &port_numbers)? {
+        if let Some(resolved) = resolve_argument_container(arg_val, environment, port_numbers)? {
             match resolved {
                 ResolvedArgument::Port(s) => {
                     env_vars.insert(key.clone(), s.clone());
@@ -106,14 +188,11 @@ fn build_config_container(app: &Application, environment: &str) -> Result<Contai
 
 async fn load_config_from_image(
     image: &String,
-    registries: &mut RegistryClients,
-    namespace: Option<&str>,
-) -> Result<(String, Application)> {
+    registries: &RegistryClients,
+    context: ConfigContext,
+) -> Result<(ConfigImageIndex, Applications)> {
     let reference = resolve_image_tag(image, registries).await?;
-    let (index, application) =
-        prepare_image_config(&reference.to_string(), registries, "local-app", namespace).await?;
-    let binary_image = format!("{}@{}", index.binary_repository, index.binary_digest);
-    Ok((binary_image, application))
+    prepare_image_config(&reference.to_string(), registries, context).await
 }
 
 fn resolve_argument_container(
@@ -125,6 +204,13 @@ fn resolve_argument_container(
         return Ok(None);
     };
     Ok(Some(match arg {
+        Argument::ConfigMapVariable(v) => match &v.path {
+            Some(path) => ResolvedArgument::VolumeMount {
+                host_path: value,
+                container_path: path.clone(),
+            },
+            None => ResolvedArgument::String(value),
+        },
         Argument::FileVariable(v) => ResolvedArgument::VolumeMount {
             host_path: value,
             container_path: v.path.clone(),
@@ -134,14 +220,26 @@ fn resolve_argument_container(
     }))
 }
 
-async fn run_container_podman(binary_image: &str, config: ContainerConfig) -> Result<()> {
-    let mut cmd = Command::new("podman");
+async fn run_container(
+    binary_image: &str,
+    config: ContainerConfig,
+    options: EngineOptions,
+) -> Result<()> {
+    let mut cmd = Command::new(match options.engine {
+        ContainerEngine::Docker => "docker",
+        ContainerEngine::Podman => "podman",
+    });
     cmd.arg("run").arg("--rm");
 
-    if binary_image.starts_with("http://") {
+    // Docker only allows insecure registries through its daemon's configuration
+    if binary_image.starts_with("http://") && options.engine == ContainerEngine::Podman {
         cmd.arg("--tls-verify=false");
     }
 
+    if let Some(network) = &options.network {
+        cmd.arg("--network").arg(network);
+    }
+
     for (key, value) in &config.env {
         cmd.arg("--env").arg(format!("{}={}", key, value));
     }
@@ -153,8 +251,8 @@ async fn run_container_podman(binary_image: &str, config: ContainerConfig) -> Re
         ));
     }
 
-    for port in &config.ports {
-        cmd.arg("--publish").arg(format!("{}:{}", port, port));
+    for publish in &options.publish {
+        cmd.arg("--publish").arg(publish);
     }
 
     cmd.arg(binary_image);
@@ -171,3 +269,24 @@ async fn run_container_podman(binary_image: &str, config: ContainerConfig) -> Re
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_publish_flags() -> Result<()> {
+        let ports = vec!["8080".to_string(), "8081".to_string()];
+        let remapped = vec![
+            parse_publish("9090:8080")?,
+            parse_publish("127.0.0.1:9000:9000")?,
+        ];
+        assert_eq!(
+            publish_flags(&ports, &remapped),
+            vec!["9090:8080", "8081:8081", "127.0.0.1:9000:9000"]
+        );
+        assert!(parse_publish("8080").is_err());
+        assert!(parse_publish("9090:http").is_err());
+        Ok(())
+    }
+}
@@ -1,26 +1,56 @@
-use anyhow::{bail, Context, Result};
-use kube::{api::{DeleteParams, DynamicObject, Patch, PatchParams}, discovery::Scope};
+use anyhow::{anyhow, bail, Context, Result};
+use futures::future::join_all;
+use kube::{
+    api::{DeleteParams, DynamicObject, Patch, PatchParams},
+    core::ErrorResponse,
+    discovery::Scope,
+};
 use sqlx::AnyPool;
-use std::time::Duration;
-use tokio::time::sleep;
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+use tokio::{signal, task::JoinHandle, time::sleep};
+use tracing::{info, instrument, warn};
 
 use crate::{
+    audit::{action_images, audit_applied},
+    cluster_identity::verify_cluster_identities,
+    events::object_applied,
     generate_diff::DiffAction,
     kubernetes_io::{
-        get_kubernetes_api, get_kubernetes_clients, KubernetesKey, MANAGER,
+        find_type, get_kubernetes_api, get_kubernetes_clients, hash_secrets, ClusterTypes,
+        KubernetesKey, MANAGER,
     },
+    metrics::apply_finished,
+    provenance::{provenance, provenance_patch, stamp_provenance},
+    pull_secrets::{docker_credentials_patch, fill_docker_credentials, forget_docker_credentials},
+    push_lock::PushLock,
+    retry::{with_retries, with_throttling_retries},
+    state_store::{delete_object, insert_object, update_object},
+    targets::TargetPolicy,
 };
 
+#[instrument(skip_all, fields(actions = changed.len()))]
 pub(crate) async fn apply_diff(
     changed: Vec<(KubernetesKey, DiffAction)>,
+    policy: &TargetPolicy,
     pool: &AnyPool,
+    lock: &PushLock,
+    hash_secret_values: bool,
+    annotate_provenance: bool,
+    max_retries: u32,
+    keep_going: bool,
 ) -> Result<()> {
     let (clients, types) = get_kubernetes_clients(changed.iter().map(|(k, _)| k)).await?;
+    verify_cluster_identities(&clients, policy).await?;
     // Check that we don't have any namespace vs resource scope mismatches
     for (key, _) in &changed {
-        let Some((_, caps)) = types.get(&(key.api_version.clone(), key.kind.clone())) else {
-            bail!("Unable to find Kubernetes type for key {:?}", key);
-        };
+        let (_, caps) = find_type(key, &types)?;
         match (&caps.scope, &key.namespace) {
             (Scope::Cluster, None) => {},
             (Scope::Cluster, Some(_)) =>
@@ -30,161 +60,358 @@ pub(crate) async fn apply_diff(
                 bail!("Creating a namespaced-scoped resource without a namespace is disallowed"),
         }
     }
-    let mut pending_deletions: Vec<(kube::Api<DynamicObject>, String)> = Vec::new();
+    let watcher = InterruptWatcher::start();
+    let run = ApplyRun {
+        clients: &clients,
+        types: &types,
+        pool,
+        lock,
+        hash_secret_values,
+        annotate_provenance,
+        max_retries,
+        keep_going,
+        interrupted: &watcher.interrupted,
+        halted: AtomicBool::new(false),
+    };
+    // Clusters don't depend on each other, so each gets its own sequence of actions. The order
+    // within a cluster is kept since namespaces have to exist before what goes in them.
+    let mut by_cluster: BTreeMap<String, Vec<(KubernetesKey, DiffAction)>> = BTreeMap::new();
     for (key, action) in changed {
-        let api = get_kubernetes_api(&key, &clients, &types)?;
+        by_cluster
+            .entry(key.cluster.clone())
+            .or_default()
+            .push((key, action));
+    }
+    let results = join_all(
+        by_cluster
+            .into_values()
+            .map(|changes| apply_to_cluster(changes, &run)),
+    )
+    .await;
+
+    let mut outcomes = Vec::new();
+    let mut unapplied = Vec::new();
+    let mut error = None;
+    for result in results {
+        outcomes.extend(result.outcomes);
+        unapplied.extend(result.unapplied);
+        if error.is_none() {
+            error = result.error;
+        }
+    }
+    if keep_going {
+        report_outcomes(&outcomes);
+    }
+    if !unapplied.is_empty() {
+        report_unapplied(&unapplied);
+    }
+    if let Some(e) = error {
+        return Err(e);
+    }
+    if !unapplied.is_empty() {
+        bail!("Interrupted with {} actions not applied", unapplied.len());
+    }
+    let failed = outcomes.iter().filter(|(_, _, e)| e.is_some()).count();
+    if failed > 0 {
+        bail!("{} of {} actions failed", failed, outcomes.len());
+    }
+    Ok(())
+}
+
+// What every cluster's sequence of actions shares
+struct ApplyRun<'a> {
+    clients: &'a HashMap<String, kube::Client>,
+    types: &'a ClusterTypes,
+    pool: &'a AnyPool,
+    lock: &'a PushLock,
+    hash_secret_values: bool,
+    annotate_provenance: bool,
+    max_retries: u32,
+    keep_going: bool,
+    interrupted: &'a AtomicBool,
+    // Set when a cluster fails without --keep-going, so the others stop too
+    halted: AtomicBool,
+}
+
+#[derive(Default)]
+struct ClusterResult {
+    outcomes: Vec<(KubernetesKey, &'static str, Option<anyhow::Error>)>,
+    unapplied: Vec<(KubernetesKey, DiffAction)>,
+    error: Option<anyhow::Error>,
+}
+
+async fn apply_to_cluster(
+    changes: Vec<(KubernetesKey, DiffAction)>,
+    run: &ApplyRun<'_>,
+) -> ClusterResult {
+    let mut result = ClusterResult::default();
+    let mut pending_deletions: Vec<(kube::Api<DynamicObject>, String)> = Vec::new();
+    let mut remaining = changes.into_iter();
+    while let Some((key, action)) = remaining.next() {
+        // Only stop between actions: every action that started is finished and recorded in the
+        // database, so the next push picks up exactly where this one stopped.
+        if run.interrupted.load(Ordering::SeqCst) || run.halted.load(Ordering::SeqCst) {
+            result.unapplied = std::iter::once((key, action)).chain(remaining).collect();
+            break;
+        }
+        // Whoever took the lock may be applying a different plan, so interleaving with it could
+        // record objects that no longer match the cluster
+        if run.lock.is_lost() {
+            run.halted.store(true, Ordering::SeqCst);
+            result.error = Some(anyhow!("Lost the database lock, stopping before {}", key));
+            result.unapplied = std::iter::once((key, action)).chain(remaining).collect();
+            break;
+        }
+        let verb = action.verb();
         let is_delete = matches!(action, DiffAction::Delete);
-        apply_single_diff(action, &key, &api, pool).await?;
-        if is_delete {
-            pending_deletions.push((api, key.name.clone()));
+        let images = action_images(&action);
+        let applied = async {
+            let api = get_kubernetes_api(&key, run.clients, run.types)?;
+            apply_single_diff(
+                action,
+                &key,
+                &api,
+                run.pool,
+                run.hash_secret_values,
+                run.annotate_provenance,
+                run.max_retries,
+            )
+            .await?;
+            Ok::<_, anyhow::Error>(api)
+        }
+        .await;
+        match applied {
+            Ok(api) => {
+                if is_delete {
+                    pending_deletions.push((api, key.name.clone()));
+                }
+                object_applied(&key, verb);
+                audit_applied(&key, verb, &images, None);
+                apply_finished(verb, true);
+                result.outcomes.push((key, verb, None));
+            }
+            // The failed action wasn't recorded, so the next push tries it again
+            Err(e) if run.keep_going => {
+                warn!(
+                    object = %key,
+                    verb,
+                    error = %format!("{:#}", e),
+                    "Failed to {} {}: {:#}",
+                    verb,
+                    key,
+                    e
+                );
+                audit_applied(&key, verb, &images, Some(&e));
+                apply_finished(verb, false);
+                result.outcomes.push((key, verb, Some(e)));
+            }
+            Err(e) => {
+                audit_applied(&key, verb, &images, Some(&e));
+                apply_finished(verb, false);
+                run.halted.store(true, Ordering::SeqCst);
+                result.error = Some(e);
+                result.unapplied = remaining.collect();
+                break;
+            }
         }
     }
     // Wait for all deletions to complete before returning
     for (api, name) in &pending_deletions {
-        wait_for_deletion(api, name).await?;
+        if run.interrupted.load(Ordering::SeqCst) {
+            info!("Not waiting for deletions to finish");
+            break;
+        }
+        if let Err(e) = wait_for_deletion(api, name, run.max_retries).await {
+            result.error.get_or_insert(e);
+            break;
+        }
     }
-    Ok(())
+    result
 }
 
+// What happened to each action of a push that kept going past failures
+fn report_outcomes(outcomes: &[(KubernetesKey, &str, Option<anyhow::Error>)]) {
+    info!("Summary:");
+    for (key, verb, error) in outcomes {
+        match error {
+            None => info!(object = %key, verb, "  ✓ {} {}", verb, key),
+            Some(e) => info!(
+                object = %key,
+                verb,
+                error = %format!("{:#}", e),
+                "  ✗ {} {}: {:#}",
+                verb,
+                key,
+                e
+            ),
+        }
+    }
+}
+
+// The first Ctrl-C asks apply_diff to stop after the actions in flight. The second exits
+// immediately, which may leave them unrecorded. The watcher only lives as long as one apply_diff,
+// so that serve and library callers get Ctrl-C back once it returns.
+struct InterruptWatcher {
+    interrupted: Arc<AtomicBool>,
+    task: JoinHandle<()>,
+}
+
+impl InterruptWatcher {
+    fn start() -> Self {
+        let interrupted = Arc::new(AtomicBool::new(false));
+        let copy = interrupted.clone();
+        let task = tokio::spawn(async move {
+            if signal::ctrl_c().await.is_err() {
+                return;
+            }
+            warn!("Interrupted, finishing the actions in flight (press Ctrl-C again to abort)");
+            copy.store(true, Ordering::SeqCst);
+            if signal::ctrl_c().await.is_ok() {
+                std::process::exit(130);
+            }
+        });
+        Self { interrupted, task }
+    }
+}
+
+impl Drop for InterruptWatcher {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+fn report_unapplied(unapplied: &[(KubernetesKey, DiffAction)]) {
+    warn!("The following actions were not applied, run push again to apply them:");
+    for (key, action) in unapplied {
+        info!(object = %key, verb = action.verb(), "  • {} {}", action.verb(), key);
+    }
+}
+
+#[instrument(skip_all, fields(action = action.verb(), key = %key))]
 async fn apply_single_diff(
     action: DiffAction,
     key: &KubernetesKey,
     api: &kube::Api<DynamicObject>,
     pool: &AnyPool,
+    hash_secret_values: bool,
+    annotate_provenance: bool,
+    max_retries: u32,
 ) -> Result<()> {
+    let what = key.to_string();
+    let force_apply = PatchParams::apply(MANAGER).force();
     match action {
-        DiffAction::Create(v) => {
-            let result = api
-                .patch(
-                    &key.name,
-                    &PatchParams::apply(MANAGER).force(),
-                    &Patch::Apply(v),
-                )
-                .await
-                .with_context(|| format!("while creating {}", key))?;
-            sqlx::query(
-                r#"
-                INSERT INTO kubernetes_objects (api_version, cluster, kind, name, namespace, yaml)
-                VALUES ($1, $2, $3, $4, $5, $6)
-                "#,
-            )
-            .bind(key.api_version.clone())
-            .bind(key.cluster.clone())
-            .bind(key.kind.clone())
-            .bind(key.name.clone())
-            .bind(namespace_or_default(key.namespace.clone()))
-            .bind(serde_yaml::to_string(&result)?)
-            .execute(pool)
-            .await?;
-            println!("Created {}", key);
+        DiffAction::Create(mut v) => {
+            if annotate_provenance {
+                stamp_provenance(&mut v);
+            }
+            fill_docker_credentials(&mut v)?;
+            let apply = Patch::Apply(&v);
+            let result = with_retries(max_retries, &what, || {
+                api.patch(&key.name, &force_apply, &apply)
+            })
+            .await
+            .with_context(|| format!("while creating {}", key))?;
+            let yaml = recorded_yaml(result, &v, hash_secret_values)?;
+            insert_object(pool, key, &yaml, provenance()).await?;
+            info!(object = %key, verb = "create", "Created {}", key);
         }
         DiffAction::Delete => {
-            api.delete(&key.name, &DeleteParams::default())
+            delete_with_retries(api, &key.name, &what, max_retries)
                 .await
                 .with_context(|| format!("while deleting {}", key))?;
-            sqlx::query(
-                r#"
-                DELETE FROM kubernetes_objects
-                WHERE
-                    api_version = $1
-                    AND cluster = $2
-                    AND kind = $3
-                    AND name = $4
-                    AND namespace = $5
-                "#,
-            )
-            .bind(key.api_version.clone())
-            .bind(key.cluster.clone())
-            .bind(key.kind.clone())
-            .bind(key.name.clone())
-            .bind(namespace_or_default(key.namespace.clone()))
-            .execute(pool)
-            .await?;
-            println!("Deleted {}", key);
-        }
-        DiffAction::Patch { patch, .. } => {
-            let result = api
-                .patch(
-                    &key.name,
-                    &PatchParams::apply(MANAGER),
-                    &Patch::<()>::Json(patch),
-                )
-                .await
-                .with_context(|| format!("while updating {}", key))?;
-            sqlx::query(
-                r#"
-                UPDATE kubernetes_objects
-                SET last_updated = CURRENT_TIMESTAMP, yaml = $1
-                WHERE
-                    api_version = $2
-                    AND cluster = $3
-                    AND kind = $4
-                    AND name = $5
-                    AND namespace = $6
-                "#,
-            )
-            .bind(serde_yaml::to_string(&result)?)
-            .bind(key.api_version.clone())
-            .bind(key.cluster.clone())
-            .bind(key.kind.clone())
-            .bind(key.name.clone())
-            .bind(namespace_or_default(key.namespace.clone()))
-            .execute(pool)
-            .await?;
-            println!("Updated {}", key);
+            delete_object(pool, key).await?;
+            info!(object = %key, verb = "delete", "Deleted {}", key);
         }
-        DiffAction::Recreate(v) => {
-            api.delete(&key.name, &DeleteParams::default())
-                .await
-                .with_context(|| format!("while replacing {}", key))?;
-            println!("Deleting prior to recreate {}", key);
-            wait_for_deletion(api, &key.name).await?;
-            let result = api
-                .patch(
-                    &key.name,
-                    &PatchParams::apply(MANAGER).force(),
-                    &Patch::Apply(v),
-                )
+        DiffAction::Patch { after, mut patch } => {
+            if annotate_provenance {
+                patch.0.push(provenance_patch());
+            }
+            patch.0.extend(docker_credentials_patch(&after)?);
+            let params = PatchParams::apply(MANAGER);
+            let json = Patch::<()>::Json(patch);
+            let result = with_throttling_retries(max_retries, &what, || {
+                api.patch(&key.name, &params, &json)
+            })
+            .await
+            .with_context(|| format!("while updating {}", key))?;
+            let yaml = recorded_yaml(result, &after, hash_secret_values)?;
+            update_object(pool, key, &yaml, Some(provenance())).await?;
+            info!(object = %key, verb = "patch", "Updated {}", key);
+        }
+        DiffAction::Recreate(mut v) => {
+            if annotate_provenance {
+                stamp_provenance(&mut v);
+            }
+            fill_docker_credentials(&mut v)?;
+            delete_with_retries(api, &key.name, &what, max_retries)
                 .await
                 .with_context(|| format!("while replacing {}", key))?;
-            sqlx::query(
-                r#"
-                UPDATE kubernetes_objects
-                SET last_updated = CURRENT_TIMESTAMP, yaml = $1
-                WHERE
-                    api_version = $2
-                    AND cluster = $3
-                    AND kind = $4
-                    AND name = $5
-                    AND namespace = $6
-                "#,
-            )
-            .bind(serde_yaml::to_string(&result)?)
-            .bind(key.api_version.clone())
-            .bind(key.cluster.clone())
-            .bind(key.kind.clone())
-            .bind(key.name.clone())
-            .bind(namespace_or_default(key.namespace.clone()))
-            .execute(pool)
-            .await?;
-            println!("Recreated {}", key);
+            info!(object = %key, verb = "recreate", "Deleting prior to recreate {}", key);
+            wait_for_deletion(api, &key.name, max_retries).await?;
+            let apply = Patch::Apply(&v);
+            let result = with_retries(max_retries, &what, || {
+                api.patch(&key.name, &force_apply, &apply)
+            })
+            .await
+            .with_context(|| format!("while replacing {}", key))?;
+            let yaml = recorded_yaml(result, &v, hash_secret_values)?;
+            update_object(pool, key, &yaml, Some(provenance())).await?;
+            info!(object = %key, verb = "recreate", "Recreated {}", key);
         }
     }
     Ok(())
 }
 
-pub(crate) fn namespace_or_default(namespace: Option<String>) -> String {
-    namespace.unwrap_or_else(|| "".to_string())
+// The API server returns Secrets with their real values, which shouldn't land in the database when
+// hashing is on. Pull Secrets' docker credentials never do.
+fn recorded_yaml(
+    mut result: DynamicObject,
+    sent: &DynamicObject,
+    hash_secret_values: bool,
+) -> Result<String> {
+    forget_docker_credentials(&mut result);
+    if hash_secret_values {
+        hash_secrets(Some(sent), &mut result)?;
+    }
+    Ok(serde_yaml::to_string(&result)?)
 }
 
-async fn wait_for_deletion(api: &kube::Api<DynamicObject>, name: &str) -> Result<()> {
+// A retried delete that finds nothing means the one before it went through
+async fn delete_with_retries(
+    api: &kube::Api<DynamicObject>,
+    name: &str,
+    what: &str,
+    max_retries: u32,
+) -> kube::Result<()> {
+    let params = DeleteParams::default();
+    let mut attempts = 0;
+    with_retries(max_retries, what, || {
+        attempts += 1;
+        let retried = attempts > 1;
+        let params = &params;
+        async move {
+            match api.delete(name, params).await {
+                Ok(_) => Ok(()),
+                Err(kube::Error::Api(ErrorResponse { code: 404, .. })) if retried => Ok(()),
+                Err(e) => Err(e),
+            }
+        }
+    })
+    .await
+}
+
+async fn wait_for_deletion(
+    api: &kube::Api<DynamicObject>,
+    name: &str,
+    max_retries: u32,
+) -> Result<()> {
     let mut i = 0;
     loop {
         if i == 1 {
-            println!("Waiting for {} to be deleted...", name);
+            info!(name, "Waiting for {} to be deleted...", name);
         }
 
-        match api.get_opt(name).await? {
+        match with_retries(max_retries, name, || api.get_opt(name)).await? {
             Some(_) => {
                 sleep(Duration::from_millis(500)).await;
             }
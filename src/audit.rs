@@ -0,0 +1,201 @@
+use anyhow::Result;
+use kube::api::DynamicObject;
+use serde_json::{json, Map, Value as JsonValue};
+use std::{
+    fs::OpenOptions,
+    io::Write,
+    path::{Path, PathBuf},
+    sync::OnceLock,
+};
+use time::{format_description::well_known::Rfc3339, OffsetDateTime};
+use tracing::warn;
+
+use crate::{
+    generate_diff::DiffAction,
+    kubernetes_io::{KubernetesKey, ORIGIN_ANNOTATION},
+    kubernetes_rendering::Origin,
+    push_lock::whoami,
+};
+
+// An append-only JSON-lines file recording every prompt answered, every change applied or failed,
+// and how each command ended, kept apart from the database so it survives whatever happens to that
+static AUDIT_LOG: OnceLock<PathBuf> = OnceLock::new();
+
+pub(crate) fn configure_audit_log(path: Option<PathBuf>) {
+    if let Some(path) = path {
+        let _ = AUDIT_LOG.set(path);
+    }
+}
+
+// Records the answer to a prompt, and which object it was about when it was about just one
+pub(crate) fn audit_prompt(question: &str, key: Option<&KubernetesKey>, answer: &str) {
+    record(json!({
+        "event": "prompt",
+        "question": question,
+        "object": key.map(|k| k.to_string()),
+        "answer": answer,
+    }));
+}
+
+// Records a change that was applied, or failed to be, along with the images it deploys
+pub(crate) fn audit_applied(
+    key: &KubernetesKey,
+    verb: &str,
+    images: &JsonValue,
+    error: Option<&anyhow::Error>,
+) {
+    record(json!({
+        "event": "apply",
+        "object": key.to_string(),
+        "verb": verb,
+        "images": images,
+        "error": error.map(|e| format!("{:#}", e)),
+    }));
+}
+
+// Records how a command ended
+pub(crate) fn audit_finished(command: &str, result: &Result<()>) {
+    record(json!({
+        "event": "finished",
+        "command": command,
+        "error": result.as_ref().err().map(|e| format!("{:#}", e)),
+    }));
+}
+
+// The config image and container images, pinned to digests when rendering resolved them, that an
+// action deploys
+pub(crate) fn action_images(action: &DiffAction) -> JsonValue {
+    match action {
+        DiffAction::Create(object)
+        | DiffAction::Recreate(object)
+        | DiffAction::Patch { after: object, .. } => object_images(object),
+        DiffAction::Delete => JsonValue::Null,
+    }
+}
+
+fn object_images(object: &DynamicObject) -> JsonValue {
+    let mut containers = Map::new();
+    collect_container_images(&object.data, &mut containers);
+    let config_image = object
+        .metadata
+        .annotations
+        .as_ref()
+        .and_then(|a| a.get(ORIGIN_ANNOTATION))
+        .and_then(|o| serde_json::from_str::<Origin>(o).ok())
+        .and_then(|o| o.config_image);
+    if containers.is_empty() && config_image.is_none() {
+        return JsonValue::Null;
+    }
+    json!({"config": config_image, "containers": containers})
+}
+
+// Finds containers wherever the pod spec is, like spec.template.spec in a Deployment or
+// spec.jobTemplate.spec.template.spec in a CronJob
+fn collect_container_images(value: &JsonValue, found: &mut Map<String, JsonValue>) {
+    match value {
+        JsonValue::Object(map) => {
+            for (field, child) in map {
+                if field == "containers" || field == "initContainers" {
+                    for container in child.as_array().into_iter().flatten() {
+                        if let (Some(name), Some(image)) =
+                            (container["name"].as_str(), container["image"].as_str())
+                        {
+                            found.insert(name.to_string(), JsonValue::from(image));
+                        }
+                    }
+                } else {
+                    collect_container_images(child, found);
+                }
+            }
+        }
+        JsonValue::Array(items) => {
+            for item in items {
+                collect_container_images(item, found);
+            }
+        }
+        _ => {}
+    }
+}
+
+fn record(mut entry: JsonValue) {
+    let Some(path) = AUDIT_LOG.get() else {
+        return;
+    };
+    entry["time"] = JsonValue::from(
+        OffsetDateTime::now_utc()
+            .format(&Rfc3339)
+            .unwrap_or_default(),
+    );
+    entry["user"] = JsonValue::from(whoami());
+    entry["pid"] = JsonValue::from(std::process::id());
+    // Losing an audit entry is bad, but failing a push halfway through because of it is worse
+    if let Err(e) = append(path, &entry) {
+        warn!(
+            error = %e,
+            "Unable to write to the audit log {}: {}",
+            path.display(),
+            e
+        );
+    }
+}
+
+fn append(path: &Path, entry: &JsonValue) -> Result<()> {
+    let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+    // One write per line keeps concurrent runs from interleaving within an entry
+    file.write_all(format!("{}\n", entry).as_bytes())?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use kube::api::{ObjectMeta, TypeMeta};
+    use std::collections::BTreeMap;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_object_images() {
+        let origin =
+            r#"{"resource":"CronJob report","configImage":"registry.test/report_config@sha256:1"}"#;
+        let cron_job = DynamicObject {
+            types: Some(TypeMeta {
+                api_version: "batch/v1".to_string(),
+                kind: "CronJob".to_string(),
+            }),
+            metadata: ObjectMeta {
+                annotations: Some(BTreeMap::from([(
+                    ORIGIN_ANNOTATION.to_string(),
+                    origin.to_string(),
+                )])),
+                ..Default::default()
+            },
+            data: json!({"spec": {"jobTemplate": {"spec": {"template": {"spec": {
+                "initContainers": [{"name": "migrate", "image": "registry.test/migrate@sha256:2"}],
+                "containers": [{"name": "report", "image": "registry.test/report@sha256:3"}],
+            }}}}}}),
+        };
+        assert_eq!(
+            object_images(&cron_job),
+            json!({
+                "config": "registry.test/report_config@sha256:1",
+                "containers": {
+                    "migrate": "registry.test/migrate@sha256:2",
+                    "report": "registry.test/report@sha256:3",
+                },
+            })
+        );
+    }
+
+    #[test]
+    fn test_append() -> Result<()> {
+        let directory = TempDir::new()?;
+        let path = directory.path().join("audit.jsonl");
+        append(&path, &json!({"event": "prompt"}))?;
+        append(&path, &json!({"event": "finished"}))?;
+        assert_eq!(
+            std::fs::read_to_string(&path)?,
+            "{\"event\":\"prompt\"}\n{\"event\":\"finished\"}\n"
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,99 @@
+use anyhow::{anyhow, bail, Result};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    sync::OnceLock,
+};
+use tracing::warn;
+
+use crate::{discovery_cache::cache_home, image_layers::verify_digest};
+
+static ENABLED: OnceLock<bool> = OnceLock::new();
+
+// Turns off reading and writing config image manifests and layers on disk, for --no-cache
+pub(crate) fn configure_blob_cache(enabled: bool) {
+    let _ = ENABLED.set(enabled);
+}
+
+fn cache_root() -> Option<PathBuf> {
+    if !*ENABLED.get().unwrap_or(&true) {
+        return None;
+    }
+    Some(cache_home()?.join("sisyphus").join("blobs"))
+}
+
+// The content with this digest if an earlier run downloaded it. Tags aren't content addressed, so
+// only digests ever hit.
+pub(crate) fn read_cached(digest: &str) -> Option<Vec<u8>> {
+    read_from(&cache_root()?, digest)
+}
+
+// Keeps content that was already checked against its digest for later runs
+pub(crate) fn write_cached(digest: &str, content: &[u8]) {
+    let Some(root) = cache_root() else {
+        return;
+    };
+    // A cache we can't write only costs the next run some time
+    if let Err(e) = write_to(&root, digest, content) {
+        warn!(digest, error = %e, "Unable to cache {}: {}", digest, e);
+    }
+}
+
+fn read_from(root: &Path, digest: &str) -> Option<Vec<u8>> {
+    let content = fs::read(blob_path(root, digest).ok()?).ok()?;
+    // A file truncated by a full disk or edited by hand is just a miss
+    verify_digest(digest, &content).ok()?;
+    Some(content)
+}
+
+fn write_to(root: &Path, digest: &str, content: &[u8]) -> Result<()> {
+    let path = blob_path(root, digest)?;
+    let parent = path
+        .parent()
+        .ok_or_else(|| anyhow!("No parent directory for {}", path.display()))?;
+    fs::create_dir_all(parent)?;
+    // Concurrent runs may race, so write aside and rename to never leave a partial file
+    let temporary = path.with_extension(std::process::id().to_string());
+    fs::write(&temporary, content)?;
+    fs::rename(&temporary, &path)?;
+    Ok(())
+}
+
+fn blob_path(root: &Path, digest: &str) -> Result<PathBuf> {
+    let Some(hex) = digest.strip_prefix("sha256:") else {
+        bail!("Only sha256 digests are cached, not {}", digest);
+    };
+    if hex.len() != 64 || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+        bail!("Invalid digest {}", digest);
+    }
+    Ok(root.join("sha256").join(hex))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sha2::{Digest, Sha256};
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_round_trip() -> Result<()> {
+        let root = TempDir::new()?;
+        let content = b"layer";
+        let digest = format!("sha256:{:x}", Sha256::digest(content));
+        assert_eq!(read_from(root.path(), &digest), None);
+        write_to(root.path(), &digest, content)?;
+        assert_eq!(read_from(root.path(), &digest), Some(content.to_vec()));
+
+        // Corrupted entries are ignored rather than unpacked
+        fs::write(blob_path(root.path(), &digest)?, b"truncated")?;
+        assert_eq!(read_from(root.path(), &digest), None);
+        Ok(())
+    }
+
+    #[test]
+    fn test_only_digests_are_cached() {
+        let root = Path::new("/cache");
+        assert!(blob_path(root, "latest").is_err());
+        assert!(blob_path(root, "sha256:../../etc/passwd").is_err());
+    }
+}
@@ -0,0 +1,141 @@
+use anyhow::{anyhow, Context, Result};
+use kube::{
+    api::{DynamicObject, ListParams, TypeMeta},
+    core::ErrorResponse,
+    discovery::Scope,
+    Error, ResourceExt,
+};
+use std::collections::{HashMap, HashSet};
+
+use crate::kubernetes_io::{ClusterTypes, KubernetesKey};
+
+// API groups whose objects are only ever written by the cluster itself
+const GENERATED_GROUPS: [&str; 3] = ["coordination.k8s.io", "events.k8s.io", "metrics.k8s.io"];
+
+// What `import --all` would take over in one namespace: every object that isn't tracked yet and
+// wasn't made by Kubernetes or a controller, optionally narrowed to one kind or API version
+pub(crate) async fn find_importable(
+    cluster: &str,
+    namespace: &str,
+    kind: Option<&str>,
+    api_version: Option<&str>,
+    clients: &HashMap<String, kube::Client>,
+    types: &ClusterTypes,
+    tracked: &HashSet<KubernetesKey>,
+) -> Result<Vec<(KubernetesKey, DynamicObject)>> {
+    let client = clients
+        .get(cluster)
+        .ok_or_else(|| anyhow!("No client defined for {}", cluster))?;
+    let cluster_types = types
+        .get(cluster)
+        .ok_or_else(|| anyhow!("No types discovered for {}", cluster))?;
+
+    let mut found = Vec::new();
+    for ((type_version, type_kind), (ar, caps)) in cluster_types {
+        if caps.scope != Scope::Namespaced
+            || kind.is_some_and(|k| k != type_kind)
+            || api_version.is_some_and(|v| v != type_version)
+            || GENERATED_GROUPS.contains(&ar.group.as_str())
+        {
+            continue;
+        }
+        let api = kube::Api::<DynamicObject>::namespaced_with(client.clone(), namespace, ar);
+        let list = match api.list(&ListParams::default()).await {
+            Ok(list) => list,
+            // Some types, like access reviews, can only be created
+            Err(Error::Api(ErrorResponse {
+                code: 404 | 405, ..
+            })) if kind.is_none() => continue,
+            Err(e) => {
+                return Err(e)
+                    .with_context(|| format!("while listing {} in {}", type_kind, namespace))
+            }
+        };
+        for mut object in list.items {
+            let key = KubernetesKey {
+                api_version: type_version.clone(),
+                cluster: cluster.to_string(),
+                kind: type_kind.clone(),
+                name: object.name_any(),
+                namespace: Some(namespace.to_string()),
+            };
+            if tracked.contains(&key) || is_generated(&key, &object) {
+                continue;
+            }
+            // Items in a list don't say what they are, but applying them requires it
+            object.types = Some(TypeMeta {
+                api_version: type_version.clone(),
+                kind: type_kind.clone(),
+            });
+            found.push((key, object));
+        }
+    }
+    found.sort_by(|a, b| a.0.cmp(&b.0));
+    Ok(found)
+}
+
+// Objects that Kubernetes or a controller makes on its own, and would make again if they were
+// deleted, so Sisyphus shouldn't own them
+fn is_generated(key: &KubernetesKey, object: &DynamicObject) -> bool {
+    !object.owner_references().is_empty()
+        || matches!(
+            (key.kind.as_str(), key.name.as_str()),
+            ("ConfigMap", "kube-root-ca.crt")
+                | ("Endpoints", _)
+                | ("Event", _)
+                | ("ServiceAccount", "default")
+        )
+        || object.data.get("type").and_then(|t| t.as_str())
+            == Some("kubernetes.io/service-account-token")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::kubernetes_io::test_key;
+    use kube::api::ObjectMeta;
+    use serde_json::json;
+
+    fn object(kind: &str, name: &str, data: serde_json::Value) -> (KubernetesKey, DynamicObject) {
+        let key = test_key("prod", "v1", kind, Some("echo"), name);
+        let object = DynamicObject {
+            types: None,
+            metadata: ObjectMeta {
+                name: Some(name.to_string()),
+                ..Default::default()
+            },
+            data,
+        };
+        (key, object)
+    }
+
+    #[test]
+    fn test_is_generated() {
+        let generated = [
+            object("ConfigMap", "kube-root-ca.crt", json!({})),
+            object("ServiceAccount", "default", json!({})),
+            object("Endpoints", "echo", json!({})),
+            object(
+                "Secret",
+                "echo-token",
+                json!({"type": "kubernetes.io/service-account-token"}),
+            ),
+        ];
+        for (key, object) in &generated {
+            assert!(is_generated(key, object), "{}", key);
+        }
+
+        let (key, mut replica_set) = object("ReplicaSet", "echo-5d4f8", json!({}));
+        replica_set.metadata.owner_references = Some(vec![Default::default()]);
+        assert!(is_generated(&key, &replica_set));
+
+        let hand_written = [
+            object("ConfigMap", "echo", json!({})),
+            object("ServiceAccount", "echo", json!({})),
+            object("Secret", "echo", json!({"type": "Opaque"})),
+        ];
+        for (key, object) in &hand_written {
+            assert!(!is_generated(key, object), "{}", key);
+        }
+    }
+}
@@ -0,0 +1,1981 @@
+use crate::{
+    answers::Answers,
+    app_deploy_local::{deploy_local, DeployLocalArgs},
+    app_run_config::{run_config, RunConfigArgs},
+    app_run_image::{run_image, RunImageArgs},
+    apply_diff::apply_diff,
+    audit::{audit_finished, audit_prompt, configure_audit_log},
+    blob_cache::configure_blob_cache,
+    bulk_import::find_importable,
+    cluster_identity::mark_cluster,
+    database::{get_objects_from_database, get_tracked_objects},
+    debug_overlay::{clear_overlay, debug_overlay, parse_ttl, Overlay},
+    delegation::{generate_key, load_delegation, sign, trust, Delegation},
+    deployed_digests::verify_deployed_digests,
+    discovery_cache::{configure_discovery, DEFAULT_DISCOVERY_TTL},
+    e2e::{run_e2e, ClusterProvider},
+    environments::Environments,
+    events::{configure_events, flush_events, object_drifted},
+    export::{export, print_rendered},
+    filter::{object_matches_filter, PartialKey},
+    generate_diff::{
+        check_protected, configure_recreate_rules, generate_diff, generate_plan, plan_order,
+        pod_impact_summary, print_diff, print_plan_summary, DiffAction, PlannedChange,
+        SecretKeyChanges,
+    },
+    git_revision::monitor_directories_at,
+    kubernetes_io::{
+        configure_ignore_paths, get_kubernetes_api, get_kubernetes_clients, hash_secrets,
+        inputs_hash, is_secret, munge_secrets, suppress_ignored_paths, KubernetesKey,
+        KubernetesResources, MANAGER,
+    },
+    list::{list, ListFormat},
+    logging::LogFormat,
+    migrations::migrate,
+    metrics::{configure_metrics, push_metrics},
+    notifications::{configure_notifications, notify_finished},
+    output::{configure_output, theme, ColorChoice, ThemeName},
+    plan::{compare, load_database_and_files, plan_changes, DiffAgainst},
+    plugins::configure_plugins,
+    profiles::{apply_profile, with_profile_filters, ProfileArgs},
+    provenance::{configure_provenance, provenance},
+    push_lock::{lock_database, PushLock},
+    registry_clients::configure_platform,
+    render_files::STDIN_CONSUMED,
+    retry::{with_retries, DEFAULT_MAX_RETRIES},
+    serve::{serve, ServeArgs},
+    show::{show, ObjectSource, ShowSources},
+    state_store::{connect_database, delete_object, find_object, insert_object, update_object},
+    status::ObjectStatus,
+    targets::{load_targets, TargetPolicy},
+    telemetry::init_telemetry,
+    timings::{Phase, Timings},
+    validate::{validate, ValidateArgs},
+    why::why,
+};
+use anyhow::{bail, Context, Result};
+use clap::{Args, CommandFactory, FromArgMatches, Parser, Subcommand};
+use indicatif::{ProgressBar, ProgressStyle};
+use kube::{
+    api::{DynamicObject, Patch, PatchParams},
+    core::ErrorResponse,
+    Error,
+};
+use similar::TextDiff;
+use sqlx::AnyPool;
+use std::{
+    collections::{BTreeMap, BTreeSet, HashSet},
+    fs::File,
+    io::{BufRead, BufReader, Write},
+    path::PathBuf,
+    sync::atomic::Ordering,
+    time::{Duration, Instant},
+};
+use tracing::{info, instrument, warn};
+
+#[derive(Parser, Debug)]
+#[command(version, about, long_about = None)]
+struct SisyphusArgs {
+    // Whether to color output. `auto` colors terminals unless NO_COLOR or CI is set.
+    #[arg(long, global = true, value_enum, default_value = "auto")]
+    color: ColorChoice,
+
+    // How to style diffs and progress bars
+    #[arg(long, global = true, value_enum, default_value = "default")]
+    theme: ThemeName,
+
+    // How many seconds to reuse a cluster's cached API discovery results for
+    #[arg(
+        long,
+        global = true,
+        env = "SISYPHUS_DISCOVERY_TTL",
+        default_value_t = DEFAULT_DISCOVERY_TTL.as_secs()
+    )]
+    discovery_ttl: u64,
+
+    // Ignore cached API discovery results and fetch them from every cluster again
+    #[arg(long, global = true)]
+    refresh_discovery: bool,
+
+    // Download config image manifests and layers from the registry instead of reading and
+    // writing them in the local cache
+    #[arg(long, global = true)]
+    no_cache: bool,
+
+    // Publish a CloudEvent for every applied change and detected drift to an http(s)://,
+    // nats://host:port/subject, or kafka+http(s):// REST proxy topic URL
+    #[arg(long, global = true, env = "SISYPHUS_EVENT_SINK")]
+    event_sink: Option<String>,
+
+    // Post a summary of each finished push and refresh to a Slack incoming webhook or, as JSON, to
+    // any other http(s):// URL
+    #[arg(long, global = true, env = "SISYPHUS_NOTIFY")]
+    notify: Option<String>,
+
+    // Pin multi-platform config images to the manifest for this os/architecture, like
+    // linux/arm64, rather than to the list
+    #[arg(long, global = true, env = "SISYPHUS_PLATFORM")]
+    platform: Option<String>,
+
+    // Ignore a JSON pointer on every object of a kind, like Deployment=/spec/replicas or
+    // apps/v1/Deployment=/spec/replicas
+    #[arg(long, global = true, env = "SISYPHUS_IGNORE_PATHS", value_delimiter = ',')]
+    ignore_path: Vec<String>,
+
+    // A YAML list of {apiVersion, kind, paths} naming more fields that can only be changed by
+    // deleting and recreating the object
+    #[arg(long, global = true, env = "SISYPHUS_RECREATE_RULES_FILE")]
+    recreate_rules_file: Option<PathBuf>,
+
+    // Append a JSON line to this file for every prompt answered, change applied or failed, and
+    // command finished, with the images each change deploys
+    #[arg(long, global = true, env = "SISYPHUS_AUDIT_LOG")]
+    audit_log: Option<PathBuf>,
+
+    // Render resources of a kind with an executable, like KafkaTopic=/usr/local/bin/kafka-topic
+    #[arg(long, global = true, env = "SISYPHUS_PLUGINS", value_delimiter = ',')]
+    plugin: Vec<String>,
+
+    // Write progress, like each object applied, as plain lines or as JSON objects for log
+    // pipelines. Prompts, plans, and diffs are always plain.
+    #[arg(long, global = true, env = "SISYPHUS_LOG_FORMAT", value_enum, default_value = "text")]
+    log_format: LogFormat,
+
+    // Serve Prometheus metrics at http://<address>/metrics for as long as the command runs, like
+    // 0.0.0.0:9464
+    #[arg(long, global = true, env = "SISYPHUS_METRICS_LISTEN")]
+    metrics_listen: Option<String>,
+
+    // Push Prometheus metrics to this Pushgateway URL when the command finishes
+    #[arg(long, global = true, env = "SISYPHUS_METRICS_PUSHGATEWAY")]
+    metrics_pushgateway: Option<String>,
+
+    #[command(flatten)]
+    profile: ProfileArgs,
+
+    #[command(subcommand)]
+    command: Commands,
+}
+
+#[derive(Debug, Subcommand)]
+enum Commands {
+    App {
+        #[command(subcommand)]
+        app_command: AppCommands,
+    },
+    // Removes a debug overlay right away instead of waiting for it to expire
+    ClearOverlay {
+        #[arg(long, env = "DATABASE_URL")]
+        database_url: String,
+
+        // Take the database lock even if another push, refresh, or import seems to hold it, for
+        // when that one died
+        #[arg(long)]
+        force_unlock: bool,
+
+        // Act as the team named in this signed delegation, refusing objects outside of its
+        // clusters and namespaces. Required once the database trusts any delegation keys.
+        #[arg(long, env = "SISYPHUS_DELEGATION")]
+        delegation: Option<PathBuf>,
+
+        // Which tracked workload to clear, usually just --name
+        #[command(flatten)]
+        filter: PartialKey,
+
+        // How many times to retry a Kubernetes call that failed with a throttling, server, or
+        // connection error
+        #[arg(long, env = "SISYPHUS_MAX_RETRIES", default_value_t = DEFAULT_MAX_RETRIES)]
+        max_retries: u32,
+    },
+    Db {
+        #[command(subcommand)]
+        db_command: DbCommands,
+    },
+    // Manages the signed delegations that let a team push to its own namespaces only
+    Delegation {
+        #[command(subcommand)]
+        delegation_command: DelegationCommands,
+    },
+    // Temporarily sets environment variables or adds arguments on a tracked workload without
+    // changing any files. Pushes keep them until they expire, and the next push after that removes
+    // them.
+    DebugOverlay {
+        #[arg(long, env = "DATABASE_URL")]
+        database_url: String,
+
+        // Take the database lock even if another push, refresh, or import seems to hold it, for
+        // when that one died
+        #[arg(long)]
+        force_unlock: bool,
+
+        // Act as the team named in this signed delegation, refusing objects outside of its
+        // clusters and namespaces. Required once the database trusts any delegation keys.
+        #[arg(long, env = "SISYPHUS_DELEGATION")]
+        delegation: Option<PathBuf>,
+
+        // Which tracked workload to change, usually just --name
+        #[command(flatten)]
+        filter: PartialKey,
+
+        // Only change this container instead of all of them
+        #[arg(long)]
+        container: Option<String>,
+
+        // An environment variable to set, as NAME=VALUE
+        #[arg(long, value_parser = parse_env_var)]
+        set_env: Vec<(String, String)>,
+
+        // An argument to append to the container's arguments
+        #[arg(long, allow_hyphen_values = true)]
+        add_arg: Vec<String>,
+
+        // How long to keep the overlay, like 30m or 2h
+        #[arg(long, default_value = "1h", value_parser = parse_ttl)]
+        ttl: Duration,
+
+        // How many times to retry a Kubernetes call that failed with a throttling, server, or
+        // connection error
+        #[arg(long, env = "SISYPHUS_MAX_RETRIES", default_value_t = DEFAULT_MAX_RETRIES)]
+        max_retries: u32,
+    },
+    Diff {
+        #[command(flatten)]
+        args: PushArgs,
+
+        // Pass `cluster` to also fetch the live objects and show what changed in the clusters since
+        // the last push or refresh
+        #[arg(long, value_enum, default_value = "database")]
+        against: DiffAgainst,
+
+        // How many times to retry a Kubernetes call that failed with a throttling, server, or
+        // connection error
+        #[arg(long, env = "SISYPHUS_MAX_RETRIES", default_value_t = DEFAULT_MAX_RETRIES)]
+        max_retries: u32,
+    },
+    // Compares what the database recorded with the live objects like refresh does, but only prints
+    // the differences and exits with 2 if there are any, so it can alert from cron or CI
+    Drift {
+        #[arg(long, env = "DATABASE_URL")]
+        database_url: String,
+
+        #[command(flatten)]
+        filter: PartialKey,
+
+        // Compare Secret values as salted hashes, for databases that refresh with --hash-secrets
+        #[arg(long, env = "SISYPHUS_HASH_SECRETS")]
+        hash_secrets: bool,
+
+        // How many times to retry a Kubernetes call that failed with a throttling, server, or
+        // connection error
+        #[arg(long, env = "SISYPHUS_MAX_RETRIES", default_value_t = DEFAULT_MAX_RETRIES)]
+        max_retries: u32,
+    },
+    // Creates a throwaway local cluster, pushes a small monitor directory to it with this binary,
+    // checks the result, exercises refresh and pruning, and deletes the cluster again
+    E2e {
+        #[arg(long, value_enum, default_value = "kind")]
+        cluster_provider: ClusterProvider,
+
+        // Leave the cluster running afterwards to poke at it
+        #[arg(long)]
+        keep_cluster: bool,
+    },
+    // Writes every object the files render to into a directory, one YAML file per object
+    Export {
+        // The filters to consider
+        #[command(flatten)]
+        filter: PartialKey,
+
+        #[arg(
+            long,
+            env = "MONITOR_DIRECTORY",
+            value_delimiter = ',',
+            required = true
+        )]
+        monitor_directory: Vec<String>,
+
+        #[arg(long, env = "SISYPHUS_ENVIRONMENTS")]
+        environments: Option<String>,
+
+        #[arg(
+            long,
+            env = "SISYPHUS_MANAGEMENT_CONTEXT",
+            conflicts_with = "environments"
+        )]
+        management_context: Option<String>,
+
+        // The directory to write to, which must be empty or not exist yet
+        #[arg(long)]
+        out: PathBuf,
+    },
+    Forget {
+        #[arg(long, env = "DATABASE_URL")]
+        database_url: String,
+
+        // Take the database lock even if another push, refresh, or import seems to hold it, for
+        // when that one died
+        #[arg(long)]
+        force_unlock: bool,
+
+        // Act as the team named in this signed delegation, refusing objects outside of its
+        // clusters and namespaces. Required once the database trusts any delegation keys.
+        #[arg(long, env = "SISYPHUS_DELEGATION")]
+        delegation: Option<PathBuf>,
+
+        // Succeed without doing anything if the object isn't tracked
+        #[arg(long)]
+        if_exists: bool,
+
+        #[command(flatten)]
+        key: FullKey,
+
+        #[command(flatten)]
+        script: ScriptArgs,
+    },
+    Import {
+        #[arg(long, env = "DATABASE_URL")]
+        database_url: String,
+
+        // Take the database lock even if another push, refresh, or import seems to hold it, for
+        // when that one died
+        #[arg(long)]
+        force_unlock: bool,
+
+        // Act as the team named in this signed delegation, refusing objects outside of its
+        // clusters and namespaces. Required once the database trusts any delegation keys.
+        #[arg(long, env = "SISYPHUS_DELEGATION")]
+        delegation: Option<PathBuf>,
+
+        // Record salted hashes of Secret values instead of the values themselves
+        #[arg(long, env = "SISYPHUS_HASH_SECRETS")]
+        hash_secrets: bool,
+
+        // Succeed without doing anything if the object is already tracked
+        #[arg(long)]
+        if_not_exists: bool,
+
+        // Import every untracked object in --namespace, or only those of --kind, after a single
+        // confirmation. Objects made by Kubernetes or a controller are left alone.
+        #[arg(long, requires = "namespace", conflicts_with = "name")]
+        all: bool,
+
+        #[command(flatten)]
+        key: ImportKey,
+
+        #[command(flatten)]
+        script: ScriptArgs,
+    },
+    // Prints the objects the database tracks
+    List {
+        #[arg(long, env = "DATABASE_URL")]
+        database_url: String,
+
+        #[command(flatten)]
+        filter: PartialKey,
+
+        #[arg(long, value_enum, default_value = "table")]
+        format: ListFormat,
+    },
+    // Records in a cluster which context it belongs to, so pushes through a context that points
+    // somewhere else are refused
+    MarkCluster {
+        #[arg(long)]
+        context: String,
+
+        // Overwrite a marker naming a different context
+        #[arg(long)]
+        force: bool,
+    },
+    Push {
+        #[command(flatten)]
+        args: PushArgs,
+
+        // Also record the Sisyphus version and flags that applied each object in an annotation on
+        // the object itself, not only in the database
+        #[arg(long, env = "SISYPHUS_ANNOTATE_PROVENANCE")]
+        annotate_provenance: bool,
+
+        // Delete and recreate objects annotated sisyphus.april.dev/protect instead of refusing to
+        #[arg(long)]
+        allow_protected: bool,
+
+        // Take the database lock even if another push, refresh, or import seems to hold it, for
+        // when that one died
+        #[arg(long)]
+        force_unlock: bool,
+
+        // Answer each change's prompt from a file of object patterns and verbs instead of asking,
+        // aborting the push if any change isn't covered
+        #[arg(long, conflicts_with = "interactive")]
+        answers_file: Option<PathBuf>,
+
+        // Push as the team named in this signed delegation, refusing changes outside of its
+        // clusters and namespaces. Required once the database trusts any delegation keys.
+        #[arg(long, env = "SISYPHUS_DELEGATION")]
+        delegation: Option<PathBuf>,
+
+        // Record salted hashes of Secret values instead of the values themselves
+        #[arg(long, env = "SISYPHUS_HASH_SECRETS")]
+        hash_secrets: bool,
+
+        // Confirm each changed object individually instead of the whole plan at once
+        #[arg(long)]
+        interactive: bool,
+
+        // Apply every change even after one fails, then report which ones failed
+        #[arg(long)]
+        keep_going: bool,
+
+        // How many times to retry a Kubernetes call that failed with a throttling, server, or
+        // connection error
+        #[arg(long, env = "SISYPHUS_MAX_RETRIES", default_value_t = DEFAULT_MAX_RETRIES)]
+        max_retries: u32,
+    },
+    Refresh {
+        #[arg(long, env = "DATABASE_URL")]
+        database_url: String,
+
+        // Take the database lock even if another push, refresh, or import seems to hold it, for
+        // when that one died
+        #[arg(long)]
+        force_unlock: bool,
+
+        // Act as the team named in this signed delegation, refusing objects outside of its
+        // clusters and namespaces. Required once the database trusts any delegation keys.
+        #[arg(long, env = "SISYPHUS_DELEGATION")]
+        delegation: Option<PathBuf>,
+
+        // Record salted hashes of Secret values instead of the values themselves
+        #[arg(long, env = "SISYPHUS_HASH_SECRETS")]
+        hash_secrets: bool,
+
+        // How many times to retry a Kubernetes call that failed with a throttling, server, or
+        // connection error
+        #[arg(long, env = "SISYPHUS_MAX_RETRIES", default_value_t = DEFAULT_MAX_RETRIES)]
+        max_retries: u32,
+    },
+    // Prints everything the files render to as multi-document YAML, without a database or any
+    // cluster, for debugging Starlark and YAML
+    Render {
+        // The filters to consider
+        #[command(flatten)]
+        filter: PartialKey,
+
+        #[arg(
+            long,
+            env = "MONITOR_DIRECTORY",
+            value_delimiter = ',',
+            required = true
+        )]
+        monitor_directory: Vec<String>,
+
+        #[arg(long, env = "SISYPHUS_ENVIRONMENTS")]
+        environments: Option<String>,
+    },
+    // Serves render, plan, approve, and apply over HTTP so that a web UI or chat bot can drive
+    // pushes, with the same lock, policy, and protection checks as push
+    Serve {
+        #[command(flatten)]
+        args: ServeArgs,
+    },
+    // Prints one object as the files render it, as the database recorded it, or as the cluster has
+    // it
+    Show {
+        #[command(flatten)]
+        key: FullKey,
+
+        #[arg(long, value_enum, default_value = "files")]
+        source: ObjectSource,
+
+        #[arg(long, env = "DATABASE_URL")]
+        database_url: Option<String>,
+
+        #[arg(long, env = "MONITOR_DIRECTORY", value_delimiter = ',')]
+        monitor_directory: Vec<String>,
+
+        #[arg(long, env = "SISYPHUS_ENVIRONMENTS")]
+        environments: Option<String>,
+
+        #[arg(
+            long,
+            env = "SISYPHUS_MANAGEMENT_CONTEXT",
+            conflicts_with = "environments"
+        )]
+        management_context: Option<String>,
+    },
+    // Reports whether each tracked object still exists, whether it was changed outside of
+    // Sisyphus, and how healthy it looks, without rendering any files
+    Status {
+        #[arg(long, env = "DATABASE_URL")]
+        database_url: String,
+
+        #[command(flatten)]
+        filter: PartialKey,
+
+        // Print a JSON array instead of a line per object
+        #[arg(long)]
+        json: bool,
+
+        // How many times to retry a Kubernetes call that failed with a throttling, server, or
+        // connection error
+        #[arg(long, env = "SISYPHUS_MAX_RETRIES", default_value_t = DEFAULT_MAX_RETRIES)]
+        max_retries: u32,
+
+        // Also check that the pods of each Deployment, StatefulSet, and DaemonSet run exactly the
+        // image digests that were pushed, failing if any don't
+        #[arg(long)]
+        verify_deployed: bool,
+    },
+    // Renders everything and checks each object against its JSON schema without a database or any
+    // cluster, for fast CI checks
+    Validate {
+        #[command(flatten)]
+        args: ValidateArgs,
+    },
+    Verify {
+        #[command(flatten)]
+        args: PushArgs,
+    },
+    // Explains which file, config image, and Starlark entrypoint produced a tracked object
+    Why {
+        #[command(flatten)]
+        key: FullKey,
+
+        #[arg(long, env = "DATABASE_URL")]
+        database_url: String,
+
+        // Also check whether the files still render the object from the same place
+        #[arg(long, env = "MONITOR_DIRECTORY", value_delimiter = ',')]
+        monitor_directory: Vec<String>,
+
+        #[arg(long, env = "SISYPHUS_ENVIRONMENTS")]
+        environments: Option<String>,
+
+        #[arg(
+            long,
+            env = "SISYPHUS_MANAGEMENT_CONTEXT",
+            conflicts_with = "environments"
+        )]
+        management_context: Option<String>,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+enum AppCommands {
+    DeployLocal {
+        #[command(flatten)]
+        args: DeployLocalArgs,
+    },
+    RunConfig {
+        #[command(flatten)]
+        args: RunConfigArgs,
+    },
+    RunImage {
+        #[command(flatten)]
+        args: RunImageArgs,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+enum DbCommands {
+    // Creates the tables Sisyphus uses in a PostgreSQL or SQLite database, or upgrades them after
+    // a new release
+    Migrate {
+        #[arg(long, env = "DATABASE_URL")]
+        database_url: String,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+enum DelegationCommands {
+    // Writes a new Ed25519 signing key and prints its public half
+    Keygen {
+        #[arg(long)]
+        out: PathBuf,
+    },
+    // Signs the policy in a delegation file in place
+    Sign {
+        #[arg(long)]
+        key: PathBuf,
+
+        policy: PathBuf,
+    },
+    // Makes the database accept delegations signed by a public key, after which every push needs
+    // one. Anyone with write access to the database can do this, so delegations only restrict
+    // people who push without it.
+    #[command(after_help = "Anyone who can write to the database can trust a key of their own, \
+                            so delegations only restrict people who push without that access.")]
+    Trust {
+        #[arg(long, env = "DATABASE_URL")]
+        database_url: String,
+
+        #[arg(long)]
+        name: String,
+
+        #[arg(long)]
+        public_key: String,
+    },
+}
+
+#[derive(Args, Debug)]
+struct FullKey {
+    #[arg(long)]
+    api_version: String,
+
+    #[arg(long)]
+    cluster: String,
+
+    #[arg(long)]
+    kind: String,
+
+    #[arg(long)]
+    name: String,
+
+    #[arg(long)]
+    namespace: Option<String>,
+}
+
+impl Into<KubernetesKey> for FullKey {
+    fn into(self) -> KubernetesKey {
+        KubernetesKey {
+            api_version: self.api_version,
+            cluster: self.cluster,
+            kind: self.kind,
+            name: self.name,
+            namespace: self.namespace,
+        }
+    }
+}
+
+pub(crate) fn parse_env_var(value: &str) -> Result<(String, String)> {
+    let Some((name, value)) = value.split_once('=') else {
+        bail!("{:?} must look like NAME=VALUE", value);
+    };
+    Ok((name.to_string(), value.to_string()))
+}
+
+// Like FullKey, but --all only needs the cluster and namespace
+#[derive(Args, Debug)]
+struct ImportKey {
+    #[arg(long)]
+    api_version: Option<String>,
+
+    #[arg(long)]
+    cluster: String,
+
+    #[arg(long, required_unless_present = "all")]
+    kind: Option<String>,
+
+    #[arg(long, required_unless_present = "all")]
+    name: Option<String>,
+
+    #[arg(long)]
+    namespace: Option<String>,
+}
+
+impl ImportKey {
+    fn into_key(self) -> Result<KubernetesKey> {
+        let (Some(api_version), Some(kind), Some(name)) = (self.api_version, self.kind, self.name)
+        else {
+            bail!("--api-version, --kind, and --name are required to import a single object");
+        };
+        Ok(KubernetesKey {
+            api_version,
+            cluster: self.cluster,
+            kind,
+            name,
+            namespace: self.namespace,
+        })
+    }
+}
+
+#[derive(Args, Debug)]
+struct ScriptArgs {
+    // Print one JSON object describing the result instead of a preview. Requires --yes since there's
+    // nothing to review.
+    #[arg(long, requires = "yes")]
+    json: bool,
+
+    // Don't ask for confirmation
+    #[arg(long)]
+    yes: bool,
+}
+
+#[derive(Args, Debug)]
+struct PushArgs {
+    #[arg(long, env = "DATABASE_URL")]
+    database_url: String,
+
+    // The filters to consider
+    #[command(flatten)]
+    filter: PartialKey,
+
+    // The paths to the directories of configuration files to monitor, or - to read resources from
+    // stdin. Pass it more than once or separate paths with commas to combine several repos.
+    #[arg(
+        long,
+        env = "MONITOR_DIRECTORY",
+        value_delimiter = ',',
+        required = true
+    )]
+    monitor_directory: Vec<String>,
+
+    // Read the monitor directory as of this commit, branch, or tag instead of from the working tree,
+    // and record the commit with every object applied
+    #[arg(long, env = "SISYPHUS_GIT_REF")]
+    git_ref: Option<String>,
+
+    // A YAML file mapping environment names to clusters, which `env:<name>` footprint entries and
+    // KubernetesYaml clusters expand through
+    #[arg(long, env = "SISYPHUS_ENVIRONMENTS")]
+    environments: Option<String>,
+
+    // A kubeconfig context whose SisyphusTarget objects define environments, frozen clusters, and
+    // protected clusters
+    #[arg(long, env = "SISYPHUS_MANAGEMENT_CONTEXT", conflicts_with = "environments")]
+    management_context: Option<String>,
+
+    // Print how long each phase (parsing, rendering, diffing, ...) took
+    #[arg(long)]
+    timings: bool,
+}
+
+// Returned by `drift` when something changed outside of Sisyphus. `main` exits with status 2 for
+// it, after the audit log, metrics and traces have been written like for any other result.
+#[derive(Debug)]
+pub struct DriftDetected;
+
+impl std::fmt::Display for DriftDetected {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Objects have drifted from what was pushed")
+    }
+}
+
+impl std::error::Error for DriftDetected {}
+
+// Everything the `sisyphus` binary does, from parsing arguments to exiting
+pub async fn run() -> Result<()> {
+    env_logger::init();
+    sqlx::any::install_default_drivers();
+
+    let profile = apply_profile()?;
+    let command = with_profile_filters(
+        SisyphusArgs::command(),
+        profile
+            .as_ref()
+            .map(|p| p.filters.as_slice())
+            .unwrap_or_default(),
+    );
+    let matches = command.clone().get_matches();
+    configure_provenance(&command, &matches);
+    let command_name = matches.subcommand_name().unwrap_or_default().to_string();
+    let args = SisyphusArgs::from_arg_matches(&matches).unwrap_or_else(|e| e.exit());
+    configure_output(args.color, args.theme);
+    configure_discovery(if args.refresh_discovery {
+        Duration::ZERO
+    } else {
+        Duration::from_secs(args.discovery_ttl)
+    });
+    configure_events(args.event_sink.as_deref())?;
+    configure_notifications(args.notify.as_deref())?;
+    configure_platform(args.platform);
+    configure_blob_cache(!args.no_cache);
+    configure_ignore_paths(&args.ignore_path)?;
+    configure_recreate_rules(args.recreate_rules_file.as_deref())?;
+    configure_audit_log(args.audit_log);
+    configure_plugins(&args.plugin)?;
+    configure_metrics(
+        args.metrics_listen.as_deref(),
+        args.metrics_pushgateway.as_deref(),
+    )?;
+    let telemetry = init_telemetry(args.log_format)?;
+    if let Some(profile) = &profile {
+        info!(profile = %profile.name, "Using profile {}", profile.name);
+        if !profile.filters.is_empty() {
+            let filters = profile.describe_filters();
+            info!(filters = %filters, "Filtering with {}", filters);
+        }
+    }
+    let result = run_command(args.command).await;
+    flush_events().await;
+    audit_finished(&command_name, &result);
+    push_metrics(&command_name).await;
+    telemetry.shutdown();
+    result
+}
+
+#[instrument(name = "sisyphus", skip_all)]
+async fn run_command(command: Commands) -> Result<()> {
+    match command {
+        Commands::App { app_command } => match app_command {
+            AppCommands::DeployLocal { args } => deploy_local(args).await?,
+            AppCommands::RunConfig { args } => run_config(args).await?,
+            AppCommands::RunImage { args } => run_image(args).await?,
+        },
+        Commands::ClearOverlay {
+            database_url,
+            force_unlock,
+            delegation,
+            filter,
+            max_retries,
+        } => {
+            let pool = connect_database(&database_url).await?;
+            let delegation = load_delegation(&pool, delegation.as_deref()).await?;
+            let lock = lock_database(&pool, "clear-overlay", force_unlock).await?;
+            let result =
+                clear_overlay(&filter, &pool, &lock, delegation.as_ref(), max_retries).await;
+            lock.release().await?;
+            result?
+        }
+        Commands::Db { db_command } => match db_command {
+            DbCommands::Migrate { database_url } => {
+                let pool = connect_database(&database_url).await?;
+                migrate(&pool).await?
+            }
+        },
+        Commands::Delegation { delegation_command } => match delegation_command {
+            DelegationCommands::Keygen { out } => println!("{}", generate_key(&out)?),
+            DelegationCommands::Sign { key, policy } => sign(&policy, &key)?,
+            DelegationCommands::Trust {
+                database_url,
+                name,
+                public_key,
+            } => {
+                let pool = connect_database(&database_url).await?;
+                trust(&pool, &name, &public_key).await?
+            }
+        },
+        Commands::DebugOverlay {
+            database_url,
+            force_unlock,
+            delegation,
+            filter,
+            container,
+            set_env,
+            add_arg,
+            ttl,
+            max_retries,
+        } => {
+            let pool = connect_database(&database_url).await?;
+            let delegation = load_delegation(&pool, delegation.as_deref()).await?;
+            let overlay = Overlay::new(container, set_env.into_iter().collect(), add_arg);
+            let lock = lock_database(&pool, "debug-overlay", force_unlock).await?;
+            let result = debug_overlay(
+                &filter,
+                overlay,
+                ttl,
+                &pool,
+                &lock,
+                delegation.as_ref(),
+                max_retries,
+            )
+            .await;
+            lock.release().await?;
+            result?
+        }
+        Commands::Diff {
+            args: PushArgs {
+                database_url,
+                environments,
+                filter,
+                git_ref,
+                management_context,
+                monitor_directory,
+                timings,
+            },
+            against,
+            max_retries,
+        } => {
+            let pool = connect_database(&database_url).await?;
+            let (monitor_directory, _checkout) =
+                monitor_directories_at(monitor_directory, git_ref.as_deref()).await?;
+            let (mut environments, _) =
+                load_targets(environments.as_deref(), management_context.as_deref()).await?;
+            let mut timings = Timings::new(timings);
+            let plan = match against {
+                DiffAgainst::Database => {
+                    let plan = diff(
+                        &filter,
+                        &monitor_directory,
+                        &mut environments,
+                        &pool,
+                        &mut timings,
+                    )
+                    .await?;
+                    for change in &plan {
+                        print!("{}", change.rendering);
+                    }
+                    plan
+                }
+                DiffAgainst::Cluster => {
+                    diff_against_cluster(
+                        &filter,
+                        &monitor_directory,
+                        &mut environments,
+                        &pool,
+                        max_retries,
+                        &mut timings,
+                    )
+                    .await?
+                }
+            };
+            if let Some(summary) = pod_impact_summary(&plan) {
+                println!("{}", summary);
+            }
+            timings.print();
+        }
+        Commands::Drift {
+            database_url,
+            filter,
+            hash_secrets,
+            max_retries,
+        } => {
+            let pool = connect_database(&database_url).await?;
+            if drift(&filter, &pool, hash_secrets, max_retries).await? {
+                return Err(DriftDetected.into());
+            }
+        }
+        Commands::E2e {
+            cluster_provider,
+            keep_cluster,
+        } => run_e2e(cluster_provider, keep_cluster).await?,
+        Commands::Export {
+            filter,
+            monitor_directory,
+            environments,
+            management_context,
+            out,
+        } => {
+            let (mut environments, _) =
+                load_targets(environments.as_deref(), management_context.as_deref()).await?;
+            export(&filter, &monitor_directory, &mut environments, &out).await?
+        }
+        Commands::Forget {
+            database_url,
+            force_unlock,
+            delegation,
+            if_exists,
+            key,
+            script,
+        } => {
+            let pool = connect_database(&database_url).await?;
+            let key = key.into();
+            if let Some(delegation) = load_delegation(&pool, delegation.as_deref()).await? {
+                delegation.check(&key)?;
+            }
+            let lock = lock_database(&pool, "forget", force_unlock).await?;
+            let result = forget(&key, if_exists, &script, &pool).await;
+            lock.release().await?;
+            let (outcome, secret_keys) = result?;
+            report_outcome(&key, outcome, secret_keys.as_ref(), &script)?;
+        }
+        Commands::Import {
+            database_url,
+            force_unlock,
+            delegation,
+            hash_secrets,
+            if_not_exists,
+            all,
+            key,
+            script,
+        } => {
+            let pool = connect_database(&database_url).await?;
+            let delegation = load_delegation(&pool, delegation.as_deref()).await?;
+            let lock = lock_database(&pool, "import", force_unlock).await?;
+            let result = async {
+                if all {
+                    import_all(&key, &script, &pool, delegation.as_ref(), hash_secrets).await
+                } else {
+                    let key = key.into_key()?;
+                    if let Some(delegation) = &delegation {
+                        delegation.check(&key)?;
+                    }
+                    let (outcome, secret_keys) =
+                        import(&key, if_not_exists, &script, &pool, hash_secrets).await?;
+                    report_outcome(&key, outcome, secret_keys.as_ref(), &script)
+                }
+            }
+            .await;
+            lock.release().await?;
+            result?
+        }
+        Commands::List {
+            database_url,
+            filter,
+            format,
+        } => {
+            let pool = connect_database(&database_url).await?;
+            list(&filter, &pool, format).await?
+        }
+        Commands::MarkCluster { context, force } => mark_cluster(&context, force).await?,
+        Commands::Push {
+            args: PushArgs {
+                database_url,
+                environments,
+                filter,
+                git_ref,
+                management_context,
+                monitor_directory,
+                timings,
+            },
+            annotate_provenance,
+            allow_protected,
+            answers_file,
+            delegation,
+            force_unlock,
+            hash_secrets,
+            interactive,
+            keep_going,
+            max_retries,
+        } => {
+            let answers = answers_file.as_deref().map(Answers::load).transpose()?;
+            let pool = connect_database(&database_url).await?;
+            let delegation = load_delegation(&pool, delegation.as_deref()).await?;
+            let (monitor_directory, _checkout) =
+                monitor_directories_at(monitor_directory, git_ref.as_deref()).await?;
+            let (mut environments, policy) =
+                load_targets(environments.as_deref(), management_context.as_deref()).await?;
+            let mut timings = Timings::new(timings);
+            let options = PushOptions {
+                hash_secret_values: hash_secrets,
+                annotate_provenance,
+                allow_protected,
+                answers,
+                delegation,
+                interactive,
+                keep_going,
+                max_retries,
+            };
+            let lock = lock_database(&pool, "push", force_unlock).await?;
+            let result = push(
+                &filter,
+                &monitor_directory,
+                &mut environments,
+                &policy,
+                &pool,
+                &lock,
+                &options,
+                &mut timings,
+            )
+            .await;
+            lock.release().await?;
+            result?;
+            timings.print();
+        }
+        Commands::Refresh {
+            database_url,
+            force_unlock,
+            delegation,
+            hash_secrets,
+            max_retries,
+        } => {
+            let pool = connect_database(&database_url).await?;
+            let delegation = load_delegation(&pool, delegation.as_deref()).await?;
+            let lock = lock_database(&pool, "refresh", force_unlock).await?;
+            let result = refresh(&pool, delegation.as_ref(), hash_secrets, max_retries).await;
+            lock.release().await?;
+            result?
+        }
+        Commands::Render {
+            filter,
+            monitor_directory,
+            environments,
+        } => {
+            let mut environments = Environments::load(environments.as_deref())?;
+            print_rendered(&filter, &monitor_directory, &mut environments).await?
+        }
+        Commands::Serve { args } => serve(args).await?,
+        Commands::Show {
+            key,
+            source,
+            database_url,
+            monitor_directory,
+            environments,
+            management_context,
+        } => {
+            let (mut environments, _) =
+                load_targets(environments.as_deref(), management_context.as_deref()).await?;
+            let sources = ShowSources {
+                monitor_directory: (!monitor_directory.is_empty())
+                    .then_some(&monitor_directory[..]),
+                environments: &mut environments,
+                database_url: database_url.as_deref(),
+            };
+            let key = key.into();
+            show(&key, source, sources).await?;
+        }
+        Commands::Status {
+            database_url,
+            filter,
+            json,
+            max_retries,
+            verify_deployed,
+        } => {
+            let pool = connect_database(&database_url).await?;
+            status(&filter, &pool, json, max_retries, verify_deployed).await?
+        }
+        Commands::Validate { args } => validate(args).await?,
+        Commands::Verify {
+            args: PushArgs {
+                database_url,
+                environments,
+                filter,
+                git_ref,
+                management_context,
+                monitor_directory,
+                timings,
+            }
+        } => {
+            let pool = connect_database(&database_url).await?;
+            let (monitor_directory, _checkout) =
+                monitor_directories_at(monitor_directory, git_ref.as_deref()).await?;
+            let (mut environments, _) =
+                load_targets(environments.as_deref(), management_context.as_deref()).await?;
+            let mut timings = Timings::new(timings);
+            verify(&filter, &monitor_directory, &mut environments, &pool, &mut timings).await?;
+            timings.print();
+        }
+        Commands::Why {
+            key,
+            database_url,
+            monitor_directory,
+            environments,
+            management_context,
+        } => {
+            let pool = connect_database(&database_url).await?;
+            let (mut environments, _) =
+                load_targets(environments.as_deref(), management_context.as_deref()).await?;
+            let key = key.into();
+            why(&key, &pool, &monitor_directory, &mut environments).await?
+        }
+    };
+    Ok(())
+}
+
+// What forget or import did, reported to scripts so they don't have to parse error messages.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum KeyOutcome {
+    Forgotten,
+    Imported,
+    AlreadyAbsent,
+    AlreadyPresent,
+    Canceled,
+}
+
+impl KeyOutcome {
+    fn as_str(&self) -> &'static str {
+        match self {
+            KeyOutcome::Forgotten => "forgotten",
+            KeyOutcome::Imported => "imported",
+            KeyOutcome::AlreadyAbsent => "absent",
+            KeyOutcome::AlreadyPresent => "present",
+            KeyOutcome::Canceled => "canceled",
+        }
+    }
+}
+
+// Secrets also report which keys were forgotten or imported, since their values aren't shown
+fn report_outcome(
+    key: &KubernetesKey,
+    outcome: KeyOutcome,
+    secret_keys: Option<&SecretKeyChanges>,
+    script: &ScriptArgs,
+) -> Result<()> {
+    if script.json {
+        println!("{}", serde_json::to_string(&outcome_json(key, outcome, secret_keys))?);
+        return Ok(());
+    }
+    match outcome {
+        KeyOutcome::Forgotten => info!(object = %key, verb = "forget", "Forgot {}", key),
+        KeyOutcome::Imported => info!(object = %key, verb = "import", "Imported {}", key),
+        KeyOutcome::AlreadyAbsent => {
+            info!(object = %key, "{} isn't tracked, nothing to forget", key)
+        }
+        KeyOutcome::AlreadyPresent => {
+            info!(object = %key, "{} is already tracked, nothing to import", key)
+        }
+        // ask_for_user_permission already said so
+        KeyOutcome::Canceled => {}
+    }
+    Ok(())
+}
+
+fn outcome_json(
+    key: &KubernetesKey,
+    outcome: KeyOutcome,
+    secret_keys: Option<&SecretKeyChanges>,
+) -> serde_json::Value {
+    let mut json = serde_json::json!({
+        "apiVersion": key.api_version,
+        "cluster": key.cluster,
+        "kind": key.kind,
+        "name": key.name,
+        "namespace": key.namespace,
+        "result": outcome.as_str(),
+    });
+    if let Some(secret_keys) = secret_keys {
+        json["secretKeys"] = serde_json::json!(secret_keys);
+    }
+    json
+}
+
+async fn forget(
+    key: &KubernetesKey,
+    if_exists: bool,
+    script: &ScriptArgs,
+    pool: &AnyPool,
+) -> Result<(KeyOutcome, Option<SecretKeyChanges>)> {
+    let Some((as_yaml, _)) = find_object(pool, key).await? else {
+        if if_exists {
+            return Ok((KeyOutcome::AlreadyAbsent, None));
+        }
+        bail!("No such object")
+    };
+    let recorded: DynamicObject = serde_yaml::from_str(&as_yaml)?;
+    let secret_keys = is_secret(&recorded).then(|| SecretKeyChanges::new(Some(&recorded), None));
+    if !script.json {
+        let diff = TextDiff::from_lines(as_yaml.as_str(), "");
+        println!("• {} {}\n", theme().delete.apply_to("forget"), key);
+        print_diff(&diff);
+        println!("");
+    }
+
+    if !script.yes && !ask_for_user_permission("forgetting")? {
+        return Ok((KeyOutcome::Canceled, None));
+    }
+
+    if delete_object(pool, key).await? == 0 {
+        bail!("Unable to find object {}", key);
+    }
+    Ok((KeyOutcome::Forgotten, secret_keys))
+}
+
+async fn import(
+    key: &KubernetesKey,
+    if_not_exists: bool,
+    script: &ScriptArgs,
+    pool: &AnyPool,
+    hash_secret_values: bool,
+) -> Result<(KeyOutcome, Option<SecretKeyChanges>)> {
+    if find_object(pool, key).await?.is_some() {
+        if if_not_exists {
+            return Ok((KeyOutcome::AlreadyPresent, None));
+        }
+        bail!("Object {} already exists", key);
+    }
+
+    let (clients, types) = get_kubernetes_clients([key]).await?;
+    let api = get_kubernetes_api(&key, &clients, &types)?;
+    if let (Some(_), None) = (&key.namespace, api.namespace()) {
+        bail!("Resource type {} is cluster scoped", key.kind);
+    }
+    let mut object = api.get(&key.name).await?;
+    munge_secrets(None, &mut object)?;
+    let secret_keys = is_secret(&object).then(|| SecretKeyChanges::new(None, Some(&object)));
+    if !script.json {
+        let as_yaml = serde_yaml::to_string(&object)?;
+        let diff = TextDiff::from_lines("", &as_yaml);
+        println!("• {} {}\n", theme().create.apply_to("import"), key);
+        print_diff(&diff);
+        println!("");
+    }
+
+    if !script.yes && !ask_for_user_permission("importing")? {
+        return Ok((KeyOutcome::Canceled, None));
+    }
+
+    record_import(key, object, &api, pool, hash_secret_values).await?;
+    Ok((KeyOutcome::Imported, secret_keys))
+}
+
+// Imports everything find_importable finds, showing it all and asking once
+async fn import_all(
+    selector: &ImportKey,
+    script: &ScriptArgs,
+    pool: &AnyPool,
+    delegation: Option<&Delegation>,
+    hash_secret_values: bool,
+) -> Result<()> {
+    let Some(namespace) = selector.namespace.as_deref() else {
+        bail!("--all requires --namespace");
+    };
+    let tracked: HashSet<_> = get_tracked_objects(pool)
+        .await?
+        .into_iter()
+        .map(|t| t.key)
+        .collect();
+    let cluster = KubernetesKey {
+        api_version: "v1".to_string(),
+        cluster: selector.cluster.clone(),
+        kind: "Namespace".to_string(),
+        name: namespace.to_string(),
+        namespace: None,
+    };
+    if let Some(delegation) = delegation {
+        delegation.check(&cluster)?;
+    }
+    let (clients, types) = get_kubernetes_clients([&cluster]).await?;
+    let mut objects = find_importable(
+        &selector.cluster,
+        namespace,
+        selector.kind.as_deref(),
+        selector.api_version.as_deref(),
+        &clients,
+        &types,
+        &tracked,
+    )
+    .await?;
+    if objects.is_empty() {
+        if !script.json {
+            info!("Nothing to import");
+        }
+        return Ok(());
+    }
+
+    for (key, object) in &mut objects {
+        munge_secrets(None, object)?;
+        if !script.json {
+            let as_yaml = serde_yaml::to_string(&object)?;
+            let diff = TextDiff::from_lines("", &as_yaml);
+            println!("• {} {}\n", theme().create.apply_to("import"), key);
+            print_diff(&diff);
+            println!("");
+        }
+    }
+
+    if !script.yes && !ask_for_user_permission(&format!("importing {} objects", objects.len()))? {
+        return Ok(());
+    }
+    for (key, object) in objects {
+        let api = get_kubernetes_api(&key, &clients, &types)?;
+        let secret_keys = is_secret(&object).then(|| SecretKeyChanges::new(None, Some(&object)));
+        record_import(&key, object, &api, pool, hash_secret_values).await?;
+        report_outcome(&key, KeyOutcome::Imported, secret_keys.as_ref(), script)?;
+    }
+    Ok(())
+}
+
+// Takes ownership of `object` in its cluster and starts tracking it
+async fn record_import(
+    key: &KubernetesKey,
+    mut object: DynamicObject,
+    api: &kube::Api<DynamicObject>,
+    pool: &AnyPool,
+    hash_secret_values: bool,
+) -> Result<()> {
+    object.metadata.managed_fields = None;
+    let mut result = api
+        .patch(
+            &key.name,
+            &PatchParams::apply(MANAGER).force(),
+            &Patch::Apply(object),
+        )
+        .await
+        .with_context(|| format!("while imporing {}", key))?;
+    if hash_secret_values {
+        hash_secrets(None, &mut result)?;
+    }
+
+    insert_object(pool, key, &serde_yaml::to_string(&result)?, provenance()).await?;
+    Ok(())
+}
+
+async fn diff(
+    filter: &PartialKey,
+    monitor_directory: &[String],
+    environments: &mut Environments,
+    pool: &AnyPool,
+    timings: &mut Timings,
+) -> Result<Vec<PlannedChange>> {
+    let changed = plan_changes(filter, monitor_directory, environments, pool, timings).await?;
+    if changed.len() == 0 {
+        info!("Nothing to do");
+    }
+    Ok(changed)
+}
+
+// Shows what a push would change alongside what changed in the clusters since the last push or
+// refresh, and calls out objects where both happened since a push would overwrite the live change
+async fn diff_against_cluster(
+    filter: &PartialKey,
+    monitor_directory: &[String],
+    environments: &mut Environments,
+    pool: &AnyPool,
+    max_retries: u32,
+    timings: &mut Timings,
+) -> Result<Vec<PlannedChange>> {
+    let (from_database, from_files) =
+        load_database_and_files(filter, monitor_directory, environments, pool, timings).await?;
+    let plan = compare(&from_database, &from_files, timings)?;
+
+    let mut from_kubernetes = get_objects_from_kubernetes(&from_database, max_retries).await?;
+    for (k, live) in &mut from_kubernetes.by_key {
+        if let Some(recorded) = from_database.by_key.get(k) {
+            suppress_ignored_paths(recorded, live)?;
+        }
+        munge_secrets(from_database.by_key.get(k), live)?;
+    }
+    let started = Instant::now();
+    let drift = generate_plan(from_database, from_kubernetes)?;
+    timings.record(Phase::Diff, started);
+
+    if plan.is_empty() && drift.is_empty() {
+        info!("Nothing to do");
+        return Ok(plan);
+    }
+    let theme = theme();
+    if !plan.is_empty() {
+        println!(
+            "{}\n",
+            theme
+                .create
+                .apply_to("Files → database: what a push would change")
+        );
+        for change in &plan {
+            print!("{}", change.rendering);
+        }
+    }
+    if !drift.is_empty() {
+        println!(
+            "{}\n",
+            theme
+                .patch
+                .apply_to("Database → cluster: what changed outside of Sisyphus")
+        );
+        for change in &drift {
+            print!("{}", change.rendering);
+        }
+    }
+    let drifted: HashSet<_> = drift.iter().map(|c| &c.key).collect();
+    let conflicts: Vec<_> = plan.iter().filter(|c| drifted.contains(&c.key)).collect();
+    if !conflicts.is_empty() {
+        println!(
+            "{}",
+            theme.delete.apply_to(
+                "Changed in both files and the cluster, so a push overwrites the live change:"
+            )
+        );
+        for change in conflicts {
+            println!("  • {}", change.key);
+        }
+        println!();
+    }
+    Ok(plan)
+}
+
+// Compares only which objects exist, not their contents, so it's cheap enough to run on every
+// merge. Fails if the database and files disagree.
+async fn verify(
+    filter: &PartialKey,
+    monitor_directory: &[String],
+    environments: &mut Environments,
+    pool: &AnyPool,
+    timings: &mut Timings,
+) -> Result<()> {
+    let (from_database, from_files) =
+        load_database_and_files(filter, monitor_directory, environments, pool, timings).await?;
+
+    let started = Instant::now();
+    let (only_in_database, only_in_files) = compare_keys(&from_database, &from_files);
+    timings.record(Phase::Diff, started);
+
+    for key in &only_in_database {
+        warn!(
+            object = %key,
+            "{} is tracked in the database but no longer in files (a candidate for forgetting)",
+            key
+        );
+    }
+    for key in &only_in_files {
+        warn!(object = %key, "{} is in files but was never pushed", key);
+    }
+    if only_in_database.is_empty() && only_in_files.is_empty() {
+        info!("Database and files agree");
+        Ok(())
+    } else {
+        bail!(
+            "{} objects are only in the database and {} are only in files",
+            only_in_database.len(),
+            only_in_files.len()
+        )
+    }
+}
+
+fn compare_keys<'a>(
+    from_database: &'a KubernetesResources,
+    from_files: &'a KubernetesResources,
+) -> (Vec<&'a KubernetesKey>, Vec<&'a KubernetesKey>) {
+    let database_keys: BTreeSet<_> = from_database
+        .namespaces
+        .keys()
+        .chain(from_database.by_key.keys())
+        .collect();
+    let file_keys: BTreeSet<_> = from_files
+        .namespaces
+        .keys()
+        .chain(from_files.by_key.keys())
+        .collect();
+    (
+        database_keys.difference(&file_keys).copied().collect(),
+        file_keys.difference(&database_keys).copied().collect(),
+    )
+}
+
+struct PushOptions {
+    hash_secret_values: bool,
+    annotate_provenance: bool,
+    allow_protected: bool,
+    answers: Option<Answers>,
+    delegation: Option<Delegation>,
+    interactive: bool,
+    keep_going: bool,
+    max_retries: u32,
+}
+
+async fn push(
+    filter: &PartialKey,
+    monitor_directory: &[String],
+    environments: &mut Environments,
+    policy: &TargetPolicy,
+    pool: &AnyPool,
+    lock: &PushLock,
+    options: &PushOptions,
+    timings: &mut Timings,
+) -> Result<()> {
+    let mut plan = diff(filter, monitor_directory, environments, pool, timings).await?;
+    if plan.len() == 0 {
+        return Ok(())
+    }
+    if let Some(delegation) = &options.delegation {
+        plan = delegation.restrict(plan)?;
+        if plan.is_empty() {
+            info!("Nothing to do");
+            return Ok(());
+        }
+    }
+    if let Some(answers) = &options.answers {
+        plan = answer_changes(plan, answers)?;
+        if plan.is_empty() {
+            info!("Nothing to do");
+            return Ok(());
+        }
+    } else if options.interactive {
+        plan = choose_changes(plan)?;
+        if plan.is_empty() {
+            info!("Nothing to do");
+            return Ok(());
+        }
+    }
+    let changed: Vec<_> = plan.iter().map(|c| (&c.key, &c.action)).collect();
+    policy.check(&changed)?;
+    if !options.allow_protected {
+        check_protected(&plan)?;
+    }
+    if options.answers.is_some() {
+        // Every change was already answered, so there's nothing left to confirm
+    } else if options.interactive {
+        if !ask_for_user_permission(&format!("pushing {} changes", plan.len()))? {
+            return Ok(());
+        }
+    } else if !review_plan(&plan)? {
+        return Ok(());
+    }
+    let summary: Vec<_> = plan.iter().map(|c| (c.key.clone(), c.action.verb())).collect();
+    let changed = plan.into_iter().map(|c| (c.key, c.action)).collect();
+    let started = Instant::now();
+    let result = apply_diff(
+        changed,
+        policy,
+        &pool,
+        lock,
+        options.hash_secret_values,
+        options.annotate_provenance,
+        options.max_retries,
+        options.keep_going,
+    )
+    .await;
+    notify_finished("push", &summary, result.as_ref().err()).await;
+    result?;
+    timings.record(Phase::Apply, started);
+    Ok(())
+}
+
+// Fetches the live copy of everything in `from_database`, made comparable to what was recorded
+async fn get_comparable_live_objects(
+    from_database: &KubernetesResources,
+    hash_secret_values: bool,
+    max_retries: u32,
+) -> Result<KubernetesResources> {
+    let mut from_kubernetes = get_objects_from_kubernetes(from_database, max_retries).await?;
+    for (k, to) in &mut from_kubernetes.by_key {
+        if let Some(from) = from_database.by_key.get(k) {
+            suppress_ignored_paths(from, to)?;
+        }
+        if hash_secret_values {
+            // Changed values show up as a changed hash for just that key
+            hash_secrets(from_database.by_key.get(k), to)?;
+        } else {
+            munge_secrets(from_database.by_key.get(k), to)?;
+        }
+    }
+    Ok(from_kubernetes)
+}
+
+// Prints how the live objects differ from what the database recorded, returning whether they do
+#[instrument(skip_all)]
+async fn drift(
+    filter: &PartialKey,
+    pool: &AnyPool,
+    hash_secret_values: bool,
+    max_retries: u32,
+) -> Result<bool> {
+    let mut from_database = get_objects_from_database(&pool).await?;
+    from_database
+        .by_key
+        .retain(|k, v| object_matches_filter(k, v, filter));
+    from_database
+        .namespaces
+        .retain(|k, v| object_matches_filter(k, v, filter));
+    let from_kubernetes =
+        get_comparable_live_objects(&from_database, hash_secret_values, max_retries).await?;
+    let drifted = generate_plan(from_database, from_kubernetes)?;
+    if drifted.is_empty() {
+        info!("No drift");
+        return Ok(false);
+    }
+
+    for change in &drifted {
+        print!("{}", change.rendering);
+        object_drifted(&change.key);
+    }
+    for change in &drifted {
+        let state = match change.action {
+            DiffAction::Delete => "missing",
+            _ => "changed",
+        };
+        warn!(
+            object = %change.key,
+            state,
+            "{} drifted from the database ({})",
+            change.key,
+            state
+        );
+    }
+    Ok(true)
+}
+
+#[instrument(skip_all)]
+async fn refresh(
+    pool: &AnyPool,
+    delegation: Option<&Delegation>,
+    hash_secret_values: bool,
+    max_retries: u32,
+) -> Result<()> {
+    let mut from_database = get_objects_from_database(&pool).await?;
+    // A team only refreshes what its delegation covers, since it may not even be able to read the
+    // rest
+    if let Some(delegation) = delegation {
+        delegation.retain(&mut from_database);
+    }
+    let from_kubernetes =
+        get_comparable_live_objects(&from_database, hash_secret_values, max_retries).await?;
+
+    // A mismatched inputs hash means something other than our last push wrote the object, which
+    // is quicker to spot here than in the full diff below.
+    let mismatched: Vec<_> = from_kubernetes
+        .by_key
+        .iter()
+        .filter(|(k, live)| {
+            from_database
+                .by_key
+                .get(k)
+                .map_or(false, |recorded| inputs_hash(live) != inputs_hash(recorded))
+        })
+        .map(|(k, _)| k)
+        .collect();
+    for key in &mismatched {
+        warn!(object = %key, "Rendering inputs of {} changed outside of Sisyphus", key);
+    }
+    let changed = generate_diff(from_database, from_kubernetes)?;
+    if changed.len() == 0 {
+        info!("Nothing to do");
+        return Ok(());
+    }
+    for (key, _) in &changed {
+        object_drifted(key);
+    }
+
+    if !ask_for_user_permission("refreshing")? {
+        return Ok(());
+    }
+
+    let summary: Vec<_> = changed.iter().map(|(k, a)| (k.clone(), a.verb())).collect();
+    let result = refresh_group(changed, &pool).await;
+    notify_finished("refresh", &summary, result.as_ref().err()).await;
+    result
+}
+
+#[instrument(skip_all)]
+async fn status(
+    filter: &PartialKey,
+    pool: &AnyPool,
+    json: bool,
+    max_retries: u32,
+    verify_deployed: bool,
+) -> Result<()> {
+    let mut from_database = get_objects_from_database(&pool).await?;
+    from_database
+        .by_key
+        .retain(|k, v| object_matches_filter(k, v, filter));
+    from_database
+        .namespaces
+        .retain(|k, v| object_matches_filter(k, v, filter));
+    let mut from_kubernetes = get_objects_from_kubernetes(&from_database, max_retries).await?;
+
+    let mut statuses = Vec::new();
+    for (recorded, live) in [
+        (&from_database.by_key, &mut from_kubernetes.by_key),
+        (&from_database.namespaces, &mut from_kubernetes.namespaces),
+    ] {
+        for (key, r) in recorded {
+            let mut l = live.get_mut(key);
+            if let Some(l) = l.as_deref_mut() {
+                // Secret values aren't recorded, so they can't count as drift
+                munge_secrets(Some(r), l)?;
+                suppress_ignored_paths(r, l)?;
+            }
+            statuses.push((key, ObjectStatus::new(r, l.as_deref())));
+        }
+    }
+
+    let undeployed = if verify_deployed {
+        verify_deployed_digests(&from_database).await?
+    } else {
+        Vec::new()
+    };
+
+    if json {
+        let as_json: Vec<_> = statuses.iter().map(|(k, s)| s.to_json(k)).collect();
+        println!("{}", serde_json::to_string(&as_json)?);
+    } else {
+        let theme = theme();
+        for (key, status) in &statuses {
+            let state = if !status.exists {
+                theme.delete.apply_to("missing")
+            } else if status.drifted {
+                theme.patch.apply_to("drifted")
+            } else {
+                theme.create.apply_to("in sync")
+            };
+            match &status.health {
+                Some(health) => println!("• {} {} ({})", state, key, health),
+                None => println!("• {} {}", state, key),
+            }
+        }
+    }
+    if !undeployed.is_empty() {
+        for problem in &undeployed {
+            warn!("Pods aren't running the pushed digest: {}", problem);
+        }
+        bail!("{} containers don't run the pushed digests", undeployed.len());
+    }
+    Ok(())
+}
+
+async fn refresh_group(changed: Vec<(KubernetesKey, DiffAction)>, pool: &AnyPool) -> Result<()> {
+    for (key, action) in changed {
+        match action {
+            DiffAction::Create(w)
+            | DiffAction::Patch { after: w, .. }
+            | DiffAction::Recreate(w) => {
+                update_object(pool, &key, &serde_yaml::to_string(&w)?, None).await?;
+                info!(object = %key, verb = "update", "Updated {}", key);
+            }
+            DiffAction::Delete => {
+                delete_object(pool, &key).await?;
+                info!(object = %key, verb = "delete", "Deleted {}", key);
+            }
+        };
+    }
+    Ok(())
+}
+
+#[instrument(skip_all)]
+async fn get_objects_from_kubernetes(
+    from_database: &KubernetesResources,
+    max_retries: u32,
+) -> Result<KubernetesResources> {
+    let mut resources = KubernetesResources {
+        by_key: BTreeMap::new(),
+        namespaces: BTreeMap::new(),
+    };
+    let (clients, types) = get_kubernetes_clients(
+        from_database
+            .by_key
+            .keys()
+            .chain(from_database.namespaces.keys()),
+    )
+    .await?;
+    let bar =
+        ProgressBar::new((from_database.by_key.len() + from_database.namespaces.len()) as u64)
+            .with_style(ProgressStyle::with_template(theme().progress_template)?)
+            .with_message("Comparing resources...");
+    for (source, destination) in [
+        (&from_database.by_key, &mut resources.by_key),
+        (&from_database.namespaces, &mut resources.namespaces),
+    ] {
+        for key in source.keys() {
+            let api = get_kubernetes_api(key, &clients, &types)?;
+            match with_retries(max_retries, &key.to_string(), || api.get(&key.name)).await {
+                Ok(o) => {
+                    destination.insert(key.clone(), o);
+                }
+                Err(Error::Api(ErrorResponse { code: 404, .. })) => { /* deletions are fine */ }
+                Err(e) => bail!("Unable to fetch item, caused by: {:?}", e),
+            };
+            bar.inc(1);
+        }
+    }
+    bar.finish();
+    Ok(resources)
+}
+
+fn ask_for_user_permission(verb: &str) -> Result<bool> {
+    print!("Continue {}? y/(n): ", verb);
+    let response = read_response()?;
+    audit_prompt(&format!("Continue {}?", verb), None, &response);
+    Ok(match response.as_str() {
+        "y" => true,
+        _ => {
+            println!("Canceled");
+            false
+        }
+    })
+}
+
+// Summarizes the plan and lets the user expand diffs before agreeing to push it
+fn review_plan(plan: &[PlannedChange]) -> Result<bool> {
+    let order = plan_order(plan);
+    print_plan_summary(plan, &order);
+    loop {
+        print!("Continue pushing? y/(n), d to show every diff, or a number to show one: ");
+        let response = read_response()?;
+        match response.as_str() {
+            "y" => {
+                audit_prompt("Continue pushing?", None, "y");
+                return Ok(true);
+            }
+            "d" => {
+                for &i in &order {
+                    print!("{}", plan[i].rendering);
+                }
+            }
+            _ => match response.parse::<usize>() {
+                Ok(n) if n >= 1 && n <= order.len() => print!("{}", plan[order[n - 1]].rendering),
+                _ => {
+                    audit_prompt("Continue pushing?", None, &response);
+                    println!("Canceled");
+                    return Ok(false);
+                }
+            },
+        }
+    }
+}
+
+// Walks through the plan one change at a time and keeps only the changes the user agrees to.
+// Quitting skips everything that hasn't been answered yet.
+fn choose_changes(plan: Vec<PlannedChange>) -> Result<Vec<PlannedChange>> {
+    let order = plan_order(&plan);
+    print_plan_summary(&plan, &order);
+    let mut chosen = HashSet::new();
+    'changes: for (number, &i) in order.iter().enumerate() {
+        print!("{}", plan[i].rendering);
+        loop {
+            print!(
+                "Change {} of {}: (a)pply, (s)kip, or (q)uit? ",
+                number + 1,
+                order.len()
+            );
+            let answer = match read_response()?.as_str() {
+                "a" | "apply" => "apply",
+                "s" | "skip" => "skip",
+                "q" | "quit" => "quit",
+                _ => continue,
+            };
+            let change = &plan[i];
+            audit_prompt(change.action.verb(), Some(&change.key), answer);
+            match answer {
+                "apply" => {
+                    chosen.insert(i);
+                    break;
+                }
+                "skip" => break,
+                _ => break 'changes,
+            }
+        }
+        println!("");
+    }
+    // Keep the original order so that namespaces are still created before what's in them
+    Ok(plan
+        .into_iter()
+        .enumerate()
+        .filter(|(i, _)| chosen.contains(i))
+        .map(|(_, c)| c)
+        .collect())
+}
+
+// Like choose_changes, but takes each answer from the answers file and prints it under the
+// change so the log reads like an interactive session. Aborts without applying anything if a
+// change has no recorded answer.
+fn answer_changes(plan: Vec<PlannedChange>, answers: &Answers) -> Result<Vec<PlannedChange>> {
+    let order = plan_order(&plan);
+    print_plan_summary(&plan, &order);
+    let mut chosen = HashSet::new();
+    for (number, &i) in order.iter().enumerate() {
+        let change = &plan[i];
+        print!("{}", change.rendering);
+        print!(
+            "Change {} of {}: (a)pply or (s)kip? ",
+            number + 1,
+            order.len()
+        );
+        match answers.for_change(&change.key, &change.action) {
+            Some(true) => {
+                println!("apply (from answers file)");
+                audit_prompt(change.action.verb(), Some(&change.key), "apply (answers file)");
+                chosen.insert(i);
+            }
+            Some(false) => {
+                println!("skip (from answers file)");
+                audit_prompt(change.action.verb(), Some(&change.key), "skip (answers file)");
+            }
+            None => {
+                println!("no answer");
+                bail!(
+                    "The answers file doesn't cover {} {}, so nothing was pushed",
+                    change.action.verb(),
+                    change.key
+                );
+            }
+        }
+        println!("");
+    }
+    Ok(plan
+        .into_iter()
+        .enumerate()
+        .filter(|(i, _)| chosen.contains(i))
+        .map(|(_, c)| c)
+        .collect())
+}
+
+fn read_response() -> Result<String> {
+    std::io::stdout().flush()?;
+    let mut response = String::new();
+    if STDIN_CONSUMED.load(Ordering::SeqCst) {
+        let tty = File::open("/dev/tty").context("stdin was used for resources")?;
+        BufReader::new(tty).read_line(&mut response)?;
+    } else {
+        std::io::stdin().read_line(&mut response)?;
+    }
+    Ok(response.trim().to_lowercase())
+}
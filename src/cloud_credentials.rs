@@ -0,0 +1,443 @@
+use anyhow::{anyhow, bail, Context, Result};
+use base64::{
+    engine::general_purpose::{STANDARD, URL_SAFE_NO_PAD},
+    Engine,
+};
+use ring::{
+    hmac,
+    rand::SystemRandom,
+    signature::{RsaKeyPair, RSA_PKCS1_SHA256},
+};
+use serde_json::Value as JsonValue;
+use sha2::{Digest, Sha256};
+use std::{env, fs, path::PathBuf, time::Duration};
+use time::OffsetDateTime;
+use tracing::instrument;
+
+use crate::globs::match_name;
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum Provider {
+    Ecr,
+    ArtifactRegistry,
+}
+
+// Registries whose credentials can be minted from the cloud credentials CI already has, so nothing
+// needs to `docker login` first
+const PROVIDERS: [(&str, Provider); 5] = [
+    ("*.dkr.ecr.*.amazonaws.com", Provider::Ecr),
+    ("*.dkr.ecr.*.amazonaws.com.cn", Provider::Ecr),
+    ("*-docker.pkg.dev", Provider::ArtifactRegistry),
+    ("gcr.io", Provider::ArtifactRegistry),
+    ("*.gcr.io", Provider::ArtifactRegistry),
+];
+
+const GOOGLE_SCOPE: &str = "https://www.googleapis.com/auth/cloud-platform";
+const GOOGLE_METADATA_TOKEN: &str =
+    "http://metadata.google.internal/computeMetadata/v1/instance/service-accounts/default/token";
+
+fn provider_for(registry: &str) -> Option<Provider> {
+    PROVIDERS
+        .iter()
+        .find(|(pattern, _)| match_name(pattern, registry))
+        .map(|(_, provider)| *provider)
+}
+
+// A username and password for `registry` exchanged from the ambient AWS or Google credentials, or
+// None if it isn't a registry we know how to do that for or there are no credentials to exchange
+#[instrument]
+pub(crate) async fn cloud_credential(registry: &str) -> Result<Option<(String, String)>> {
+    let credential = match provider_for(registry) {
+        None => return Ok(None),
+        Some(Provider::Ecr) => ecr_credential(registry).await,
+        Some(Provider::ArtifactRegistry) => google_credential().await,
+    };
+    credential.with_context(|| format!("while getting a token for {}", registry))
+}
+
+struct AwsCredentials {
+    access_key_id: String,
+    secret_access_key: String,
+    session_token: Option<String>,
+}
+
+async fn ecr_credential(registry: &str) -> Result<Option<(String, String)>> {
+    let Some(credentials) = aws_credentials()? else {
+        return Ok(None);
+    };
+    // Hosts look like 123456789012.dkr.ecr.us-east-1.amazonaws.com
+    let Some(region) = registry.split('.').nth(3) else {
+        bail!("Unable to find the region in {}", registry);
+    };
+    let suffix = if registry.ends_with(".cn") { ".cn" } else { "" };
+    let host = format!("api.ecr.{}.amazonaws.com{}", region, suffix);
+    let body = "{}";
+    let mut headers = vec![
+        (
+            "content-type".to_string(),
+            "application/x-amz-json-1.1".to_string(),
+        ),
+        ("host".to_string(), host.clone()),
+        (
+            "x-amz-date".to_string(),
+            amz_date(OffsetDateTime::now_utc()),
+        ),
+        (
+            "x-amz-target".to_string(),
+            "AmazonEC2ContainerRegistry_V20150921.GetAuthorizationToken".to_string(),
+        ),
+    ];
+    if let Some(token) = &credentials.session_token {
+        headers.push(("x-amz-security-token".to_string(), token.clone()));
+    }
+    let authorization = sign_v4(
+        &credentials,
+        "POST",
+        "",
+        &headers,
+        body.as_bytes(),
+        region,
+        "ecr",
+    );
+
+    let mut request = reqwest::Client::new()
+        .post(format!("https://{}/", host))
+        .header("authorization", authorization)
+        .body(body);
+    for (name, value) in &headers {
+        if name != "host" {
+            request = request.header(name, value);
+        }
+    }
+    let response = read_json(request).await?;
+    let token = response["authorizationData"][0]["authorizationToken"]
+        .as_str()
+        .ok_or_else(|| anyhow!("ECR didn't return an authorization token"))?;
+    let decoded = String::from_utf8(STANDARD.decode(token)?)?;
+    let Some((username, password)) = decoded.split_once(':') else {
+        bail!("ECR returned a malformed authorization token");
+    };
+    Ok(Some((username.to_string(), password.to_string())))
+}
+
+// Reads credentials from the environment and then from the shared credentials file, like the AWS
+// CLI does for static keys
+fn aws_credentials() -> Result<Option<AwsCredentials>> {
+    if let (Ok(access_key_id), Ok(secret_access_key)) = (
+        env::var("AWS_ACCESS_KEY_ID"),
+        env::var("AWS_SECRET_ACCESS_KEY"),
+    ) {
+        return Ok(Some(AwsCredentials {
+            access_key_id,
+            secret_access_key,
+            session_token: env::var("AWS_SESSION_TOKEN").ok(),
+        }));
+    }
+
+    let path = match env::var_os("AWS_SHARED_CREDENTIALS_FILE") {
+        Some(path) => PathBuf::from(path),
+        None => match env::var_os("HOME") {
+            Some(home) => PathBuf::from(home).join(".aws/credentials"),
+            None => return Ok(None),
+        },
+    };
+    if !path.is_file() {
+        return Ok(None);
+    }
+    let content = fs::read_to_string(&path).with_context(|| format!("while reading {:?}", path))?;
+    let profile = env::var("AWS_PROFILE").unwrap_or_else(|_| "default".to_string());
+    let values = ini_section(&content, &profile);
+    let (Some(access_key_id), Some(secret_access_key)) = (
+        values.iter().find(|(k, _)| k == "aws_access_key_id"),
+        values.iter().find(|(k, _)| k == "aws_secret_access_key"),
+    ) else {
+        return Ok(None);
+    };
+    Ok(Some(AwsCredentials {
+        access_key_id: access_key_id.1.clone(),
+        secret_access_key: secret_access_key.1.clone(),
+        session_token: values
+            .iter()
+            .find(|(k, _)| k == "aws_session_token")
+            .map(|(_, v)| v.clone()),
+    }))
+}
+
+fn ini_section(content: &str, section: &str) -> Vec<(String, String)> {
+    let mut in_section = false;
+    let mut values = Vec::new();
+    for line in content.lines().map(str::trim) {
+        if let Some(name) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+            in_section = name.trim() == section;
+        } else if let (true, Some((key, value))) = (in_section, line.split_once('=')) {
+            values.push((key.trim().to_string(), value.trim().to_string()));
+        }
+    }
+    values
+}
+
+fn amz_date(now: OffsetDateTime) -> String {
+    format!(
+        "{:04}{:02}{:02}T{:02}{:02}{:02}Z",
+        now.year(),
+        u8::from(now.month()),
+        now.day(),
+        now.hour(),
+        now.minute(),
+        now.second()
+    )
+}
+
+// Builds the Authorization header for an AWS Signature Version 4 request to the root path. The
+// query must already be canonical and `headers` must include host and x-amz-date.
+fn sign_v4(
+    credentials: &AwsCredentials,
+    method: &str,
+    query: &str,
+    headers: &[(String, String)],
+    body: &[u8],
+    region: &str,
+    service: &str,
+) -> String {
+    let mut headers = headers.to_vec();
+    headers.sort();
+    let amz_date = headers
+        .iter()
+        .find(|(name, _)| name == "x-amz-date")
+        .map(|(_, value)| value.as_str())
+        .unwrap_or_default();
+    let date = &amz_date[..8.min(amz_date.len())];
+    let canonical_headers: String = headers
+        .iter()
+        .map(|(name, value)| format!("{}:{}\n", name, value))
+        .collect();
+    let signed_headers = headers
+        .iter()
+        .map(|(name, _)| name.as_str())
+        .collect::<Vec<_>>()
+        .join(";");
+    let canonical_request = format!(
+        "{}\n/\n{}\n{}\n{}\n{:x}",
+        method,
+        query,
+        canonical_headers,
+        signed_headers,
+        Sha256::digest(body)
+    );
+    let scope = format!("{}/{}/{}/aws4_request", date, region, service);
+    let string_to_sign = format!(
+        "AWS4-HMAC-SHA256\n{}\n{}\n{:x}",
+        amz_date,
+        scope,
+        Sha256::digest(canonical_request.as_bytes())
+    );
+
+    let mut key = format!("AWS4{}", credentials.secret_access_key).into_bytes();
+    for part in [date, region, service, "aws4_request"] {
+        key = hmac_sha256(&key, part.as_bytes());
+    }
+    let signature = hmac_sha256(&key, string_to_sign.as_bytes());
+    format!(
+        "AWS4-HMAC-SHA256 Credential={}/{}, SignedHeaders={}, Signature={}",
+        credentials.access_key_id,
+        scope,
+        signed_headers,
+        signature
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect::<String>()
+    )
+}
+
+fn hmac_sha256(key: &[u8], data: &[u8]) -> Vec<u8> {
+    hmac::sign(&hmac::Key::new(hmac::HMAC_SHA256, key), data)
+        .as_ref()
+        .to_vec()
+}
+
+// Uses Application Default Credentials: the file GOOGLE_APPLICATION_CREDENTIALS names, then the
+// one `gcloud auth application-default login` writes, then the metadata server on GCE and GKE
+async fn google_credential() -> Result<Option<(String, String)>> {
+    let path = match env::var_os("GOOGLE_APPLICATION_CREDENTIALS") {
+        Some(path) => Some(PathBuf::from(path)),
+        None => env::var_os("HOME")
+            .map(|home| {
+                PathBuf::from(home).join(".config/gcloud/application_default_credentials.json")
+            })
+            .filter(|path| path.is_file()),
+    };
+    let token = match path {
+        Some(path) => {
+            let content =
+                fs::read_to_string(&path).with_context(|| format!("while reading {:?}", path))?;
+            let file: JsonValue = serde_json::from_str(&content)
+                .with_context(|| format!("while parsing {:?}", path))?;
+            google_token_from_file(&file).await?
+        }
+        None => match google_metadata_token().await {
+            Ok(token) => token,
+            // Not running on Google Cloud
+            Err(_) => return Ok(None),
+        },
+    };
+    Ok(Some(("oauth2accesstoken".to_string(), token)))
+}
+
+async fn google_token_from_file(file: &JsonValue) -> Result<String> {
+    let field = |name: &str| {
+        file[name]
+            .as_str()
+            .map(|v| v.to_string())
+            .ok_or_else(|| anyhow!("Expected {} in the Google credentials", name))
+    };
+    let form = match file["type"].as_str() {
+        Some("authorized_user") => vec![
+            ("grant_type", "refresh_token".to_string()),
+            ("client_id", field("client_id")?),
+            ("client_secret", field("client_secret")?),
+            ("refresh_token", field("refresh_token")?),
+        ],
+        Some("service_account") => vec![
+            (
+                "grant_type",
+                "urn:ietf:params:oauth:grant-type:jwt-bearer".to_string(),
+            ),
+            (
+                "assertion",
+                service_account_assertion(
+                    &field("client_email")?,
+                    &field("private_key")?,
+                    OffsetDateTime::now_utc(),
+                )?,
+            ),
+        ],
+        other => bail!("Unsupported Google credentials type {:?}", other),
+    };
+    let request = reqwest::Client::new()
+        .post("https://oauth2.googleapis.com/token")
+        .form(&form);
+    access_token(&read_json(request).await?)
+}
+
+// A JWT signed by the service account's key, which Google exchanges for an access token
+fn service_account_assertion(
+    email: &str,
+    private_key: &str,
+    now: OffsetDateTime,
+) -> Result<String> {
+    let header = URL_SAFE_NO_PAD.encode(r#"{"alg":"RS256","typ":"JWT"}"#);
+    let issued = now.unix_timestamp();
+    let claims = URL_SAFE_NO_PAD.encode(
+        serde_json::json!({
+            "iss": email,
+            "scope": GOOGLE_SCOPE,
+            "aud": "https://oauth2.googleapis.com/token",
+            "iat": issued,
+            "exp": issued + 3600,
+        })
+        .to_string(),
+    );
+    let message = format!("{}.{}", header, claims);
+
+    let der: String = private_key
+        .lines()
+        .filter(|line| !line.starts_with("-----"))
+        .collect();
+    let key = RsaKeyPair::from_pkcs8(&STANDARD.decode(der)?)
+        .map_err(|e| anyhow!("Invalid service account key: {}", e))?;
+    let mut signature = vec![0; key.public().modulus_len()];
+    key.sign(
+        &RSA_PKCS1_SHA256,
+        &SystemRandom::new(),
+        message.as_bytes(),
+        &mut signature,
+    )
+    .map_err(|_| anyhow!("Unable to sign with the service account key"))?;
+    Ok(format!("{}.{}", message, URL_SAFE_NO_PAD.encode(signature)))
+}
+
+async fn google_metadata_token() -> Result<String> {
+    let request = reqwest::Client::builder()
+        .connect_timeout(Duration::from_secs(2))
+        .build()?
+        .get(GOOGLE_METADATA_TOKEN)
+        .header("Metadata-Flavor", "Google");
+    access_token(&read_json(request).await?)
+}
+
+fn access_token(response: &JsonValue) -> Result<String> {
+    response["access_token"]
+        .as_str()
+        .map(|t| t.to_string())
+        .ok_or_else(|| anyhow!("Google didn't return an access token"))
+}
+
+async fn read_json(request: reqwest::RequestBuilder) -> Result<JsonValue> {
+    let response = request.send().await?;
+    let status = response.status();
+    let body = response.text().await?;
+    if !status.is_success() {
+        bail!("Request failed with {}: {}", status, body);
+    }
+    Ok(serde_json::from_str(&body)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_provider_for() {
+        assert_eq!(
+            provider_for("123456789012.dkr.ecr.us-east-1.amazonaws.com"),
+            Some(Provider::Ecr)
+        );
+        assert_eq!(
+            provider_for("us-docker.pkg.dev"),
+            Some(Provider::ArtifactRegistry)
+        );
+        assert_eq!(provider_for("eu.gcr.io"), Some(Provider::ArtifactRegistry));
+        assert_eq!(provider_for("ghcr.io"), None);
+    }
+
+    #[test]
+    fn test_sign_v4() {
+        // The example from AWS's Signature Version 4 documentation
+        let credentials = AwsCredentials {
+            access_key_id: "AKIDEXAMPLE".to_string(),
+            secret_access_key: "wJalrXUtnFEMI/K7MDENG+bPxRfiCYEXAMPLEKEY".to_string(),
+            session_token: None,
+        };
+        let headers = vec![
+            (
+                "content-type".to_string(),
+                "application/x-www-form-urlencoded; charset=utf-8".to_string(),
+            ),
+            ("host".to_string(), "iam.amazonaws.com".to_string()),
+            ("x-amz-date".to_string(), "20150830T123600Z".to_string()),
+        ];
+        assert_eq!(
+            sign_v4(
+                &credentials,
+                "GET",
+                "Action=ListUsers&Version=2010-05-08",
+                &headers,
+                b"",
+                "us-east-1",
+                "iam",
+            ),
+            "AWS4-HMAC-SHA256 Credential=AKIDEXAMPLE/20150830/us-east-1/iam/aws4_request, \
+             SignedHeaders=content-type;host;x-amz-date, \
+             Signature=5d672d79c15b13162d9279b0855cfba6789a8edb4c82c400e06b5924a6f2b5d7"
+        );
+    }
+
+    #[test]
+    fn test_ini_section() {
+        let content = "[default]\naws_access_key_id = A\n\n[ci]\naws_access_key_id=B\n";
+        assert_eq!(
+            ini_section(content, "ci"),
+            vec![("aws_access_key_id".to_string(), "B".to_string())]
+        );
+    }
+}
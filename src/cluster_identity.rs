@@ -0,0 +1,219 @@
+use anyhow::{bail, Context, Result};
+use k8s_openapi::api::core::v1::{ConfigMap, Namespace};
+use kube::{
+    api::{ObjectMeta, Patch, PatchParams},
+    config::KubeConfigOptions,
+    core::ErrorResponse,
+};
+use std::collections::{BTreeMap, HashMap};
+use tracing::{info, instrument, warn};
+
+use crate::{kubernetes_io::MANAGER, targets::TargetPolicy};
+
+// Written into each cluster by `mark-cluster` so that a push can tell which cluster a kubeconfig
+// context really points at
+const MARKER_NAMESPACE: &str = "kube-system";
+const MARKER_NAME: &str = "sisyphus-cluster";
+const MARKER_KEY: &str = "cluster";
+
+// Refuses to continue if any client is connected to a cluster other than the one its context is
+// named for. Reused or mistyped context names otherwise send a push to the wrong environment.
+#[instrument(skip_all)]
+pub(crate) async fn verify_cluster_identities(
+    clients: &HashMap<String, kube::Client>,
+    policy: &TargetPolicy,
+) -> Result<()> {
+    let mut unmarked = Vec::new();
+    let mut unreadable = Vec::new();
+    for (cluster, client) in clients {
+        // Namespace-scoped users, like delegated teams, can't read kube-system. Their pushes go on
+        // unverified rather than failing outright.
+        let marker = unless_denied(
+            kube::Api::<ConfigMap>::namespaced(client.clone(), MARKER_NAMESPACE)
+                .get_opt(MARKER_NAME)
+                .await,
+        )
+        .with_context(|| format!("while reading the cluster marker in {}", cluster))?;
+        let Some(marker) = marker else {
+            unreadable.push(cluster.as_str());
+            continue;
+        };
+        let uid = match policy.expected_uid(cluster) {
+            Some(_) => match unless_denied(
+                kube::Api::<Namespace>::all(client.clone())
+                    .get(MARKER_NAMESPACE)
+                    .await,
+            )
+            .with_context(|| format!("while reading the cluster UID of {}", cluster))?
+            {
+                Some(namespace) => namespace.metadata.uid,
+                None => {
+                    unreadable.push(cluster.as_str());
+                    continue;
+                }
+            },
+            None => None,
+        };
+        let marked = marker_cluster(marker.as_ref());
+        check_identity(cluster, marked, policy.expected_uid(cluster), uid.as_deref())?;
+        if marked.is_none() && policy.expected_uid(cluster).is_none() {
+            unmarked.push(cluster.as_str());
+        }
+    }
+    if !unmarked.is_empty() {
+        unmarked.sort();
+        warn!(
+            clusters = %unmarked.join(", "),
+            "Unable to verify the identity of {} (run mark-cluster to record it)",
+            unmarked.join(", ")
+        );
+    }
+    if !unreadable.is_empty() {
+        unreadable.sort();
+        warn!(
+            clusters = %unreadable.join(", "),
+            "Unable to verify the identity of {} (not allowed to read {})",
+            unreadable.join(", "),
+            MARKER_NAMESPACE
+        );
+    }
+    Ok(())
+}
+
+// None when this user isn't allowed to see what was asked for
+fn unless_denied<T>(result: kube::Result<T>) -> kube::Result<Option<T>> {
+    match result {
+        Ok(value) => Ok(Some(value)),
+        Err(kube::Error::Api(ErrorResponse {
+            code: 403 | 404, ..
+        })) => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+// Records which cluster a context points at. Refuses to rename a cluster that's already marked
+// unless forced, since that usually means the context is the wrong one.
+pub(crate) async fn mark_cluster(context: &str, force: bool) -> Result<()> {
+    let config = kube::Config::from_kubeconfig(&KubeConfigOptions {
+        context: Some(context.to_string()),
+        cluster: None,
+        user: None,
+    })
+    .await?;
+    let client = kube::Client::try_from(config)?;
+    let api = kube::Api::<ConfigMap>::namespaced(client, MARKER_NAMESPACE);
+    let existing = api
+        .get_opt(MARKER_NAME)
+        .await
+        .with_context(|| format!("while reading the cluster marker in {}", context))?;
+    if let Some(marked) = marker_cluster(existing.as_ref()) {
+        if marked == context {
+            info!("{} is already marked", context);
+            return Ok(());
+        }
+        if !force {
+            bail!("Context {} points at the cluster marked {}", context, marked);
+        }
+    }
+    let marker = ConfigMap {
+        metadata: ObjectMeta {
+            name: Some(MARKER_NAME.to_string()),
+            namespace: Some(MARKER_NAMESPACE.to_string()),
+            ..Default::default()
+        },
+        data: Some(BTreeMap::from([(
+            MARKER_KEY.to_string(),
+            context.to_string(),
+        )])),
+        ..Default::default()
+    };
+    api.patch(
+        MARKER_NAME,
+        &PatchParams::apply(MANAGER).force(),
+        &Patch::Apply(&marker),
+    )
+    .await
+    .with_context(|| format!("while marking {}", context))?;
+    info!("Marked {}", context);
+    Ok(())
+}
+
+fn marker_cluster(marker: Option<&ConfigMap>) -> Option<&str> {
+    marker
+        .and_then(|m| m.data.as_ref())
+        .and_then(|d| d.get(MARKER_KEY))
+        .map(|v| v.as_str())
+}
+
+fn check_identity(
+    cluster: &str,
+    marked: Option<&str>,
+    expected_uid: Option<&str>,
+    uid: Option<&str>,
+) -> Result<()> {
+    if let Some(marked) = marked {
+        if marked != cluster {
+            bail!(
+                "Refusing to push: context {} points at the cluster marked {}",
+                cluster,
+                marked
+            );
+        }
+    }
+    if let Some(expected) = expected_uid {
+        if uid != Some(expected) {
+            bail!(
+                "Refusing to push: context {} points at a cluster with UID {} instead of {}",
+                cluster,
+                uid.unwrap_or("(none)"),
+                expected
+            );
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check_identity() {
+        assert!(check_identity("us-east", None, None, None).is_ok());
+        assert!(check_identity("us-east", Some("us-east"), None, None).is_ok());
+        assert!(check_identity("us-east", Some("eu-west"), None, None).is_err());
+        assert!(check_identity("us-east", None, Some("1234"), Some("1234")).is_ok());
+        assert!(check_identity("us-east", None, Some("1234"), Some("5678")).is_err());
+        assert!(check_identity("us-east", Some("us-east"), Some("1234"), None).is_err());
+    }
+
+    #[test]
+    fn test_marker_cluster() {
+        let marker = ConfigMap {
+            data: Some(BTreeMap::from([(
+                MARKER_KEY.to_string(),
+                "us-east".to_string(),
+            )])),
+            ..Default::default()
+        };
+        assert_eq!(marker_cluster(Some(&marker)), Some("us-east"));
+        assert_eq!(marker_cluster(Some(&ConfigMap::default())), None);
+        assert_eq!(marker_cluster(None), None);
+    }
+
+    #[test]
+    fn test_unless_denied() {
+        let denied = |code| {
+            kube::Result::<()>::Err(kube::Error::Api(ErrorResponse {
+                status: "Failure".to_string(),
+                message: "".to_string(),
+                reason: "".to_string(),
+                code,
+            }))
+        };
+        assert!(matches!(unless_denied(Ok(())), Ok(Some(()))));
+        assert!(matches!(unless_denied(denied(403)), Ok(None)));
+        assert!(matches!(unless_denied(denied(404)), Ok(None)));
+        assert!(unless_denied(denied(500)).is_err());
+    }
+}
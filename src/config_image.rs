@@ -1,3 +1,4 @@
+use crate::starlark::Context;
 use allocative::Allocative;
 use anyhow::{anyhow, bail, Result};
 use serde::Deserialize;
@@ -8,7 +9,8 @@ use starlark::{
     starlark_module,
     values::{
         dict::UnpackDictEntries, float::StarlarkFloat, list_or_tuple::UnpackListOrTuple,
-        starlark_value, NoSerialize, StarlarkValue, UnpackValue, Value, ValueLike,
+        starlark_value, structs::StructRef, NoSerialize, StarlarkValue, UnpackValue, Value,
+        ValueLike,
     },
 };
 use std::{
@@ -25,9 +27,39 @@ mod tests;
 pub(crate) struct ConfigImageIndex {
     pub binary_digest: String,
     pub binary_repository: String,
+    // Binaries for the other containers of a multi-container config, keyed by container name
+    #[serde(default)]
+    pub binaries: BTreeMap<String, BinaryImage>,
     pub config_entrypoint: String,
 }
 
+#[derive(Deserialize, Debug)]
+pub(crate) struct BinaryImage {
+    pub digest: String,
+    pub repository: String,
+}
+
+impl ConfigImageIndex {
+    // Containers listed in `binaries` run their own image and everything else runs the main binary.
+    pub(crate) fn image_for(&self, container: &str) -> String {
+        match self.binaries.get(container) {
+            Some(binary) => format!("{}@{}", binary.repository, binary.digest),
+            None => format!("{}@{}", self.binary_repository, self.binary_digest),
+        }
+    }
+
+    // Every binary image the index pins, the main one first
+    pub(crate) fn pinned_images(&self) -> Vec<String> {
+        std::iter::once(format!("{}@{}", self.binary_repository, self.binary_digest))
+            .chain(
+                self.binaries
+                    .values()
+                    .map(|b| format!("{}@{}", b.repository, b.digest)),
+            )
+            .collect()
+    }
+}
+
 #[derive(Allocative, Clone, Debug, NoSerialize, ProvidesStaticType)]
 pub(crate) struct Application {
     pub args: Vec<ArgumentValues>,
@@ -36,6 +68,7 @@ pub(crate) struct Application {
     pub liveness: Option<Probe>,
     pub readiness: Option<Probe>,
     pub resources: Resources,
+    pub security_context: Option<SecurityContext>,
     pub startup: Option<Probe>,
 }
 
@@ -48,10 +81,92 @@ impl fmt::Display for Application {
 #[starlark_value(type = "Application", UnpackValue, StarlarkTypeRepr)]
 impl<'v> StarlarkValue<'v> for Application {}
 
+// What a config's main returns: one Application, or a struct (or dict) of them keyed by container
+// name when the pod runs several binaries (say an app and its metrics exporter).
+#[derive(Clone, Debug)]
+pub(crate) enum Applications {
+    Single(Application),
+    Named(BTreeMap<String, Application>),
+}
+
+impl Applications {
+    pub(crate) fn unpack_value(value: Value) -> Result<Self> {
+        if let Some(v) = value.downcast_ref::<Application>() {
+            return Ok(Self::Single(v.clone()));
+        }
+        let entries = if let Some(v) = StructRef::from_value(value) {
+            v.iter()
+                .map(|(k, v)| (k.as_str().to_string(), v))
+                .collect::<Vec<_>>()
+        } else if let Some(v) = UnpackDictEntries::<String, Value>::unpack_value(value)
+            .map_err(|e| anyhow!("Unable to read config result: {:?}", e))?
+        {
+            v.entries
+        } else {
+            bail!("Config didn't return an Application or a struct of them");
+        };
+        if entries.is_empty() {
+            bail!("Config didn't return any Applications");
+        }
+        let mut named = BTreeMap::new();
+        for (name, v) in entries {
+            let application = v
+                .downcast_ref::<Application>()
+                .ok_or_else(|| anyhow!("Container {} isn't an Application", name))?;
+            named.insert(name, application.clone());
+        }
+        Ok(Self::Named(named))
+    }
+
+    // Keyed by container name. A lone Application runs in a container named after the resource.
+    pub(crate) fn by_container(&self, resource_name: &str) -> BTreeMap<String, &Application> {
+        match self {
+            Self::Single(v) => BTreeMap::from([(resource_name.to_string(), v)]),
+            Self::Named(v) => v.iter().map(|(k, v)| (k.clone(), v)).collect(),
+        }
+    }
+
+    // Picks the application for one container, for running it locally.
+    pub(crate) fn select(&self, container: Option<&str>) -> Result<&Application> {
+        match (self, container) {
+            (Self::Single(v), None) => Ok(v),
+            (Self::Single(_), Some(_)) => {
+                bail!("--container only applies to configs with multiple containers")
+            }
+            (Self::Named(v), Some(name)) => v
+                .get(name)
+                .ok_or_else(|| anyhow!("Config doesn't define a container named {}", name)),
+            (Self::Named(v), None) => bail!(
+                "Config defines containers {}, pick one with --container",
+                v.keys().cloned().collect::<Vec<_>>().join(", ")
+            ),
+        }
+    }
+
+    // Labels from every container, which all land on the same pod.
+    pub(crate) fn labels(&self) -> BTreeMap<String, String> {
+        match self {
+            Self::Single(v) => v.labels.clone(),
+            Self::Named(v) => v.values().flat_map(|a| a.labels.clone()).collect(),
+        }
+    }
+
+    // Containers share the pod's network, so ports are numbered across all of them.
+    pub(crate) fn assign_ports(&self, environment: &str) -> Result<BTreeMap<String, u16>> {
+        match self {
+            Self::Single(v) => assign_ports(&[v], environment),
+            Self::Named(v) => assign_ports(&v.values().collect::<Vec<_>>(), environment),
+        }
+    }
+}
+
 #[derive(Allocative, Clone, Debug)]
 pub(crate) enum Argument {
+    ConfigMapVariable(ConfigMapVariable),
+    FieldVariable(FieldVariable),
     FileVariable(FileVariable),
     Port(Port),
+    ResourceFieldVariable(ResourceFieldVariable),
     String(String),
     StringVariable(StringVariable),
 }
@@ -60,6 +175,12 @@ impl Argument {
     fn unpack_value(value: Value) -> starlark::Result<Self> {
         if let Some(v) = value.downcast_ref::<Port>() {
             Ok(Self::Port(v.clone()))
+        } else if let Some(v) = value.downcast_ref::<ConfigMapVariable>() {
+            Ok(Self::ConfigMapVariable(v.clone()))
+        } else if let Some(v) = value.downcast_ref::<FieldVariable>() {
+            Ok(Self::FieldVariable(v.clone()))
+        } else if let Some(v) = value.downcast_ref::<ResourceFieldVariable>() {
+            Ok(Self::ResourceFieldVariable(v.clone()))
         } else if let Some(v) = value.downcast_ref::<FileVariable>() {
             Ok(Self::FileVariable(v.clone()))
         } else if let Some(v) = value.downcast_ref::<StringVariable>() {
@@ -107,6 +228,42 @@ impl ArgumentValues {
     }
 }
 
+// A key of a ConfigMap, passed as an environment variable or, when `path` is set, mounted as a file.
+#[derive(Allocative, Clone, Debug, NoSerialize, ProvidesStaticType)]
+pub(crate) struct ConfigMapVariable {
+    pub name: String,
+    pub key: String,
+    pub path: Option<String>,
+}
+
+impl fmt::Display for ConfigMapVariable {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> Result<(), std::fmt::Error> {
+        write!(f, "ConfigMapVariable(name={}, key={}", self.name, self.key)?;
+        if let Some(path) = &self.path {
+            write!(f, ", path={}", path)?;
+        }
+        write!(f, ")")
+    }
+}
+
+#[starlark_value(type = "ConfigMapVariable", UnpackValue, StarlarkTypeRepr)]
+impl<'v> StarlarkValue<'v> for ConfigMapVariable {}
+
+// A field of the pod, like `metadata.name` or `status.podIP`, from the downward API.
+#[derive(Allocative, Clone, Debug, NoSerialize, ProvidesStaticType)]
+pub(crate) struct FieldVariable {
+    pub path: String,
+}
+
+impl fmt::Display for FieldVariable {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> Result<(), std::fmt::Error> {
+        write!(f, "FieldVariable(path={})", self.path)
+    }
+}
+
+#[starlark_value(type = "FieldVariable", UnpackValue, StarlarkTypeRepr)]
+impl<'v> StarlarkValue<'v> for FieldVariable {}
+
 #[derive(Allocative, Clone, Debug, NoSerialize, ProvidesStaticType)]
 pub(crate) struct FileVariable {
     pub name: String,
@@ -203,6 +360,26 @@ impl fmt::Display for Probe {
 #[starlark_value(type = "Probe", UnpackValue, StarlarkTypeRepr)]
 impl<'v> StarlarkValue<'v> for Probe {}
 
+// A request or limit of the app's own container, like `limits.cpu`, from the downward API.
+#[derive(Allocative, Clone, Debug, NoSerialize, ProvidesStaticType)]
+pub(crate) struct ResourceFieldVariable {
+    pub resource: String,
+    pub divisor: Option<String>,
+}
+
+impl fmt::Display for ResourceFieldVariable {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> Result<(), std::fmt::Error> {
+        write!(f, "ResourceFieldVariable(resource={}", self.resource)?;
+        if let Some(divisor) = &self.divisor {
+            write!(f, ", divisor={}", divisor)?;
+        }
+        write!(f, ")")
+    }
+}
+
+#[starlark_value(type = "ResourceFieldVariable", UnpackValue, StarlarkTypeRepr)]
+impl<'v> StarlarkValue<'v> for ResourceFieldVariable {}
+
 #[derive(Allocative, Clone, Debug, Default, NoSerialize, ProvidesStaticType)]
 pub(crate) struct Resources {
     pub requests: BTreeMap<String, ArgumentValues>,
@@ -222,6 +399,29 @@ impl fmt::Display for Resources {
 #[starlark_value(type = "Resources", UnpackValue, StarlarkTypeRepr)]
 impl<'v> StarlarkValue<'v> for Resources {}
 
+// Hardening for the app's container. `fs_group` applies to the whole pod.
+#[derive(Allocative, Clone, Debug, Default, NoSerialize, ProvidesStaticType)]
+pub(crate) struct SecurityContext {
+    pub add_capabilities: Vec<String>,
+    pub allow_privilege_escalation: Option<bool>,
+    pub drop_capabilities: Vec<String>,
+    pub fs_group: Option<i64>,
+    pub read_only_root_filesystem: Option<bool>,
+    pub run_as_group: Option<i64>,
+    pub run_as_non_root: Option<bool>,
+    pub run_as_user: Option<i64>,
+    pub seccomp_profile: Option<String>,
+}
+
+impl fmt::Display for SecurityContext {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> Result<(), std::fmt::Error> {
+        write!(f, "{:?}", self)
+    }
+}
+
+#[starlark_value(type = "SecurityContext", UnpackValue, StarlarkTypeRepr)]
+impl<'v> StarlarkValue<'v> for SecurityContext {}
+
 #[derive(Allocative, Clone, Debug, NoSerialize, ProvidesStaticType)]
 pub(crate) struct StringVariable {
     pub name: String,
@@ -245,6 +445,7 @@ fn starlark_types(builder: &mut GlobalsBuilder) {
         #[starlark(require = named)] liveness: Option<Value>,
         #[starlark(require = named)] readiness: Option<Value>,
         #[starlark(require = named)] resources: Option<Value>,
+        #[starlark(require = named)] security_context: Option<Value>,
         #[starlark(require = named)] startup: Option<Value>,
         eval: &mut Evaluator<'v, '_, '_>,
     ) -> starlark::Result<Value<'v>> {
@@ -269,6 +470,16 @@ fn starlark_types(builder: &mut GlobalsBuilder) {
                 .clone(),
             None => Resources::default(),
         };
+        let security_context_value = match security_context {
+            Some(v) if !v.is_none() => Some(
+                v.downcast_ref::<SecurityContext>()
+                    .ok_or_else(|| {
+                        function_error("security_context must be a SecurityContext object")
+                    })?
+                    .clone(),
+            ),
+            _ => None,
+        };
         let startup_value = unpack_probe("startup", startup)?;
         Ok(eval.heap().alloc_simple(Application {
             args: args_value,
@@ -277,10 +488,50 @@ fn starlark_types(builder: &mut GlobalsBuilder) {
             liveness: liveness_value,
             readiness: readiness_value,
             resources: resources_value,
+            security_context: security_context_value,
             startup: startup_value,
         }))
     }
 
+    fn ConfigMapVariable<'v>(
+        #[starlark(require = named)] name: Value,
+        #[starlark(require = named)] key: Value,
+        #[starlark(require = named)] path: Option<Value>,
+        eval: &mut Evaluator<'v, '_, '_>,
+    ) -> starlark::Result<Value<'v>> {
+        let name_str = name
+            .unpack_str()
+            .ok_or_else(|| function_error("name must be a str"))?;
+        let key_str = key
+            .unpack_str()
+            .ok_or_else(|| function_error("key must be a str"))?;
+        let path_value = match path {
+            Some(p) => Some(
+                p.unpack_str()
+                    .ok_or_else(|| function_error("path must be a str"))?
+                    .to_string(),
+            ),
+            None => None,
+        };
+        Ok(eval.heap().alloc_simple(ConfigMapVariable {
+            name: name_str.to_string(),
+            key: key_str.to_string(),
+            path: path_value,
+        }))
+    }
+
+    fn FieldVariable<'v>(
+        path: Value,
+        eval: &mut Evaluator<'v, '_, '_>,
+    ) -> starlark::Result<Value<'v>> {
+        let path_str = path
+            .unpack_str()
+            .ok_or_else(|| function_error("path must be a str"))?;
+        Ok(eval.heap().alloc_simple(FieldVariable {
+            path: path_str.to_string(),
+        }))
+    }
+
     fn FileVariable<'v>(
         #[starlark(require = named)] name: Value,
         #[starlark(require = named)] path: Value,
@@ -368,6 +619,28 @@ fn starlark_types(builder: &mut GlobalsBuilder) {
         }))
     }
 
+    fn ResourceFieldVariable<'v>(
+        resource: Value,
+        #[starlark(require = named)] divisor: Option<Value>,
+        eval: &mut Evaluator<'v, '_, '_>,
+    ) -> starlark::Result<Value<'v>> {
+        let resource_str = resource
+            .unpack_str()
+            .ok_or_else(|| function_error("resource must be a str"))?;
+        let divisor_value = match divisor {
+            Some(d) => Some(
+                d.unpack_str()
+                    .ok_or_else(|| function_error("divisor must be a str"))?
+                    .to_string(),
+            ),
+            None => None,
+        };
+        Ok(eval.heap().alloc_simple(ResourceFieldVariable {
+            resource: resource_str.to_string(),
+            divisor: divisor_value,
+        }))
+    }
+
     fn Resources<'v>(
         #[starlark(require = named)] requests: Option<Value>,
         #[starlark(require = named)] limits: Option<Value>,
@@ -387,6 +660,45 @@ fn starlark_types(builder: &mut GlobalsBuilder) {
         }))
     }
 
+    fn SecurityContext<'v>(
+        #[starlark(require = named)] add_capabilities: Option<Value>,
+        #[starlark(require = named)] allow_privilege_escalation: Option<Value>,
+        #[starlark(require = named)] drop_capabilities: Option<Value>,
+        #[starlark(require = named)] fs_group: Option<Value>,
+        #[starlark(require = named)] read_only_root_filesystem: Option<Value>,
+        #[starlark(require = named)] run_as_group: Option<Value>,
+        #[starlark(require = named)] run_as_non_root: Option<Value>,
+        #[starlark(require = named)] run_as_user: Option<Value>,
+        #[starlark(require = named)] seccomp_profile: Option<Value>,
+        eval: &mut Evaluator<'v, '_, '_>,
+    ) -> starlark::Result<Value<'v>> {
+        let seccomp_profile_value = match seccomp_profile {
+            Some(p) => Some(
+                p.unpack_str()
+                    .ok_or_else(|| function_error("seccomp_profile must be a str"))?
+                    .to_string(),
+            ),
+            None => None,
+        };
+        Ok(eval.heap().alloc_simple(SecurityContext {
+            add_capabilities: unpack_string_list("add_capabilities", add_capabilities)?,
+            allow_privilege_escalation: unpack_optional_bool(
+                "allow_privilege_escalation",
+                allow_privilege_escalation,
+            )?,
+            drop_capabilities: unpack_string_list("drop_capabilities", drop_capabilities)?,
+            fs_group: unpack_optional_i32("fs_group", fs_group)?.map(i64::from),
+            read_only_root_filesystem: unpack_optional_bool(
+                "read_only_root_filesystem",
+                read_only_root_filesystem,
+            )?,
+            run_as_group: unpack_optional_i32("run_as_group", run_as_group)?.map(i64::from),
+            run_as_non_root: unpack_optional_bool("run_as_non_root", run_as_non_root)?,
+            run_as_user: unpack_optional_i32("run_as_user", run_as_user)?.map(i64::from),
+            seccomp_profile: seccomp_profile_value,
+        }))
+    }
+
     fn StringVariable<'v>(
         name: Value,
         eval: &mut Evaluator<'v, '_, '_>,
@@ -402,39 +714,39 @@ fn starlark_types(builder: &mut GlobalsBuilder) {
 
 pub(crate) async fn get_config(
     root: &Path,
-    name: &str,
-    namespace: Option<&str>,
-) -> Result<(ConfigImageIndex, Application)> {
+    context: Context,
+) -> Result<(ConfigImageIndex, Applications)> {
     let index_path = root.join("index.json");
     let index: ConfigImageIndex =
         serde_json::from_str(&tokio::fs::read_to_string(index_path).await?)?;
     let config_path = root.join(&index.config_entrypoint);
-    let application =
-        crate::starlark::load_starlark_config(root, &config_path, name, namespace).await?;
-    Ok((index, application))
+    let applications = crate::starlark::load_starlark_config(root, &config_path, context).await?;
+    Ok((index, applications))
 }
 
 // The first port number handed out by auto-assignment.
 const BASE_PORT: u16 = 8080;
 
-// Resolves a concrete number for every port the application exposes in `environment`. Ports with an
+// Resolves a concrete number for every port the applications expose in `environment`. Ports with an
 // explicit number keep it; the rest are auto-assigned deterministically (sorted by name, counting up
 // from BASE_PORT and skipping numbers already taken). Returns an error if a name is declared with
 // conflicting numbers or if two ports want the same number.
-pub(crate) fn assign_ports(
-    application: &Application,
+fn assign_ports(
+    applications: &[&Application],
     environment: &str,
 ) -> Result<BTreeMap<String, u16>> {
     let mut names: BTreeSet<String> = BTreeSet::new();
     let mut assigned: BTreeMap<String, u16> = BTreeMap::new();
-    let values = application
-        .args
-        .iter()
-        .chain(application.env.values())
-        .chain(application.resources.requests.values())
-        .chain(application.resources.limits.values());
-    for value in values {
-        collect_port(value, environment, &mut names, &mut assigned)?;
+    for application in applications {
+        let values = application
+            .args
+            .iter()
+            .chain(application.env.values())
+            .chain(application.resources.requests.values())
+            .chain(application.resources.limits.values());
+        for value in values {
+            collect_port(value, environment, &mut names, &mut assigned)?;
+        }
     }
 
     let mut owners: BTreeMap<u16, &str> = BTreeMap::new();
@@ -542,6 +854,25 @@ fn unpack_optional_i32(name: &str, value: Option<Value>) -> starlark::Result<Opt
     }
 }
 
+fn unpack_optional_bool(name: &str, value: Option<Value>) -> starlark::Result<Option<bool>> {
+    match value {
+        Some(v) => Ok(Some(
+            v.unpack_bool()
+                .ok_or_else(|| function_error(format!("{} must be a bool", name)))?,
+        )),
+        None => Ok(None),
+    }
+}
+
+fn unpack_string_list(name: &str, value: Option<Value>) -> starlark::Result<Vec<String>> {
+    match value {
+        Some(v) => Ok(UnpackListOrTuple::<String>::unpack_value(v)?
+            .ok_or_else(|| function_error(format!("{} must be a list of str", name)))?
+            .items),
+        None => Ok(Vec::new()),
+    }
+}
+
 fn unpack_probe(name: &str, value: Option<Value>) -> starlark::Result<Option<Probe>> {
     match value {
         Some(v) if !v.is_none() => Ok(Some(
@@ -85,6 +85,76 @@ fn test_starlark_file_variable_creation() -> anyhow::Result<()> {
     Ok(())
 }
 
+#[test]
+fn test_starlark_config_map_variable_creation() -> anyhow::Result<()> {
+    let module = Module::new();
+    let globals = make_starlark_globals();
+
+    let mut eval = Evaluator::new(&module);
+    let code = r#"ConfigMapVariable(name="echo-settings", key="log-level")"#;
+    let ast = AstModule::parse("test", code.to_string(), &Dialect::Standard)
+        .map_err(|e| anyhow!("Parse error: {:?}", e))?;
+    let result = eval
+        .eval_module(ast, &globals)
+        .map_err(|e| anyhow!("Eval error: {:?}", e))?;
+
+    let cv = result.downcast_ref::<ConfigMapVariable>().unwrap();
+    assert_eq!(cv.name, "echo-settings");
+    assert_eq!(cv.key, "log-level");
+    assert_eq!(cv.path, None);
+
+    Ok(())
+}
+
+#[test]
+fn test_starlark_resource_field_variable_creation() -> anyhow::Result<()> {
+    let module = Module::new();
+    let globals = make_starlark_globals();
+
+    let mut eval = Evaluator::new(&module);
+    let code = r#"ResourceFieldVariable("limits.memory", divisor="1Mi")"#;
+    let ast = AstModule::parse("test", code.to_string(), &Dialect::Standard)
+        .map_err(|e| anyhow!("Parse error: {:?}", e))?;
+    let result = eval
+        .eval_module(ast, &globals)
+        .map_err(|e| anyhow!("Eval error: {:?}", e))?;
+
+    let rv = result.downcast_ref::<ResourceFieldVariable>().unwrap();
+    assert_eq!(rv.resource, "limits.memory");
+    assert_eq!(rv.divisor.as_deref(), Some("1Mi"));
+
+    Ok(())
+}
+
+#[test]
+fn test_starlark_security_context_creation() -> anyhow::Result<()> {
+    let module = Module::new();
+    let globals = make_starlark_globals();
+
+    let mut eval = Evaluator::new(&module);
+    let code = r#"SecurityContext(
+    run_as_non_root=True,
+    run_as_user=1000,
+    drop_capabilities=["ALL"],
+    seccomp_profile="RuntimeDefault",
+)"#;
+    let ast = AstModule::parse("test", code.to_string(), &Dialect::Standard)
+        .map_err(|e| anyhow!("Parse error: {:?}", e))?;
+    let result = eval
+        .eval_module(ast, &globals)
+        .map_err(|e| anyhow!("Eval error: {:?}", e))?;
+
+    let sc = result.downcast_ref::<SecurityContext>().unwrap();
+    assert_eq!(sc.run_as_non_root, Some(true));
+    assert_eq!(sc.run_as_user, Some(1000));
+    assert_eq!(sc.drop_capabilities, vec!["ALL".to_string()]);
+    assert!(sc.add_capabilities.is_empty());
+    assert_eq!(sc.read_only_root_filesystem, None);
+    assert_eq!(sc.seccomp_profile.as_deref(), Some("RuntimeDefault"));
+
+    Ok(())
+}
+
 #[test]
 fn test_starlark_string_variable_creation() -> anyhow::Result<()> {
     let module = Module::new();
@@ -269,6 +339,7 @@ fn port_app(ports: &[(&str, Option<u16>)]) -> Application {
         liveness: None,
         readiness: None,
         resources: Resources::default(),
+        security_context: None,
         startup: None,
     }
 }
@@ -276,7 +347,7 @@ fn port_app(ports: &[(&str, Option<u16>)]) -> Application {
 #[test]
 fn test_assign_ports_auto_sorted_from_base() -> anyhow::Result<()> {
     let app = port_app(&[("b", None), ("a", None)]);
-    let assigned = assign_ports(&app, "prod")?;
+    let assigned = assign_ports(&[&app], "prod")?;
     assert_eq!(assigned.get("a"), Some(&8080));
     assert_eq!(assigned.get("b"), Some(&8081));
     Ok(())
@@ -286,7 +357,7 @@ fn test_assign_ports_auto_sorted_from_base() -> anyhow::Result<()> {
 fn test_assign_ports_skips_explicit() -> anyhow::Result<()> {
     // The explicit 8080 should be kept, and the auto-assigned port should skip past it.
     let app = port_app(&[("http", None), ("pinned", Some(8080))]);
-    let assigned = assign_ports(&app, "prod")?;
+    let assigned = assign_ports(&[&app], "prod")?;
     assert_eq!(assigned.get("pinned"), Some(&8080));
     assert_eq!(assigned.get("http"), Some(&8081));
     Ok(())
@@ -295,7 +366,7 @@ fn test_assign_ports_skips_explicit() -> anyhow::Result<()> {
 #[test]
 fn test_assign_ports_duplicate_number_errors() {
     let app = port_app(&[("a", Some(8443)), ("b", Some(8443))]);
-    let err = assign_ports(&app, "prod").unwrap_err().to_string();
+    let err = assign_ports(&[&app], "prod").unwrap_err().to_string();
     assert!(err.contains("both request number"), "{}", err);
 }
 
@@ -319,8 +390,9 @@ fn test_assign_ports_conflicting_number_for_name_errors() {
         liveness: None,
         readiness: None,
         resources: Resources::default(),
+        security_context: None,
         startup: None,
     };
-    let err = assign_ports(&app, "prod").unwrap_err().to_string();
+    let err = assign_ports(&[&app], "prod").unwrap_err().to_string();
     assert!(err.contains("conflicting numbers"), "{}", err);
 }
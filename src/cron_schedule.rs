@@ -0,0 +1,247 @@
+use anyhow::{anyhow, bail, Context, Result};
+use std::collections::BTreeSet;
+
+// The macros Kubernetes accepts in place of the five fields
+const MACROS: [&str; 7] = [
+    "@yearly",
+    "@annually",
+    "@monthly",
+    "@weekly",
+    "@daily",
+    "@midnight",
+    "@hourly",
+];
+
+const MONTHS: [&str; 12] = [
+    "jan", "feb", "mar", "apr", "may", "jun", "jul", "aug", "sep", "oct", "nov", "dec",
+];
+const WEEKDAYS: [&str; 7] = ["sun", "mon", "tue", "wed", "thu", "fri", "sat"];
+const DAYS_IN_MONTH: [u32; 12] = [31, 29, 31, 30, 31, 30, 31, 31, 30, 31, 30, 31];
+
+struct Field {
+    name: &'static str,
+    min: u32,
+    max: u32,
+    names: &'static [&'static str],
+    // Whether `?` may stand in for `*`, which only makes sense for the two day fields
+    allows_question_mark: bool,
+}
+
+const FIELDS: [Field; 5] = [
+    Field {
+        name: "minute",
+        min: 0,
+        max: 59,
+        names: &[],
+        allows_question_mark: false,
+    },
+    Field {
+        name: "hour",
+        min: 0,
+        max: 23,
+        names: &[],
+        allows_question_mark: false,
+    },
+    Field {
+        name: "day of month",
+        min: 1,
+        max: 31,
+        names: &[],
+        allows_question_mark: true,
+    },
+    Field {
+        name: "month",
+        min: 1,
+        max: 12,
+        names: &MONTHS,
+        allows_question_mark: false,
+    },
+    Field {
+        name: "day of week",
+        min: 0,
+        max: 6,
+        names: &WEEKDAYS,
+        allows_question_mark: true,
+    },
+];
+
+// Checks a CronJob schedule the way the apiserver will, so a typo fails the render instead of
+// leaving a CronJob that never runs. Returns warnings about schedules that are valid but probably
+// not what was meant.
+pub(crate) fn check_schedule(schedule: &str) -> Result<Vec<String>> {
+    let schedule = schedule.trim();
+    if schedule.starts_with("TZ=") || schedule.starts_with("CRON_TZ=") {
+        bail!(
+            "Schedule {:?} can't set a time zone, use timeZone instead",
+            schedule
+        );
+    }
+    if let Some(rest) = schedule.strip_prefix("@every ") {
+        if rest.trim().is_empty() {
+            bail!("Schedule {:?} is missing a duration", schedule);
+        }
+        return Ok(Vec::new());
+    }
+    if schedule.starts_with('@') {
+        if !MACROS.contains(&schedule) {
+            bail!("Schedule {:?} isn't one of {}", schedule, MACROS.join(", "));
+        }
+        return Ok(Vec::new());
+    }
+
+    let parts: Vec<&str> = schedule.split_whitespace().collect();
+    if parts.len() != FIELDS.len() {
+        bail!(
+            "Schedule {:?} has {} fields instead of minute, hour, day of month, month, and weekday",
+            schedule,
+            parts.len()
+        );
+    }
+    let mut values = Vec::new();
+    for (part, field) in parts.iter().zip(&FIELDS) {
+        values.push(
+            parse_field(part, field)
+                .with_context(|| format!("in the {} of schedule {:?}", field.name, schedule))?,
+        );
+    }
+
+    let [minutes, _, days, months, weekdays] = &values[..] else {
+        unreachable!("Every schedule has 5 fields");
+    };
+    // Cron runs on the listed days of the month or of the week when both are restricted, so only
+    // a day of the month that never exists is an error on its own
+    if weekdays.is_none() {
+        if let Some(days) = days {
+            let months = months.clone().unwrap_or_else(|| (1..=12).collect());
+            let longest = months
+                .iter()
+                .map(|m| DAYS_IN_MONTH[*m as usize - 1])
+                .max()
+                .unwrap_or(31);
+            if days.iter().all(|d| *d > longest) {
+                bail!(
+                    "Schedule {:?} never runs since those days don't exist",
+                    schedule
+                );
+            }
+        }
+    }
+
+    let mut warnings = Vec::new();
+    if minutes.is_none() {
+        warnings.push(format!("Schedule {:?} runs every minute", schedule));
+    }
+    if days.is_some() && weekdays.is_some() {
+        warnings.push(format!(
+            "Schedule {:?} runs on the listed days of the month and also the listed weekdays",
+            schedule
+        ));
+    }
+    Ok(warnings)
+}
+
+// The values a field allows, or None if it allows every value
+fn parse_field(field: &str, spec: &Field) -> Result<Option<BTreeSet<u32>>> {
+    let mut values = BTreeSet::new();
+    let mut unrestricted = false;
+    for item in field.split(',') {
+        let (range, step) = match item.split_once('/') {
+            Some((range, step)) => {
+                let step: u32 = step
+                    .parse()
+                    .map_err(|_| anyhow!("{:?} isn't a valid step", step))?;
+                if step == 0 {
+                    bail!("A step can't be 0");
+                }
+                (range, Some(step))
+            }
+            None => (item, None),
+        };
+        let (start, end) = if range == "*" || (range == "?" && spec.allows_question_mark) {
+            unrestricted |= step.unwrap_or(1) == 1;
+            (spec.min, spec.max)
+        } else if let Some((start, end)) = range.split_once('-') {
+            (parse_value(start, spec)?, parse_value(end, spec)?)
+        } else {
+            let start = parse_value(range, spec)?;
+            // `5/15` means every 15 starting from 5
+            (start, if step.is_some() { spec.max } else { start })
+        };
+        if start > end {
+            bail!("{:?} ends before it starts", item);
+        }
+        values.extend((start..=end).step_by(step.unwrap_or(1) as usize));
+    }
+    Ok(if unrestricted { None } else { Some(values) })
+}
+
+fn parse_value(value: &str, spec: &Field) -> Result<u32> {
+    let lower = value.to_ascii_lowercase();
+    if let Some(i) = spec.names.iter().position(|n| *n == lower) {
+        return Ok(spec.min + i as u32);
+    }
+    let number: u32 = value
+        .parse()
+        .map_err(|_| anyhow!("{:?} isn't a number", value))?;
+    if number < spec.min || number > spec.max {
+        bail!("{} is outside of {}-{}", number, spec.min, spec.max);
+    }
+    Ok(number)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_valid_schedules() -> Result<()> {
+        for schedule in [
+            "0 2 * * *",
+            "*/15 9-17 * * mon-fri",
+            "0 0 1,15 * ?",
+            "30 4 29 feb *",
+            "5/10 * * * *",
+            "@daily",
+            "@every 1h30m",
+        ] {
+            assert_eq!(
+                check_schedule(schedule)?,
+                Vec::<String>::new(),
+                "{}",
+                schedule
+            );
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_invalid_schedules() {
+        for schedule in [
+            "0 2 * *",
+            "60 * * * *",
+            "0 24 * * *",
+            "0 0 0 * *",
+            "0 0 * 13 *",
+            "0 0 * * 7",
+            "0 0 * * funday",
+            "0 0 5-1 * *",
+            "*/0 * * * *",
+            "? * * * *",
+            "0 0 31 2,4 *",
+            "@fortnightly",
+            "TZ=UTC 0 2 * * *",
+        ] {
+            assert!(check_schedule(schedule).is_err(), "{}", schedule);
+        }
+    }
+
+    #[test]
+    fn test_suspicious_schedules() -> Result<()> {
+        assert_eq!(check_schedule("* * * * *")?.len(), 1);
+        assert_eq!(check_schedule("*/1 0 * * *")?.len(), 1);
+        assert_eq!(check_schedule("0 0 13 * fri")?.len(), 1);
+        // Restricting the day of the week makes the impossible day of the month irrelevant
+        assert_eq!(check_schedule("0 0 31 2 mon")?.len(), 1);
+        Ok(())
+    }
+}
@@ -0,0 +1,108 @@
+use anyhow::{Context, Result};
+use kube::api::DynamicObject;
+use sqlx::{
+    any::{AnyTypeInfo, AnyValueRef},
+    error::BoxDynError,
+    Any, AnyPool, Decode, Type,
+};
+use std::{collections::BTreeMap, fmt};
+use time::{format_description::well_known::Rfc3339, OffsetDateTime};
+use tracing::instrument;
+
+use crate::{
+    kubernetes_io::{KubernetesKey, KubernetesResources},
+    state_store::{all_objects, tracked_objects},
+};
+
+#[instrument(skip_all)]
+pub(crate) async fn get_objects_from_database(pool: &AnyPool) -> Result<KubernetesResources> {
+    let mut resources = KubernetesResources {
+        by_key: BTreeMap::new(),
+        namespaces: BTreeMap::new(),
+    };
+    for (key, yaml) in all_objects(pool).await? {
+        let object: DynamicObject = serde_yaml::from_str(&yaml)
+            .with_context(|| format!("Failed to parse stored yaml for {:?}", key))?;
+        if key.api_version == "v1" && key.kind == "Namespace" {
+            resources.namespaces.insert(key, object);
+        } else {
+            resources.by_key.insert(key, object);
+        };
+    }
+    Ok(resources)
+}
+
+// What's tracked and when, without parsing every object's YAML
+pub(crate) struct TrackedObject {
+    pub key: KubernetesKey,
+    pub created: DecodableOffsetDateTime,
+    pub last_updated: DecodableOffsetDateTime,
+}
+
+#[instrument(skip_all)]
+pub(crate) async fn get_tracked_objects(pool: &AnyPool) -> Result<Vec<TrackedObject>> {
+    tracked_objects(pool).await
+}
+
+// A timestamp column read through `AnyPool`, which doesn't support timestamps. Select the column
+// with `CAST(... AS VARCHAR(64))` and this parses the text Postgres or SQLite return.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub(crate) struct DecodableOffsetDateTime(pub OffsetDateTime);
+
+impl Type<Any> for DecodableOffsetDateTime {
+    fn type_info() -> AnyTypeInfo {
+        <String as Type<Any>>::type_info()
+    }
+}
+
+impl<'r> Decode<'r, Any> for DecodableOffsetDateTime {
+    fn decode(value: AnyValueRef<'r>) -> Result<Self, BoxDynError> {
+        let text = <String as Decode<Any>>::decode(value)?;
+        Ok(DecodableOffsetDateTime(parse_timestamp(&text)?))
+    }
+}
+
+impl fmt::Display for DecodableOffsetDateTime {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let formatted = self.0.format(&Rfc3339).map_err(|_| fmt::Error)?;
+        f.write_str(&formatted)
+    }
+}
+
+// Postgres writes `2025-03-26 02:09:18.5+00` and SQLite writes `2025-03-26 02:09:18` in UTC, so
+// massage either into RFC 3339.
+fn parse_timestamp(text: &str) -> Result<OffsetDateTime, time::error::Parse> {
+    let mut normalized = text.trim().replacen(' ', "T", 1);
+    match normalized.rfind(|c| c == '+' || c == '-') {
+        // Past the date's dashes, so it's an offset
+        Some(i) if i > 10 => {
+            if normalized.len() - i == 3 {
+                normalized.push_str(":00");
+            }
+        }
+        _ => normalized.push('Z'),
+    }
+    OffsetDateTime::parse(&normalized, &Rfc3339)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_timestamp() {
+        let parsed = |text| DecodableOffsetDateTime(parse_timestamp(text).unwrap()).to_string();
+
+        assert_eq!(parsed("2025-03-26 02:09:18"), "2025-03-26T02:09:18Z");
+        assert_eq!(parsed("2025-03-26 02:09:18.5+00"), "2025-03-26T02:09:18.5Z");
+        assert_eq!(
+            parsed("2025-03-26 02:09:18-07"),
+            "2025-03-26T02:09:18-07:00"
+        );
+        assert_eq!(
+            parsed("2025-03-26 02:09:18+05:30"),
+            "2025-03-26T02:09:18+05:30"
+        );
+        assert!(parse_timestamp("yesterday").is_err());
+    }
+}
@@ -0,0 +1,456 @@
+use anyhow::{anyhow, bail, Context, Result};
+use kube::api::DynamicObject;
+use serde::{Deserialize, Serialize};
+use serde_json::Value as JsonValue;
+use sqlx::AnyPool;
+use std::{collections::BTreeMap, time::Duration};
+use time::{format_description::well_known::Rfc3339, OffsetDateTime};
+use tracing::info;
+
+use crate::{
+    apply_diff::apply_diff,
+    database::{get_objects_from_database, get_tracked_objects},
+    delegation::Delegation,
+    filter::{key_matches_filter, object_matches_filter, PartialKey},
+    generate_diff::DiffAction,
+    kubernetes_io::{KubernetesKey, KubernetesResources},
+    push_lock::PushLock,
+    state_store::{all_overlays, delete_overlays, insert_overlay},
+    targets::TargetPolicy,
+};
+
+// Environment variables and arguments added to a workload's containers for a while, without
+// changing any files
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+pub(crate) struct Overlay {
+    // Only this container, or every container if unset
+    pub container: Option<String>,
+    pub env: BTreeMap<String, String>,
+    pub args: Vec<String>,
+    // The env entries each container had before, by container and then variable name, so clearing
+    // the overlay can put them back. None means the variable wasn't set.
+    #[serde(default)]
+    previous_env: BTreeMap<String, BTreeMap<String, Option<JsonValue>>>,
+}
+
+impl Overlay {
+    pub(crate) fn new(
+        container: Option<String>,
+        env: BTreeMap<String, String>,
+        args: Vec<String>,
+    ) -> Self {
+        Overlay {
+            container,
+            env,
+            args,
+            previous_env: BTreeMap::new(),
+        }
+    }
+}
+
+// Where each kind that Sisyphus can overlay keeps its pod spec
+fn pod_spec_pointer(kind: &str) -> Option<&'static str> {
+    match kind {
+        "DaemonSet" | "Deployment" | "Job" | "StatefulSet" => Some("/spec/template/spec"),
+        "CronJob" => Some("/spec/jobTemplate/spec/template/spec"),
+        _ => None,
+    }
+}
+
+fn containers<'a>(
+    object: &'a mut DynamicObject,
+    kind: &str,
+    only: Option<&str>,
+) -> Result<Vec<&'a mut serde_json::Map<String, JsonValue>>> {
+    let pointer = pod_spec_pointer(kind)
+        .ok_or_else(|| anyhow!("Debug overlays only work on workloads, not {}", kind))?;
+    let containers: Vec<_> = object
+        .data
+        .pointer_mut(&format!("{}/containers", pointer))
+        .and_then(|c| c.as_array_mut())
+        .ok_or_else(|| anyhow!("{} has no containers", kind))?
+        .iter_mut()
+        .filter_map(|c| c.as_object_mut())
+        .filter(|c| only.is_none() || c.get("name").and_then(|n| n.as_str()) == only)
+        .collect();
+    if containers.is_empty() {
+        bail!("No container named {}", only.unwrap_or_default());
+    }
+    Ok(containers)
+}
+
+fn container_name(container: &serde_json::Map<String, JsonValue>) -> String {
+    container
+        .get("name")
+        .and_then(|n| n.as_str())
+        .unwrap_or_default()
+        .to_string()
+}
+
+fn array<'a>(
+    container: &'a mut serde_json::Map<String, JsonValue>,
+    field: &str,
+) -> Result<&'a mut Vec<JsonValue>> {
+    container
+        .entry(field)
+        .or_insert_with(|| JsonValue::Array(Vec::new()))
+        .as_array_mut()
+        .ok_or_else(|| anyhow!("{} must be a list", field))
+}
+
+fn env_name(entry: &JsonValue) -> Option<&str> {
+    entry.get("name").and_then(|n| n.as_str())
+}
+
+// Sets the overlay's variables, replacing any with the same name, and appends its arguments.
+// Returns what each replaced variable was before.
+fn apply_overlay(
+    object: &mut DynamicObject,
+    kind: &str,
+    overlay: &Overlay,
+) -> Result<BTreeMap<String, BTreeMap<String, Option<JsonValue>>>> {
+    let mut previous = BTreeMap::new();
+    for container in containers(object, kind, overlay.container.as_deref())? {
+        let before: &mut BTreeMap<_, _> = previous.entry(container_name(container)).or_default();
+        if !overlay.env.is_empty() {
+            let env = array(container, "env")?;
+            for (name, value) in &overlay.env {
+                let entry = serde_json::json!({"name": name, "value": value});
+                match env.iter_mut().find(|e| env_name(e) == Some(name)) {
+                    Some(existing) => {
+                        before.insert(name.clone(), Some(std::mem::replace(existing, entry)));
+                    }
+                    None => {
+                        before.insert(name.clone(), None);
+                        env.push(entry);
+                    }
+                }
+            }
+        }
+        if !overlay.args.is_empty() {
+            let args = array(container, "args")?;
+            args.extend(overlay.args.iter().map(|a| JsonValue::String(a.clone())));
+        }
+    }
+    Ok(previous)
+}
+
+// Undoes apply_overlay. Arguments are only removed if they're still the last ones.
+fn revert_overlay(object: &mut DynamicObject, kind: &str, overlay: &Overlay) -> Result<()> {
+    for container in containers(object, kind, overlay.container.as_deref())? {
+        let before = overlay.previous_env.get(&container_name(container));
+        if !overlay.env.is_empty() {
+            let env = array(container, "env")?;
+            for name in overlay.env.keys() {
+                match before.and_then(|b| b.get(name)).cloned().flatten() {
+                    Some(entry) => match env.iter_mut().find(|e| env_name(e) == Some(name)) {
+                        Some(existing) => *existing = entry,
+                        None => env.push(entry),
+                    },
+                    None => env.retain(|e| env_name(e) != Some(name)),
+                }
+            }
+        }
+        if !overlay.args.is_empty() {
+            let args = array(container, "args")?;
+            let added: Vec<_> = overlay
+                .args
+                .iter()
+                .map(|a| JsonValue::String(a.clone()))
+                .collect();
+            if args.ends_with(&added) {
+                args.truncate(args.len() - added.len());
+            }
+        }
+        // Rendering leaves out empty lists, so don't leave ones that only held the overlay
+        for field in ["env", "args"] {
+            if container
+                .get(field)
+                .and_then(|v| v.as_array())
+                .is_some_and(|v| v.is_empty())
+            {
+                container.remove(field);
+            }
+        }
+    }
+    Ok(())
+}
+
+// Parses a duration like `90s`, `30m`, `2h`, or `1d`
+pub(crate) fn parse_ttl(ttl: &str) -> Result<Duration> {
+    let ttl = ttl.trim();
+    let split = ttl.len() - ttl.trim_start_matches(|c: char| c.is_ascii_digit()).len();
+    let (number, unit) = ttl.split_at(split);
+    let number: u64 = number
+        .parse()
+        .map_err(|_| anyhow!("{:?} must start with a number, like 2h", ttl))?;
+    let seconds = match unit {
+        "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        _ => bail!("{:?} must end in s, m, h, or d", ttl),
+    };
+    Ok(Duration::from_secs(number * seconds))
+}
+
+// The one tracked workload `filter` matches
+async fn find_workload(pool: &AnyPool, filter: &PartialKey) -> Result<KubernetesKey> {
+    let mut matches: Vec<_> = if filter.needs_objects() {
+        get_objects_from_database(pool)
+            .await?
+            .by_key
+            .into_iter()
+            .filter(|(k, v)| object_matches_filter(k, v, filter))
+            .map(|(k, _)| k)
+            .filter(|k| pod_spec_pointer(&k.kind).is_some())
+            .collect()
+    } else {
+        get_tracked_objects(pool)
+            .await?
+            .into_iter()
+            .map(|t| t.key)
+            .filter(|k| key_matches_filter(k, filter) && pod_spec_pointer(&k.kind).is_some())
+            .collect()
+    };
+    match matches.len() {
+        0 => bail!("No tracked workload matches"),
+        1 => Ok(matches.remove(0)),
+        _ => {
+            let names: Vec<_> = matches.iter().map(|k| k.to_string()).collect();
+            bail!(
+                "More than one tracked workload matches, narrow it down with --cluster, \
+                 --namespace, or --kind: {}",
+                names.join(", ")
+            )
+        }
+    }
+}
+
+async fn recorded_object(pool: &AnyPool, key: &KubernetesKey) -> Result<DynamicObject> {
+    get_objects_from_database(pool)
+        .await?
+        .by_key
+        .remove(key)
+        .ok_or_else(|| anyhow!("{} isn't tracked in the database", key))
+}
+
+// Patches the live object from what's recorded to `after` and records the result, the same way a
+// push would
+async fn push_object(
+    key: &KubernetesKey,
+    recorded: &DynamicObject,
+    after: DynamicObject,
+    pool: &AnyPool,
+    lock: &PushLock,
+    max_retries: u32,
+) -> Result<()> {
+    let patch = json_patch::diff(
+        &serde_json::to_value(recorded)?,
+        &serde_json::to_value(&after)?,
+    );
+    if patch.0.is_empty() {
+        return Ok(());
+    }
+    apply_diff(
+        vec![(key.clone(), DiffAction::Patch { after, patch })],
+        &TargetPolicy::default(),
+        pool,
+        lock,
+        /* hash_secret_values= */ false,
+        /* annotate_provenance= */ false,
+        max_retries,
+        /* keep_going= */ false,
+    )
+    .await
+}
+
+// Applies `overlay` to the workload `filter` matches right away and keeps it there until `ttl`
+// passes. Pushes in the meantime keep it, and the first push after that removes it.
+pub(crate) async fn debug_overlay(
+    filter: &PartialKey,
+    mut overlay: Overlay,
+    ttl: Duration,
+    pool: &AnyPool,
+    lock: &PushLock,
+    delegation: Option<&Delegation>,
+    max_retries: u32,
+) -> Result<()> {
+    if overlay.env.is_empty() && overlay.args.is_empty() {
+        bail!("Pass --set-env or --add-arg to say what to change");
+    }
+    let key = find_workload(pool, filter).await?;
+    if let Some(delegation) = delegation {
+        delegation.check(&key)?;
+    }
+    let recorded = recorded_object(pool, &key).await?;
+    let mut after = recorded.clone();
+    overlay.previous_env = apply_overlay(&mut after, &key.kind, &overlay)?;
+    push_object(&key, &recorded, after, pool, lock, max_retries).await?;
+
+    let expires = OffsetDateTime::now_utc().replace_nanosecond(0)? + ttl;
+    insert_overlay(
+        pool,
+        &key,
+        &expires.format(&Rfc3339)?,
+        &serde_json::to_string(&overlay)?,
+    )
+    .await?;
+    info!(
+        object = %key,
+        "Overlaid {} until {}, run clear-overlay to remove it sooner",
+        key,
+        expires.format(&Rfc3339)?
+    );
+    Ok(())
+}
+
+// Removes every overlay on the workload `filter` matches, expired or not, and reverts the live
+// object right away
+pub(crate) async fn clear_overlay(
+    filter: &PartialKey,
+    pool: &AnyPool,
+    lock: &PushLock,
+    delegation: Option<&Delegation>,
+    max_retries: u32,
+) -> Result<()> {
+    let key = find_workload(pool, filter).await?;
+    if let Some(delegation) = delegation {
+        delegation.check(&key)?;
+    }
+    let overlays: Vec<_> = get_overlays(pool)
+        .await?
+        .into_iter()
+        .filter(|(k, _, _)| *k == key)
+        .collect();
+    if overlays.is_empty() {
+        info!(object = %key, "{} has no debug overlay", key);
+        return Ok(());
+    }
+
+    let recorded = recorded_object(pool, &key).await?;
+    let mut after = recorded.clone();
+    for (_, _, overlay) in overlays.iter().rev() {
+        revert_overlay(&mut after, &key.kind, overlay)?;
+    }
+    push_object(&key, &recorded, after, pool, lock, max_retries).await?;
+
+    delete_overlays(pool, &key).await?;
+    info!(object = %key, "Cleared the debug overlay on {}", key);
+    Ok(())
+}
+
+// Every overlay in the order they were made, with when it expires
+async fn get_overlays(pool: &AnyPool) -> Result<Vec<(KubernetesKey, OffsetDateTime, Overlay)>> {
+    let mut overlays = Vec::new();
+    for (key, expires, overlay) in all_overlays(pool).await? {
+        let expires = OffsetDateTime::parse(&expires, &Rfc3339)
+            .with_context(|| format!("Invalid expiry for the debug overlay on {}", key))?;
+        let overlay = serde_json::from_str(&overlay)
+            .with_context(|| format!("Invalid debug overlay on {}", key))?;
+        overlays.push((key, expires, overlay));
+    }
+    Ok(overlays)
+}
+
+// Adds the overlays that haven't expired to what the files render, so pushing doesn't remove them
+// early
+pub(crate) async fn apply_active_overlays(
+    from_files: &mut KubernetesResources,
+    pool: &AnyPool,
+) -> Result<()> {
+    let now = OffsetDateTime::now_utc();
+    for (key, expires, overlay) in get_overlays(pool).await? {
+        if expires <= now {
+            continue;
+        }
+        if let Some(object) = from_files.by_key.get_mut(&key) {
+            apply_overlay(object, &key.kind, &overlay)?;
+            info!(
+                object = %key,
+                "Keeping the debug overlay on {} until {}",
+                key,
+                expires.format(&Rfc3339)?
+            );
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn deployment() -> DynamicObject {
+        DynamicObject {
+            types: None,
+            metadata: Default::default(),
+            data: json!({
+                "spec": {"template": {"spec": {"containers": [
+                    {
+                        "name": "app",
+                        "args": ["--port", "8080"],
+                        "env": [{"name": "LOG_LEVEL", "value": "info"}],
+                    },
+                    {"name": "sidecar"},
+                ]}}}
+            }),
+        }
+    }
+
+    #[test]
+    fn test_overlay_round_trip() -> Result<()> {
+        let mut overlay = Overlay {
+            container: Some("app".to_string()),
+            env: BTreeMap::from([
+                ("LOG_LEVEL".to_string(), "debug".to_string()),
+                ("TRACE".to_string(), "1".to_string()),
+            ]),
+            args: vec!["--verbose".to_string()],
+            ..Default::default()
+        };
+        let mut object = deployment();
+
+        overlay.previous_env = apply_overlay(&mut object, "Deployment", &overlay)?;
+        assert_eq!(
+            object.data["spec"]["template"]["spec"]["containers"][0],
+            json!({
+                "name": "app",
+                "args": ["--port", "8080", "--verbose"],
+                "env": [
+                    {"name": "LOG_LEVEL", "value": "debug"},
+                    {"name": "TRACE", "value": "1"},
+                ],
+            })
+        );
+        assert_eq!(
+            object.data["spec"]["template"]["spec"]["containers"][1],
+            json!({"name": "sidecar"})
+        );
+
+        revert_overlay(&mut object, "Deployment", &overlay)?;
+        assert_eq!(object.data, deployment().data);
+        Ok(())
+    }
+
+    #[test]
+    fn test_overlay_rejects_unknown_containers_and_kinds() {
+        let overlay = Overlay {
+            container: Some("missing".to_string()),
+            ..Default::default()
+        };
+        assert!(apply_overlay(&mut deployment(), "Deployment", &overlay).is_err());
+        assert!(apply_overlay(&mut deployment(), "ConfigMap", &Overlay::default()).is_err());
+    }
+
+    #[test]
+    fn test_parse_ttl() -> Result<()> {
+        assert_eq!(parse_ttl("90s")?, Duration::from_secs(90));
+        assert_eq!(parse_ttl("2h")?, Duration::from_secs(7200));
+        assert_eq!(parse_ttl("1d")?, Duration::from_secs(86400));
+        assert!(parse_ttl("h").is_err());
+        assert!(parse_ttl("2w").is_err());
+        Ok(())
+    }
+}
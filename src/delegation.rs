@@ -0,0 +1,291 @@
+use anyhow::{anyhow, bail, Context, Result};
+use ring::{
+    rand::SystemRandom,
+    signature::{Ed25519KeyPair, KeyPair, UnparsedPublicKey, ED25519},
+};
+use serde::{Deserialize, Serialize};
+use sqlx::AnyPool;
+use std::{
+    collections::BTreeSet,
+    fs::{self, OpenOptions},
+    io::{ErrorKind, Write},
+    os::unix::fs::OpenOptionsExt,
+    path::Path,
+};
+use time::{format_description::well_known::Rfc3339, OffsetDateTime};
+use tracing::info;
+
+use crate::{
+    generate_diff::{DiffAction, PlannedChange},
+    globs::match_name,
+    kubernetes_io::{KubernetesKey, KubernetesResources},
+    provenance::record_delegation,
+    state_store::{delegation_keys, insert_delegation_key},
+};
+
+// What a team may push, signed by someone the database trusts. A delegated push only sees the
+// namespaces it covers, so objects elsewhere are neither changed nor deleted.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(deny_unknown_fields, rename_all = "camelCase")]
+pub(crate) struct Delegation {
+    pub team: String,
+    pub clusters: BTreeSet<String>,
+    // Names or patterns like echo-*
+    pub namespaces: BTreeSet<String>,
+    // RFC 3339, after which the delegation stops working
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+struct DelegationFile {
+    policy: Delegation,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    signature: Option<String>,
+}
+
+impl Delegation {
+    fn covers(&self, key: &KubernetesKey) -> bool {
+        if !self.clusters.contains(&key.cluster) {
+            return false;
+        }
+        // Teams may manage their Namespace objects but nothing else outside of a namespace
+        let namespace = match &key.namespace {
+            Some(namespace) => namespace,
+            None if key.api_version == "v1" && key.kind == "Namespace" => &key.name,
+            None => return false,
+        };
+        self.namespaces.iter().any(|p| match_name(p, namespace))
+    }
+
+    // Refuses an object outside of the delegation, for commands that change one at a time
+    pub(crate) fn check(&self, key: &KubernetesKey) -> Result<()> {
+        if !self.covers(key) {
+            bail!("{} is outside of {}'s delegation", key, self.team);
+        }
+        Ok(())
+    }
+
+    // Drops every object outside of the delegation
+    pub(crate) fn retain(&self, resources: &mut KubernetesResources) {
+        resources.by_key.retain(|k, _| self.covers(k));
+        resources.namespaces.retain(|k, _| self.covers(k));
+    }
+
+    // Drops deletions outside of the delegation, since the team's files were never meant to
+    // describe those objects, and refuses anything else outside of it
+    pub(crate) fn restrict(&self, plan: Vec<PlannedChange>) -> Result<Vec<PlannedChange>> {
+        let mut violations = Vec::new();
+        let mut allowed = Vec::new();
+        for change in plan {
+            if self.covers(&change.key) {
+                allowed.push(change);
+            } else if !matches!(change.action, DiffAction::Delete) {
+                violations.push(format!("{} {}", change.action.verb(), change.key));
+            }
+        }
+        if !violations.is_empty() {
+            bail!(
+                "Refusing to push outside of {}'s delegation:\n  • {}",
+                self.team,
+                violations.join("\n  • ")
+            );
+        }
+        Ok(allowed)
+    }
+}
+
+// Reads the delegation a command runs under. Once the database trusts any key, every command that
+// changes clusters or the database must carry a delegation signed by one of them.
+pub(crate) async fn load_delegation(
+    pool: &AnyPool,
+    path: Option<&Path>,
+) -> Result<Option<Delegation>> {
+    let keys = delegation_keys(pool).await?;
+    let Some(path) = path else {
+        if !keys.is_empty() {
+            bail!("This database only accepts changes with a signed --delegation");
+        }
+        return Ok(None);
+    };
+    if keys.is_empty() {
+        bail!("The database doesn't trust any delegation keys yet, see `delegation trust`");
+    }
+
+    let file: DelegationFile = serde_yaml::from_str(
+        &fs::read_to_string(path).with_context(|| format!("while reading {:?}", path))?,
+    )
+    .with_context(|| format!("while parsing {:?}", path))?;
+    let Some(signature) = &file.signature else {
+        bail!("{:?} isn't signed", path);
+    };
+    let signature = from_hex(signature)?;
+    let message = serde_json::to_vec(&file.policy)?;
+    let signer = keys.iter().find(|(_, key)| {
+        from_hex(key).is_ok_and(|key| {
+            UnparsedPublicKey::new(&ED25519, key)
+                .verify(&message, &signature)
+                .is_ok()
+        })
+    });
+    let Some((signer, _)) = signer else {
+        bail!("{:?} isn't signed by a key the database trusts", path);
+    };
+    if let Some(expires) = &file.policy.expires {
+        let expires = OffsetDateTime::parse(expires, &Rfc3339).context("Invalid expiry")?;
+        if expires <= OffsetDateTime::now_utc() {
+            bail!("{}'s delegation expired at {}", file.policy.team, expires);
+        }
+    }
+    info!(
+        team = %file.policy.team,
+        signer = %signer,
+        "Running as {} (delegated by {})",
+        file.policy.team,
+        signer
+    );
+    record_delegation(&file.policy.team);
+    Ok(Some(file.policy))
+}
+
+// Writes a new signing key to `out`, readable only by its owner, and returns its public half for
+// `trust`. An existing file is never replaced.
+pub(crate) fn generate_key(out: &Path) -> Result<String> {
+    let pkcs8 = Ed25519KeyPair::generate_pkcs8(&SystemRandom::new())
+        .map_err(|_| anyhow!("Unable to generate a key"))?;
+    let mut file = match OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(0o600)
+        .open(out)
+    {
+        Ok(file) => file,
+        Err(e) if e.kind() == ErrorKind::AlreadyExists => bail!("{:?} already exists", out),
+        Err(e) => return Err(e).with_context(|| format!("while creating {:?}", out)),
+    };
+    file.write_all(pkcs8.as_ref())
+        .with_context(|| format!("while writing {:?}", out))?;
+    let pair = Ed25519KeyPair::from_pkcs8(pkcs8.as_ref())
+        .map_err(|e| anyhow!("Generated an invalid key: {}", e))?;
+    Ok(to_hex(pair.public_key().as_ref()))
+}
+
+// Signs the policy in the delegation file at `path` in place
+pub(crate) fn sign(path: &Path, key: &Path) -> Result<()> {
+    let pkcs8 = fs::read(key).with_context(|| format!("while reading {:?}", key))?;
+    let pair =
+        Ed25519KeyPair::from_pkcs8(&pkcs8).map_err(|e| anyhow!("Invalid key {:?}: {}", key, e))?;
+    let mut file: DelegationFile = serde_yaml::from_str(&fs::read_to_string(path)?)
+        .with_context(|| format!("while parsing {:?}", path))?;
+    let message = serde_json::to_vec(&file.policy)?;
+    file.signature = Some(to_hex(pair.sign(&message).as_ref()));
+    fs::write(path, serde_yaml::to_string(&file)?)?;
+    Ok(())
+}
+
+// Anyone who can write to the database can trust their own key this way, so delegations only
+// restrict people who push without that access
+pub(crate) async fn trust(pool: &AnyPool, name: &str, public_key: &str) -> Result<()> {
+    if from_hex(public_key)?.len() != 32 {
+        bail!("Expected a 32 byte Ed25519 public key");
+    }
+    insert_delegation_key(pool, name, public_key)
+        .await
+        .with_context(|| format!("while trusting {}", name))
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn from_hex(hex: &str) -> Result<Vec<u8>> {
+    if hex.len() % 2 != 0 {
+        bail!("Invalid hex {}", hex);
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| {
+            hex.get(i..i + 2)
+                .and_then(|b| u8::from_str_radix(b, 16).ok())
+                .ok_or_else(|| anyhow!("Invalid hex {}", hex))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::kubernetes_io::test_key;
+    use std::os::unix::fs::PermissionsExt;
+
+    #[test]
+    fn test_covers() {
+        let delegation = Delegation {
+            team: "echo".to_string(),
+            clusters: BTreeSet::from(["prod".to_string()]),
+            namespaces: BTreeSet::from(["echo-*".to_string()]),
+            expires: None,
+        };
+        assert!(delegation.covers(&test_key("prod", "v1", "ConfigMap", Some("echo-api"), "a")));
+        assert!(delegation.covers(&test_key("prod", "v1", "Namespace", None, "echo-api")));
+        assert!(!delegation.covers(&test_key("dev", "v1", "ConfigMap", Some("echo-api"), "a")));
+        assert!(!delegation.covers(&test_key("prod", "v1", "ConfigMap", Some("billing"), "a")));
+        assert!(!delegation.covers(&test_key("prod", "v1", "ClusterRole", None, "echo-api")));
+    }
+
+    #[test]
+    fn test_check_and_retain() {
+        let delegation = Delegation {
+            team: "echo".to_string(),
+            clusters: BTreeSet::from(["prod".to_string()]),
+            namespaces: BTreeSet::from(["echo".to_string()]),
+            expires: None,
+        };
+        let ours = test_key("prod", "v1", "ConfigMap", Some("echo"), "a");
+        let theirs = test_key("prod", "v1", "ConfigMap", Some("billing"), "a");
+        assert!(delegation.check(&ours).is_ok());
+        assert!(delegation.check(&theirs).is_err());
+
+        let object: kube::api::DynamicObject = serde_json::from_value(serde_json::json!({
+            "apiVersion": "v1",
+            "kind": "ConfigMap",
+            "metadata": {"name": "a"},
+        }))
+        .unwrap();
+        let mut resources = KubernetesResources {
+            by_key: [(ours.clone(), object.clone()), (theirs, object)].into(),
+            namespaces: Default::default(),
+        };
+        delegation.retain(&mut resources);
+        assert_eq!(resources.by_key.keys().collect::<Vec<_>>(), vec![&ours]);
+    }
+
+    #[test]
+    fn test_signatures() -> Result<()> {
+        let directory = tempfile::TempDir::new()?;
+        let key = directory.path().join("key.pk8");
+        let public_key = generate_key(&key)?;
+        assert_eq!(fs::metadata(&key)?.permissions().mode() & 0o777, 0o600);
+        assert!(generate_key(&key).is_err());
+        let path = directory.path().join("echo.yaml");
+        fs::write(
+            &path,
+            "policy:\n  team: echo\n  clusters: [prod]\n  namespaces: [echo]\n",
+        )?;
+        sign(&path, &key)?;
+
+        let file: DelegationFile = serde_yaml::from_str(&fs::read_to_string(&path)?)?;
+        let message = serde_json::to_vec(&file.policy)?;
+        let signature = from_hex(file.signature.as_deref().unwrap_or_default())?;
+        let verifier = UnparsedPublicKey::new(&ED25519, from_hex(&public_key)?);
+        assert!(verifier.verify(&message, &signature).is_ok());
+
+        // Widening the policy after signing breaks the signature
+        let mut widened = file.policy.clone();
+        widened.namespaces.insert("billing".to_string());
+        let message = serde_json::to_vec(&widened)?;
+        assert!(verifier.verify(&message, &signature).is_err());
+        Ok(())
+    }
+}
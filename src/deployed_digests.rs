@@ -0,0 +1,182 @@
+use anyhow::{Context, Result};
+use k8s_openapi::api::core::v1::Pod;
+use kube::api::{DynamicObject, ListParams};
+use std::collections::BTreeMap;
+
+use crate::kubernetes_io::{get_kubernetes_clients, KubernetesKey, KubernetesResources};
+
+// Checks that the pods of each tracked workload run exactly the image digests its recorded pod
+// template pins, which catches digests that were never published and pods that never rolled.
+// Returns a line for each container that doesn't.
+pub(crate) async fn verify_deployed_digests(recorded: &KubernetesResources) -> Result<Vec<String>> {
+    let workloads: Vec<_> = recorded
+        .by_key
+        .iter()
+        .filter(|(k, _)| {
+            k.api_version == "apps/v1"
+                && ["DaemonSet", "Deployment", "StatefulSet"].contains(&k.kind.as_str())
+        })
+        .collect();
+    let (clients, _) = get_kubernetes_clients(workloads.iter().map(|(k, _)| *k)).await?;
+
+    let mut problems = Vec::new();
+    for (key, workload) in workloads {
+        let pinned = pinned_digests(workload);
+        if pinned.is_empty() {
+            continue;
+        }
+        let Some(client) = clients.get(&key.cluster) else {
+            continue;
+        };
+        let pods: kube::Api<Pod> = kube::Api::namespaced(
+            client.clone(),
+            key.namespace.as_deref().unwrap_or("default"),
+        );
+        let selector = match_labels(workload);
+        if selector.is_empty() {
+            continue;
+        }
+        let listed = pods
+            .list(&ListParams::default().labels(&selector))
+            .await
+            .with_context(|| format!("while listing the pods of {}", key))?;
+        if listed.items.is_empty() {
+            problems.push(format!("{} has no pods", key));
+        }
+        for pod in &listed.items {
+            problems.extend(mismatched_containers(key, &pinned, pod));
+        }
+    }
+    Ok(problems)
+}
+
+// The digest each container of the pod template is pinned to, for images given as name@digest
+fn pinned_digests(workload: &DynamicObject) -> BTreeMap<String, String> {
+    let spec = &workload.data["spec"]["template"]["spec"];
+    let mut pinned = BTreeMap::new();
+    for field in ["initContainers", "containers"] {
+        let Some(containers) = spec[field].as_array() else {
+            continue;
+        };
+        for container in containers {
+            let (Some(name), Some(image)) =
+                (container["name"].as_str(), container["image"].as_str())
+            else {
+                continue;
+            };
+            if let Some((_, digest)) = image.split_once('@') {
+                pinned.insert(name.to_string(), digest.to_string());
+            }
+        }
+    }
+    pinned
+}
+
+fn match_labels(workload: &DynamicObject) -> String {
+    let labels = workload.data["spec"]["selector"]["matchLabels"]
+        .as_object()
+        .cloned()
+        .unwrap_or_default();
+    labels
+        .iter()
+        .filter_map(|(k, v)| v.as_str().map(|v| format!("{}={}", k, v)))
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+fn mismatched_containers(
+    key: &KubernetesKey,
+    pinned: &BTreeMap<String, String>,
+    pod: &Pod,
+) -> Vec<String> {
+    let pod_name = pod.metadata.name.as_deref().unwrap_or("unknown");
+    let status = pod.status.as_ref();
+    let statuses = status
+        .and_then(|s| s.init_container_statuses.as_ref())
+        .into_iter()
+        .chain(status.and_then(|s| s.container_statuses.as_ref()))
+        .flatten();
+    let mut running = BTreeMap::new();
+    for container in statuses {
+        // Runtimes report the digest they pulled as something like docker-pullable://name@digest
+        let digest = container.image_id.split_once('@').map(|(_, d)| d);
+        running.insert(container.name.as_str(), digest);
+    }
+
+    let mut problems = Vec::new();
+    for (container, digest) in pinned {
+        match running.get(container.as_str()) {
+            Some(Some(actual)) if *actual == digest.as_str() => {}
+            Some(Some(actual)) => problems.push(format!(
+                "{}: pod {} runs {} with {} instead of {}",
+                key, pod_name, container, actual, digest
+            )),
+            _ => problems.push(format!(
+                "{}: pod {} hasn't pulled {} yet",
+                key, pod_name, container
+            )),
+        }
+    }
+    problems
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::kubernetes_io::test_key;
+
+    #[test]
+    fn test_mismatched_containers() -> Result<()> {
+        let key = test_key("prod", "apps/v1", "Deployment", Some("echo"), "echo");
+        let workload: DynamicObject = serde_json::from_value(serde_json::json!({
+            "apiVersion": "apps/v1",
+            "kind": "Deployment",
+            "metadata": {"name": "echo"},
+            "spec": {
+                "selector": {"matchLabels": {"app.kubernetes.io/name": "echo"}},
+                "template": {"spec": {"containers": [
+                    {"name": "main", "image": "registry.test/echo@sha256:aaa"},
+                    {"name": "sidecar", "image": "registry.test/proxy:latest"},
+                    {"name": "exporter", "image": "registry.test/exporter@sha256:ccc"},
+                ]}},
+            },
+        }))?;
+        let pinned = pinned_digests(&workload);
+        assert_eq!(
+            pinned,
+            BTreeMap::from([
+                ("exporter".to_string(), "sha256:ccc".to_string()),
+                ("main".to_string(), "sha256:aaa".to_string()),
+            ])
+        );
+        assert_eq!(match_labels(&workload), "app.kubernetes.io/name=echo");
+
+        let pod: Pod = serde_json::from_value(serde_json::json!({
+            "metadata": {"name": "echo-1"},
+            "status": {"containerStatuses": [
+                {
+                    "name": "main",
+                    "image": "registry.test/echo@sha256:aaa",
+                    "imageID": "docker-pullable://registry.test/echo@sha256:aaa",
+                    "ready": true,
+                    "restartCount": 0,
+                },
+                {
+                    "name": "exporter",
+                    "image": "registry.test/exporter@sha256:ccc",
+                    "imageID": "registry.test/exporter@sha256:bbb",
+                    "ready": true,
+                    "restartCount": 0,
+                },
+            ]},
+        }))?;
+        assert_eq!(
+            mismatched_containers(&key, &pinned, &pod),
+            vec![
+                "Deployment echo/echo (prod): pod echo-1 runs exporter with sha256:bbb instead of \
+                 sha256:ccc"
+            ]
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,255 @@
+use anyhow::{anyhow, Result};
+use kube::{
+    api::ApiResource,
+    discovery::{ApiCapabilities, Scope},
+    Discovery,
+};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    sync::OnceLock,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+use tracing::warn;
+
+// Installing a CRD doesn't change the server version, so cached types still go stale eventually
+pub(crate) const DEFAULT_DISCOVERY_TTL: Duration = Duration::from_secs(60 * 60);
+
+static DISCOVERY_TTL: OnceLock<Duration> = OnceLock::new();
+
+// Sets how old a cached discovery may be before it's fetched again. Zero always refetches.
+pub(crate) fn configure_discovery(ttl: Duration) {
+    let _ = DISCOVERY_TTL.set(ttl);
+}
+
+fn discovery_ttl() -> Duration {
+    *DISCOVERY_TTL.get().unwrap_or(&DEFAULT_DISCOVERY_TTL)
+}
+
+#[derive(Debug, Deserialize, PartialEq, Serialize)]
+struct CachedDiscovery {
+    server: String,
+    server_version: String,
+    fetched_at: u64,
+    resources: Vec<CachedResource>,
+}
+
+#[derive(Debug, Deserialize, PartialEq, Serialize)]
+struct CachedResource {
+    group: String,
+    version: String,
+    kind: String,
+    plural: String,
+    namespaced: bool,
+}
+
+impl CachedDiscovery {
+    fn is_fresh(&self, server: &str, server_version: &str, now: u64, ttl: Duration) -> bool {
+        self.server == server
+            && self.server_version == server_version
+            && now.saturating_sub(self.fetched_at) < ttl.as_secs()
+    }
+
+    fn into_resources(self) -> Vec<(ApiResource, ApiCapabilities)> {
+        self.resources
+            .into_iter()
+            .map(|r| {
+                let api_version = if r.group.is_empty() {
+                    r.version.clone()
+                } else {
+                    format!("{}/{}", r.group, r.version)
+                };
+                let ar = ApiResource {
+                    group: r.group,
+                    version: r.version,
+                    api_version,
+                    kind: r.kind,
+                    plural: r.plural,
+                };
+                let caps = ApiCapabilities {
+                    scope: if r.namespaced {
+                        Scope::Namespaced
+                    } else {
+                        Scope::Cluster
+                    },
+                    subresources: Vec::new(),
+                    operations: Vec::new(),
+                };
+                (ar, caps)
+            })
+            .collect()
+    }
+}
+
+// Runs API discovery against `client`, reusing the last result for this cluster if it's recent and
+// the server hasn't been upgraded since.
+pub(crate) async fn discover(
+    cluster: &str,
+    server: &str,
+    client: kube::Client,
+) -> Result<Vec<(ApiResource, ApiCapabilities)>> {
+    let server_version = client.apiserver_version().await?.git_version;
+    let path = cache_home().map(|h| cache_path(&h, cluster));
+    let now = unix_now();
+    if let Some(cached) = path.as_deref().and_then(read_cache) {
+        if cached.is_fresh(server, &server_version, now, discovery_ttl()) {
+            return Ok(cached.into_resources());
+        }
+    }
+
+    let discovery = Discovery::new(client).run().await?;
+    let resources: Vec<_> = discovery
+        .groups()
+        .flat_map(|g| g.recommended_resources())
+        .collect();
+    let cached = CachedDiscovery {
+        server: server.to_string(),
+        server_version,
+        fetched_at: now,
+        resources: resources
+            .iter()
+            .map(|(ar, caps)| CachedResource {
+                group: ar.group.clone(),
+                version: ar.version.clone(),
+                kind: ar.kind.clone(),
+                plural: ar.plural.clone(),
+                namespaced: caps.scope == Scope::Namespaced,
+            })
+            .collect(),
+    };
+    // A cache we can't write only costs the next run some time
+    if let Some(path) = path {
+        if let Err(e) = write_cache(&path, &cached) {
+            warn!(cluster, error = %e, "Unable to cache discovery for {}: {}", cluster, e);
+        }
+    }
+    Ok(resources)
+}
+
+fn read_cache(path: &Path) -> Option<CachedDiscovery> {
+    let content = fs::read(path).ok()?;
+    serde_json::from_slice(&content).ok()
+}
+
+fn write_cache(path: &Path, cached: &CachedDiscovery) -> Result<()> {
+    let parent = path
+        .parent()
+        .ok_or_else(|| anyhow!("No parent directory for {}", path.display()))?;
+    fs::create_dir_all(parent)?;
+    // Concurrent runs may race, so write aside and rename to never leave a partial file
+    let temporary = path.with_extension(format!("json.{}", std::process::id()));
+    fs::write(&temporary, serde_json::to_vec(cached)?)?;
+    fs::rename(&temporary, path)?;
+    Ok(())
+}
+
+pub(crate) fn cache_home() -> Option<PathBuf> {
+    match env_value("XDG_CACHE_HOME") {
+        Some(v) => Some(PathBuf::from(v)),
+        None => Some(PathBuf::from(env_value("HOME")?).join(".cache")),
+    }
+}
+
+fn cache_path(cache_home: &Path, cluster: &str) -> PathBuf {
+    // Context names often have slashes and colons in them, so keep them readable but unambiguous
+    let readable: String = cluster
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '-' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    let digest = format!("{:x}", Sha256::digest(cluster.as_bytes()));
+    cache_home.join("sisyphus").join("discovery").join(format!(
+        "{}-{}.json",
+        readable,
+        &digest[..12]
+    ))
+}
+
+fn env_value(key: &str) -> Option<String> {
+    std::env::var(key).ok().filter(|v| !v.is_empty())
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn cached(fetched_at: u64) -> CachedDiscovery {
+        CachedDiscovery {
+            server: "https://cluster.example".to_string(),
+            server_version: "v1.31.2".to_string(),
+            fetched_at,
+            resources: vec![
+                CachedResource {
+                    group: "".to_string(),
+                    version: "v1".to_string(),
+                    kind: "Namespace".to_string(),
+                    plural: "namespaces".to_string(),
+                    namespaced: false,
+                },
+                CachedResource {
+                    group: "apps".to_string(),
+                    version: "v1".to_string(),
+                    kind: "Deployment".to_string(),
+                    plural: "deployments".to_string(),
+                    namespaced: true,
+                },
+            ],
+        }
+    }
+
+    #[test]
+    fn test_is_fresh() {
+        let c = cached(1_000);
+        let ttl = DEFAULT_DISCOVERY_TTL;
+        assert!(c.is_fresh("https://cluster.example", "v1.31.2", 1_060, ttl));
+        assert!(!c.is_fresh("https://cluster.example", "v1.32.0", 1_060, ttl));
+        assert!(!c.is_fresh("https://other.example", "v1.31.2", 1_060, ttl));
+        assert!(!c.is_fresh(
+            "https://cluster.example",
+            "v1.31.2",
+            1_000 + ttl.as_secs(),
+            ttl
+        ));
+        // --refresh-discovery
+        assert!(!c.is_fresh("https://cluster.example", "v1.31.2", 1_000, Duration::ZERO));
+    }
+
+    #[test]
+    fn test_cache_round_trip() -> Result<()> {
+        let directory = TempDir::new()?;
+        let path = directory.path().join("discovery").join("cluster.json");
+
+        write_cache(&path, &cached(1_000))?;
+
+        let resources = read_cache(&path).unwrap().into_resources();
+        assert_eq!(resources[0].0.api_version, "v1");
+        assert_eq!(resources[0].1.scope, Scope::Cluster);
+        assert_eq!(resources[1].0.api_version, "apps/v1");
+        assert_eq!(resources[1].0.plural, "deployments");
+        assert_eq!(resources[1].1.scope, Scope::Namespaced);
+        Ok(())
+    }
+
+    #[test]
+    fn test_cache_path_is_unambiguous() {
+        let home = Path::new("/cache");
+        let a = cache_path(home, "arn:aws:eks:us-east-1:1234:cluster/prod");
+        let b = cache_path(home, "arn_aws_eks_us-east-1_1234_cluster_prod");
+        assert!(a.starts_with("/cache/sisyphus/discovery"));
+        assert_ne!(a, b);
+    }
+}
@@ -0,0 +1,287 @@
+use anyhow::{bail, Context, Result};
+use clap::ValueEnum;
+use k8s_openapi::api::core::v1::{ConfigMap, Namespace};
+use kube::{
+    api::{Patch, PatchParams},
+    config::KubeConfigOptions,
+    Api,
+};
+use std::{fs, path::Path, process::Stdio};
+use tempfile::TempDir;
+use tokio::{io::AsyncWriteExt, process::Command};
+use tracing::{info, warn};
+
+// Which tool makes the throwaway cluster
+#[derive(Clone, Copy, Debug, Eq, PartialEq, ValueEnum)]
+pub(crate) enum ClusterProvider {
+    Kind,
+    K3d,
+}
+
+const NAMESPACE: &str = "sisyphus-e2e";
+
+// A cluster that only exists for one run, deleted again when this is dropped unless kept
+struct ThrowawayCluster {
+    provider: ClusterProvider,
+    name: String,
+    keep: bool,
+}
+
+impl ThrowawayCluster {
+    async fn create(provider: ClusterProvider, kubeconfig: &Path, keep: bool) -> Result<Self> {
+        let name = format!("sisyphus-e2e-{:08x}", rand::random::<u32>());
+        info!("Creating {:?} cluster {}", provider, name);
+        let kubeconfig = kubeconfig.to_string_lossy();
+        let cluster = ThrowawayCluster {
+            provider,
+            name,
+            keep,
+        };
+        match provider {
+            ClusterProvider::Kind => {
+                run(
+                    "kind",
+                    &[
+                        "create",
+                        "cluster",
+                        "--name",
+                        &cluster.name,
+                        "--kubeconfig",
+                        &kubeconfig,
+                        "--wait",
+                        "120s",
+                    ],
+                )
+                .await?;
+            }
+            ClusterProvider::K3d => {
+                run(
+                    "k3d",
+                    &[
+                        "cluster",
+                        "create",
+                        &cluster.name,
+                        "--wait",
+                        "--kubeconfig-update-default=false",
+                        "--kubeconfig-switch-context=false",
+                    ],
+                )
+                .await?;
+                let config = output("k3d", &["kubeconfig", "get", &cluster.name]).await?;
+                fs::write(kubeconfig.as_ref(), config)?;
+            }
+        }
+        Ok(cluster)
+    }
+
+    // The kubeconfig context, which is also what monitor directories call the cluster
+    fn context(&self) -> String {
+        match self.provider {
+            ClusterProvider::Kind => format!("kind-{}", self.name),
+            ClusterProvider::K3d => format!("k3d-{}", self.name),
+        }
+    }
+}
+
+impl Drop for ThrowawayCluster {
+    fn drop(&mut self) {
+        if self.keep {
+            info!("Keeping cluster {}", self.name);
+            return;
+        }
+        info!("Deleting cluster {}", self.name);
+        let (program, args) = match self.provider {
+            ClusterProvider::Kind => ("kind", vec!["delete", "cluster", "--name", &self.name]),
+            ClusterProvider::K3d => ("k3d", vec!["cluster", "delete", &self.name]),
+        };
+        if let Err(e) = std::process::Command::new(program).args(args).status() {
+            warn!("Unable to delete cluster {}: {}", self.name, e);
+        }
+    }
+}
+
+// Pushes a small monitor directory to a throwaway cluster with this very binary, checks what it
+// applied, and exercises refresh and pruning, so a new Sisyphus release can be tried without
+// touching a real cluster
+pub(crate) async fn run_e2e(provider: ClusterProvider, keep_cluster: bool) -> Result<()> {
+    let workspace = TempDir::new()?;
+    let kubeconfig = workspace.path().join("kubeconfig");
+    let cluster = ThrowawayCluster::create(provider, &kubeconfig, keep_cluster).await?;
+    // Both this process and the pushes it runs only see the throwaway cluster
+    std::env::set_var("KUBECONFIG", &kubeconfig);
+    let context = cluster.context();
+
+    let database = workspace.path().join("sisyphus.db");
+    let database_url = format!("sqlite://{}?mode=rwc", database.display());
+    let monitor = workspace.path().join("monitor");
+    let answers = workspace.path().join("answers.yaml");
+    fs::write(
+        &answers,
+        "verbs:\n  create: y\n  delete: y\n  patch: y\n  recreate: y\n",
+    )?;
+    let sisyphus = Sisyphus {
+        database_url: &database_url,
+        monitor: &monitor,
+        answers: &answers,
+    };
+
+    info!("Migrating {}", database.display());
+    sisyphus.run(&["db", "migrate"], None).await?;
+
+    info!("Pushing");
+    write_monitor_directory(&monitor, &context, true)?;
+    sisyphus.push().await?;
+    let config_maps = config_maps(&context).await?;
+    expect_greeting(&config_maps, "hello").await?;
+    let namespaces: Api<Namespace> = Api::all(client(&context).await?);
+    if namespaces.get_opt(NAMESPACE).await?.is_none() {
+        bail!("push didn't create namespace {}", NAMESPACE);
+    }
+
+    info!("Refreshing after a change outside of Sisyphus");
+    config_maps
+        .patch(
+            "greeting",
+            &PatchParams::default(),
+            &Patch::Merge(serde_json::json!({"data": {"greeting": "drifted"}})),
+        )
+        .await?;
+    sisyphus.run(&["refresh"], Some("y\n")).await?;
+    sisyphus.push().await?;
+    expect_greeting(&config_maps, "hello").await?;
+
+    info!("Pruning");
+    write_monitor_directory(&monitor, &context, false)?;
+    sisyphus.push().await?;
+    if config_maps.get_opt("greeting").await?.is_some() {
+        bail!("push didn't delete ConfigMap {}/greeting", NAMESPACE);
+    }
+
+    info!("Passed");
+    drop(cluster);
+    Ok(())
+}
+
+struct Sisyphus<'a> {
+    database_url: &'a str,
+    monitor: &'a Path,
+    answers: &'a Path,
+}
+
+impl Sisyphus<'_> {
+    async fn push(&self) -> Result<()> {
+        let monitor = self.monitor.to_string_lossy();
+        let answers = self.answers.to_string_lossy();
+        self.run(
+            &[
+                "push",
+                "--monitor-directory",
+                &monitor,
+                "--answers-file",
+                &answers,
+            ],
+            None,
+        )
+        .await
+    }
+
+    // Runs this binary with `args`, feeding it `input` for any prompts
+    async fn run(&self, args: &[&str], input: Option<&str>) -> Result<()> {
+        let mut child = Command::new(std::env::current_exe()?)
+            .args(args)
+            .arg("--database-url")
+            .arg(self.database_url)
+            .env("NO_COLOR", "1")
+            .stdin(Stdio::piped())
+            .spawn()
+            .with_context(|| format!("while running sisyphus {}", args.join(" ")))?;
+        if let (Some(input), Some(mut stdin)) = (input, child.stdin.take()) {
+            stdin.write_all(input.as_bytes()).await?;
+        }
+        if !child.wait().await?.success() {
+            bail!("sisyphus {} failed", args.join(" "));
+        }
+        Ok(())
+    }
+}
+
+// A namespace holding one ConfigMap, or nothing once `with_config_map` is false so that the push
+// prunes it
+fn write_monitor_directory(monitor: &Path, context: &str, with_config_map: bool) -> Result<()> {
+    let directory = monitor.join(NAMESPACE);
+    fs::create_dir_all(&directory)?;
+    let objects = if with_config_map {
+        "objects:\n  - apiVersion: v1\n    kind: ConfigMap\n    metadata:\n      name: greeting\n    \
+         data:\n      greeting: hello\n"
+    } else {
+        "objects: []\n"
+    };
+    fs::write(
+        directory.join("index.yaml"),
+        format!(
+            "apiVersion: sisyphus/v1\nkind: KubernetesYaml\nmetadata:\n  name: e2e\n{}clusters:\n  - \
+             {}\n",
+            objects, context
+        ),
+    )?;
+    Ok(())
+}
+
+async fn client(context: &str) -> Result<kube::Client> {
+    let config = kube::Config::from_kubeconfig(&KubeConfigOptions {
+        context: Some(context.to_string()),
+        ..Default::default()
+    })
+    .await?;
+    Ok(kube::Client::try_from(config)?)
+}
+
+async fn config_maps(context: &str) -> Result<Api<ConfigMap>> {
+    Ok(Api::namespaced(client(context).await?, NAMESPACE))
+}
+
+async fn expect_greeting(config_maps: &Api<ConfigMap>, expected: &str) -> Result<()> {
+    let greeting = config_maps
+        .get_opt("greeting")
+        .await?
+        .and_then(|c| c.data)
+        .and_then(|d| d.get("greeting").cloned());
+    if greeting.as_deref() != Some(expected) {
+        bail!(
+            "Expected ConfigMap {}/greeting to say {} but found {:?}",
+            NAMESPACE,
+            expected,
+            greeting
+        );
+    }
+    Ok(())
+}
+
+async fn run(program: &str, args: &[&str]) -> Result<()> {
+    let status = Command::new(program)
+        .args(args)
+        .status()
+        .await
+        .with_context(|| format!("Failed to execute {}, is it installed?", program))?;
+    if !status.success() {
+        bail!("{} {} failed", program, args.join(" "));
+    }
+    Ok(())
+}
+
+async fn output(program: &str, args: &[&str]) -> Result<Vec<u8>> {
+    let output = Command::new(program)
+        .args(args)
+        .output()
+        .await
+        .with_context(|| format!("Failed to execute {}, is it installed?", program))?;
+    if !output.status.success() {
+        bail!(
+            "{} {} failed: {}",
+            program,
+            args.join(" "),
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(output.stdout)
+}
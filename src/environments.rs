@@ -0,0 +1,196 @@
+use anyhow::{anyhow, bail, Context, Result};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fs::File,
+};
+
+use crate::sisyphus_yaml::SisyphusResource;
+
+// Footprint keys and KubernetesYaml clusters with this prefix name an environment instead of a
+// cluster, e.g. `env:production`.
+const ENVIRONMENT_PREFIX: &str = "env:";
+
+// Maps environment names to the clusters currently in them. Kept outside of app repos (see
+// `--environments`) so clusters can be added or drained without touching every footprint.
+#[derive(Clone, Debug, Default)]
+pub struct Environments {
+    clusters_by_environment: BTreeMap<String, Vec<String>>,
+    // The environments referenced so far, so we can tell the user what they expanded to
+    used: BTreeSet<String>,
+}
+
+impl Environments {
+    pub fn new(clusters_by_environment: BTreeMap<String, Vec<String>>) -> Self {
+        Environments {
+            clusters_by_environment,
+            used: BTreeSet::new(),
+        }
+    }
+
+    pub fn load(path: Option<&str>) -> Result<Self> {
+        let Some(path) = path else {
+            return Ok(Environments::default());
+        };
+        let file = File::open(path).with_context(|| format!("while opening {}", path))?;
+        let clusters_by_environment = serde_yaml::from_reader(file)
+            .with_context(|| format!("while parsing environments from {}", path))?;
+        Ok(Environments::new(clusters_by_environment))
+    }
+
+    // Replaces every `env:` entry with one entry per cluster in the environment. Entries that name
+    // a cluster directly win over an environment that also contains it.
+    pub(crate) fn expand_footprint<T: Clone>(
+        &mut self,
+        footprint: &BTreeMap<String, T>,
+    ) -> Result<BTreeMap<String, T>> {
+        let mut expanded = BTreeMap::new();
+        let mut from_environment: BTreeMap<String, String> = BTreeMap::new();
+        for (key, entry) in footprint {
+            let Some(environment) = key.strip_prefix(ENVIRONMENT_PREFIX) else {
+                continue;
+            };
+            for cluster in self.clusters(environment)? {
+                if footprint.contains_key(&cluster) {
+                    continue;
+                }
+                if let Some(other) = from_environment.insert(cluster.clone(), key.clone()) {
+                    bail!("Cluster {} is in both {} and {}", cluster, other, key);
+                }
+                expanded.insert(cluster, entry.clone());
+            }
+        }
+        for (key, entry) in footprint {
+            if !key.starts_with(ENVIRONMENT_PREFIX) {
+                expanded.insert(key.clone(), entry.clone());
+            }
+        }
+        Ok(expanded)
+    }
+
+    pub(crate) fn expand_clusters(&mut self, clusters: &[String]) -> Result<Vec<String>> {
+        let mut expanded = Vec::new();
+        for cluster in clusters {
+            let names = match cluster.strip_prefix(ENVIRONMENT_PREFIX) {
+                Some(environment) => self.clusters(environment)?,
+                None => vec![cluster.clone()],
+            };
+            for name in names {
+                if !expanded.contains(&name) {
+                    expanded.push(name);
+                }
+            }
+        }
+        Ok(expanded)
+    }
+
+    pub(crate) fn expand_resource(&mut self, resource: &mut SisyphusResource) -> Result<()> {
+        match resource {
+            SisyphusResource::KubernetesYaml(v) => {
+                v.clusters = self.expand_clusters(&v.clusters)?;
+            }
+            SisyphusResource::SisyphusCronJob(v) => {
+                v.footprint = self.expand_footprint(&v.footprint)?;
+            }
+            SisyphusResource::SisyphusDeployment(v) => {
+                v.footprint = self.expand_footprint(&v.footprint)?;
+            }
+            SisyphusResource::SisyphusJob(v) => {
+                v.footprint = self.expand_footprint(&v.footprint)?;
+            }
+            SisyphusResource::Plugin(v) => {
+                v.clusters = self.expand_clusters(&v.clusters)?;
+            }
+            SisyphusResource::SisyphusYaml(_) => {}
+        }
+        Ok(())
+    }
+
+    // Lists what each referenced environment expanded to. A mapping change shows up in the diff as
+    // creates and deletes in the affected clusters, and this says why.
+    pub(crate) fn print_expansions(&self) {
+        if self.used.is_empty() {
+            return;
+        }
+        for environment in &self.used {
+            let clusters = &self.clusters_by_environment[environment];
+            println!("Environment {} is {}", environment, clusters.join(", "));
+        }
+        println!();
+    }
+
+    fn clusters(&mut self, environment: &str) -> Result<Vec<String>> {
+        let clusters = self
+            .clusters_by_environment
+            .get(environment)
+            .ok_or_else(|| anyhow!("Unknown environment {}", environment))?
+            .clone();
+        self.used.insert(environment.to_string());
+        Ok(clusters)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn environments() -> Environments {
+        Environments {
+            clusters_by_environment: BTreeMap::from([
+                (
+                    "production".to_string(),
+                    vec!["us-east".to_string(), "eu-west".to_string()],
+                ),
+                ("staging".to_string(), vec!["staging-1".to_string()]),
+            ]),
+            used: BTreeSet::new(),
+        }
+    }
+
+    #[test]
+    fn test_expand_footprint() -> Result<()> {
+        let mut environments = environments();
+        let footprint = BTreeMap::from([
+            ("env:production".to_string(), 3),
+            ("eu-west".to_string(), 1),
+            ("dev".to_string(), 1),
+        ]);
+
+        let expanded = environments.expand_footprint(&footprint)?;
+
+        assert_eq!(
+            expanded,
+            BTreeMap::from([
+                ("dev".to_string(), 1),
+                ("eu-west".to_string(), 1),
+                ("us-east".to_string(), 3),
+            ])
+        );
+        assert_eq!(
+            environments.used,
+            BTreeSet::from(["production".to_string()])
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_expand_footprint_unknown_environment() {
+        let mut environments = environments();
+        let footprint = BTreeMap::from([("env:qa".to_string(), 1)]);
+
+        assert!(environments.expand_footprint(&footprint).is_err());
+    }
+
+    #[test]
+    fn test_expand_clusters() -> Result<()> {
+        let mut environments = environments();
+
+        let expanded = environments.expand_clusters(&[
+            "staging-1".to_string(),
+            "env:staging".to_string(),
+            "env:production".to_string(),
+        ])?;
+
+        assert_eq!(expanded, vec!["staging-1", "us-east", "eu-west"]);
+        Ok(())
+    }
+}
@@ -0,0 +1,317 @@
+use anyhow::{bail, Context, Result};
+use serde_json::{json, Value as JsonValue};
+use std::{sync::Mutex, time::Duration};
+use time::{format_description::well_known::Rfc3339, OffsetDateTime};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+    net::TcpStream,
+    sync::mpsc::{unbounded_channel, UnboundedSender},
+    task::JoinHandle,
+    time::timeout,
+};
+use tracing::{instrument, warn};
+
+use crate::{kubernetes_io::KubernetesKey, provenance::provenance};
+
+// Where CloudEvents about applied changes and drift are published, so other automation can follow
+// deployments without reading the database
+#[derive(Clone, Debug, Eq, PartialEq)]
+enum EventSink {
+    // Structured-mode CloudEvents POSTed to a URL
+    Http(String),
+    // Core NATS publishes to a subject
+    Nats { address: String, subject: String },
+    // Records POSTed to a Kafka REST proxy's topic URL, since Sisyphus doesn't speak Kafka itself
+    KafkaRest(String),
+}
+
+// How long one event gets to be published, and how long exiting waits for the ones still queued
+const PUBLISH_TIMEOUT: Duration = Duration::from_secs(5);
+const FLUSH_TIMEOUT: Duration = Duration::from_secs(15);
+
+// Events are published in the background, so a slow or unreachable sink never holds up a push that
+// has the database locked
+static QUEUE: Mutex<Option<Queue>> = Mutex::new(None);
+
+struct Queue {
+    sender: UnboundedSender<JsonValue>,
+    worker: JoinHandle<()>,
+}
+
+// Sets where events go from a URL like https://hooks.example.com/sisyphus,
+// nats://nats.example.com:4222/deployments, or kafka+https://rest-proxy.example.com/topics/deploys
+pub(crate) fn configure_events(sink: Option<&str>) -> Result<()> {
+    let Some(sink) = sink else {
+        return Ok(());
+    };
+    let sink = parse_sink(sink)?;
+    let client = reqwest::Client::builder()
+        .timeout(PUBLISH_TIMEOUT)
+        .build()?;
+    let (sender, mut receiver) = unbounded_channel::<JsonValue>();
+    let worker = tokio::spawn(async move {
+        while let Some(event) = receiver.recv().await {
+            publish(&sink, &client, &event, PUBLISH_TIMEOUT).await;
+        }
+    });
+    *QUEUE.lock().unwrap() = Some(Queue { sender, worker });
+    Ok(())
+}
+
+// Waits for the events still queued to be published, for a while
+pub(crate) async fn flush_events() {
+    let Some(Queue { sender, worker }) = QUEUE.lock().unwrap().take() else {
+        return;
+    };
+    drop(sender);
+    if timeout(FLUSH_TIMEOUT, worker).await.is_err() {
+        warn!("Gave up publishing events after {:?}", FLUSH_TIMEOUT);
+    }
+}
+
+fn parse_sink(sink: &str) -> Result<EventSink> {
+    if sink.starts_with("http://") || sink.starts_with("https://") {
+        Ok(EventSink::Http(sink.to_string()))
+    } else if let Some(rest) = sink.strip_prefix("kafka+") {
+        if !rest.starts_with("http://") && !rest.starts_with("https://") {
+            bail!("Expected kafka+http:// or kafka+https:// but got {}", sink);
+        }
+        Ok(EventSink::KafkaRest(rest.to_string()))
+    } else if let Some(rest) = sink.strip_prefix("nats://") {
+        let Some((address, subject)) = rest.split_once('/') else {
+            bail!("Expected nats://host:port/subject but got {}", sink);
+        };
+        let address = if address.contains(':') {
+            address.to_string()
+        } else {
+            format!("{}:4222", address)
+        };
+        if subject.is_empty() {
+            bail!("Expected a subject in {}", sink);
+        }
+        Ok(EventSink::Nats {
+            address,
+            subject: subject.to_string(),
+        })
+    } else {
+        bail!(
+            "Unsupported event sink {}, expected an http(s)://, nats://, or kafka+http(s):// URL",
+            sink
+        )
+    }
+}
+
+// Reports that `verb` (create, delete, patch, or recreate) was applied to `key`
+pub(crate) fn object_applied(key: &KubernetesKey, verb: &str) {
+    let verb = match verb {
+        "create" => "created",
+        "delete" => "deleted",
+        "patch" => "patched",
+        "recreate" => "recreated",
+        other => other,
+    };
+    enqueue(cloud_event(key, verb, OffsetDateTime::now_utc()));
+}
+
+// Reports that refresh found `key` changed outside of Sisyphus
+pub(crate) fn object_drifted(key: &KubernetesKey) {
+    enqueue(cloud_event(key, "drifted", OffsetDateTime::now_utc()));
+}
+
+fn enqueue(event: JsonValue) {
+    if let Some(queue) = QUEUE.lock().unwrap().as_ref() {
+        let _ = queue.sender.send(event);
+    }
+}
+
+fn cloud_event(key: &KubernetesKey, what: &str, now: OffsetDateTime) -> JsonValue {
+    json!({
+        "specversion": "1.0",
+        "id": format!("{:016x}{:016x}", rand::random::<u64>(), rand::random::<u64>()),
+        "source": format!("/sisyphus/clusters/{}", key.cluster),
+        "type": format!("dev.april.sisyphus.object.{}", what),
+        "subject": key.to_string(),
+        "time": now.format(&Rfc3339).unwrap_or_default(),
+        "datacontenttype": "application/json",
+        "data": {
+            "apiVersion": key.api_version,
+            "kind": key.kind,
+            "cluster": key.cluster,
+            "namespace": key.namespace,
+            "name": key.name,
+            "provenance": serde_json::from_str::<JsonValue>(provenance()).unwrap_or_default(),
+        },
+    })
+}
+
+// The change already happened by the time its event is published, so failing to publish only
+// warns rather than failing the push
+#[instrument(skip_all)]
+async fn publish(sink: &EventSink, client: &reqwest::Client, event: &JsonValue, limit: Duration) {
+    let error = match timeout(limit, send(sink, client, event)).await {
+        Ok(Ok(())) => return,
+        Ok(Err(e)) => format!("{:#}", e),
+        Err(_) => format!("timed out after {:?}", limit),
+    };
+    warn!(
+        event = %event["type"],
+        error = %error,
+        "Unable to publish {}: {}",
+        event["type"],
+        error
+    );
+}
+
+async fn send(sink: &EventSink, client: &reqwest::Client, event: &JsonValue) -> Result<()> {
+    match sink {
+        EventSink::Http(url) => {
+            let response = client
+                .post(url)
+                .header("content-type", "application/cloudevents+json")
+                .body(event.to_string())
+                .send()
+                .await?;
+            if !response.status().is_success() {
+                bail!("{} returned {}", url, response.status());
+            }
+        }
+        EventSink::KafkaRest(url) => {
+            let response = client
+                .post(url)
+                .header("content-type", "application/vnd.kafka.json.v2+json")
+                .body(json!({"records": [{"key": event["subject"], "value": event}]}).to_string())
+                .send()
+                .await?;
+            if !response.status().is_success() {
+                bail!("{} returned {}", url, response.status());
+            }
+        }
+        EventSink::Nats { address, subject } => {
+            nats_publish(address, subject, event.to_string().as_bytes())
+                .await
+                .with_context(|| format!("while publishing to {}", address))?;
+        }
+    }
+    Ok(())
+}
+
+// Speaks just enough of the NATS protocol to publish one message: read the server's INFO, send
+// CONNECT and PUB, and wait for the PONG that follows them
+async fn nats_publish(address: &str, subject: &str, payload: &[u8]) -> Result<()> {
+    let stream = TcpStream::connect(address).await?;
+    let (reader, mut writer) = stream.into_split();
+    let mut reader = BufReader::new(reader);
+    let mut line = String::new();
+    reader.read_line(&mut line).await?;
+    if !line.starts_with("INFO") {
+        bail!("Expected INFO from the server but got {}", line.trim());
+    }
+
+    let mut message = format!(
+        "CONNECT {{\"verbose\":false,\"name\":\"sisyphus\"}}\r\nPUB {} {}\r\n",
+        subject,
+        payload.len()
+    )
+    .into_bytes();
+    message.extend_from_slice(payload);
+    message.extend_from_slice(b"\r\nPING\r\n");
+    writer.write_all(&message).await?;
+    loop {
+        line.clear();
+        if reader.read_line(&mut line).await? == 0 {
+            bail!("The server closed the connection");
+        }
+        match line.trim() {
+            "PONG" => return Ok(()),
+            "PING" => writer.write_all(b"PONG\r\n").await?,
+            l if l.starts_with("-ERR") => bail!("The server refused the message: {}", l),
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::kubernetes_io::test_key;
+    use tokio::net::TcpListener;
+
+    #[test]
+    fn test_parse_sink() -> Result<()> {
+        assert_eq!(
+            parse_sink("nats://nats.test/deployments")?,
+            EventSink::Nats {
+                address: "nats.test:4222".to_string(),
+                subject: "deployments".to_string(),
+            }
+        );
+        assert_eq!(
+            parse_sink("kafka+https://proxy.test/topics/deploys")?,
+            EventSink::KafkaRest("https://proxy.test/topics/deploys".to_string())
+        );
+        assert!(parse_sink("kafka://broker.test:9092").is_err());
+        assert!(parse_sink("nats://nats.test").is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_nats_publish() -> Result<()> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let address = listener.local_addr()?.to_string();
+        let server = tokio::spawn(async move {
+            let (stream, _) = listener.accept().await?;
+            let (reader, mut writer) = stream.into_split();
+            writer.write_all(b"INFO {}\r\n").await?;
+            let mut reader = BufReader::new(reader);
+            let mut received = Vec::new();
+            let mut line = String::new();
+            while reader.read_line(&mut line).await? > 0 {
+                if line.trim() == "PING" {
+                    writer.write_all(b"PONG\r\n").await?;
+                    break;
+                }
+                received.push(line.trim().to_string());
+                line.clear();
+            }
+            Ok::<_, anyhow::Error>(received)
+        });
+
+        nats_publish(&address, "deployments", b"{}").await?;
+        assert_eq!(
+            server.await??,
+            vec![
+                "CONNECT {\"verbose\":false,\"name\":\"sisyphus\"}",
+                "PUB deployments 2",
+                "{}",
+            ]
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_publish_gives_up_on_a_silent_sink() -> Result<()> {
+        // Accepts the connection but never says INFO
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let sink = EventSink::Nats {
+            address: listener.local_addr()?.to_string(),
+            subject: "deployments".to_string(),
+        };
+        let event = json!({"type": "dev.april.sisyphus.object.patched"});
+
+        let client = reqwest::Client::new();
+        let published = publish(&sink, &client, &event, Duration::from_millis(100));
+        assert!(timeout(Duration::from_secs(5), published).await.is_ok());
+        drop(listener);
+        Ok(())
+    }
+
+    #[test]
+    fn test_cloud_event() {
+        let key = test_key("prod", "apps/v1", "Deployment", Some("echo"), "echo");
+        let event = cloud_event(&key, "patched", OffsetDateTime::UNIX_EPOCH);
+        assert_eq!(event["type"], "dev.april.sisyphus.object.patched");
+        assert_eq!(event["source"], "/sisyphus/clusters/prod");
+        assert_eq!(event["time"], "1970-01-01T00:00:00Z");
+        assert_eq!(event["data"]["namespace"], "echo");
+    }
+}
@@ -0,0 +1,153 @@
+use anyhow::{bail, Context, Result};
+use kube::api::DynamicObject;
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::{Path, PathBuf},
+};
+use tracing::info;
+
+use crate::{
+    environments::Environments,
+    filter::{object_matches_filter, PartialKey},
+    kubernetes_io::{munge_secrets, KubernetesKey},
+    render_files::render_files,
+    timings::Timings,
+};
+
+// Writes everything the files render to under `out`, one object per file, so the manifests can be
+// reviewed or handed to other tools. Secret values are replaced like `show` does.
+pub(crate) async fn export(
+    filter: &PartialKey,
+    monitor_directory: &[String],
+    environments: &mut Environments,
+    out: &Path,
+) -> Result<()> {
+    // Files from an earlier export would look like objects that still exist
+    if fs::read_dir(out).is_ok_and(|mut entries| entries.next().is_some()) {
+        bail!(
+            "{} isn't empty, remove it first so deleted objects don't linger",
+            out.display()
+        );
+    }
+
+    let rendered = render_files(monitor_directory, environments, &mut Timings::new(false)).await?;
+    let mut by_path: BTreeMap<PathBuf, (KubernetesKey, DynamicObject)> = BTreeMap::new();
+    for (key, object) in rendered.namespaces.into_iter().chain(rendered.by_key) {
+        if !object_matches_filter(&key, &object, filter) {
+            continue;
+        }
+        let path = out.join(export_path(&key));
+        if let Some((other, _)) = by_path.get(&path) {
+            bail!(
+                "{} and {} would both be exported to {}",
+                other,
+                key,
+                path.display()
+            );
+        }
+        by_path.insert(path, (key, object));
+    }
+
+    let count = by_path.len();
+    for (path, (key, mut object)) in by_path {
+        munge_secrets(None, &mut object)?;
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(path, serde_yaml::to_string(&object)?)
+            .with_context(|| format!("while exporting {}", key))?;
+    }
+    info!("Exported {} objects to {}", count, out.display());
+    Ok(())
+}
+
+// Prints everything the files render to as one multi-document YAML stream, each object headed by a
+// comment naming its cluster since the objects themselves don't say
+pub(crate) async fn print_rendered(
+    filter: &PartialKey,
+    monitor_directory: &[String],
+    environments: &mut Environments,
+) -> Result<()> {
+    let rendered = render_files(monitor_directory, environments, &mut Timings::new(false)).await?;
+    for (key, mut object) in rendered.namespaces.into_iter().chain(rendered.by_key) {
+        if !object_matches_filter(&key, &object, filter) {
+            continue;
+        }
+        munge_secrets(None, &mut object)?;
+        print!("{}", yaml_document(&key, &object)?);
+    }
+    Ok(())
+}
+
+fn yaml_document(key: &KubernetesKey, object: &DynamicObject) -> Result<String> {
+    Ok(format!(
+        "---\n# {}\n{}",
+        key,
+        serde_yaml::to_string(object)?
+    ))
+}
+
+// `<cluster>/<namespace>/<kind>-<name>.yaml`, or `<cluster>/<kind>-<name>.yaml` for cluster-scoped
+// objects
+fn export_path(key: &KubernetesKey) -> PathBuf {
+    let mut path = PathBuf::from(path_segment(&key.cluster));
+    if let Some(namespace) = &key.namespace {
+        path.push(path_segment(namespace));
+    }
+    path.push(format!(
+        "{}-{}.yaml",
+        path_segment(&key.kind),
+        path_segment(&key.name)
+    ));
+    path
+}
+
+// Context names often have slashes in them, like EKS ARNs
+fn path_segment(value: &str) -> String {
+    value.replace(['/', '\\'], "_")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::kubernetes_io::test_key;
+
+    #[test]
+    fn test_export_path() {
+        assert_eq!(
+            export_path(&test_key("prod", "v1", "Service", Some("echo"), "echo")),
+            PathBuf::from("prod/echo/Service-echo.yaml")
+        );
+        assert_eq!(
+            export_path(&test_key("prod", "v1", "Namespace", None, "echo")),
+            PathBuf::from("prod/Namespace-echo.yaml")
+        );
+        assert_eq!(
+            export_path(&test_key(
+                "arn:aws:eks:us-east-1:1234:cluster/prod",
+                "v1",
+                "Namespace",
+                None,
+                "echo"
+            )),
+            PathBuf::from("arn:aws:eks:us-east-1:1234:cluster_prod/Namespace-echo.yaml")
+        );
+    }
+
+    #[test]
+    fn test_yaml_document() -> Result<()> {
+        let object: DynamicObject = serde_yaml::from_str(
+            "apiVersion: v1\nkind: ConfigMap\nmetadata:\n  name: echo\n  namespace: echo\n",
+        )?;
+        assert_eq!(
+            yaml_document(
+                &test_key("prod", "v1", "ConfigMap", Some("echo"), "echo"),
+                &object
+            )?,
+            "---\n# ConfigMap echo/echo (prod)\napiVersion: v1\nkind: ConfigMap\nmetadata:\n  \
+             name: echo\n  namespace: echo\n"
+        );
+        Ok(())
+    }
+}
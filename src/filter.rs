@@ -1,52 +1,199 @@
-use crate::kubernetes_io::KubernetesKey;
+use crate::{
+    globs::{has_wildcards, match_name},
+    kubernetes_io::{owner_key, KubernetesKey, ORIGIN_ANNOTATION},
+    kubernetes_rendering::Origin,
+};
+use anyhow::{bail, Context, Result};
 use clap::Args;
-use std::collections::HashSet;
-
-#[derive(Args, Debug)]
+use kube::api::DynamicObject;
+use regex::Regex;
+use serde_json::Value as JsonValue;
+use std::{collections::HashSet, str::FromStr};
+
+// Filters are only ever set by flags or a profile (see with_profile_filters), never by the
+// environment, so that a stray variable can't quietly narrow a push.
+#[derive(Args, Debug, Default)]
 pub(crate) struct PartialKey {
     #[arg(long)]
-    api_version: Option<String>,
+    api_version: Option<FilterValue>,
+
+    // Only objects rendered from the Sisyphus resource (Deployment, CronJob, Job, or KubernetesYaml)
+    // with this name, in every cluster it renders to
+    #[arg(long)]
+    app: Option<FilterValue>,
 
     #[arg(long)]
-    cluster: Option<String>,
+    cluster: Option<FilterValue>,
 
+    // Compare every filter literally, without globs, regexes, or negation
     #[arg(long)]
-    kind: Option<String>,
+    exact: bool,
 
     #[arg(long)]
-    name: Option<String>,
+    kind: Option<FilterValue>,
 
     #[arg(long)]
-    namespace: Option<String>,
+    name: Option<FilterValue>,
+
+    #[arg(long)]
+    namespace: Option<FilterValue>,
 }
 
-pub(crate) fn key_matches_filter(key: &KubernetesKey, filter: &PartialKey) -> bool {
-    if let Some(v) = &filter.api_version {
-        if &key.api_version != v {
-            return false;
-        }
+// One filter's value. A leading `!` negates it, regex syntax like `prod-.*` makes it a regex that
+// has to match the whole value, `*`, `?`, and `[...]` make it a glob like `web-*`, and anything else
+// has to match exactly.
+#[derive(Clone, Debug)]
+pub(crate) struct FilterValue {
+    raw: String,
+    negated: bool,
+    pattern: Pattern,
+}
+
+#[derive(Clone, Debug)]
+enum Pattern {
+    Literal(String),
+    Glob(String),
+    Regex(Regex),
+}
+
+// Characters that Kubernetes names and globs don't use, so seeing one means a regex was meant
+const REGEX_SYNTAX: [&str; 8] = [".*", "^", "$", "+", "(", ")", "|", "{"];
+
+impl FromStr for FilterValue {
+    type Err = anyhow::Error;
+
+    fn from_str(raw: &str) -> Result<Self> {
+        let (negated, rest) = match raw.strip_prefix('!') {
+            Some(rest) => (true, rest),
+            None => (false, raw),
+        };
+        let pattern = if REGEX_SYNTAX.iter().any(|s| rest.contains(s)) {
+            let regex = Regex::new(&format!("^(?:{})$", rest))
+                .with_context(|| format!("while parsing the regex {}", rest))?;
+            Pattern::Regex(regex)
+        } else if has_wildcards(rest) {
+            Pattern::Glob(rest.to_string())
+        } else {
+            Pattern::Literal(rest.to_string())
+        };
+        Ok(FilterValue {
+            raw: raw.to_string(),
+            negated,
+            pattern,
+        })
     }
-    if let Some(v) = &filter.cluster {
-        if &key.cluster != v {
-            return false;
+}
+
+impl FilterValue {
+    fn matches(&self, value: &str, exact: bool) -> bool {
+        if exact {
+            return self.raw == value;
         }
+        let matched = match &self.pattern {
+            Pattern::Literal(literal) => literal == value,
+            Pattern::Glob(glob) => match_name(glob, value),
+            Pattern::Regex(regex) => regex.is_match(value),
+        };
+        matched != self.negated
     }
-    if let Some(v) = &filter.kind {
-        if &key.kind != v {
-            return false;
+
+    // Something missing, like the namespace of a cluster-scoped object, only matches a negation
+    fn matches_optional(&self, value: Option<&str>, exact: bool) -> bool {
+        match value {
+            Some(value) => self.matches(value, exact),
+            None => self.negated && !exact,
         }
     }
-    if let Some(v) = &filter.name {
-        if &key.name != v {
-            return false;
+}
+
+pub(crate) fn key_matches_filter(key: &KubernetesKey, filter: &PartialKey) -> bool {
+    let exact = filter.exact;
+    [
+        (&filter.api_version, Some(key.api_version.as_str())),
+        (&filter.cluster, Some(key.cluster.as_str())),
+        (&filter.kind, Some(key.kind.as_str())),
+        (&filter.name, Some(key.name.as_str())),
+        (&filter.namespace, key.namespace.as_deref()),
+    ]
+    .into_iter()
+    .all(|(pattern, value)| {
+        pattern
+            .as_ref()
+            .map_or(true, |p| p.matches_optional(value, exact))
+    })
+}
+
+impl PartialKey {
+    // Whether matching needs the objects themselves and not just their keys
+    pub(crate) fn needs_objects(&self) -> bool {
+        self.app.is_some()
+    }
+
+    // Reads a filter from JSON with the flags' names, like {"cluster": "prod-*", "app": "echo"}
+    pub(crate) fn from_json(value: &JsonValue) -> Result<Self> {
+        let mut filter = PartialKey::default();
+        let Some(fields) = value.as_object() else {
+            bail!("Expected the filter to be an object but got {}", value);
+        };
+        for (field, value) in fields {
+            if field == "exact" {
+                let Some(exact) = value.as_bool() else {
+                    bail!("Expected exact to be true or false but got {}", value);
+                };
+                filter.exact = exact;
+                continue;
+            }
+            let slot = match field.as_str() {
+                "api_version" => &mut filter.api_version,
+                "app" => &mut filter.app,
+                "cluster" => &mut filter.cluster,
+                "kind" => &mut filter.kind,
+                "name" => &mut filter.name,
+                "namespace" => &mut filter.namespace,
+                _ => bail!("Unknown filter {}", field),
+            };
+            let Some(value) = value.as_str() else {
+                bail!("Expected {} to be a string but got {}", field, value);
+            };
+            *slot = Some(value.parse()?);
         }
+        Ok(filter)
     }
-    if filter.namespace.is_some() {
-        if key.namespace != filter.namespace {
+}
+
+// Like key_matches_filter, but also checks which Sisyphus resource rendered the object
+pub(crate) fn object_matches_filter(
+    key: &KubernetesKey,
+    object: &DynamicObject,
+    filter: &PartialKey,
+) -> bool {
+    if let Some(app) = &filter.app {
+        if !app.matches_optional(rendered_from(object).as_deref(), filter.exact) {
             return false;
         }
     }
-    true
+    key_matches_filter(key, filter)
+}
+
+// The name of the Sisyphus resource recorded in the object's ORIGIN_ANNOTATION
+fn rendered_from(object: &DynamicObject) -> Option<String> {
+    let origin = object.metadata.annotations.as_ref()?.get(ORIGIN_ANNOTATION)?;
+    let origin: Origin = serde_json::from_str(origin).ok()?;
+    let (_, name) = origin.resource.split_once(' ')?;
+    Some(name.to_string())
+}
+
+/// Whether to keep an object when filtering a push. Objects owned by another, like the Service
+/// rendered for a Deployment, follow their owner so that `--kind Deployment` deleting a Deployment
+/// also deletes what was rendered alongside it.
+pub(crate) fn key_retained(
+    key: &KubernetesKey,
+    object: &DynamicObject,
+    filter: &PartialKey,
+    retained_owners: &HashSet<KubernetesKey>,
+) -> bool {
+    object_matches_filter(key, object, filter)
+        || owner_key(key, object).map_or(false, |o| retained_owners.contains(&o))
 }
 
 /// The `(namespace, cluster)` pairs that the given resource keys live in.
@@ -67,13 +214,30 @@ pub(crate) fn namespace_key_retained(
     filter: &PartialKey,
     required: &HashSet<(String, String)>,
 ) -> bool {
-    key_matches_filter(key, filter)
+    // Namespaces aren't rendered from any one app, so --app only keeps the ones it needs
+    (filter.app.is_none() && key_matches_filter(key, filter))
         || required.contains(&(key.name.clone(), key.cluster.clone()))
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::kubernetes_io::test_key;
+
+    fn value(raw: &str) -> FilterValue {
+        raw.parse().unwrap()
+    }
+
+    #[test]
+    fn test_from_json() -> Result<()> {
+        let filter =
+            PartialKey::from_json(&serde_json::json!({"cluster": "prod-*", "kind": "!Job"}))?;
+        let key = test_key("prod-east", "apps/v1", "Deployment", Some("echo"), "echo");
+        assert!(key_matches_filter(&key, &filter));
+        assert!(PartialKey::from_json(&serde_json::json!({"cluster": 1})).is_err());
+        assert!(PartialKey::from_json(&serde_json::json!({"pod": "echo"})).is_err());
+        Ok(())
+    }
 
     // Tests for key_matches_filter
     #[test]
@@ -87,7 +251,9 @@ mod tests {
         };
         let filter = PartialKey {
             api_version: None,
+            app: None,
             cluster: None,
+            exact: false,
             kind: None,
             name: None,
             namespace: None,
@@ -106,8 +272,10 @@ mod tests {
             namespace: Some("default".to_string()),
         };
         let filter = PartialKey {
-            api_version: Some("apps/v1".to_string()),
+            api_version: Some(value("apps/v1")),
+            app: None,
             cluster: None,
+            exact: false,
             kind: None,
             name: None,
             namespace: None,
@@ -127,7 +295,9 @@ mod tests {
         };
         let filter = PartialKey {
             api_version: None,
-            cluster: Some("dev".to_string()),
+            app: None,
+            cluster: Some(value("dev")),
+            exact: false,
             kind: None,
             name: None,
             namespace: None,
@@ -147,8 +317,10 @@ mod tests {
         };
         let filter = PartialKey {
             api_version: None,
+            app: None,
             cluster: None,
-            kind: Some("Deployment".to_string()),
+            exact: false,
+            kind: Some(value("Deployment")),
             name: None,
             namespace: None,
         };
@@ -167,9 +339,11 @@ mod tests {
         };
         let filter = PartialKey {
             api_version: None,
+            app: None,
             cluster: None,
+            exact: false,
             kind: None,
-            name: Some("other-pod".to_string()),
+            name: Some(value("other-pod")),
             namespace: None,
         };
 
@@ -187,10 +361,12 @@ mod tests {
         };
         let filter = PartialKey {
             api_version: None,
+            app: None,
             cluster: None,
+            exact: false,
             kind: None,
             name: None,
-            namespace: Some("production".to_string()),
+            namespace: Some(value("production")),
         };
 
         assert!(!key_matches_filter(&key, &filter));
@@ -206,8 +382,10 @@ mod tests {
             namespace: Some("default".to_string()),
         };
         let filter = PartialKey {
-            api_version: Some("v1".to_string()),
-            cluster: Some("prod".to_string()),
+            api_version: Some(value("v1")),
+            app: None,
+            cluster: Some(value("prod")),
+            exact: false,
             kind: None,
             name: None,
             namespace: None,
@@ -227,7 +405,9 @@ mod tests {
         };
         let filter = PartialKey {
             api_version: None,
+            app: None,
             cluster: None,
+            exact: false,
             kind: None,
             name: None,
             namespace: None,
@@ -259,7 +439,9 @@ mod tests {
     fn empty_filter() -> PartialKey {
         PartialKey {
             api_version: None,
+            app: None,
             cluster: None,
+            exact: false,
             kind: None,
             name: None,
             namespace: None,
@@ -297,7 +479,7 @@ mod tests {
         // `--name` filters out the Namespace object, but it's kept because a
         // resource that survived the filter lives in it.
         let filter = PartialKey {
-            name: Some("my-deployment".to_string()),
+            name: Some(value("my-deployment")),
             ..empty_filter()
         };
         let required = required_namespace_identities(
@@ -314,7 +496,7 @@ mod tests {
     #[test]
     fn test_namespace_dropped_when_unrelated_and_filtered_out() {
         let filter = PartialKey {
-            name: Some("my-deployment".to_string()),
+            name: Some(value("my-deployment")),
             ..empty_filter()
         };
         let required = required_namespace_identities(
@@ -345,7 +527,7 @@ mod tests {
         // The resource is pushed only to cluster-b, so the namespace is kept
         // there but not on cluster-a.
         let filter = PartialKey {
-            name: Some("my-deployment".to_string()),
+            name: Some(value("my-deployment")),
             ..empty_filter()
         };
         let required = required_namespace_identities(
@@ -363,4 +545,132 @@ mod tests {
             &required
         ));
     }
+
+    #[test]
+    fn test_companions_follow_their_owner() {
+        use crate::kubernetes_io::PART_OF_LABEL;
+        use kube::api::ObjectMeta;
+        use std::collections::BTreeMap;
+
+        let filter = PartialKey {
+            kind: Some(value("Deployment")),
+            ..empty_filter()
+        };
+        let owners = HashSet::from([resource_key("echo", "cluster-a", "echo")]);
+        let service_key = KubernetesKey {
+            api_version: "v1".to_string(),
+            kind: "Service".to_string(),
+            ..resource_key("echo", "cluster-a", "echo")
+        };
+        let service = |owner: &str| DynamicObject {
+            types: None,
+            metadata: ObjectMeta {
+                labels: Some(BTreeMap::from([(PART_OF_LABEL.to_string(), owner.to_string())])),
+                ..Default::default()
+            },
+            data: serde_json::json!({}),
+        };
+
+        assert!(key_retained(&service_key, &service("echo"), &filter, &owners));
+        assert!(!key_retained(&service_key, &service("other"), &filter, &owners));
+        let unlabeled = DynamicObject {
+            metadata: ObjectMeta::default(),
+            ..service("echo")
+        };
+        assert!(!key_retained(&service_key, &unlabeled, &filter, &owners));
+    }
+
+    #[test]
+    fn test_patterns() {
+        let key = resource_key("web-frontend", "prod-east", "echo");
+        let matches = |filter: PartialKey| key_matches_filter(&key, &filter);
+
+        assert!(matches(PartialKey {
+            name: Some(value("web-*")),
+            ..empty_filter()
+        }));
+        assert!(!matches(PartialKey {
+            name: Some(value("api-*")),
+            ..empty_filter()
+        }));
+        assert!(matches(PartialKey {
+            cluster: Some(value("prod-.*")),
+            ..empty_filter()
+        }));
+        // Regexes have to match the whole value
+        assert!(!matches(PartialKey {
+            cluster: Some(value("prod")),
+            ..empty_filter()
+        }));
+        assert!(!matches(PartialKey {
+            cluster: Some(value("(dev|staging)-.*")),
+            ..empty_filter()
+        }));
+        assert!(matches(PartialKey {
+            namespace: Some(value("!kube-system")),
+            ..empty_filter()
+        }));
+        assert!(!matches(PartialKey {
+            namespace: Some(value("!echo")),
+            ..empty_filter()
+        }));
+        assert!(!matches(PartialKey {
+            name: Some(value("web-*")),
+            exact: true,
+            ..empty_filter()
+        }));
+        assert!(key_matches_filter(
+            &namespace_key("kube-system", "prod-east"),
+            &PartialKey {
+                namespace: Some(value("!kube-system")),
+                ..empty_filter()
+            }
+        ));
+        assert!("(unclosed".parse::<FilterValue>().is_err());
+    }
+
+    #[test]
+    fn test_app_matches_the_rendering_resource() {
+        use kube::api::ObjectMeta;
+        use std::collections::BTreeMap;
+
+        let filter = PartialKey {
+            app: Some(value("echo")),
+            ..empty_filter()
+        };
+        let key = resource_key("echo-frontend", "cluster-a", "echo");
+        let rendered_from = |origin: &str| DynamicObject {
+            types: None,
+            metadata: ObjectMeta {
+                annotations: Some(BTreeMap::from([(
+                    ORIGIN_ANNOTATION.to_string(),
+                    origin.to_string(),
+                )])),
+                ..Default::default()
+            },
+            data: serde_json::json!({}),
+        };
+
+        assert!(object_matches_filter(
+            &key,
+            &rendered_from(r#"{"resource":"Deployment echo"}"#),
+            &filter
+        ));
+        assert!(object_matches_filter(
+            &key,
+            &rendered_from(r#"{"resource":"KubernetesYaml echo"}"#),
+            &filter
+        ));
+        assert!(!object_matches_filter(
+            &key,
+            &rendered_from(r#"{"resource":"Deployment other"}"#),
+            &filter
+        ));
+        assert!(!object_matches_filter(&key, &rendered_from("{}"), &filter));
+        assert!(!namespace_key_retained(
+            &namespace_key("echo", "cluster-a"),
+            &filter,
+            &HashSet::new()
+        ));
+    }
 }
@@ -1,14 +1,29 @@
-use crate::kubernetes_io::{KubernetesKey, KubernetesResources};
-use anyhow::{anyhow, bail, Result};
-use console::{style, Style};
+use crate::{
+    kubernetes_io::{
+        is_protected, is_secret, wave, KubernetesKey, KubernetesResources, ORIGIN_ANNOTATION,
+        PROTECT_ANNOTATION,
+    },
+    kubernetes_rendering::{without_origin, Origin},
+    output::{theme, Theme},
+};
+use anyhow::{anyhow, bail, Context, Result};
+use console::Style;
 use kube::api::{DynamicObject, TypeMeta};
+use serde::{Deserialize, Serialize};
+use serde_json::Value as JsonValue;
 use similar::{ChangeTag, TextDiff};
-use std::collections::HashSet;
+use std::{
+    cmp::Reverse,
+    collections::{BTreeMap, HashSet},
+    fs,
+    path::Path,
+    sync::OnceLock,
+};
 
 #[cfg(test)]
 mod tests;
 
-pub(crate) enum DiffAction {
+pub enum DiffAction {
     Delete,
     Create(DynamicObject),
     Recreate(DynamicObject),
@@ -18,60 +33,285 @@ pub(crate) enum DiffAction {
     },
 }
 
+impl DiffAction {
+    pub fn verb(&self) -> &'static str {
+        match self {
+            DiffAction::Create(_) => "create",
+            DiffAction::Delete => "delete",
+            DiffAction::Patch { .. } => "patch",
+            DiffAction::Recreate(_) => "delete and recreate",
+        }
+    }
+}
+
+// A change and its rendered diff, kept so that `push` can summarize first and show diffs on
+// request.
+pub struct PlannedChange {
+    pub key: KubernetesKey,
+    pub action: DiffAction,
+    pub rendering: String,
+    // Whether the object as recorded or as rendered has PROTECT_ANNOTATION
+    pub protected: bool,
+    // For Secrets, which keys the change adds, removes, or changes
+    pub secret_keys: Option<SecretKeyChanges>,
+}
+
 pub(crate) fn generate_diff(
-    mut have: KubernetesResources,
+    have: KubernetesResources,
     want: KubernetesResources,
 ) -> Result<Vec<(KubernetesKey, DiffAction)>> {
+    let plan = generate_plan(have, want)?;
+    for change in &plan {
+        print!("{}", change.rendering);
+    }
+    Ok(plan.into_iter().map(|c| (c.key, c.action)).collect())
+}
+
+pub(crate) fn generate_plan(
+    mut have: KubernetesResources,
+    want: KubernetesResources,
+) -> Result<Vec<PlannedChange>> {
     let mut changed = Vec::new();
     let mut after = HashSet::new();
     for (key, w) in want.namespaces {
         let h = have.namespaces.remove(&key);
-        if h.as_ref() == Some(&w) {
+        if unchanged(h.as_ref(), &w) {
             continue;
         }
-        changed.push((key.clone(), generate_single_diff(&key, h, Some(w))?));
+        changed.push(generate_single_diff(key.clone(), h, Some(w))?);
         after.insert(key);
     }
 
+    // Namespaces stay first and last regardless of waves since everything else lives in them
+    let mut applied = Vec::new();
     for (key, w) in want.by_key {
         let h = have.by_key.remove(&key);
-        if h.as_ref() == Some(&w) {
+        if unchanged(h.as_ref(), &w) {
             continue;
         }
-        changed.push((key.clone(), generate_single_diff(&key, h, Some(w))?));
+        applied.push((wave(&w)?, generate_single_diff(key.clone(), h, Some(w))?));
         after.insert(key);
     }
+    applied.sort_by_key(|(wave, _)| *wave);
+    changed.extend(applied.into_iter().map(|(_, c)| c));
 
+    let mut deleted = Vec::new();
     for (key, h) in have.by_key {
         if !after.contains(&key) {
-            changed.push((key.clone(), generate_single_diff(&key, Some(h), None)?));
+            deleted.push((wave(&h)?, generate_single_diff(key, Some(h), None)?));
         }
     }
+    deleted.sort_by_key(|(wave, _)| Reverse(*wave));
+    changed.extend(deleted.into_iter().map(|(_, c)| c));
 
     for (key, h) in have.namespaces {
         if !after.contains(&key) {
-            changed.push((key.clone(), generate_single_diff(&key, Some(h), None)?));
+            changed.push(generate_single_diff(key, Some(h), None)?);
         }
     }
 
     Ok(changed)
 }
 
-fn generate_single_diff<'a>(
-    key: &KubernetesKey,
+// The order changes are listed in a plan summary: by cluster, then namespace, with
+// cluster-scoped objects first.
+pub(crate) fn plan_order(plan: &[PlannedChange]) -> Vec<usize> {
+    let mut order: Vec<_> = (0..plan.len()).collect();
+    order.sort_by_key(|&i| {
+        let key = &plan[i].key;
+        (&key.cluster, &key.namespace, &key.kind, &key.name)
+    });
+    order
+}
+
+fn plan_counts(plan: &[PlannedChange]) -> Vec<(&'static str, usize)> {
+    ["create", "patch", "delete and recreate", "delete"]
+        .into_iter()
+        .map(|verb| {
+            (
+                verb,
+                plan.iter().filter(|c| c.action.verb() == verb).count(),
+            )
+        })
+        .collect()
+}
+
+// What applying a change to a workload does to its pods
+#[derive(Debug, Eq, PartialEq)]
+pub(crate) struct PodImpact {
+    restarts: bool,
+    // None for DaemonSets, which run a pod on every matching node
+    replicas: Option<u64>,
+}
+
+// Workloads roll their pods when anything under the pod template changes, and recreating one
+// replaces every pod at once. None for changes that can't restart pods.
+pub(crate) fn pod_impact(key: &KubernetesKey, action: &DiffAction) -> Option<PodImpact> {
+    if !key.api_version.starts_with("apps/")
+        || !["DaemonSet", "Deployment", "ReplicaSet", "StatefulSet"].contains(&key.kind.as_str())
+    {
+        return None;
+    }
+    let (after, restarts) = match action {
+        DiffAction::Patch { after, patch } => (
+            after,
+            patch
+                .0
+                .iter()
+                .any(|o| o.path().to_string().starts_with("/spec/template")),
+        ),
+        DiffAction::Recreate(after) => (after, true),
+        DiffAction::Create(_) | DiffAction::Delete => return None,
+    };
+    let replicas = if key.kind == "DaemonSet" {
+        None
+    } else {
+        // Kubernetes defaults to a single replica
+        Some(after.data["spec"]["replicas"].as_u64().unwrap_or(1))
+    };
+    Some(PodImpact { restarts, replicas })
+}
+
+// A line like "Restarts pods: yes, 5 replicas across 2 clusters", or None if the plan doesn't
+// change any workloads
+pub(crate) fn pod_impact_summary(plan: &[PlannedChange]) -> Option<String> {
+    let impacts: Vec<_> = plan
+        .iter()
+        .filter_map(|c| pod_impact(&c.key, &c.action).map(|i| (&c.key.cluster, i)))
+        .collect();
+    if impacts.is_empty() {
+        return None;
+    }
+    let restarting: Vec<_> = impacts.iter().filter(|(_, i)| i.restarts).collect();
+    if restarting.is_empty() {
+        return Some("Restarts pods: no".to_string());
+    }
+    let replicas: u64 = restarting.iter().filter_map(|(_, i)| i.replicas).sum();
+    let daemon_sets = restarting
+        .iter()
+        .filter(|(_, i)| i.replicas.is_none())
+        .count();
+    let clusters = restarting
+        .iter()
+        .map(|(cluster, _)| cluster)
+        .collect::<HashSet<_>>()
+        .len();
+    let mut pods = vec![];
+    if daemon_sets < restarting.len() {
+        pods.push(plural(replicas, "replica"));
+    }
+    if daemon_sets > 0 {
+        pods.push(plural(daemon_sets as u64, "DaemonSet"));
+    }
+    Some(format!(
+        "Restarts pods: yes, {} across {}",
+        pods.join(" and "),
+        plural(clusters as u64, "cluster")
+    ))
+}
+
+fn plural(count: u64, noun: &str) -> String {
+    if count == 1 {
+        format!("{} {}", count, noun)
+    } else {
+        format!("{} {}s", count, noun)
+    }
+}
+
+// Prints one numbered line per change, grouped by cluster and namespace. The numbers index into
+// `order`.
+pub(crate) fn print_plan_summary(plan: &[PlannedChange], order: &[usize]) {
+    let counts: Vec<_> = plan_counts(plan)
+        .into_iter()
+        .map(|(verb, count)| format!("{} to {}", count, verb))
+        .collect();
+    println!("Plan: {}", counts.join(", "));
+    if let Some(summary) = pod_impact_summary(plan) {
+        println!("{}", summary);
+    }
+    println!("");
+
+    let theme = theme();
+    let mut group = None;
+    for (number, &i) in order.iter().enumerate() {
+        let change = &plan[i];
+        let key = &change.key;
+        if group != Some((&key.cluster, &key.namespace)) {
+            match &key.namespace {
+                Some(namespace) => println!("{} / {}", key.cluster, namespace),
+                None => println!("{} (cluster-scoped)", key.cluster),
+            }
+            group = Some((&key.cluster, &key.namespace));
+        }
+        let restarts = match pod_impact(key, &change.action) {
+            Some(PodImpact { restarts: true, .. }) => " (restarts pods)",
+            _ => "",
+        };
+        println!(
+            "  {:>3}. {} {} {}{}",
+            number + 1,
+            verb_style(theme, &change.action).apply_to(change.action.verb()),
+            key.kind,
+            key.name,
+            restarts
+        );
+    }
+    println!("");
+}
+
+fn verb_style(theme: &Theme, action: &DiffAction) -> Style {
+    match action {
+        DiffAction::Create(_) => theme.create.clone(),
+        DiffAction::Delete => theme.delete.clone(),
+        DiffAction::Patch { .. } => theme.patch.clone(),
+        DiffAction::Recreate(_) => theme.delete.clone(),
+    }
+}
+
+// Moving an object within the files changes only its origin, which isn't worth a patch. The origin
+// catches up the next time something else about the object changes.
+fn unchanged(have: Option<&DynamicObject>, want: &DynamicObject) -> bool {
+    have.is_some_and(|h| h == want || without_origin(h) == without_origin(want))
+}
+
+// Where the files define the object, like `echo/index.yaml`, as recorded when it was rendered
+fn defined_at(object: &DynamicObject) -> Option<String> {
+    let origin = object
+        .metadata
+        .annotations
+        .as_ref()?
+        .get(ORIGIN_ANNOTATION)?;
+    serde_json::from_str::<Origin>(origin).ok()?.location()
+}
+
+fn generate_single_diff(
+    key: KubernetesKey,
     have: Option<DynamicObject>,
     want: Option<DynamicObject>,
-) -> Result<DiffAction> {
+) -> Result<PlannedChange> {
+    // Secret values are placeholders or hashes, so a line diff of them is mostly noise. Diff the
+    // rest of the object and summarize which keys changed instead.
+    let secret_keys = if have.iter().chain(want.iter()).any(is_secret) {
+        Some(SecretKeyChanges::new(have.as_ref(), want.as_ref()))
+    } else {
+        None
+    };
     let hs = if let Some(h) = &have {
-        serde_yaml::to_string(&h)?
+        to_diffable_yaml(h, secret_keys.is_some())?
     } else {
         "".to_string()
     };
     let ws = if let Some(w) = &want {
-        serde_yaml::to_string(&w)?
+        to_diffable_yaml(w, secret_keys.is_some())?
     } else {
         "".to_string()
     };
+    let protected = have.iter().chain(want.iter()).any(is_protected);
+    let location = match (&have, &want) {
+        (_, Some(w)) => defined_at(w).map(|l| format!("Defined in {}", l)),
+        (Some(h), None) => defined_at(h).map(|l| format!("Was defined in {}", l)),
+        (None, None) => None,
+    };
     let action = match (have, want) {
         (Some(h), Some(mut w)) => {
             let patch = json_patch::diff(&serde_json::to_value(&h)?, &serde_json::to_value(&w)?);
@@ -89,94 +329,256 @@ fn generate_single_diff<'a>(
         (None, None) => bail!("Expected a difference"),
     };
 
-    let verb = match &action {
-        DiffAction::Create(_) => style("create").green(),
-        DiffAction::Delete => style("delete").red(),
-        DiffAction::Patch { .. } => style("patch").yellow(),
-        DiffAction::Recreate(_) => style("delete and recreate").red(),
-    };
+    let verb = verb_style(theme(), &action).apply_to(action.verb());
+    let mut rendering = format!("• {} {}\n\n", verb, key);
+    if let Some(location) = location {
+        rendering.push_str(&format!("{}\n\n", location));
+    }
+    if let Some(impact) = pod_impact(&key, &action) {
+        let replicas = match impact.replicas {
+            Some(n) => plural(n, "replica"),
+            None => "every node".to_string(),
+        };
+        rendering.push_str(&match impact.restarts {
+            true => format!("Restarts pods: yes, {}\n\n", replicas),
+            false => "Restarts pods: no\n\n".to_string(),
+        });
+    }
+    if let Some(changes) = secret_keys.as_ref().filter(|c| !c.is_empty()) {
+        rendering.push_str(&format!("{}\n\n", changes));
+    }
+    rendering.push_str(&format_diff(&TextDiff::from_lines(&hs, &ws)));
+    rendering.push('\n');
+    Ok(PlannedChange {
+        key,
+        action,
+        rendering,
+        protected,
+        secret_keys,
+    })
+}
 
-    let diff = TextDiff::from_lines(&hs, &ws);
-    println!("• {} {}\n", verb, key);
-    print_diff(&diff);
-    println!("");
-    Ok(action)
+// Fails if the plan deletes or recreates a protected object, since either loses what's in it
+pub(crate) fn check_protected<'a>(plan: impl IntoIterator<Item = &'a PlannedChange>) -> Result<()> {
+    let violations: Vec<_> = plan
+        .into_iter()
+        .filter(|c| c.protected && matches!(c.action, DiffAction::Delete | DiffAction::Recreate(_)))
+        .map(|c| format!("{} {}", c.action.verb(), c.key))
+        .collect();
+    if violations.is_empty() {
+        return Ok(());
+    }
+    bail!(
+        "Refusing to delete or recreate objects annotated {} (pass --allow-protected to anyway):\
+         \n  • {}",
+        PROTECT_ANNOTATION,
+        violations.join("\n  • ")
+    )
 }
 
-fn requires_recreate(types: &TypeMeta, patch: &json_patch::Patch) -> bool {
-    match (types.api_version.as_str(), types.kind.as_str()) {
-        ("apps/v1", "Deployment") => {
-            for modification in &patch.0 {
-                match modification {
-                    json_patch::PatchOperation::Add(o) => {
-                        let path = o.path.to_string();
-                        if path.starts_with("/spec/selector/") {
-                            return true;
-                        }
-                    }
-                    json_patch::PatchOperation::Remove(o) => {
-                        let path = o.path.to_string();
-                        if path.starts_with("/spec/selector/") {
-                            return true;
-                        }
-                    }
-                    json_patch::PatchOperation::Replace(o) => {
-                        let path = o.path.to_string();
-                        if path.starts_with("/spec/selector/") {
-                            return true;
-                        }
-                    }
-                    _ => {}
-                }
+fn to_diffable_yaml(object: &DynamicObject, without_secret_values: bool) -> Result<String> {
+    if !without_secret_values {
+        return Ok(serde_yaml::to_string(object)?);
+    }
+    let mut copy = object.clone();
+    if let Some(data) = copy.data.as_object_mut() {
+        data.remove("data");
+        data.remove("stringData");
+    }
+    Ok(serde_yaml::to_string(&copy)?)
+}
+
+// Which keys of a Secret were added, removed, or given a new value, counting `data` and
+// `stringData` together.
+#[derive(Clone, Debug, Default, Eq, PartialEq, Serialize)]
+pub struct SecretKeyChanges {
+    pub added: Vec<String>,
+    pub removed: Vec<String>,
+    pub changed: Vec<String>,
+}
+
+impl SecretKeyChanges {
+    pub(crate) fn new(have: Option<&DynamicObject>, want: Option<&DynamicObject>) -> Self {
+        let h = secret_values(have);
+        let w = secret_values(want);
+        let mut changes = SecretKeyChanges::default();
+        for (k, v) in &w {
+            match h.get(k) {
+                None => changes.added.push(k.to_string()),
+                Some(hv) if hv != v => changes.changed.push(k.to_string()),
+                Some(_) => {}
             }
         }
-        ("batch/v1", "Job") => {
-            let prefixes: Vec<&str> = vec![
-                "/spec/selector",
-                "/spec/template",
-            ];
-            for modification in &patch.0 {
-                match modification {
-                    json_patch::PatchOperation::Add(o) => {
-                        let path = o.path.to_string();
-                        for prefix in &prefixes {
-                            if path.starts_with(prefix) {
-                                return true;
-                            }
-                        }
-                    }
-                    json_patch::PatchOperation::Remove(o) => {
-                        let path = o.path.to_string();
-                        for prefix in &prefixes {
-                            if path.starts_with(prefix) {
-                                return true;
-                            }
-                        }
-                    }
-                    json_patch::PatchOperation::Replace(o) => {
-                        let path = o.path.to_string();
-                        for prefix in &prefixes {
-                            if path.starts_with(prefix) {
-                                return true;
-                            }
-                        }
-                    }
-                    _ => {}
-                }
+        for k in h.keys() {
+            if !w.contains_key(k) {
+                changes.removed.push(k.to_string());
             }
         }
-        _ => {},
+        changes
+    }
+
+    fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.changed.is_empty()
+    }
+}
+
+impl std::fmt::Display for SecretKeyChanges {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let parts: Vec<_> = [
+            ("added", &self.added),
+            ("removed", &self.removed),
+            ("changed", &self.changed),
+        ]
+        .into_iter()
+        .filter(|(_, keys)| !keys.is_empty())
+        .map(|(verb, keys)| format!("{} {}", verb, keys.join(", ")))
+        .collect();
+        write!(f, "Secret keys {}", parts.join("; "))
+    }
+}
+
+fn secret_values(object: Option<&DynamicObject>) -> BTreeMap<&str, &JsonValue> {
+    let mut values = BTreeMap::new();
+    let Some(object) = object else {
+        return values;
     };
-    false
+    for field in ["data", "stringData"] {
+        if let Some(map) = object.data.get(field).and_then(|v| v.as_object()) {
+            for (k, v) in map {
+                values.insert(k.as_str(), v);
+            }
+        }
+    }
+    values
+}
+
+// Fields that the API server won't change in place, as JSON pointers. Changing anything at or under
+// one of them deletes and recreates the object instead. `*` matches any one array index or key.
+const RECREATE_RULES: [(&str, &str, &[&str]); 6] = [
+    ("apps/v1", "DaemonSet", &["/spec/selector"]),
+    ("apps/v1", "Deployment", &["/spec/selector"]),
+    (
+        "apps/v1",
+        "StatefulSet",
+        &[
+            "/spec/podManagementPolicy",
+            "/spec/selector",
+            "/spec/serviceName",
+            "/spec/volumeClaimTemplates",
+        ],
+    ),
+    ("batch/v1", "Job", &["/spec/selector", "/spec/template"]),
+    (
+        "v1",
+        "PersistentVolumeClaim",
+        &[
+            "/spec/accessModes",
+            "/spec/dataSource",
+            "/spec/dataSourceRef",
+            "/spec/selector",
+            "/spec/storageClassName",
+            "/spec/volumeMode",
+            "/spec/volumeName",
+        ],
+    ),
+    (
+        "v1",
+        "Service",
+        &["/spec/clusterIP", "/spec/clusterIPs", "/spec/type"],
+    ),
+];
+
+// More fields that force a recreate, read from --recreate-rules-file, for kinds like CRDs that
+// Sisyphus can't know about
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq)]
+#[serde(deny_unknown_fields, rename_all = "camelCase")]
+struct RecreateRule {
+    api_version: String,
+    kind: String,
+    paths: Vec<String>,
+}
+
+static RECREATE_RULES_FILE: OnceLock<Vec<RecreateRule>> = OnceLock::new();
+
+pub(crate) fn configure_recreate_rules(file: Option<&Path>) -> Result<()> {
+    let Some(file) = file else {
+        return Ok(());
+    };
+    let content =
+        fs::read_to_string(file).with_context(|| format!("while reading {}", file.display()))?;
+    let rules = parse_recreate_rules(&content)
+        .with_context(|| format!("while parsing {}", file.display()))?;
+    let _ = RECREATE_RULES_FILE.set(rules);
+    Ok(())
+}
+
+fn parse_recreate_rules(content: &str) -> Result<Vec<RecreateRule>> {
+    let rules: Vec<RecreateRule> = serde_yaml::from_str(content)?;
+    for path in rules.iter().flat_map(|r| &r.paths) {
+        if !path.starts_with('/') {
+            bail!("{} isn't a JSON pointer", path);
+        }
+    }
+    Ok(rules)
+}
+
+fn requires_recreate(types: &TypeMeta, patch: &json_patch::Patch) -> bool {
+    let configured = RECREATE_RULES_FILE.get().map_or(&[][..], |r| r.as_slice());
+    requires_recreate_with(types, patch, configured)
+}
+
+fn requires_recreate_with(
+    types: &TypeMeta,
+    patch: &json_patch::Patch,
+    configured: &[RecreateRule],
+) -> bool {
+    let built_in = RECREATE_RULES
+        .iter()
+        .filter(|(api_version, kind, _)| *api_version == types.api_version && *kind == types.kind)
+        .flat_map(|(_, _, paths)| paths.iter().copied());
+    let configured = configured
+        .iter()
+        .filter(|r| r.api_version == types.api_version && r.kind == types.kind)
+        .flat_map(|r| r.paths.iter().map(|p| p.as_str()));
+    let immutable: Vec<&str> = built_in.chain(configured).collect();
+    if immutable.is_empty() {
+        return false;
+    }
+    patch.0.iter().any(|modification| {
+        let path = match modification {
+            json_patch::PatchOperation::Add(o) => o.path.to_string(),
+            json_patch::PatchOperation::Remove(o) => o.path.to_string(),
+            json_patch::PatchOperation::Replace(o) => o.path.to_string(),
+            _ => return false,
+        };
+        immutable.iter().any(|rule| path_is_under(&path, rule))
+    })
+}
+
+// Whether `path` is `rule` or something inside of it
+fn path_is_under(path: &str, rule: &str) -> bool {
+    let mut path = path.split('/');
+    rule.split('/')
+        .all(|r| path.next().is_some_and(|p| r == "*" || r == p))
 }
 
 pub(crate) fn print_diff<'a>(diff: &TextDiff<'a, 'a, 'a, str>) -> () {
+    print!("{}", format_diff(diff));
+}
+
+fn format_diff<'a>(diff: &TextDiff<'a, 'a, 'a, str>) -> String {
+    let mut formatted = String::new();
     for change in diff.iter_all_changes() {
         let (sign, style) = match change.tag() {
-            ChangeTag::Delete => ("-", Style::new().red()),
-            ChangeTag::Insert => ("+", Style::new().green()),
+            ChangeTag::Delete => ("-", theme().deleted_line.clone()),
+            ChangeTag::Insert => ("+", theme().inserted_line.clone()),
             ChangeTag::Equal => (" ", Style::new()),
         };
-        print!("{}{}", style.apply_to(sign).bold(), style.apply_to(change));
+        formatted.push_str(&format!(
+            "{}{}",
+            style.apply_to(sign).bold(),
+            style.apply_to(change)
+        ));
     }
+    formatted
 }
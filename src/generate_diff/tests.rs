@@ -1,9 +1,10 @@
 use std::collections::BTreeMap;
 
 use kube::api::{ObjectMeta, TypeMeta};
-use serde_json::json;
+use serde_json::{json, Value as JsonValue};
 
 use super::*;
+use crate::kubernetes_io::test_key;
 
 #[test]
 fn test_generate_diff_no_changes() -> Result<()> {
@@ -589,3 +590,454 @@ fn test_job_non_template_change_triggers_patch() -> Result<()> {
 
     Ok(())
 }
+
+#[test]
+fn test_secret_key_changes() {
+    let secret = |data: JsonValue| DynamicObject {
+        types: Some(TypeMeta {
+            api_version: "v1".to_string(),
+            kind: "Secret".to_string(),
+        }),
+        metadata: ObjectMeta::default(),
+        data,
+    };
+    let have = secret(json!({"data": {"kept": "YQ==", "rotated": "Yg==", "old": "Yw=="}}));
+    let want = secret(json!({
+        "data": {"kept": "YQ==", "rotated": "ZA=="},
+        "stringData": {"new": "e"},
+    }));
+
+    let changes = SecretKeyChanges::new(Some(&have), Some(&want));
+
+    assert_eq!(
+        changes,
+        SecretKeyChanges {
+            added: vec!["new".to_string()],
+            removed: vec!["old".to_string()],
+            changed: vec!["rotated".to_string()],
+        }
+    );
+    assert_eq!(
+        changes.to_string(),
+        "Secret keys added new; removed old; changed rotated"
+    );
+    assert_eq!(
+        serde_json::to_value(&changes).unwrap(),
+        json!({"added": ["new"], "removed": ["old"], "changed": ["rotated"]})
+    );
+    assert!(SecretKeyChanges::new(Some(&have), Some(&have)).is_empty());
+}
+
+#[test]
+fn test_secret_values_are_left_out_of_line_diffs() -> Result<()> {
+    let secret = DynamicObject {
+        types: Some(TypeMeta {
+            api_version: "v1".to_string(),
+            kind: "Secret".to_string(),
+        }),
+        metadata: ObjectMeta::default(),
+        data: json!({"data": {"token": "YQ=="}, "type": "Opaque"}),
+    };
+
+    let yaml = to_diffable_yaml(&secret, true)?;
+
+    assert!(!yaml.contains("token"));
+    assert!(yaml.contains("Opaque"));
+    Ok(())
+}
+
+#[test]
+fn test_plan_is_grouped_by_cluster_and_namespace() -> Result<()> {
+    let object = |kind: &str| DynamicObject {
+        types: Some(TypeMeta {
+            api_version: "v1".to_string(),
+            kind: kind.to_string(),
+        }),
+        metadata: ObjectMeta::default(),
+        data: json!({}),
+    };
+
+    let have = KubernetesResources {
+        by_key: BTreeMap::from([(
+            test_key("prod", "v1", "ConfigMap", Some("echo"), "old"),
+            object("ConfigMap"),
+        )]),
+        namespaces: BTreeMap::new(),
+    };
+    let want = KubernetesResources {
+        by_key: BTreeMap::from([
+            (
+                test_key("dev", "v1", "ConfigMap", Some("echo"), "new"),
+                object("ConfigMap"),
+            ),
+            (
+                test_key("prod", "v1", "ConfigMap", Some("api"), "new"),
+                object("ConfigMap"),
+            ),
+        ]),
+        namespaces: BTreeMap::from([(
+            test_key("prod", "v1", "Namespace", None, "api"),
+            object("Namespace"),
+        )]),
+    };
+    let plan = generate_plan(have, want)?;
+
+    let listed: Vec<_> = plan_order(&plan)
+        .into_iter()
+        .map(|i| &plan[i].key)
+        .collect();
+    assert_eq!(
+        listed,
+        vec![
+            &test_key("dev", "v1", "ConfigMap", Some("echo"), "new"),
+            &test_key("prod", "v1", "Namespace", None, "api"),
+            &test_key("prod", "v1", "ConfigMap", Some("api"), "new"),
+            &test_key("prod", "v1", "ConfigMap", Some("echo"), "old"),
+        ]
+    );
+    assert_eq!(
+        plan_counts(&plan),
+        vec![
+            ("create", 3),
+            ("patch", 0),
+            ("delete and recreate", 0),
+            ("delete", 1),
+        ]
+    );
+    Ok(())
+}
+
+#[test]
+fn test_plan_follows_waves() -> Result<()> {
+    let object = |wave: Option<&str>| DynamicObject {
+        types: Some(TypeMeta {
+            api_version: "v1".to_string(),
+            kind: "ConfigMap".to_string(),
+        }),
+        metadata: ObjectMeta {
+            annotations: wave.map(|w| {
+                BTreeMap::from([(
+                    crate::kubernetes_io::WAVE_ANNOTATION.to_string(),
+                    w.to_string(),
+                )])
+            }),
+            ..Default::default()
+        },
+        data: json!({}),
+    };
+
+    let have = KubernetesResources {
+        by_key: BTreeMap::from([
+            (
+                test_key("prod", "v1", "ConfigMap", Some("echo"), "gone-early"),
+                object(Some("-1")),
+            ),
+            (
+                test_key("prod", "v1", "ConfigMap", Some("echo"), "gone-late"),
+                object(Some("3")),
+            ),
+        ]),
+        namespaces: BTreeMap::new(),
+    };
+    let want = KubernetesResources {
+        by_key: BTreeMap::from([
+            (
+                test_key("prod", "v1", "ConfigMap", Some("echo"), "a-operator"),
+                object(Some("1")),
+            ),
+            (
+                test_key("prod", "v1", "ConfigMap", Some("echo"), "b-default"),
+                object(None),
+            ),
+            (
+                test_key("prod", "v1", "ConfigMap", Some("echo"), "c-crd"),
+                object(Some("-2")),
+            ),
+        ]),
+        namespaces: BTreeMap::new(),
+    };
+    let plan = generate_plan(have, want)?;
+
+    let names: Vec<_> = plan.iter().map(|c| c.key.name.as_str()).collect();
+    assert_eq!(
+        names,
+        vec![
+            "c-crd",
+            "b-default",
+            "a-operator",
+            "gone-late",
+            "gone-early"
+        ]
+    );
+    Ok(())
+}
+
+#[test]
+fn test_invalid_wave_fails() {
+    let object = DynamicObject {
+        types: None,
+        metadata: ObjectMeta {
+            annotations: Some(BTreeMap::from([(
+                crate::kubernetes_io::WAVE_ANNOTATION.to_string(),
+                "soon".to_string(),
+            )])),
+            ..Default::default()
+        },
+        data: json!({}),
+    };
+
+    assert!(wave(&object).is_err());
+}
+
+#[test]
+fn test_pod_impact_follows_the_pod_template() -> Result<()> {
+    let object = |kind: &str, replicas: u64, image: &str| DynamicObject {
+        types: Some(TypeMeta {
+            api_version: "apps/v1".to_string(),
+            kind: kind.to_string(),
+        }),
+        metadata: ObjectMeta::default(),
+        data: json!({
+            "spec": {
+                "replicas": replicas,
+                "template": {
+                    "spec": {
+                        "containers": [{"name": "echo", "image": image}]
+                    }
+                }
+            }
+        }),
+    };
+
+    let have = KubernetesResources {
+        by_key: BTreeMap::from([
+            (
+                test_key("dev", "apps/v1", "Deployment", Some("echo"), "scaled"),
+                object("Deployment", 1, "echo:1"),
+            ),
+            (
+                test_key("dev", "apps/v1", "Deployment", Some("echo"), "web"),
+                object("Deployment", 2, "echo:1"),
+            ),
+            (
+                test_key("prod", "apps/v1", "Deployment", Some("echo"), "web"),
+                object("Deployment", 3, "echo:1"),
+            ),
+            (
+                test_key("prod", "apps/v1", "DaemonSet", Some("echo"), "agent"),
+                object("DaemonSet", 1, "agent:1"),
+            ),
+        ]),
+        namespaces: BTreeMap::new(),
+    };
+    let want = KubernetesResources {
+        by_key: BTreeMap::from([
+            (
+                test_key("dev", "apps/v1", "Deployment", Some("echo"), "scaled"),
+                object("Deployment", 4, "echo:1"),
+            ),
+            (
+                test_key("dev", "apps/v1", "Deployment", Some("echo"), "web"),
+                object("Deployment", 2, "echo:2"),
+            ),
+            (
+                test_key("prod", "apps/v1", "Deployment", Some("echo"), "web"),
+                object("Deployment", 3, "echo:2"),
+            ),
+            (
+                test_key("prod", "apps/v1", "DaemonSet", Some("echo"), "agent"),
+                object("DaemonSet", 1, "agent:2"),
+            ),
+        ]),
+        namespaces: BTreeMap::new(),
+    };
+    let plan = generate_plan(have, want)?;
+
+    let scaled = plan
+        .iter()
+        .find(|c| c.key.name == "scaled")
+        .ok_or_else(|| anyhow!("Expected a change"))?;
+    assert_eq!(
+        pod_impact(&scaled.key, &scaled.action),
+        Some(PodImpact {
+            restarts: false,
+            replicas: Some(4),
+        })
+    );
+    assert!(scaled.rendering.contains("Restarts pods: no"));
+    assert_eq!(
+        pod_impact_summary(&plan).as_deref(),
+        Some("Restarts pods: yes, 5 replicas and 1 DaemonSet across 2 clusters")
+    );
+    assert_eq!(pod_impact_summary(&plan[..0]), None);
+    Ok(())
+}
+
+#[test]
+fn test_recreate_rules() -> Result<()> {
+    let types = |api_version: &str, kind: &str| TypeMeta {
+        api_version: api_version.to_string(),
+        kind: kind.to_string(),
+    };
+    let patch = |before: JsonValue, after: JsonValue| json_patch::diff(&before, &after);
+    let configured = parse_recreate_rules(
+        "- apiVersion: example.com/v1\n  kind: Widget\n  paths: [/spec/ports/*/name]\n",
+    )?;
+
+    assert!(requires_recreate_with(
+        &types("v1", "Service"),
+        &patch(
+            json!({"spec": {"type": "ClusterIP"}}),
+            json!({"spec": {"type": "LoadBalancer"}})
+        ),
+        &configured
+    ));
+    assert!(requires_recreate_with(
+        &types("apps/v1", "StatefulSet"),
+        &patch(
+            json!({"spec": {"volumeClaimTemplates": [{"spec": {"storage": "1Gi"}}]}}),
+            json!({"spec": {"volumeClaimTemplates": [{"spec": {"storage": "2Gi"}}]}})
+        ),
+        &configured
+    ));
+    assert!(!requires_recreate_with(
+        &types("apps/v1", "StatefulSet"),
+        &patch(json!({"spec": {"replicas": 1}}), json!({"spec": {"replicas": 2}})),
+        &configured
+    ));
+    assert!(!requires_recreate_with(
+        &types("v1", "PersistentVolumeClaim"),
+        &patch(
+            json!({"spec": {"resources": {"requests": {"storage": "1Gi"}}}}),
+            json!({"spec": {"resources": {"requests": {"storage": "2Gi"}}}})
+        ),
+        &configured
+    ));
+    assert!(requires_recreate_with(
+        &types("example.com/v1", "Widget"),
+        &patch(
+            json!({"spec": {"ports": [{"name": "a"}]}}),
+            json!({"spec": {"ports": [{"name": "b"}]}})
+        ),
+        &configured
+    ));
+    assert!(!requires_recreate_with(
+        &types("example.com/v1", "Widget"),
+        &patch(
+            json!({"spec": {"ports": [{"number": 1}]}}),
+            json!({"spec": {"ports": [{"number": 2}]}})
+        ),
+        &configured
+    ));
+
+    assert!(parse_recreate_rules("- apiVersion: v1\n  kind: Service\n  paths: [spec]\n").is_err());
+    Ok(())
+}
+
+#[test]
+fn test_protected_objects_are_not_deleted() -> Result<()> {
+    let claim = |protected: bool| DynamicObject {
+        types: Some(TypeMeta {
+            api_version: "v1".to_string(),
+            kind: "PersistentVolumeClaim".to_string(),
+        }),
+        metadata: ObjectMeta {
+            annotations: protected
+                .then(|| BTreeMap::from([(PROTECT_ANNOTATION.to_string(), "true".to_string())])),
+            ..Default::default()
+        },
+        data: json!({"spec": {"storageClassName": "ssd"}}),
+    };
+    let mut moved = claim(true);
+    moved.data = json!({"spec": {"storageClassName": "hdd"}});
+
+    let have = KubernetesResources {
+        by_key: BTreeMap::from([
+            (
+                test_key("prod", "v1", "PersistentVolumeClaim", Some("db"), "data"),
+                claim(true),
+            ),
+            (
+                test_key("prod", "v1", "PersistentVolumeClaim", Some("db"), "cache"),
+                claim(false),
+            ),
+            (
+                test_key("prod", "v1", "PersistentVolumeClaim", Some("db"), "logs"),
+                claim(true),
+            ),
+        ]),
+        namespaces: BTreeMap::new(),
+    };
+    let want = KubernetesResources {
+        by_key: BTreeMap::from([(
+            test_key("prod", "v1", "PersistentVolumeClaim", Some("db"), "logs"),
+            moved,
+        )]),
+        namespaces: BTreeMap::new(),
+    };
+    let plan = generate_plan(have, want)?;
+    let error = check_protected(&plan).unwrap_err().to_string();
+
+    assert!(error.contains("delete PersistentVolumeClaim db/data"));
+    assert!(error.contains("delete and recreate PersistentVolumeClaim db/logs"));
+    assert!(!error.contains("db/cache"));
+    Ok(())
+}
+
+#[test]
+fn test_diffs_say_where_objects_are_defined() -> Result<()> {
+    let config_map = |file: &str| DynamicObject {
+        types: Some(TypeMeta {
+            api_version: "v1".to_string(),
+            kind: "ConfigMap".to_string(),
+        }),
+        metadata: ObjectMeta {
+            annotations: Some(BTreeMap::from([(
+                ORIGIN_ANNOTATION.to_string(),
+                json!({
+                    "resource": "KubernetesYaml config",
+                    "file": file,
+                })
+                .to_string(),
+            )])),
+            ..Default::default()
+        },
+        data: json!({"data": {"key": "value"}}),
+    };
+
+    let have = KubernetesResources {
+        by_key: BTreeMap::from([(
+            test_key("prod", "v1", "ConfigMap", Some("echo"), "old"),
+            config_map("echo/old.yaml"),
+        )]),
+        namespaces: BTreeMap::new(),
+    };
+    let want = KubernetesResources {
+        by_key: BTreeMap::from([(
+            test_key("prod", "v1", "ConfigMap", Some("echo"), "new"),
+            config_map("echo/index.yaml"),
+        )]),
+        namespaces: BTreeMap::new(),
+    };
+    let plan = generate_plan(have, want)?;
+
+    assert!(plan[0]
+        .rendering
+        .contains("\n\nDefined in echo/index.yaml\n\n"));
+    assert!(plan[1]
+        .rendering
+        .contains("\n\nWas defined in echo/old.yaml\n\n"));
+
+    // Moving an object to another file alone isn't a change
+    let key = test_key("prod", "v1", "ConfigMap", Some("echo"), "moved");
+    let have = KubernetesResources {
+        by_key: BTreeMap::from([(key.clone(), config_map("echo/old.yaml"))]),
+        namespaces: BTreeMap::new(),
+    };
+    let want = KubernetesResources {
+        by_key: BTreeMap::from([(key, config_map("echo/index.yaml"))]),
+        namespaces: BTreeMap::new(),
+    };
+    assert!(generate_plan(have, want)?.is_empty());
+    Ok(())
+}
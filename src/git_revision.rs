@@ -0,0 +1,126 @@
+use anyhow::{bail, Context, Result};
+use std::{io::Cursor, path::Path};
+use tempfile::TempDir;
+use tokio::process::Command;
+use tracing::info;
+
+use crate::provenance::record_source_commit;
+
+// A monitor directory as of some commit, extracted into a temporary directory that's removed when
+// this is dropped
+pub(crate) struct GitCheckout {
+    _tree: TempDir,
+    pub(crate) monitor_directory: String,
+    pub(crate) commit: String,
+}
+
+// Swaps the monitor directory for its contents at `git_ref`, if given, and records the commit in
+// the provenance of everything applied afterwards
+pub(crate) async fn monitor_directories_at(
+    monitor_directories: Vec<String>,
+    git_ref: Option<&str>,
+) -> Result<(Vec<String>, Option<GitCheckout>)> {
+    let Some(git_ref) = git_ref else {
+        return Ok((monitor_directories, None));
+    };
+    let [monitor_directory] = &monitor_directories[..] else {
+        bail!("--git-ref reads a single monitor directory");
+    };
+    if monitor_directory == "-" {
+        bail!("--git-ref can't be used when reading resources from stdin");
+    }
+    let checkout = checkout_revision(Path::new(monitor_directory), git_ref).await?;
+    info!(commit = %checkout.commit, "Rendering {} at {}", monitor_directory, checkout.commit);
+    record_source_commit(&checkout.commit);
+    Ok((vec![checkout.monitor_directory.clone()], Some(checkout)))
+}
+
+// Extracts the whole repository rather than only the monitor directory, since `sources` may point
+// outside of it
+async fn checkout_revision(monitor_directory: &Path, git_ref: &str) -> Result<GitCheckout> {
+    let commit = git(
+        monitor_directory,
+        &["rev-parse", "--verify", &format!("{}^{{commit}}", git_ref)],
+    )
+    .await
+    .with_context(|| format!("while resolving {}", git_ref))?;
+    let commit = String::from_utf8(commit)?.trim().to_string();
+    let prefix = git(monitor_directory, &["rev-parse", "--show-prefix"]).await?;
+    let prefix = String::from_utf8(prefix)?.trim().to_string();
+    // Run from the top since git archive otherwise only includes the current folder
+    let top = git(monitor_directory, &["rev-parse", "--show-toplevel"]).await?;
+    let top = String::from_utf8(top)?.trim().to_string();
+    let tar = git(Path::new(&top), &["archive", "--format=tar", &commit])
+        .await
+        .with_context(|| format!("while reading the tree at {}", commit))?;
+
+    let tree = TempDir::new()?;
+    tar::Archive::new(Cursor::new(tar))
+        .unpack(tree.path())
+        .context("while extracting the git tree")?;
+    let directory = tree.path().join(&prefix);
+    if !directory.is_dir() {
+        bail!(
+            "{} doesn't exist at {}",
+            monitor_directory.display(),
+            commit
+        );
+    }
+    Ok(GitCheckout {
+        monitor_directory: directory.to_string_lossy().into_owned(),
+        _tree: tree,
+        commit,
+    })
+}
+
+// Runs git in `directory`, returning what it printed
+async fn git(directory: &Path, args: &[&str]) -> Result<Vec<u8>> {
+    let output = Command::new("git")
+        .arg("-C")
+        .arg(directory)
+        .args(args)
+        .output()
+        .await
+        .context("Failed to execute git")?;
+    if !output.status.success() {
+        bail!(
+            "git {} failed: {}",
+            args.join(" "),
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(output.stdout)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    async fn run(directory: &Path, args: &[&str]) -> Result<()> {
+        let mut all = vec!["-c", "user.name=test", "-c", "user.email=test@example.com"];
+        all.extend_from_slice(args);
+        git(directory, &all).await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_checkout_reads_the_committed_tree() -> Result<()> {
+        let repo = TempDir::new()?;
+        let monitor = repo.path().join("production");
+        fs::create_dir_all(monitor.join("echo"))?;
+        fs::write(monitor.join("echo/index.yaml"), "committed")?;
+        run(repo.path(), &["init", "--quiet"]).await?;
+        run(repo.path(), &["add", "."]).await?;
+        run(repo.path(), &["commit", "--quiet", "-m", "First"]).await?;
+        fs::write(monitor.join("echo/index.yaml"), "uncommitted")?;
+
+        let checkout = checkout_revision(&monitor, "HEAD").await?;
+        assert_eq!(checkout.commit.len(), 40);
+        let index = Path::new(&checkout.monitor_directory).join("echo/index.yaml");
+        assert_eq!(fs::read_to_string(index)?, "committed");
+
+        assert!(checkout_revision(&monitor, "missing-branch").await.is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,169 @@
+use anyhow::{Context, Result};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+// Whether a path from a resource's `sources` is a pattern rather than a single file
+pub(crate) fn has_wildcards(pattern: &str) -> bool {
+    pattern.contains(['*', '?', '['])
+}
+
+// The files under `directory` that `pattern` matches, in sorted order. Hidden files and folders are
+// skipped like a shell would, which keeps editor swap files out.
+pub(crate) fn expand_glob(directory: &Path, pattern: &str) -> Result<Vec<PathBuf>> {
+    let segments: Vec<&str> = pattern
+        .split('/')
+        .filter(|s| !s.is_empty() && *s != ".")
+        .collect();
+    // Leading segments without wildcards, like `../shared`, just pick where to start
+    let literal = segments.iter().take_while(|s| !has_wildcards(s)).count();
+    let mut base = directory.to_path_buf();
+    base.extend(&segments[..literal]);
+    let rest: Vec<String> = segments[literal..].iter().map(|s| s.to_string()).collect();
+
+    let mut found = Vec::new();
+    walk(&base, &mut Vec::new(), &rest, &mut found)
+        .with_context(|| format!("while expanding {:?}", pattern))?;
+    found.sort();
+    Ok(found)
+}
+
+fn walk(
+    directory: &Path,
+    relative: &mut Vec<String>,
+    pattern: &[String],
+    found: &mut Vec<PathBuf>,
+) -> Result<()> {
+    let recursive = pattern.iter().any(|s| s == "**");
+    for entry in fs::read_dir(directory)? {
+        let path = entry?.path();
+        let Some(name) = path.file_name().map(|n| n.to_string_lossy().into_owned()) else {
+            continue;
+        };
+        if name.starts_with('.') {
+            continue;
+        }
+        relative.push(name);
+        if path.is_dir() {
+            if recursive || relative.len() < pattern.len() {
+                walk(&path, relative, pattern, found)?;
+            }
+        } else if match_segments(pattern, relative) {
+            found.push(path);
+        }
+        relative.pop();
+    }
+    Ok(())
+}
+
+// Whether `path` matches `pattern` segment by segment, where a `**` segment matches any number of
+// segments
+pub(crate) fn match_segments(pattern: &[String], path: &[String]) -> bool {
+    match pattern.split_first() {
+        None => path.is_empty(),
+        Some((first, rest)) if first == "**" => {
+            (0..=path.len()).any(|skip| match_segments(rest, &path[skip..]))
+        }
+        Some((first, rest)) => match path.split_first() {
+            Some((name, path)) => match_name(first, name) && match_segments(rest, path),
+            None => false,
+        },
+    }
+}
+
+// Whether one file or folder name matches a pattern with `*`, `?`, and `[...]`
+pub(crate) fn match_name(pattern: &str, name: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+    match_chars(&pattern, &name)
+}
+
+fn match_chars(pattern: &[char], name: &[char]) -> bool {
+    let Some((&first, rest)) = pattern.split_first() else {
+        return name.is_empty();
+    };
+    match first {
+        '*' => (0..=name.len()).any(|skip| match_chars(rest, &name[skip..])),
+        '?' => !name.is_empty() && match_chars(rest, &name[1..]),
+        '[' => match (name.first(), match_class(rest)) {
+            (Some(c), Some((matches, after))) => matches(*c) && match_chars(after, &name[1..]),
+            // An unclosed bracket is just a bracket
+            (Some(c), None) => *c == '[' && match_chars(rest, &name[1..]),
+            (None, _) => false,
+        },
+        '\\' if !rest.is_empty() => {
+            name.first() == Some(&rest[0]) && match_chars(&rest[1..], &name[1..])
+        }
+        c => name.first() == Some(&c) && match_chars(rest, &name[1..]),
+    }
+}
+
+// Parses the inside of `[...]`, returning what it matches and the pattern after the `]`
+fn match_class(pattern: &[char]) -> Option<(impl Fn(char) -> bool + '_, &[char])> {
+    let (negated, body) = match pattern.first() {
+        Some('!' | '^') => (true, &pattern[1..]),
+        _ => (false, pattern),
+    };
+    // A `]` right after the opening bracket is part of the class
+    let close = body.iter().skip(1).position(|c| *c == ']').map(|i| i + 1)?;
+    let class = &body[..close];
+    let matches = move |c: char| {
+        let mut i = 0;
+        let mut found = false;
+        while i < class.len() {
+            if i + 2 < class.len() && class[i + 1] == '-' {
+                found |= class[i] <= c && c <= class[i + 2];
+                i += 3;
+            } else {
+                found |= class[i] == c;
+                i += 1;
+            }
+        }
+        found != negated
+    };
+    Some((matches, &body[close + 1..]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_expand_glob() -> Result<()> {
+        let directory = TempDir::new()?;
+        let root = directory.path();
+        for file in [
+            "echo/a.yaml",
+            "echo/b.yaml",
+            "echo/.b.yaml.swp",
+            "echo/notes.md",
+            "echo/team/c.yaml",
+            "shared/d.yaml",
+        ] {
+            fs::create_dir_all(root.join(file).parent().unwrap())?;
+            fs::write(root.join(file), "")?;
+        }
+        let echo = root.join("echo");
+
+        assert_eq!(
+            expand_glob(&echo, "*.yaml")?,
+            vec![echo.join("a.yaml"), echo.join("b.yaml")]
+        );
+        assert_eq!(
+            expand_glob(&echo, "**/*.yaml")?,
+            vec![
+                echo.join("a.yaml"),
+                echo.join("b.yaml"),
+                echo.join("team/c.yaml")
+            ]
+        );
+        assert_eq!(
+            expand_glob(&echo, "../shared/*.yaml")?,
+            vec![echo.join("../shared/d.yaml")]
+        );
+        assert!(expand_glob(&echo, "*.json")?.is_empty());
+        Ok(())
+    }
+}
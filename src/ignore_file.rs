@@ -0,0 +1,152 @@
+use anyhow::{Context, Result};
+use std::{fs, io::ErrorKind, path::Path};
+
+use crate::globs::{match_name, match_segments};
+
+// Lists paths in the monitor directory that Sisyphus should act like don't exist, using the same
+// syntax as .gitignore
+pub(crate) const IGNORE_FILE: &str = ".sisyphusignore";
+
+#[derive(Debug, Default)]
+pub(crate) struct IgnoreRules {
+    rules: Vec<Rule>,
+}
+
+#[derive(Debug)]
+struct Rule {
+    segments: Vec<String>,
+    // Patterns with a slash before the end match whole paths from the monitor directory, and
+    // others match a name at any depth
+    anchored: bool,
+    directory_only: bool,
+    negated: bool,
+}
+
+impl IgnoreRules {
+    // The rules in `directory`'s ignore file, or none if it doesn't have one
+    pub(crate) fn load(directory: &Path) -> Result<IgnoreRules> {
+        let path = directory.join(IGNORE_FILE);
+        match fs::read_to_string(&path) {
+            Ok(content) => Ok(IgnoreRules::parse(&content)),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(IgnoreRules::default()),
+            Err(e) => Err(e).with_context(|| format!("while reading {:?}", path)),
+        }
+    }
+
+    fn parse(content: &str) -> IgnoreRules {
+        let mut rules = Vec::new();
+        for line in content.lines() {
+            let mut pattern = line.trim_end();
+            if pattern.is_empty() || pattern.starts_with('#') {
+                continue;
+            }
+            let negated = pattern.starts_with('!');
+            if negated {
+                pattern = &pattern[1..];
+            } else if pattern.starts_with("\\#") || pattern.starts_with("\\!") {
+                pattern = &pattern[1..];
+            }
+            let directory_only = pattern.ends_with('/');
+            let pattern = pattern.trim_end_matches('/');
+            let anchored = pattern.contains('/');
+            let segments: Vec<String> = pattern
+                .trim_start_matches('/')
+                .split('/')
+                .map(|s| s.to_string())
+                .collect();
+            if segments.iter().all(|s| s.is_empty()) {
+                continue;
+            }
+            rules.push(Rule {
+                segments,
+                anchored,
+                directory_only,
+                negated,
+            });
+        }
+        IgnoreRules { rules }
+    }
+
+    // Whether `relative`, a path inside the monitor directory, or any folder it's in is ignored
+    pub(crate) fn is_ignored(&self, relative: &Path, is_dir: bool) -> bool {
+        if self.rules.is_empty() {
+            return false;
+        }
+        let segments: Vec<String> = relative
+            .components()
+            .map(|c| c.as_os_str().to_string_lossy().into_owned())
+            .collect();
+        // Like git, nothing inside an ignored folder can be brought back with a negated pattern
+        for end in 1..segments.len() {
+            if self.last_match(&segments[..end], true) {
+                return true;
+            }
+        }
+        self.last_match(&segments, is_dir)
+    }
+
+    fn last_match(&self, path: &[String], is_dir: bool) -> bool {
+        let mut ignored = false;
+        for rule in &self.rules {
+            if rule.directory_only && !is_dir {
+                continue;
+            }
+            let matched = if rule.anchored {
+                match_segments(&rule.segments, path)
+            } else {
+                path.last()
+                    .is_some_and(|name| match_name(&rule.segments[0], name))
+            };
+            if matched {
+                ignored = !rule.negated;
+            }
+        }
+        ignored
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ignored(rules: &str, path: &str, is_dir: bool) -> bool {
+        IgnoreRules::parse(rules).is_ignored(Path::new(path), is_dir)
+    }
+
+    #[test]
+    fn test_names_match_at_any_depth() {
+        let rules = "# editor files\n*~\n.*.swp\nREADME.md\n";
+        assert!(ignored(rules, "echo/index.yaml~", false));
+        assert!(ignored(rules, "echo/.index.yaml.swp", false));
+        assert!(ignored(rules, "README.md", false));
+        assert!(ignored(rules, "echo/README.md", false));
+        assert!(!ignored(rules, "echo/index.yaml", false));
+    }
+
+    #[test]
+    fn test_anchored_and_directory_patterns() {
+        let rules = "/experimental/\nscratch/\ndocs/**/*.yaml\n";
+        assert!(ignored(rules, "experimental", true));
+        assert!(ignored(rules, "experimental/index.yaml", false));
+        assert!(!ignored(rules, "echo/experimental", false));
+        assert!(ignored(rules, "echo/scratch/index.yaml", false));
+        assert!(!ignored(rules, "scratch", false));
+        assert!(ignored(rules, "docs/example.yaml", false));
+        assert!(ignored(rules, "docs/a/b/example.yaml", false));
+        assert!(!ignored(rules, "echo/docs/example.yaml", false));
+    }
+
+    #[test]
+    fn test_negation_and_classes() {
+        let rules = "test-*\n!test-keep\n[0-9]*\n\\#literal\n";
+        assert!(ignored(rules, "test-echo", true));
+        assert!(!ignored(rules, "test-keep", true));
+        assert!(ignored(rules, "2024-old", true));
+        assert!(!ignored(rules, "echo", true));
+        assert!(ignored(rules, "#literal", false));
+
+        // Negations can't reach inside an ignored folder
+        let rules = "old/\n!old/index.yaml\n";
+        assert!(ignored(rules, "old/index.yaml", false));
+    }
+}
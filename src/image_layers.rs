@@ -0,0 +1,150 @@
+use anyhow::{bail, Context, Result};
+use sha2::{Digest, Sha256};
+use std::{
+    fs,
+    io::{Cursor, Read},
+    path::Path,
+};
+
+// OCI allows whiteout entries in a layer to delete files from earlier layers
+const WHITEOUT_PREFIX: &str = ".wh.";
+
+#[derive(Debug, Eq, PartialEq)]
+enum Compression {
+    Gzip,
+    Zstd,
+    None,
+}
+
+impl Compression {
+    // Registries don't always report a layer's media type faithfully, so go by its magic number
+    fn detect(blob: &[u8]) -> Self {
+        if blob.starts_with(&[0x1f, 0x8b]) {
+            Compression::Gzip
+        } else if blob.starts_with(&[0x28, 0xb5, 0x2f, 0xfd]) {
+            Compression::Zstd
+        } else {
+            Compression::None
+        }
+    }
+}
+
+// Fails unless `blob` hashes to `digest`, so that a truncated or corrupted download is caught here
+// instead of as garbage in the unpacked config.
+pub(crate) fn verify_digest(digest: &str, blob: &[u8]) -> Result<()> {
+    let Some(expected) = digest.strip_prefix("sha256:") else {
+        bail!("Unsupported digest algorithm in {}", digest);
+    };
+    let actual = format!("{:x}", Sha256::digest(blob));
+    if actual != expected {
+        bail!("Blob {} has digest sha256:{} instead", digest, actual);
+    }
+    Ok(())
+}
+
+// Unpacks image layers in order into `target`, whether they're gzip, zstd, or uncompressed tars.
+pub(crate) fn unpack_layers(layers: &[Vec<u8>], target: &Path) -> Result<()> {
+    for (i, layer) in layers.iter().enumerate() {
+        let tar = decompress(layer).with_context(|| format!("while decompressing layer {}", i))?;
+        unpack_tar(&tar, target).with_context(|| format!("while unpacking layer {}", i))?;
+    }
+    Ok(())
+}
+
+fn decompress(blob: &[u8]) -> Result<Vec<u8>> {
+    let mut tar = Vec::new();
+    match Compression::detect(blob) {
+        Compression::Gzip => {
+            libflate::gzip::Decoder::new(blob)?.read_to_end(&mut tar)?;
+        }
+        Compression::Zstd => {
+            zstd::stream::read::Decoder::new(blob)?.read_to_end(&mut tar)?;
+        }
+        Compression::None => tar.extend_from_slice(blob),
+    }
+    Ok(tar)
+}
+
+fn unpack_tar(tar: &[u8], target: &Path) -> Result<()> {
+    let mut archive = tar::Archive::new(Cursor::new(tar));
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        let path = entry.path()?.into_owned();
+        let whiteout = path
+            .file_name()
+            .and_then(|n| n.to_str())
+            .and_then(|n| n.strip_prefix(WHITEOUT_PREFIX));
+        let Some(removed) = whiteout else {
+            entry.unpack_in(target)?;
+            continue;
+        };
+        // Deletes whatever an earlier layer put there
+        let removed = target.join(path.with_file_name(removed));
+        if removed.is_dir() {
+            fs::remove_dir_all(&removed)?;
+        } else if removed.exists() {
+            fs::remove_file(&removed)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use tempfile::TempDir;
+
+    fn tar_with(files: &[(&str, &str)]) -> Vec<u8> {
+        let mut builder = tar::Builder::new(Vec::new());
+        for (path, content) in files {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(content.len() as u64);
+            header.set_mode(0o644);
+            header.set_cksum();
+            builder
+                .append_data(&mut header, path, content.as_bytes())
+                .unwrap();
+        }
+        builder.into_inner().unwrap()
+    }
+
+    fn gzip(data: &[u8]) -> Vec<u8> {
+        let mut encoder = libflate::gzip::Encoder::new(Vec::new()).unwrap();
+        encoder.write_all(data).unwrap();
+        encoder.finish().into_result().unwrap()
+    }
+
+    #[test]
+    fn test_unpack_layers_of_each_compression() -> Result<()> {
+        let directory = TempDir::new()?;
+        let layers = vec![
+            gzip(&tar_with(&[("index.json", "{}"), ("old.star", "old")])),
+            zstd::encode_all(&tar_with(&[("main.star", "main")])[..], 0)?,
+            tar_with(&[(".wh.old.star", "")]),
+        ];
+
+        unpack_layers(&layers, directory.path())?;
+
+        assert_eq!(
+            fs::read_to_string(directory.path().join("index.json"))?,
+            "{}"
+        );
+        assert_eq!(
+            fs::read_to_string(directory.path().join("main.star"))?,
+            "main"
+        );
+        assert!(!directory.path().join("old.star").exists());
+        assert!(!directory.path().join(".wh.old.star").exists());
+        Ok(())
+    }
+
+    #[test]
+    fn test_verify_digest() {
+        let digest = format!("sha256:{:x}", Sha256::digest(b"layer"));
+
+        assert!(verify_digest(&digest, b"layer").is_ok());
+        assert!(verify_digest(&digest, b"corrupted").is_err());
+        assert!(verify_digest("sha512:abcd", b"layer").is_err());
+    }
+}
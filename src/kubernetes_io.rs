@@ -1,21 +1,26 @@
+use crate::{
+    discovery_cache::discover, provenance::keep_provenance, pull_secrets::forget_docker_credentials,
+};
 use anyhow::{anyhow, bail, Context, Result};
 use kube::{
     api::{ApiResource, DynamicObject},
     config::KubeConfigOptions,
     discovery::{ApiCapabilities, Scope},
-    Discovery, ResourceExt,
+    ResourceExt,
 };
 use serde_json::Value as JsonValue;
+use sha2::{Digest, Sha256};
 use std::{
-    collections::{BTreeMap, HashMap},
+    collections::{BTreeMap, BTreeSet, HashMap},
     fmt,
+    sync::OnceLock,
 };
 
 #[cfg(test)]
 mod tests;
 
 #[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
-pub(crate) struct KubernetesKey {
+pub struct KubernetesKey {
     pub name: String,
     pub kind: String,
     pub api_version: String,
@@ -37,14 +42,177 @@ impl fmt::Display for KubernetesKey {
     }
 }
 
+// Builds keys in tests, where spelling out every field hides what each test is about
+#[cfg(test)]
+pub(crate) fn test_key(
+    cluster: &str,
+    api_version: &str,
+    kind: &str,
+    namespace: Option<&str>,
+    name: &str,
+) -> KubernetesKey {
+    KubernetesKey {
+        api_version: api_version.to_string(),
+        cluster: cluster.to_string(),
+        kind: kind.to_string(),
+        name: name.to_string(),
+        namespace: namespace.map(|n| n.to_string()),
+    }
+}
+
 #[derive(Clone, Debug)]
-pub(crate) struct KubernetesResources {
+pub struct KubernetesResources {
     pub by_key: BTreeMap<KubernetesKey, DynamicObject>,
     pub namespaces: BTreeMap<KubernetesKey, DynamicObject>,
 }
 
 pub(crate) const MANAGER: &str = "sisyphus";
 
+// Stamped on every rendered object with a short hash of what it was rendered from
+pub(crate) const INPUTS_HASH_ANNOTATION: &str = "sisyphus.april.dev/inputs-hash";
+
+pub(crate) fn inputs_hash(object: &DynamicObject) -> Option<&str> {
+    object
+        .metadata
+        .annotations
+        .as_ref()
+        .and_then(|a| a.get(INPUTS_HASH_ANNOTATION))
+        .map(|v| v.as_str())
+}
+
+// Stamped on every rendered object with JSON describing the file, config image digest, and Starlark
+// entrypoint it was rendered from, so `why` can explain a live object
+pub(crate) const ORIGIN_ANNOTATION: &str = "sisyphus.april.dev/origin";
+
+// A comma-separated list of JSON pointers, like `/spec/replicas`, that something other than Sisyphus
+// is expected to change on this object, such as an autoscaler
+pub(crate) const IGNORE_PATHS_ANNOTATION: &str = "sisyphus.april.dev/ignore-paths";
+
+// Paths that controllers change on every object of a kind, so they're ignored without an annotation
+const DEFAULT_KIND_IGNORE_PATHS: [(&str, &str, &str); 1] = [(
+    "apps/v1",
+    "Deployment",
+    "/metadata/annotations/deployment.kubernetes.io~1revision",
+)];
+
+// A path ignored on every object of a kind, from --ignore-path
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub(crate) struct KindIgnorePath {
+    // None matches the kind in any API version
+    api_version: Option<String>,
+    kind: String,
+    path: String,
+}
+
+static KIND_IGNORE_PATHS: OnceLock<Vec<KindIgnorePath>> = OnceLock::new();
+
+// Reads rules like `Deployment=/spec/replicas` or `apps/v1/Deployment=/spec/replicas`
+pub(crate) fn configure_ignore_paths(rules: &[String]) -> Result<()> {
+    let rules = rules
+        .iter()
+        .map(|r| parse_kind_ignore_path(r))
+        .collect::<Result<Vec<_>>>()?;
+    let _ = KIND_IGNORE_PATHS.set(rules);
+    Ok(())
+}
+
+fn parse_kind_ignore_path(rule: &str) -> Result<KindIgnorePath> {
+    let Some((kind, path)) = rule.split_once('=') else {
+        bail!("Expected KIND=/json/pointer but got {}", rule);
+    };
+    if !path.starts_with('/') {
+        bail!("{} isn't a JSON pointer in {}", path, rule);
+    }
+    let (api_version, kind) = match kind.rsplit_once('/') {
+        Some((api_version, kind)) => (Some(api_version.to_string()), kind),
+        None => (None, kind),
+    };
+    if kind.is_empty() {
+        bail!("Expected a kind in {}", rule);
+    }
+    Ok(KindIgnorePath {
+        api_version,
+        kind: kind.to_string(),
+        path: path.to_string(),
+    })
+}
+
+// The built-in and configured paths ignored on every object of `object`'s kind
+fn kind_ignore_paths<'a>(object: &DynamicObject, configured: &'a [KindIgnorePath]) -> Vec<&'a str> {
+    let Some(types) = &object.types else {
+        return Vec::new();
+    };
+    let defaults = DEFAULT_KIND_IGNORE_PATHS
+        .iter()
+        .filter(|(api_version, kind, _)| *api_version == types.api_version && *kind == types.kind)
+        .map(|(_, _, path)| *path);
+    let configured = configured
+        .iter()
+        .filter(|r| {
+            r.kind == types.kind
+                && r.api_version
+                    .as_ref()
+                    .map_or(true, |v| *v == types.api_version)
+        })
+        .map(|r| r.path.as_str());
+    defaults.chain(configured).collect()
+}
+
+// Set to "true" on objects like databases and PersistentVolumeClaims that a push must not delete or
+// recreate unless it's run with --allow-protected
+pub(crate) const PROTECT_ANNOTATION: &str = "sisyphus.april.dev/protect";
+
+pub(crate) fn is_protected(object: &DynamicObject) -> bool {
+    object
+        .annotations()
+        .get(PROTECT_ANNOTATION)
+        .is_some_and(|v| v.trim() == "true")
+}
+
+// An integer that orders when an object is applied, like Argo CD's sync waves. Lower waves are
+// created and patched first and deleted last. Objects without one are in wave 0.
+pub(crate) const WAVE_ANNOTATION: &str = "sisyphus.april.dev/wave";
+
+pub(crate) fn wave(object: &DynamicObject) -> Result<i64> {
+    let Some(value) = object.annotations().get(WAVE_ANNOTATION) else {
+        return Ok(0);
+    };
+    value.trim().parse().with_context(|| {
+        format!(
+            "{} on {} must be an integer, not {:?}",
+            WAVE_ANNOTATION,
+            object.name_any(),
+            value
+        )
+    })
+}
+
+// Set on the objects rendered alongside a Deployment (its Service, PodDisruptionBudget, ...) to the
+// Deployment's name, so they're handled together even when a filter only names the Deployment
+pub(crate) const PART_OF_LABEL: &str = "sisyphus.april.dev/part-of";
+
+// The key of the object that owns this one, either through PART_OF_LABEL or ownerReferences. Owners
+// are always in the same cluster and namespace.
+pub(crate) fn owner_key(key: &KubernetesKey, object: &DynamicObject) -> Option<KubernetesKey> {
+    let owner = |api_version: &str, kind: &str, name: &str| KubernetesKey {
+        api_version: api_version.to_string(),
+        cluster: key.cluster.clone(),
+        kind: kind.to_string(),
+        name: name.to_string(),
+        namespace: key.namespace.clone(),
+    };
+    if let Some(name) = object.labels().get(PART_OF_LABEL) {
+        return Some(owner("apps/v1", "Deployment", name));
+    }
+    object
+        .metadata
+        .owner_references
+        .as_ref()?
+        .iter()
+        .find(|r| r.controller == Some(true))
+        .map(|r| owner(&r.api_version, &r.kind, &r.name))
+}
+
 struct Selector<'a> {
     data: &'a JsonValue,
     matcher: serde_json::Map<String, JsonValue>,
@@ -227,57 +395,83 @@ fn copy_unmanaged_fields(
     })
 }
 
+// The types each cluster serves, by cluster and then by (api_version, kind). Clusters can run
+// different sets of CRDs, or different versions of the same ones.
+pub(crate) type ClusterTypes =
+    HashMap<String, HashMap<(String, String), (ApiResource, ApiCapabilities)>>;
+
 pub(crate) async fn get_kubernetes_clients(
     keys: impl IntoIterator<Item = &KubernetesKey>,
-) -> Result<(
-    HashMap<String, kube::Client>,
-    HashMap<(String, String), (ApiResource, ApiCapabilities)>,
-)> {
+) -> Result<(HashMap<String, kube::Client>, ClusterTypes)> {
     let mut clients = HashMap::new();
-    for key in keys.into_iter() {
+    let mut servers = HashMap::new();
+    // Loading the kubeconfig isn't free, so do it once per cluster rather than once per object
+    let clusters: BTreeSet<&str> = keys.into_iter().map(|k| k.cluster.as_str()).collect();
+    for cluster in clusters {
         let config = kube::Config::from_kubeconfig(&KubeConfigOptions {
-            context: Some(key.cluster.to_string()),
+            context: Some(cluster.to_string()),
             cluster: None,
             user: None,
         })
         .await?;
-        clients.insert(key.cluster.to_string(), kube::Client::try_from(config)?);
+        servers.insert(cluster.to_string(), config.cluster_url.to_string());
+        clients.insert(cluster.to_string(), kube::Client::try_from(config)?);
     }
     if clients.len() == 0 {
         return Ok((HashMap::new(), HashMap::new()));
     }
 
-    // We need to fetch types from all clusters because they may have different sets of CRDs
     let mut futures = Vec::new();
-    for client in clients.values() {
+    for (cluster, client) in &clients {
+        let cluster = cluster.clone();
+        let server = servers.remove(&cluster).unwrap_or_default();
         let copy = client.clone();
-        futures.push(tokio::spawn(
-            async move { Discovery::new(copy).run().await },
-        ));
+        futures.push(tokio::spawn(async move {
+            let resources = discover(&cluster, &server, copy).await;
+            (cluster, resources)
+        }));
     }
     let mut types = HashMap::new();
     for future in futures {
-        for group in future.await??.groups() {
-            for (ar, caps) in group.recommended_resources() {
-                types.insert((ar.api_version.clone(), ar.kind.clone()), (ar, caps));
-            }
-        }
+        let (cluster, resources) = future.await?;
+        let served = resources?
+            .into_iter()
+            .map(|(ar, caps)| ((ar.api_version.clone(), ar.kind.clone()), (ar, caps)))
+            .collect();
+        types.insert(cluster, served);
     }
     Ok((clients, types))
 }
 
+// Looks up the type of `key` in the cluster it belongs to
+pub(crate) fn find_type<'a>(
+    key: &KubernetesKey,
+    types: &'a ClusterTypes,
+) -> Result<&'a (ApiResource, ApiCapabilities)> {
+    types
+        .get(&key.cluster)
+        .and_then(|served| served.get(&(key.api_version.clone(), key.kind.clone())))
+        .ok_or_else(|| {
+            anyhow!(
+                "Unable to find type {} in {} on {} (pass --refresh-discovery if it was just \
+                 installed)",
+                key.kind,
+                key.api_version,
+                key.cluster
+            )
+        })
+}
+
 pub(crate) fn get_kubernetes_api(
     key: &KubernetesKey,
     clients: &HashMap<String, kube::Client>,
-    types: &HashMap<(String, String), (ApiResource, ApiCapabilities)>,
+    types: &ClusterTypes,
 ) -> Result<kube::Api<DynamicObject>> {
     let client = clients
         .get(&key.cluster)
         .ok_or_else(|| anyhow!("No client defined for {}", key.cluster))?
         .clone();
-    let Some((ar, caps)) = types.get(&(key.api_version.clone(), key.kind.clone())) else {
-        bail!("Unable to find type {} in {}", key.kind, key.api_version);
-    };
+    let (ar, caps) = find_type(key, types)?;
     Ok(match caps.scope {
         Scope::Cluster => kube::Api::all_with(client, ar),
         Scope::Namespaced => {
@@ -293,9 +487,16 @@ pub(crate) fn make_comparable(
     let us = Some(MANAGER.to_string());
     for (key, w) in &mut to.by_key {
         copy_single_unspecified_data(from.by_key.get_mut(&key), w, &us)?;
+        if let Some(h) = from.by_key.get(&key) {
+            suppress_ignored_paths(h, w)?;
+            keep_provenance(h, w);
+        }
     }
     for (key, w) in &mut to.namespaces {
         copy_single_unspecified_data(from.namespaces.get_mut(&key), w, &us)?;
+        if let Some(h) = from.namespaces.get(&key) {
+            keep_provenance(h, w);
+        }
     }
 
     Ok((from, to))
@@ -335,15 +536,126 @@ fn copy_single_unspecified_data(
     Ok(())
 }
 
-pub(crate) fn munge_secrets(from: Option<&DynamicObject>, to: &mut DynamicObject) -> Result<()> {
-    let is_secret = to
+// Makes `to` agree with `from` on every path either one's IGNORE_PATHS_ANNOTATION lists, or that
+// is ignored for its whole kind, so those fields never show up as changes.
+pub(crate) fn suppress_ignored_paths(from: &DynamicObject, to: &mut DynamicObject) -> Result<()> {
+    let configured = KIND_IGNORE_PATHS.get().map_or(&[][..], |r| r.as_slice());
+    let paths: BTreeSet<&str> = [&*from, &*to]
+        .into_iter()
+        .filter_map(|o| o.annotations().get(IGNORE_PATHS_ANNOTATION))
+        .flat_map(|v| v.split(','))
+        .map(str::trim)
+        .filter(|p| !p.is_empty())
+        .chain(kind_ignore_paths(to, configured))
+        .collect();
+    if paths.is_empty() {
+        return Ok(());
+    }
+
+    let source = serde_json::to_value(from)?;
+    let mut target = serde_json::to_value(&*to)?;
+    for path in paths {
+        for concrete in expand_wildcards(&[&source, &target], path) {
+            copy_path(&source, &mut target, &concrete)
+                .with_context(|| format!("in ignored paths of {}", to.name_any()))?;
+        }
+    }
+    *to = serde_json::from_value(target)
+        .context("Failed to rebuild object after copying ignored paths")?;
+    Ok(())
+}
+
+// Copies whatever is at the JSON pointer `path` in `source` to the same place in `target`, removing
+// it from `target` if `source` doesn't have it.
+fn copy_path(source: &JsonValue, target: &mut JsonValue, path: &str) -> Result<()> {
+    let Some((parent, last)) = path.rsplit_once('/').filter(|_| path.starts_with('/')) else {
+        bail!("{} isn't a JSON pointer", path);
+    };
+    let last = unescape_pointer_token(last);
+    let Some(value) = source.pointer(path) else {
+        if let Some(JsonValue::Object(map)) = target.pointer_mut(parent) {
+            map.remove(&last);
+        }
+        return Ok(());
+    };
+
+    let mut current = target;
+    for token in parent.split('/').skip(1) {
+        let token = unescape_pointer_token(token);
+        current = match current {
+            JsonValue::Object(map) => map
+                .entry(token)
+                .or_insert_with(|| JsonValue::Object(Default::default())),
+            JsonValue::Array(items) => {
+                match token.parse::<usize>().ok().and_then(|i| items.get_mut(i)) {
+                    Some(item) => item,
+                    None => return Ok(()),
+                }
+            }
+            _ => return Ok(()),
+        };
+    }
+    match current {
+        JsonValue::Object(map) => {
+            map.insert(last, value.clone());
+        }
+        JsonValue::Array(items) => {
+            if let Some(item) = last.parse::<usize>().ok().and_then(|i| items.get_mut(i)) {
+                *item = value.clone();
+            }
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+// Replaces each `*` in `path` with every array index or object key at that point in any of
+// `values`, like /webhooks/*/clientConfig/caBundle
+fn expand_wildcards(values: &[&JsonValue], path: &str) -> Vec<String> {
+    if !path.starts_with('/') {
+        return vec![path.to_string()];
+    }
+    let tokens: Vec<&str> = path.split('/').skip(1).collect();
+    let Some(i) = tokens.iter().position(|t| *t == "*") else {
+        return vec![path.to_string()];
+    };
+    let prefix: String = tokens[..i].iter().map(|t| format!("/{}", t)).collect();
+    let suffix: String = tokens[i + 1..].iter().map(|t| format!("/{}", t)).collect();
+    let mut children = BTreeSet::new();
+    for value in values {
+        match value.pointer(&prefix) {
+            Some(JsonValue::Array(items)) => {
+                children.extend((0..items.len()).map(|i| i.to_string()))
+            }
+            Some(JsonValue::Object(map)) => {
+                children.extend(map.keys().map(|k| k.replace('~', "~0").replace('/', "~1")))
+            }
+            _ => {}
+        }
+    }
+    children
+        .into_iter()
+        .flat_map(|c| expand_wildcards(values, &format!("{}/{}{}", prefix, c, suffix)))
+        .collect()
+}
+
+fn unescape_pointer_token(token: &str) -> String {
+    token.replace("~1", "/").replace("~0", "~")
+}
+
+pub(crate) fn is_secret(object: &DynamicObject) -> bool {
+    object
         .types
         .as_ref()
         .map(|t| t.api_version == "v1" && t.kind == "Secret")
-        .unwrap_or(false);
-    if !is_secret {
+        .unwrap_or(false)
+}
+
+pub(crate) fn munge_secrets(from: Option<&DynamicObject>, to: &mut DynamicObject) -> Result<()> {
+    if !is_secret(to) {
         return Ok(());
     }
+    forget_docker_credentials(to);
 
     let fd = from
         .map(|v| v.data.as_object())
@@ -389,3 +701,76 @@ pub(crate) fn munge_secrets(from: Option<&DynamicObject>, to: &mut DynamicObject
     }
     Ok(())
 }
+
+const HASHED_SECRET_PREFIX: &str = "sisyphus-sha256:";
+
+// Replaces the values of a Secret read from Kubernetes with salted hashes. A value whose hash
+// matches what the database recorded keeps the recorded hash, so only keys whose value actually
+// changed show up in a diff.
+pub(crate) fn hash_secrets(from: Option<&DynamicObject>, to: &mut DynamicObject) -> Result<()> {
+    if !is_secret(to) {
+        return Ok(());
+    }
+
+    let fd = from
+        .and_then(|v| v.data.get("data"))
+        .and_then(|v| v.as_object());
+    let Some(tdd) = to
+        .data
+        .get_mut("data")
+        .and_then(|v| v.as_object_mut())
+    else {
+        return Ok(());
+    };
+    for (k, v) in tdd.iter_mut() {
+        let Some(value) = v.as_str() else {
+            bail!("Secret key {} must have a string value", k);
+        };
+        let recorded = fd.and_then(|d| d.get(k)).and_then(|r| r.as_str());
+        let hashed = match recorded {
+            Some(r) if hashed_secret_matches(r, value) => r.to_string(),
+            _ => hash_secret_value(&rand::random::<[u8; 8]>(), value),
+        };
+        *v = JsonValue::String(hashed);
+    }
+    Ok(())
+}
+
+fn hash_secret_value(salt: &[u8], value: &str) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(salt);
+    hasher.update(value.as_bytes());
+    format!(
+        "{}{}:{}",
+        HASHED_SECRET_PREFIX,
+        to_hex(salt),
+        to_hex(&hasher.finalize())
+    )
+}
+
+fn hashed_secret_matches(recorded: &str, value: &str) -> bool {
+    let Some((salt, _)) = recorded
+        .strip_prefix(HASHED_SECRET_PREFIX)
+        .and_then(|r| r.split_once(':'))
+    else {
+        return false;
+    };
+    let Some(salt) = from_hex(salt) else {
+        return false;
+    };
+    hash_secret_value(&salt, value) == recorded
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn from_hex(hex: &str) -> Option<Vec<u8>> {
+    if hex.len() % 2 != 0 {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
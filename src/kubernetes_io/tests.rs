@@ -646,3 +646,238 @@ fn test_munge_secrets_no_from_data() -> Result<()> {
     );
     Ok(())
 }
+
+// Tests for hash_secrets
+fn secret_with_data(data: JsonValue) -> DynamicObject {
+    DynamicObject {
+        types: Some(TypeMeta {
+            api_version: "v1".to_string(),
+            kind: "Secret".to_string(),
+        }),
+        metadata: ObjectMeta::default(),
+        data: json!({ "data": data }),
+    }
+}
+
+#[test]
+fn test_hash_secrets_hides_values() -> Result<()> {
+    let mut to = secret_with_data(json!({"password": "c2VjcmV0"}));
+
+    hash_secrets(None, &mut to)?;
+
+    let hashed = to.data["data"]["password"].as_str().unwrap();
+    assert!(hashed.starts_with(HASHED_SECRET_PREFIX));
+    assert!(!hashed.contains("c2VjcmV0"));
+    assert!(hashed_secret_matches(hashed, "c2VjcmV0"));
+    assert!(!hashed_secret_matches(hashed, "b3RoZXI="));
+    Ok(())
+}
+
+#[test]
+fn test_hash_secrets_keeps_recorded_hash_when_unchanged() -> Result<()> {
+    let mut from = secret_with_data(json!({"password": "c2VjcmV0", "username": "YWRtaW4="}));
+    hash_secrets(None, &mut from)?;
+
+    let mut to = secret_with_data(json!({"password": "c2VjcmV0", "username": "cm9vdA=="}));
+    hash_secrets(Some(&from), &mut to)?;
+
+    // Only the changed key gets a new hash
+    assert_eq!(to.data["data"]["password"], from.data["data"]["password"]);
+    assert_ne!(to.data["data"]["username"], from.data["data"]["username"]);
+    assert!(hashed_secret_matches(
+        to.data["data"]["username"].as_str().unwrap(),
+        "cm9vdA=="
+    ));
+    Ok(())
+}
+
+#[test]
+fn test_hash_secrets_rehashes_placeholders() -> Result<()> {
+    let from = secret_with_data(json!({"password": "c29tZSBzdHVmZg=="}));
+    let mut to = secret_with_data(json!({"password": "c29tZSBzdHVmZg=="}));
+
+    hash_secrets(Some(&from), &mut to)?;
+
+    // Values recorded before hashing was turned on aren't hashes, so they always change once
+    assert!(hashed_secret_matches(
+        to.data["data"]["password"].as_str().unwrap(),
+        "c29tZSBzdHVmZg=="
+    ));
+    Ok(())
+}
+
+// Tests for suppress_ignored_paths
+fn deployment(annotation: Option<&str>, spec: JsonValue) -> DynamicObject {
+    DynamicObject {
+        types: Some(TypeMeta {
+            api_version: "apps/v1".to_string(),
+            kind: "Deployment".to_string(),
+        }),
+        metadata: ObjectMeta {
+            name: Some("echo".to_string()),
+            annotations: annotation
+                .map(|a| BTreeMap::from([(IGNORE_PATHS_ANNOTATION.to_string(), a.to_string())])),
+            ..Default::default()
+        },
+        data: json!({ "spec": spec }),
+    }
+}
+
+#[test]
+fn test_suppress_ignored_paths_copies_from_have() -> Result<()> {
+    let from = deployment(None, json!({"replicas": 7, "paused": true}));
+    let mut to = deployment(
+        Some("/spec/replicas, /spec/paused,/spec/template/metadata/annotations/a~1b"),
+        json!({"replicas": 3, "template": {}}),
+    );
+
+    suppress_ignored_paths(&from, &mut to)?;
+
+    assert_eq!(
+        to.data["spec"],
+        json!({"replicas": 7, "paused": true, "template": {}})
+    );
+    Ok(())
+}
+
+#[test]
+fn test_suppress_ignored_paths_removes_what_have_lacks() -> Result<()> {
+    let from = deployment(Some("/spec/replicas"), json!({}));
+    let mut to = deployment(None, json!({"replicas": 3}));
+
+    suppress_ignored_paths(&from, &mut to)?;
+
+    assert_eq!(to.data["spec"], json!({}));
+    Ok(())
+}
+
+#[test]
+fn test_suppress_ignored_paths_leaves_unannotated_objects() -> Result<()> {
+    let from = deployment(None, json!({"replicas": 7}));
+    let mut to = deployment(None, json!({"replicas": 3}));
+
+    suppress_ignored_paths(&from, &mut to)?;
+
+    assert_eq!(to.data["spec"], json!({"replicas": 3}));
+    Ok(())
+}
+
+#[test]
+fn test_suppress_ignored_paths_rejects_bad_pointers() {
+    let from = deployment(None, json!({}));
+    let mut to = deployment(Some("spec.replicas"), json!({}));
+
+    assert!(suppress_ignored_paths(&from, &mut to).is_err());
+}
+
+#[test]
+fn test_suppress_ignored_paths_expands_wildcards() -> Result<()> {
+    let from = deployment(
+        None,
+        json!({"containers": [{"image": "a@sha256:1"}, {"image": "b@sha256:2"}]}),
+    );
+    let mut to = deployment(
+        Some("/spec/containers/*/image"),
+        json!({"containers": [{"image": "a"}, {"image": "b"}]}),
+    );
+
+    suppress_ignored_paths(&from, &mut to)?;
+
+    assert_eq!(to.data["spec"], from.data["spec"]);
+    Ok(())
+}
+
+#[test]
+fn test_suppress_ignored_paths_skips_deployment_revisions() -> Result<()> {
+    let mut from = deployment(None, json!({}));
+    from.metadata.annotations = Some(BTreeMap::from([(
+        "deployment.kubernetes.io/revision".to_string(),
+        "4".to_string(),
+    )]));
+    let mut to = deployment(None, json!({}));
+
+    suppress_ignored_paths(&from, &mut to)?;
+
+    assert_eq!(to.metadata.annotations, from.metadata.annotations);
+    Ok(())
+}
+
+#[test]
+fn test_kind_ignore_paths() -> Result<()> {
+    let configured = vec![
+        parse_kind_ignore_path("Deployment=/spec/replicas")?,
+        parse_kind_ignore_path("apps/v1beta1/Deployment=/spec/paused")?,
+        parse_kind_ignore_path("StatefulSet=/spec/replicas")?,
+    ];
+    assert_eq!(
+        kind_ignore_paths(&deployment(None, json!({})), &configured),
+        vec![
+            "/metadata/annotations/deployment.kubernetes.io~1revision",
+            "/spec/replicas"
+        ]
+    );
+    assert!(parse_kind_ignore_path("Deployment").is_err());
+    assert!(parse_kind_ignore_path("Deployment=spec.replicas").is_err());
+    assert!(parse_kind_ignore_path("apps/v1/=/spec/replicas").is_err());
+    Ok(())
+}
+
+#[test]
+fn test_find_type_is_per_cluster() {
+    let certificate = ApiResource {
+        group: "cert-manager.io".to_string(),
+        version: "v1".to_string(),
+        api_version: "cert-manager.io/v1".to_string(),
+        kind: "Certificate".to_string(),
+        plural: "certificates".to_string(),
+    };
+    let caps = ApiCapabilities {
+        scope: Scope::Namespaced,
+        subresources: Vec::new(),
+        operations: Vec::new(),
+    };
+    let types: ClusterTypes = HashMap::from([
+        (
+            "with-crd".to_string(),
+            HashMap::from([(
+                ("cert-manager.io/v1".to_string(), "Certificate".to_string()),
+                (certificate, caps),
+            )]),
+        ),
+        ("without-crd".to_string(), HashMap::new()),
+    ]);
+
+    assert!(find_type(
+        &test_key(
+            "with-crd",
+            "cert-manager.io/v1",
+            "Certificate",
+            Some("echo"),
+            "echo"
+        ),
+        &types
+    )
+    .is_ok());
+    assert!(find_type(
+        &test_key(
+            "without-crd",
+            "cert-manager.io/v1",
+            "Certificate",
+            Some("echo"),
+            "echo"
+        ),
+        &types
+    )
+    .is_err());
+    assert!(find_type(
+        &test_key(
+            "unknown",
+            "cert-manager.io/v1",
+            "Certificate",
+            Some("echo"),
+            "echo"
+        ),
+        &types
+    )
+    .is_err());
+}
@@ -1,35 +1,56 @@
 use crate::{
     config_image::{
-        assign_ports, get_config, Application, Argument, ArgumentValues, ConfigImageIndex,
-        FileVariable, Probe, ProbeAction,
+        get_config, Application, Applications, Argument, ArgumentValues, ConfigImageIndex,
+        FileVariable, Probe, ProbeAction, SecurityContext,
     },
-    kubernetes_io::KubernetesKey,
-    registry_clients::RegistryClients,
-    sisyphus_yaml::{DeploymentServiceConfig, SisyphusResource, VariableSource},
+    cron_schedule::check_schedule,
+    kubernetes_io::{KubernetesKey, INPUTS_HASH_ANNOTATION, ORIGIN_ANNOTATION, PART_OF_LABEL},
+    plugins::render_plugin,
+    pull_secrets::{build_docker_config_secret, DOCKER_CREDENTIALS_ANNOTATION},
+    registry_clients::{pull_image, verify_image_exists, RegistryClients},
+    render_progress::{image_done, image_stage, Stage},
+    sisyphus_yaml::{
+        DeploymentServiceConfig, DisruptionBudgetConfig, ImagePullSecret,
+        KubernetesConfigMapKeyRef, KubernetesSecretKeyRef, KubernetesYaml, PluginResource,
+        SisyphusResource, Spread, VariableSource,
+    },
+    starlark::Context,
 };
-use anyhow::{anyhow, bail, Result};
-use docker_registry::render as containerRender;
+use anyhow::{anyhow, bail, Context as _, Result};
 use futures::future::try_join_all;
 use k8s_openapi::{
     api::{
         apps::v1::{Deployment, DeploymentSpec, DeploymentStrategy, RollingUpdateDeployment},
-        batch::v1::{CronJob, CronJobSpec, JobSpec, JobTemplateSpec},
+        batch::v1::{CronJob, CronJobSpec, Job, JobSpec, JobTemplateSpec},
         core::v1::{
-            Container, ContainerPort, EnvVar, EnvVarSource, HTTPGetAction, KeyToPath,
-            PodSecurityContext, PodSpec, PodTemplateSpec, Probe as KubeProbe, ResourceRequirements,
-            SecretKeySelector, SecretVolumeSource, Service, ServicePort, ServiceSpec, Volume,
-            VolumeMount,
+            Affinity, Capabilities, ConfigMapKeySelector, ConfigMapVolumeSource, Container,
+            ContainerPort, EnvVar, EnvVarSource, HTTPGetAction, KeyToPath, LocalObjectReference,
+            ObjectFieldSelector, PodAffinityTerm, PodAntiAffinity, PodSecurityContext, PodSpec,
+            PodTemplateSpec, Probe as KubeProbe, ResourceFieldSelector, ResourceRequirements,
+            SeccompProfile, SecretKeySelector, SecretVolumeSource,
+            SecurityContext as KubeSecurityContext, Service, ServicePort, ServiceSpec,
+            TopologySpreadConstraint, Volume, VolumeMount, WeightedPodAffinityTerm,
+        },
+        networking::v1::{
+            HTTPIngressPath, HTTPIngressRuleValue, Ingress, IngressBackend, IngressRule,
+            IngressServiceBackend, IngressSpec, IngressTLS, ServiceBackendPort,
         },
+        policy::v1::{PodDisruptionBudget, PodDisruptionBudgetSpec},
+    },
+    apimachinery::pkg::{
+        api::resource::Quantity, apis::meta::v1::LabelSelector, util::intstr::IntOrString,
     },
-    apimachinery::pkg::{api::resource::Quantity, util::intstr::IntOrString},
 };
 use kube::{
     api::{DynamicObject, ObjectMeta},
     ResourceExt,
 };
-use serde::Deserialize;
-use std::{collections::BTreeMap, path::Path};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value as JsonValue};
+use sha2::{Digest, Sha256};
+use std::{collections::BTreeMap, fmt, path::Path};
 use tempfile::TempDir;
+use tracing::{instrument, warn};
 
 #[cfg(test)]
 mod tests;
@@ -41,51 +62,354 @@ pub(crate) async fn render_sisyphus_resource(
     allow_any_namespace: bool,
     maybe_namespace: &Option<String>,
     by_key: &mut BTreeMap<KubernetesKey, DynamicObject>,
-    registries: &mut RegistryClients,
+    registries: &RegistryClients,
+) -> Result<()> {
+    // Checked once here rather than for each cluster so warnings aren't repeated
+    if let SisyphusResource::SisyphusCronJob(v) = object {
+        let warnings = check_schedule(&v.config.schedule)
+            .with_context(|| format!("while rendering CronJob {}", v.metadata.name))?;
+        for warning in warnings {
+            warn!(cronjob = %v.metadata.name, "CronJob {}: {}", v.metadata.name, warning);
+        }
+    }
+    let mut rendered = BTreeMap::new();
+    let mut entrypoint = None;
+    for (cluster, part) in split_by_cluster(object) {
+        entrypoint = render_unstamped_sisyphus_resource(
+            &part,
+            cluster.as_deref(),
+            allow_any_namespace,
+            maybe_namespace,
+            &mut rendered,
+            registries,
+        )
+        .await?;
+    }
+    // A KubernetesYaml's objects are each written out as is, so each is hashed on its own. Editing
+    // one shouldn't make its siblings look changed.
+    let hash = match object {
+        SisyphusResource::KubernetesYaml(_) => None,
+        _ => Some(hash_rendering_inputs(object)?),
+    };
+    let origin = serde_json::to_string(&Origin::of(object, entrypoint))?;
+    let mut stamped = BTreeMap::new();
+    for (key, mut value) in rendered {
+        // Pull Secrets are shared by every resource naming them, so they're hashed on their own too
+        let shared = value
+            .annotations()
+            .contains_key(DOCKER_CREDENTIALS_ANNOTATION);
+        let hash = match &hash {
+            Some(hash) if !shared => hash.clone(),
+            _ => hash_rendering_inputs(&value)?,
+        };
+        stamp_inputs_hash(&mut value, &hash);
+        value
+            .annotations_mut()
+            .insert(ORIGIN_ANNOTATION.to_string(), origin.clone());
+        check_object_size(&key, &value)?;
+        stamped.insert(key, value);
+    }
+    insert_rendered(by_key, stamped)
+}
+
+// Adds the objects one resource rendered. Objects from different folders can still land on the
+// same key, like a global/ KubernetesYaml naming a namespace, and silently keeping whichever
+// rendered last would hide that. Pull Secrets are the exception: any resource in the namespace may
+// name the same one, and it's kept once as long as they all agree on its registries.
+pub(crate) fn insert_rendered(
+    by_key: &mut BTreeMap<KubernetesKey, DynamicObject>,
+    mut rendered: BTreeMap<KubernetesKey, DynamicObject>,
 ) -> Result<()> {
-    match object {
+    rendered.retain(|key, object| {
+        by_key
+            .get(key)
+            .map_or(true, |existing| !same_pull_secret(existing, object))
+    });
+    for (key, object) in &rendered {
+        if let Some(existing) = by_key.get(key) {
+            bail!(
+                "{} is rendered by both {} and {}",
+                key,
+                describe_origin(existing),
+                describe_origin(object)
+            );
+        }
+    }
+    by_key.extend(rendered);
+    Ok(())
+}
+
+fn same_pull_secret(a: &DynamicObject, b: &DynamicObject) -> bool {
+    a.annotations().contains_key(DOCKER_CREDENTIALS_ANNOTATION)
+        && without_origin(a) == without_origin(b)
+}
+
+// The origin only says where the files define an object, so it's left out when deciding whether
+// two renderings are the same object
+pub(crate) fn without_origin(object: &DynamicObject) -> DynamicObject {
+    let mut object = object.clone();
+    if let Some(annotations) = object.metadata.annotations.as_mut() {
+        annotations.remove(ORIGIN_ANNOTATION);
+    }
+    object
+}
+
+fn describe_origin(object: &DynamicObject) -> String {
+    object
+        .annotations()
+        .get(ORIGIN_ANNOTATION)
+        .and_then(|o| serde_json::from_str::<Origin>(o).ok())
+        .map_or_else(|| "another resource".to_string(), |o| o.to_string())
+}
+
+// etcd rejects objects over 1.5MiB and the apiserver rejects annotations totaling over 256KiB. Both
+// fail with unhelpful errors halfway through a push, so catch them while rendering.
+const MAX_OBJECT_BYTES: usize = 1572864;
+const MAX_ANNOTATIONS_BYTES: usize = 262144;
+
+fn check_object_size(key: &KubernetesKey, object: &DynamicObject) -> Result<()> {
+    let annotations_size: usize = object
+        .metadata
+        .annotations
+        .iter()
+        .flatten()
+        .map(|(k, v)| k.len() + v.len())
+        .sum();
+    if annotations_size > MAX_ANNOTATIONS_BYTES {
+        let (largest, size) = object
+            .metadata
+            .annotations
+            .iter()
+            .flatten()
+            .map(|(k, v)| (k.as_str(), k.len() + v.len()))
+            .max_by_key(|(_, size)| *size)
+            .unwrap_or_default();
+        bail!(
+            "{} has {} bytes of annotations, over the {} byte limit. The largest is \
+             metadata.annotations.{} at {} bytes.",
+            key,
+            annotations_size,
+            MAX_ANNOTATIONS_BYTES,
+            largest,
+            size
+        );
+    }
+
+    let value = serde_json::to_value(object)?;
+    let size = serde_json::to_vec(&value)?.len();
+    if size > MAX_OBJECT_BYTES {
+        let (path, largest) = largest_field(&value, "");
+        bail!(
+            "{} is {} bytes, over the {} byte limit. The largest field is {} at {} bytes.",
+            key,
+            size,
+            MAX_OBJECT_BYTES,
+            path,
+            largest
+        );
+    }
+    Ok(())
+}
+
+// Finds the scalar that takes up the most space when serialized, returning its dotted path.
+fn largest_field(value: &JsonValue, path: &str) -> (String, usize) {
+    let join = |k: &str| {
+        if path.is_empty() {
+            k.to_string()
+        } else {
+            format!("{}.{}", path, k)
+        }
+    };
+    match value {
+        JsonValue::Object(m) => m
+            .iter()
+            .map(|(k, v)| largest_field(v, &join(k)))
+            .max_by_key(|(_, size)| *size)
+            .unwrap_or_else(|| (path.to_string(), 2)),
+        JsonValue::Array(a) => a
+            .iter()
+            .enumerate()
+            .map(|(i, v)| largest_field(v, &format!("{}[{}]", path, i)))
+            .max_by_key(|(_, size)| *size)
+            .unwrap_or_else(|| (path.to_string(), 2)),
+        _ => (path.to_string(), value.to_string().len()),
+    }
+}
+
+// A short hash of everything an object was rendered from: the resource or object as written (which,
+// by the time it's rendered, has its config image resolved to a digest) and the version of
+// Sisyphus.
+pub(crate) fn hash_rendering_inputs(object: &impl Serialize) -> Result<String> {
+    let mut hasher = Sha256::new();
+    hasher.update(env!("CARGO_PKG_VERSION").as_bytes());
+    hasher.update(b"\n");
+    hasher.update(serde_json::to_string(object)?.as_bytes());
+    Ok(hasher
+        .finalize()
+        .iter()
+        .take(6)
+        .map(|b| format!("{:02x}", b))
+        .collect())
+}
+
+fn stamp_inputs_hash(object: &mut DynamicObject, hash: &str) {
+    object
+        .metadata
+        .annotations
+        .get_or_insert_with(BTreeMap::new)
+        .insert(INPUTS_HASH_ANNOTATION.to_string(), hash.to_string());
+}
+
+// What a rendered object came from, recorded in its ORIGIN_ANNOTATION. It leaves out which
+// document of the file the resource is, since adding or removing one would then change every object
+// defined after it.
+#[derive(Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct Origin {
+    // Like `Deployment echo`
+    pub resource: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub file: Option<String>,
+    // The config image pinned to the digest its tag resolved to
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub config_image: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub entrypoint: Option<String>,
+}
+
+impl Origin {
+    fn of(object: &SisyphusResource, entrypoint: Option<String>) -> Origin {
+        let (kind, config_image) = match object {
+            SisyphusResource::KubernetesYaml(_) => ("KubernetesYaml", None),
+            SisyphusResource::SisyphusCronJob(v) => ("CronJob", Some(v.config.image.clone())),
+            SisyphusResource::SisyphusDeployment(v) => ("Deployment", Some(v.config.image.clone())),
+            SisyphusResource::SisyphusJob(v) => ("Job", Some(v.config.image.clone())),
+            SisyphusResource::SisyphusYaml(_) => ("SisyphusYaml", None),
+            SisyphusResource::Plugin(v) => (v.kind.as_str(), None),
+        };
+        Origin {
+            resource: format!("{} {}", kind, object.metadata().name),
+            file: object.defined_in().map(|p| p.display().to_string()),
+            config_image,
+            entrypoint,
+        }
+    }
+
+    // Like `echo/index.yaml`
+    pub(crate) fn location(&self) -> Option<String> {
+        self.file.clone()
+    }
+}
+
+impl fmt::Display for Origin {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.location() {
+            Some(location) => write!(f, "{} in {}", self.resource, location),
+            None => write!(f, "{}", self.resource),
+        }
+    }
+}
+
+// Configs can differ per cluster (see ctx.cluster()), so resources with a footprint are evaluated
+// once for each cluster in it.
+fn split_by_cluster(object: &SisyphusResource) -> Vec<(Option<String>, SisyphusResource)> {
+    let clusters: Vec<String> = match object {
+        SisyphusResource::SisyphusCronJob(v) => v.footprint.keys().cloned().collect(),
+        SisyphusResource::SisyphusDeployment(v) => v.footprint.keys().cloned().collect(),
+        SisyphusResource::SisyphusJob(v) => v.footprint.keys().cloned().collect(),
+        SisyphusResource::Plugin(v) => v.clusters.clone(),
+        SisyphusResource::KubernetesYaml(_) | SisyphusResource::SisyphusYaml(_) => {
+            return vec![(None, object.clone())];
+        }
+    };
+    clusters
+        .into_iter()
+        .map(|cluster| {
+            let mut part = object.clone();
+            // A footprint entry's variables win over the config's in its cluster
+            match &mut part {
+                SisyphusResource::SisyphusCronJob(v) => {
+                    v.footprint.retain(|c, _| *c == cluster);
+                    v.config
+                        .variables
+                        .extend(v.footprint[&cluster].variables.clone());
+                }
+                SisyphusResource::SisyphusDeployment(v) => {
+                    v.footprint.retain(|c, _| *c == cluster);
+                    v.config
+                        .variables
+                        .extend(v.footprint[&cluster].variables.clone());
+                }
+                SisyphusResource::SisyphusJob(v) => {
+                    v.footprint.retain(|c, _| *c == cluster);
+                    v.config
+                        .variables
+                        .extend(v.footprint[&cluster].variables.clone());
+                }
+                SisyphusResource::Plugin(v) => v.clusters = vec![cluster.clone()],
+                SisyphusResource::KubernetesYaml(_) | SisyphusResource::SisyphusYaml(_) => {}
+            }
+            (Some(cluster), part)
+        })
+        .collect()
+}
+
+async fn render_unstamped_sisyphus_resource(
+    object: &SisyphusResource,
+    cluster: Option<&str>,
+    allow_any_namespace: bool,
+    maybe_namespace: &Option<String>,
+    by_key: &mut BTreeMap<KubernetesKey, DynamicObject>,
+    registries: &RegistryClients,
+) -> Result<Option<String>> {
+    let entrypoint = match object {
         SisyphusResource::KubernetesYaml(v) => {
             handle_kubernetes_yaml_resource(v, allow_any_namespace, maybe_namespace, by_key)?;
+            None
         }
         SisyphusResource::SisyphusCronJob(v) => {
-            let (index, application) = prepare_image_config(
+            let (index, applications) = prepare_image_config(
                 &v.config.image,
                 registries,
-                &v.metadata.name,
-                maybe_namespace.as_deref(),
+                Context {
+                    name: v.metadata.name.clone(),
+                    namespace: maybe_namespace.clone(),
+                    environment: v.config.env.clone(),
+                    cluster: cluster.map(|c| c.to_string()),
+                },
             )
             .await?;
 
             let metadata = render_deployment_metadata(
                 &v.metadata.name,
-                &application.labels,
+                &applications.labels(),
                 &v.metadata.labels,
                 &v.metadata.annotations,
                 maybe_namespace,
             )?
             .metadata;
+            let (variables, external_secrets) =
+                resolve_external_secrets(&v.metadata.name, &metadata, &v.config.variables)?;
 
             // Probes don't apply to CronJobs, whose pods run to completion.
-            if application.liveness.is_some()
-                || application.readiness.is_some()
-                || application.startup.is_some()
-            {
+            if has_probes(&applications) {
                 bail!(
                     "{} is a CronJob, which can't have liveness, readiness, or startup probes",
                     v.metadata.name
                 );
             }
 
-            let (container, _, volumes) = build_container_config(
+            let (containers, _, volumes) = build_containers(
                 &v.metadata.name,
                 &index,
-                &application,
+                &applications,
                 &v.config.env,
-                &v.config.variables,
+                &variables,
             )?;
 
             let restart_policy = v.config.restart_policy.as_deref().unwrap_or("OnFailure");
-            let pod_spec = build_pod_spec(container, restart_policy, volumes);
+            let mut pod_spec = build_pod_spec(containers, restart_policy, volumes);
+            pod_spec.security_context = Some(build_pod_security_context(&applications)?);
 
             let namespace = maybe_namespace
                 .as_ref()
@@ -100,40 +424,62 @@ pub(crate) async fn render_sisyphus_resource(
                 namespace,
                 by_key,
             )?;
+            insert_external_secrets(&external_secrets, v.footprint.keys(), namespace, by_key)?;
+            Some(index.config_entrypoint.clone())
         }
         SisyphusResource::SisyphusDeployment(v) => {
-            let (index, application) = prepare_image_config(
+            let (index, applications) = prepare_image_config(
                 &v.config.image,
                 registries,
-                &v.metadata.name,
-                maybe_namespace.as_deref(),
+                Context {
+                    name: v.metadata.name.clone(),
+                    namespace: maybe_namespace.clone(),
+                    environment: v.config.env.clone(),
+                    cluster: cluster.map(|c| c.to_string()),
+                },
             )
             .await?;
 
             let RenderedMetadata { metadata, selector } = render_deployment_metadata(
                 &v.metadata.name,
-                &application.labels,
+                &applications.labels(),
                 &v.metadata.labels,
                 &v.metadata.annotations,
                 maybe_namespace,
             )?;
             let labels = metadata.labels.clone().unwrap_or_default();
             let annotations = metadata.annotations.clone().unwrap_or_default();
+            let (variables, external_secrets) =
+                resolve_external_secrets(&v.metadata.name, &metadata, &v.config.variables)?;
 
             let mut independent_spec =
                 build_base_deployment_spec(labels, selector.clone(), annotations);
 
-            let (container, ports, volumes) = build_container_config(
+            let (containers, ports, volumes) = build_containers(
                 &v.metadata.name,
                 &index,
-                &application,
+                &applications,
                 &v.config.env,
-                &v.config.variables,
+                &variables,
             )?;
 
-            independent_spec.template.spec = Some(build_pod_spec(container, "Always", volumes));
+            let mut pod_spec = build_pod_spec(containers, "Always", volumes);
+            pod_spec.security_context = Some(build_pod_security_context(&applications)?);
+            if let Some(spread) = v.config.spread {
+                apply_spread(&mut pod_spec, spread, &selector);
+            }
+            independent_spec.template.spec = Some(pod_spec);
 
-            let service_spec_option = build_service_spec(&v.config.service, &ports, selector)?;
+            let mut companions = DeploymentCompanions {
+                disruption_budget: build_disruption_budget_spec(
+                    &v.config.disruption_budget,
+                    selector.clone(),
+                )?,
+                ingress: build_ingress_spec(&v.config.service, &v.metadata.name)?,
+                service: build_service_spec(&v.config.service, &ports, selector)?,
+                service_export: false,
+            };
+            companions.service_export = build_service_export(&v.config.service, &companions)?;
 
             let namespace = maybe_namespace
                 .as_ref()
@@ -143,20 +489,223 @@ pub(crate) async fn render_sisyphus_resource(
                 v,
                 &metadata,
                 &independent_spec,
-                &service_spec_option,
+                &companions,
                 namespace,
                 by_key,
             )?;
+            insert_external_secrets(&external_secrets, v.footprint.keys(), namespace, by_key)?;
+            Some(index.config_entrypoint.clone())
+        }
+        SisyphusResource::SisyphusJob(v) => {
+            let (index, applications) = prepare_image_config(
+                &v.config.image,
+                registries,
+                Context {
+                    name: v.metadata.name.clone(),
+                    namespace: maybe_namespace.clone(),
+                    environment: v.config.env.clone(),
+                    cluster: cluster.map(|c| c.to_string()),
+                },
+            )
+            .await?;
+
+            let metadata = render_deployment_metadata(
+                &v.metadata.name,
+                &applications.labels(),
+                &v.metadata.labels,
+                &v.metadata.annotations,
+                maybe_namespace,
+            )?
+            .metadata;
+            let (variables, external_secrets) =
+                resolve_external_secrets(&v.metadata.name, &metadata, &v.config.variables)?;
+
+            // Like CronJobs, Jobs run to completion and so can't have probes.
+            if has_probes(&applications) {
+                bail!(
+                    "{} is a Job, which can't have liveness, readiness, or startup probes",
+                    v.metadata.name
+                );
+            }
+
+            let (containers, _, volumes) = build_containers(
+                &v.metadata.name,
+                &index,
+                &applications,
+                &v.config.env,
+                &variables,
+            )?;
+
+            let restart_policy = v.config.restart_policy.as_deref().unwrap_or("OnFailure");
+            let mut pod_spec = build_pod_spec(containers, restart_policy, volumes);
+            pod_spec.security_context = Some(build_pod_security_context(&applications)?);
+
+            let namespace = maybe_namespace
+                .as_ref()
+                .ok_or_else(|| anyhow!("Namespace must be explicit"))?;
+
+            process_job_footprint(v, &metadata, &pod_spec, namespace, by_key)?;
+            insert_external_secrets(&external_secrets, v.footprint.keys(), namespace, by_key)?;
+            Some(index.config_entrypoint.clone())
         }
         SisyphusResource::SisyphusYaml(_) => {
             unreachable!("These should already have been resolved")
         }
+        SisyphusResource::Plugin(v) => {
+            let cluster = cluster.ok_or_else(|| anyhow!("Plugins render one cluster at a time"))?;
+            handle_plugin_resource(v, cluster, allow_any_namespace, maybe_namespace, by_key)
+                .await?;
+            None
+        }
+    };
+    Ok(entrypoint)
+}
+
+// Places what the plugin rendered like the objects of a KubernetesYaml, so the same namespace rules
+// apply
+async fn handle_plugin_resource(
+    v: &PluginResource,
+    cluster: &str,
+    allow_any_namespace: bool,
+    maybe_namespace: &Option<String>,
+    by_key: &mut BTreeMap<KubernetesKey, DynamicObject>,
+) -> Result<()> {
+    let objects = render_plugin(v, cluster, maybe_namespace).await?;
+    for object in &objects {
+        if let Some(namespace) = &object.metadata.namespace {
+            if !allow_any_namespace && Some(namespace) != maybe_namespace.as_ref() {
+                bail!(
+                    "{} {} rendered {} into namespace {} but its folder determines the namespace",
+                    v.kind,
+                    v.metadata.name,
+                    object.name_any(),
+                    namespace
+                );
+            }
+        }
+    }
+    let rendered = KubernetesYaml {
+        api_version: v.api_version.clone(),
+        metadata: v.metadata.clone(),
+        clusters: vec![cluster.to_string()],
+        objects,
+        sources: Vec::new(),
+        defined_in: v.defined_in.clone(),
+        document: v.document,
     };
+    handle_kubernetes_yaml_resource(&rendered, allow_any_namespace, maybe_namespace, by_key)
+}
+
+// The key ExternalSecrets store their value under in the Secret they create
+const EXTERNAL_SECRET_KEY: &str = "value";
+
+// Swaps every ExternalSecret variable for a reference to the Secret that its ExternalSecret object
+// creates, returning the (name, object) of each ExternalSecret to render alongside the resource.
+fn resolve_external_secrets(
+    resource_name: &str,
+    metadata: &ObjectMeta,
+    variables: &BTreeMap<String, VariableSource>,
+) -> Result<(BTreeMap<String, VariableSource>, Vec<(String, JsonValue)>)> {
+    let mut resolved = BTreeMap::new();
+    let mut external_secrets = Vec::new();
+    for (variable, source) in variables {
+        let VariableSource::ExternalSecret(external) = source else {
+            resolved.insert(variable.clone(), source.clone());
+            continue;
+        };
+
+        let name = format!("{}-{}", resource_name, variable);
+        let mut remote_ref = json!({ "key": external.key });
+        if let Some(property) = &external.property {
+            remote_ref["property"] = json!(property);
+        }
+        external_secrets.push((
+            name.clone(),
+            json!({
+                "apiVersion": "external-secrets.io/v1beta1",
+                "kind": "ExternalSecret",
+                "metadata": {
+                    "labels": metadata.labels,
+                    "name": name,
+                    "namespace": metadata.namespace,
+                },
+                "spec": {
+                    "data": [{
+                        "remoteRef": remote_ref,
+                        "secretKey": EXTERNAL_SECRET_KEY,
+                    }],
+                    "refreshInterval": external.refresh_interval.as_deref().unwrap_or("1h"),
+                    "secretStoreRef": {
+                        "kind": external.store_kind.as_deref().unwrap_or("SecretStore"),
+                        "name": external.store,
+                    },
+                    "target": {
+                        "creationPolicy": "Owner",
+                        "name": name,
+                    },
+                },
+            }),
+        ));
+        resolved.insert(
+            variable.clone(),
+            VariableSource::SecretKeyRef(KubernetesSecretKeyRef {
+                name,
+                key: EXTERNAL_SECRET_KEY.to_string(),
+            }),
+        );
+    }
+    Ok((resolved, external_secrets))
+}
+
+fn insert_external_secrets<'a>(
+    external_secrets: &[(String, JsonValue)],
+    clusters: impl Iterator<Item = &'a String>,
+    namespace: &str,
+    by_key: &mut BTreeMap<KubernetesKey, DynamicObject>,
+) -> Result<()> {
+    for cluster in clusters {
+        for (name, object) in external_secrets {
+            insert_rendered_object(object, cluster, name, namespace, by_key)?;
+        }
+    }
+    Ok(())
+}
+
+// Sets the pod's pull secrets in one cluster, where a footprint entry's list replaces the config's,
+// and renders the Secrets that are filled in from docker credentials when they're pushed.
+fn apply_image_pull_secrets(
+    pod_spec: &mut PodSpec,
+    configured: &[ImagePullSecret],
+    for_cluster: &Option<Vec<ImagePullSecret>>,
+    cluster: &str,
+    namespace: &str,
+    by_key: &mut BTreeMap<KubernetesKey, DynamicObject>,
+) -> Result<()> {
+    let secrets = for_cluster.as_deref().unwrap_or(configured);
+    if secrets.is_empty() {
+        return Ok(());
+    }
+    for secret in secrets {
+        if secret.from_docker_credentials.is_empty() {
+            continue;
+        }
+        let generated =
+            build_docker_config_secret(&secret.name, namespace, &secret.from_docker_credentials);
+        insert_rendered_object(&generated, cluster, &secret.name, namespace, by_key)?;
+    }
+    pod_spec.image_pull_secrets = Some(
+        secrets
+            .iter()
+            .map(|s| LocalObjectReference {
+                name: s.name.clone(),
+            })
+            .collect(),
+    );
     Ok(())
 }
 
 fn handle_kubernetes_yaml_resource(
-    v: &crate::sisyphus_yaml::KubernetesYaml,
+    v: &KubernetesYaml,
     allow_any_namespace: bool,
     maybe_namespace: &Option<String>,
     by_key: &mut BTreeMap<KubernetesKey, DynamicObject>,
@@ -188,27 +737,28 @@ fn handle_kubernetes_yaml_resource(
     Ok(())
 }
 
+#[instrument(skip(registries, context))]
 pub(crate) async fn prepare_image_config(
     image_config: &String,
-    registries: &mut RegistryClients,
-    name: &str,
-    namespace: Option<&str>,
-) -> Result<(ConfigImageIndex, Application)> {
-    let (image, registry) = registries.get_reference_and_registry(image_config).await?;
-    let repository = image.repository();
-    let manifest = registry
-        .get_manifest(&repository, image.version().as_ref())
-        .await?;
-    let layers_digests = manifest.layers_digests(None)?;
-    let blob_futures = layers_digests
-        .iter()
-        .map(|layer_digest| registry.get_blob(&repository, layer_digest))
-        .collect::<Vec<_>>();
-    let blobs = try_join_all(blob_futures).await?;
+    registries: &RegistryClients,
+    context: Context,
+) -> Result<(ConfigImageIndex, Applications)> {
     let path = TempDir::new()?;
-    containerRender::unpack(&blobs, path.path())?;
-    let (index, application) = get_config(path.path(), name, namespace).await?;
-    Ok((index, application))
+    pull_image(image_config, registries, path.path()).await?;
+    image_stage(image_config, Stage::Evaluating);
+    let (index, applications) = get_config(path.path(), context).await?;
+    // A broken publishing pipeline can write an index naming binaries it never pushed, which would
+    // otherwise only show up as pods that can't pull their image
+    try_join_all(
+        index
+            .pinned_images()
+            .iter()
+            .map(|binary| verify_image_exists(binary, registries)),
+    )
+    .await
+    .with_context(|| format!("in {}", image_config))?;
+    image_done(image_config);
+    Ok((index, applications))
 }
 
 #[derive(Debug)]
@@ -373,33 +923,70 @@ fn render_resource_requirements_map(
     Ok(copy)
 }
 
-fn build_container_config(
-    deployment_name: &str,
+fn has_probes(applications: &Applications) -> bool {
+    let has = |application: &Application| {
+        application.liveness.is_some()
+            || application.readiness.is_some()
+            || application.startup.is_some()
+    };
+    match applications {
+        Applications::Single(v) => has(v),
+        Applications::Named(v) => v.values().any(has),
+    }
+}
+
+// Builds a container per application. They share the pod's volumes, so a file variable used by
+// several containers is backed by one volume.
+fn build_containers(
+    resource_name: &str,
     index: &ConfigImageIndex,
+    applications: &Applications,
+    config_env: &str,
+    config_vars: &BTreeMap<String, VariableSource>,
+) -> Result<(Vec<Container>, BTreeMap<String, ContainerPort>, Vec<Volume>)> {
+    let port_numbers = applications.assign_ports(config_env)?;
+    let mut containers = Vec::new();
+    let mut ports = BTreeMap::new();
+    let mut volumes = Vec::new();
+    for (name, application) in applications.by_container(resource_name) {
+        let (container, container_ports) = build_container_config(
+            &name,
+            index.image_for(&name),
+            application,
+            config_env,
+            &port_numbers,
+            config_vars,
+            &mut volumes,
+        )?;
+        containers.push(container);
+        ports.extend(container_ports);
+    }
+    Ok((containers, ports, volumes))
+}
+
+fn build_container_config(
+    container_name: &str,
+    image: String,
     application: &Application,
     config_env: &str,
+    port_numbers: &BTreeMap<String, u16>,
     config_vars: &BTreeMap<String, VariableSource>,
-) -> Result<(Container, BTreeMap<String, ContainerPort>, Vec<Volume>)> {
+    volumes: &mut Vec<Volume>,
+) -> Result<(Container, BTreeMap<String, ContainerPort>)> {
     let mut container = Container::default();
-    container.name = deployment_name.to_string();
-    container.image = Some(format!(
-        "{}@{}",
-        index.binary_repository, index.binary_digest
-    ));
+    container.name = container_name.to_string();
+    container.image = Some(image);
 
     let mut ports = BTreeMap::new();
-    let mut volumes = Vec::new();
     let mut volume_mounts = Vec::new();
 
-    let port_numbers = assign_ports(application, config_env)?;
-
     let args = render_container_args(
         &application.args,
         config_env,
-        &port_numbers,
+        port_numbers,
         &mut ports,
         config_vars,
-        &mut volumes,
+        volumes,
         &mut volume_mounts,
     )?;
     if args.len() > 0 {
@@ -409,10 +996,10 @@ fn build_container_config(
     let env_vars = render_container_env_vars(
         &application.env,
         config_env,
-        &port_numbers,
+        port_numbers,
         &mut ports,
         config_vars,
-        &mut volumes,
+        volumes,
         &mut volume_mounts,
     )?;
     if env_vars.len() > 0 {
@@ -424,10 +1011,10 @@ fn build_container_config(
         resources.requests = Some(render_resource_requirements_map(
             &application.resources.requests,
             config_env,
-            &port_numbers,
+            port_numbers,
             &mut ports,
             config_vars,
-            &mut volumes,
+            volumes,
             &mut volume_mounts,
         )?);
     }
@@ -435,10 +1022,10 @@ fn build_container_config(
         resources.limits = Some(render_resource_requirements_map(
             &application.resources.limits,
             config_env,
-            &port_numbers,
+            port_numbers,
             &mut ports,
             config_vars,
-            &mut volumes,
+            volumes,
             &mut volume_mounts,
         )?);
     }
@@ -451,14 +1038,18 @@ fn build_container_config(
         container.volume_mounts = Some(volume_mounts);
     }
 
+    if let Some(security_context) = &application.security_context {
+        container.security_context = Some(build_security_context(security_context));
+    }
+
     if let Some(probe) = &application.liveness {
-        container.liveness_probe = Some(build_probe(probe, &port_numbers)?);
+        container.liveness_probe = Some(build_probe(probe, &ports)?);
     }
     if let Some(probe) = &application.readiness {
-        container.readiness_probe = Some(build_probe(probe, &port_numbers)?);
+        container.readiness_probe = Some(build_probe(probe, &ports)?);
     }
     if let Some(probe) = &application.startup {
-        container.startup_probe = Some(build_probe(probe, &port_numbers)?);
+        container.startup_probe = Some(build_probe(probe, &ports)?);
     }
 
     // Set some defaults
@@ -466,10 +1057,58 @@ fn build_container_config(
     container.termination_message_path = Some("/dev/termination-log".to_string());
     container.termination_message_policy = Some("File".to_string());
 
-    Ok((container, ports, volumes))
+    Ok((container, ports))
 }
 
-fn build_probe(probe: &Probe, port_numbers: &BTreeMap<String, u16>) -> Result<KubeProbe> {
+fn build_security_context(security_context: &SecurityContext) -> KubeSecurityContext {
+    let capabilities = if security_context.add_capabilities.is_empty()
+        && security_context.drop_capabilities.is_empty()
+    {
+        None
+    } else {
+        Some(Capabilities {
+            add: Some(security_context.add_capabilities.clone()).filter(|v| !v.is_empty()),
+            drop: Some(security_context.drop_capabilities.clone()).filter(|v| !v.is_empty()),
+        })
+    };
+    KubeSecurityContext {
+        allow_privilege_escalation: security_context.allow_privilege_escalation,
+        capabilities,
+        read_only_root_filesystem: security_context.read_only_root_filesystem,
+        run_as_group: security_context.run_as_group,
+        run_as_non_root: security_context.run_as_non_root,
+        run_as_user: security_context.run_as_user,
+        seccomp_profile: security_context.seccomp_profile.as_ref().map(|v| SeccompProfile {
+            type_: v.clone(),
+            ..Default::default()
+        }),
+        ..Default::default()
+    }
+}
+
+// Everything but fs_group is set per container. fs_group decides who owns mounted volumes, which
+// the containers share, so they have to agree on it.
+fn build_pod_security_context(applications: &Applications) -> Result<PodSecurityContext> {
+    let mut fs_group = None;
+    for (name, application) in applications.by_container("") {
+        let Some(group) = application.security_context.as_ref().and_then(|v| v.fs_group) else {
+            continue;
+        };
+        match fs_group {
+            Some(existing) if existing != group => {
+                bail!("Container {} wants fs_group {} but another wants {}", name, group, existing)
+            }
+            _ => fs_group = Some(group),
+        }
+    }
+    Ok(PodSecurityContext {
+        fs_group,
+        ..Default::default()
+    })
+}
+
+// Probes can only reference ports of the container they're for.
+fn build_probe(probe: &Probe, ports: &BTreeMap<String, ContainerPort>) -> Result<KubeProbe> {
     let mut kube_probe = KubeProbe {
         initial_delay_seconds: probe.initial_delay_seconds,
         period_seconds: probe.period_seconds,
@@ -480,7 +1119,7 @@ fn build_probe(probe: &Probe, port_numbers: &BTreeMap<String, u16>) -> Result<Ku
     };
     match &probe.action {
         ProbeAction::HttpGet { path, port } => {
-            if !port_numbers.contains_key(port) {
+            if !ports.contains_key(port) {
                 bail!(
                     "Probe references port {:?} which the application does not declare",
                     port
@@ -496,9 +1135,13 @@ fn build_probe(probe: &Probe, port_numbers: &BTreeMap<String, u16>) -> Result<Ku
     Ok(kube_probe)
 }
 
-fn build_pod_spec(container: Container, restart_policy: &str, volumes: Vec<Volume>) -> PodSpec {
+fn build_pod_spec(
+    containers: Vec<Container>,
+    restart_policy: &str,
+    volumes: Vec<Volume>,
+) -> PodSpec {
     let mut pod_spec = PodSpec::default();
-    pod_spec.containers.push(container);
+    pod_spec.containers = containers;
     if volumes.len() > 0 {
         pod_spec.volumes = Some(volumes);
     }
@@ -547,6 +1190,153 @@ fn build_service_spec(
     }
 }
 
+// The objects rendered alongside a Deployment in every cluster of its footprint.
+#[derive(Debug, Default)]
+struct DeploymentCompanions {
+    disruption_budget: Option<PodDisruptionBudgetSpec>,
+    ingress: Option<IngressSpec>,
+    service: Option<ServiceSpec>,
+    service_export: bool,
+}
+
+// Whether to export the Deployment's Service to the rest of its ClusterSet. A ServiceExport without
+// a Service in the same cluster exports nothing, so that's an error rather than a silent no-op.
+fn build_service_export(
+    config_service: &Option<DeploymentServiceConfig>,
+    companions: &DeploymentCompanions,
+) -> Result<bool> {
+    if !config_service.as_ref().map_or(false, |s| s.export) {
+        return Ok(false);
+    }
+    if companions.service.is_none() {
+        bail!("service.export requires the service to have at least one port");
+    }
+    Ok(true)
+}
+
+fn build_ingress_spec(
+    config_service: &Option<DeploymentServiceConfig>,
+    service_name: &str,
+) -> Result<Option<IngressSpec>> {
+    let Some(service) = config_service else {
+        return Ok(None);
+    };
+    let Some(ingress) = &service.ingress else {
+        return Ok(None);
+    };
+
+    // Service ports are named after the config port unless they're renamed
+    let service_port_names: Vec<&String> = service
+        .ports
+        .iter()
+        .map(|(k, v)| v.name.as_ref().unwrap_or(k))
+        .collect();
+    let port = match &ingress.port {
+        Some(port) => {
+            if !service_port_names.contains(&port) {
+                bail!("The ingress references service port {} which isn't defined", port);
+            }
+            port.clone()
+        }
+        None => match service_port_names.as_slice() {
+            [only] => only.to_string(),
+            [] => bail!("An ingress requires the service to define a port"),
+            _ => bail!("The service has multiple ports so the ingress must specify a port"),
+        },
+    };
+
+    Ok(Some(IngressSpec {
+        ingress_class_name: ingress.class_name.clone(),
+        rules: Some(vec![IngressRule {
+            host: Some(ingress.host.clone()),
+            http: Some(HTTPIngressRuleValue {
+                paths: vec![HTTPIngressPath {
+                    backend: IngressBackend {
+                        service: Some(IngressServiceBackend {
+                            name: service_name.to_string(),
+                            port: Some(ServiceBackendPort {
+                                name: Some(port),
+                                number: None,
+                            }),
+                        }),
+                        ..Default::default()
+                    },
+                    path: Some(ingress.path.clone().unwrap_or_else(|| "/".to_string())),
+                    path_type: "Prefix".to_string(),
+                }],
+            }),
+        }]),
+        tls: ingress.tls_secret.as_ref().map(|secret| {
+            vec![IngressTLS {
+                hosts: Some(vec![ingress.host.clone()]),
+                secret_name: Some(secret.clone()),
+            }]
+        }),
+        ..Default::default()
+    }))
+}
+
+fn build_disruption_budget_spec(
+    config_disruption_budget: &Option<DisruptionBudgetConfig>,
+    selector: BTreeMap<String, String>,
+) -> Result<Option<PodDisruptionBudgetSpec>> {
+    let Some(budget) = config_disruption_budget else {
+        return Ok(None);
+    };
+    match (&budget.min_available, &budget.max_unavailable) {
+        (Some(_), Some(_)) => {
+            bail!("disruptionBudget can't set both minAvailable and maxUnavailable")
+        }
+        (None, None) => bail!("disruptionBudget must set one of minAvailable or maxUnavailable"),
+        _ => {}
+    }
+    Ok(Some(PodDisruptionBudgetSpec {
+        max_unavailable: budget.max_unavailable.clone(),
+        min_available: budget.min_available.clone(),
+        selector: Some(LabelSelector {
+            match_labels: Some(selector),
+            ..Default::default()
+        }),
+        ..Default::default()
+    }))
+}
+
+// Prefers putting replicas in different topology domains without ever refusing to schedule them,
+// since a Deployment with more replicas than nodes or zones should still come up.
+fn apply_spread(pod_spec: &mut PodSpec, spread: Spread, selector: &BTreeMap<String, String>) {
+    let topology_key = match spread {
+        Spread::Host => "kubernetes.io/hostname",
+        Spread::Zone => "topology.kubernetes.io/zone",
+    };
+    let label_selector = LabelSelector {
+        match_labels: Some(selector.clone()),
+        ..Default::default()
+    };
+    pod_spec.topology_spread_constraints = Some(vec![TopologySpreadConstraint {
+        label_selector: Some(label_selector.clone()),
+        max_skew: 1,
+        topology_key: topology_key.to_string(),
+        when_unsatisfiable: "ScheduleAnyway".to_string(),
+        ..Default::default()
+    }]);
+    pod_spec.affinity = Some(Affinity {
+        pod_anti_affinity: Some(PodAntiAffinity {
+            preferred_during_scheduling_ignored_during_execution: Some(vec![
+                WeightedPodAffinityTerm {
+                    pod_affinity_term: PodAffinityTerm {
+                        label_selector: Some(label_selector),
+                        topology_key: topology_key.to_string(),
+                        ..Default::default()
+                    },
+                    weight: 100,
+                },
+            ]),
+            ..Default::default()
+        }),
+        ..Default::default()
+    });
+}
+
 fn process_cronjob_footprint(
     sisyphus_cronjob: &crate::sisyphus_yaml::SisyphusCronJob,
     metadata: &ObjectMeta,
@@ -561,16 +1351,29 @@ fn process_cronjob_footprint(
         annotations: metadata.annotations.clone(),
         ..Default::default()
     };
-    for (cluster, _) in &sisyphus_cronjob.footprint {
+    for (cluster, entry) in &sisyphus_cronjob.footprint {
+        let mut pod_spec = pod_spec.clone();
+        apply_image_pull_secrets(
+            &mut pod_spec,
+            &sisyphus_cronjob.config.image_pull_secrets,
+            &entry.image_pull_secrets,
+            cluster,
+            namespace,
+            by_key,
+        )?;
         let cronjob_spec = CronJobSpec {
             concurrency_policy: concurrency_policy.clone(),
+            failed_jobs_history_limit: sisyphus_cronjob.config.failed_jobs_history_limit,
             schedule: schedule.to_string(),
+            starting_deadline_seconds: sisyphus_cronjob.config.starting_deadline_seconds,
+            successful_jobs_history_limit: sisyphus_cronjob.config.successful_jobs_history_limit,
+            suspend: sisyphus_cronjob.config.suspend,
             job_template: JobTemplateSpec {
                 metadata: Some(template_metadata.clone()),
                 spec: Some(JobSpec {
                     template: PodTemplateSpec {
                         metadata: Some(template_metadata.clone()),
-                        spec: Some(pod_spec.clone()),
+                        spec: Some(pod_spec),
                     },
                     ..Default::default()
                 }),
@@ -578,25 +1381,65 @@ fn process_cronjob_footprint(
             ..Default::default()
         };
 
-        let serialized = serde_yaml::to_string(&CronJob {
-            metadata: metadata.clone(),
-            spec: Some(cronjob_spec),
-            status: None,
-        })?;
-        let converted =
-            DynamicObject::deserialize(serde_yaml::Deserializer::from_str(&serialized))?;
-        let types = converted
-            .types
-            .clone()
-            .ok_or_else(|| anyhow!("Object {} is type-free", converted.name_any()))?;
-        let key = KubernetesKey {
-            api_version: types.api_version,
-            cluster: cluster.clone(),
-            kind: types.kind,
-            name: sisyphus_cronjob.metadata.name.clone(),
-            namespace: Some(namespace.to_string()),
+        insert_rendered_object(
+            &CronJob {
+                metadata: metadata.clone(),
+                spec: Some(cronjob_spec),
+                status: None,
+            },
+            cluster,
+            &sisyphus_cronjob.metadata.name,
+            namespace,
+            by_key,
+        )?;
+    }
+    Ok(())
+}
+
+fn process_job_footprint(
+    sisyphus_job: &crate::sisyphus_yaml::SisyphusJob,
+    metadata: &ObjectMeta,
+    pod_spec: &PodSpec,
+    namespace: &str,
+    by_key: &mut BTreeMap<KubernetesKey, DynamicObject>,
+) -> Result<()> {
+    let template_metadata = ObjectMeta {
+        labels: metadata.labels.clone(),
+        annotations: metadata.annotations.clone(),
+        ..Default::default()
+    };
+    for (cluster, entry) in &sisyphus_job.footprint {
+        let mut pod_spec = pod_spec.clone();
+        apply_image_pull_secrets(
+            &mut pod_spec,
+            &sisyphus_job.config.image_pull_secrets,
+            &entry.image_pull_secrets,
+            cluster,
+            namespace,
+            by_key,
+        )?;
+        let job_spec = JobSpec {
+            backoff_limit: sisyphus_job.config.backoff_limit,
+            completions: sisyphus_job.config.completions,
+            template: PodTemplateSpec {
+                metadata: Some(template_metadata.clone()),
+                spec: Some(pod_spec),
+            },
+            ttl_seconds_after_finished: sisyphus_job.config.ttl_seconds_after_finished,
+            ..Default::default()
         };
-        by_key.insert(key, converted);
+
+        insert_rendered_object(
+            &Job {
+                metadata: metadata.clone(),
+                spec: Some(job_spec),
+                status: None,
+            },
+            cluster,
+            &sisyphus_job.metadata.name,
+            namespace,
+            by_key,
+        )?;
     }
     Ok(())
 }
@@ -605,62 +1448,131 @@ fn process_deployment_footprint(
     sisyphus_deployment: &crate::sisyphus_yaml::SisyphusDeployment,
     metadata: &ObjectMeta,
     independent_spec: &DeploymentSpec,
-    service_spec_option: &Option<ServiceSpec>,
+    companions: &DeploymentCompanions,
     namespace: &str,
     by_key: &mut BTreeMap<KubernetesKey, DynamicObject>,
 ) -> Result<()> {
+    let name = &sisyphus_deployment.metadata.name;
+    let mut companion_metadata = metadata.clone();
+    companion_metadata
+        .labels
+        .get_or_insert_with(BTreeMap::new)
+        .insert(PART_OF_LABEL.to_string(), name.to_string());
     for (cluster, cluster_spec) in &sisyphus_deployment.footprint {
-        {
-            let mut spec = independent_spec.clone();
-            spec.replicas = Some(cluster_spec.replicas);
-            let serialized = serde_yaml::to_string(&Deployment {
+        let mut spec = independent_spec.clone();
+        spec.replicas = Some(cluster_spec.replicas);
+        if let Some(pod_spec) = spec.template.spec.as_mut() {
+            apply_image_pull_secrets(
+                pod_spec,
+                &sisyphus_deployment.config.image_pull_secrets,
+                &cluster_spec.image_pull_secrets,
+                cluster,
+                namespace,
+                by_key,
+            )?;
+        }
+        insert_rendered_object(
+            &Deployment {
                 metadata: metadata.clone(),
                 spec: Some(spec),
                 status: None,
-            })?;
-            let converted =
-                DynamicObject::deserialize(serde_yaml::Deserializer::from_str(&serialized))?;
-            let types = converted
-                .types
-                .clone()
-                .ok_or_else(|| anyhow!("Object {} is type-free", converted.name_any()))?;
-            let key = KubernetesKey {
-                api_version: types.api_version,
-                cluster: cluster.clone(),
-                kind: types.kind,
-                name: sisyphus_deployment.metadata.name.clone(),
-                namespace: Some(namespace.to_string()),
-            };
-            by_key.insert(key, converted);
-        }
+            },
+            cluster,
+            name,
+            namespace,
+            by_key,
+        )?;
 
-        if let Some(service_spec) = service_spec_option {
+        if let Some(service_spec) = &companions.service {
             if service_spec.ports.as_ref().map_or(false, |p| !p.is_empty()) {
-                let serialized = serde_yaml::to_string(&Service {
-                    metadata: metadata.clone(),
-                    spec: Some(service_spec.clone()),
-                    status: None,
-                })?;
-                let converted =
-                    DynamicObject::deserialize(serde_yaml::Deserializer::from_str(&serialized))?;
-                let types = converted
-                    .types
-                    .clone()
-                    .ok_or_else(|| anyhow!("Object {} is type-free", converted.name_any()))?;
-                let key = KubernetesKey {
-                    api_version: types.api_version,
-                    cluster: cluster.clone(),
-                    kind: types.kind,
-                    name: sisyphus_deployment.metadata.name.clone(),
-                    namespace: Some(namespace.to_string()),
-                };
-                by_key.insert(key, converted);
+                insert_rendered_object(
+                    &Service {
+                        metadata: companion_metadata.clone(),
+                        spec: Some(service_spec.clone()),
+                        status: None,
+                    },
+                    cluster,
+                    name,
+                    namespace,
+                    by_key,
+                )?;
             }
         }
+
+        if companions.service_export {
+            insert_rendered_object(
+                &json!({
+                    "apiVersion": "multicluster.x-k8s.io/v1alpha1",
+                    "kind": "ServiceExport",
+                    "metadata": {
+                        "labels": companion_metadata.labels,
+                        "name": name,
+                        "namespace": namespace,
+                    },
+                }),
+                cluster,
+                name,
+                namespace,
+                by_key,
+            )?;
+        }
+
+        if let Some(disruption_budget_spec) = &companions.disruption_budget {
+            insert_rendered_object(
+                &PodDisruptionBudget {
+                    metadata: companion_metadata.clone(),
+                    spec: Some(disruption_budget_spec.clone()),
+                    status: None,
+                },
+                cluster,
+                name,
+                namespace,
+                by_key,
+            )?;
+        }
+
+        if let Some(ingress_spec) = &companions.ingress {
+            insert_rendered_object(
+                &Ingress {
+                    metadata: companion_metadata.clone(),
+                    spec: Some(ingress_spec.clone()),
+                    status: None,
+                },
+                cluster,
+                name,
+                namespace,
+                by_key,
+            )?;
+        }
     }
     Ok(())
 }
 
+// Converts a typed Kubernetes object into a DynamicObject and files it under its key.
+fn insert_rendered_object(
+    object: &impl Serialize,
+    cluster: &str,
+    name: &str,
+    namespace: &str,
+    by_key: &mut BTreeMap<KubernetesKey, DynamicObject>,
+) -> Result<()> {
+    let serialized = serde_yaml::to_string(object)?;
+    let converted = DynamicObject::deserialize(serde_yaml::Deserializer::from_str(&serialized))?;
+    let types = converted
+        .types
+        .clone()
+        .ok_or_else(|| anyhow!("Object {} is type-free", converted.name_any()))?;
+    let key = KubernetesKey {
+        api_version: types.api_version,
+        cluster: cluster.to_string(),
+        kind: types.kind,
+        name: name.to_string(),
+        namespace: Some(namespace.to_string()),
+    };
+    by_key.insert(key, converted);
+    Ok(())
+}
+
 #[derive(Debug)]
 enum RenderedArgument {
     String(String),
@@ -684,6 +1596,38 @@ fn render_argument(
         return Ok(None);
     };
     Ok(Some(match single {
+        Argument::ConfigMapVariable(var) => {
+            let source = KubernetesConfigMapKeyRef {
+                name: var.name.clone(),
+                key: var.key.clone(),
+            };
+            match &var.path {
+                Some(path) => render_file_variable(
+                    &FileVariable {
+                        name: var.name.clone(),
+                        path: path.clone(),
+                    },
+                    &VariableSource::ConfigMapKeyRef(source),
+                    volumes,
+                    volume_mounts,
+                )?,
+                None => RenderedArgument::ValueFrom(EnvVarSource {
+                    config_map_key_ref: Some(ConfigMapKeySelector {
+                        name: source.name,
+                        key: source.key,
+                        optional: None,
+                    }),
+                    ..Default::default()
+                }),
+            }
+        }
+        Argument::FieldVariable(var) => RenderedArgument::ValueFrom(EnvVarSource {
+            field_ref: Some(ObjectFieldSelector {
+                api_version: None,
+                field_path: var.path.clone(),
+            }),
+            ..Default::default()
+        }),
         Argument::FileVariable(var) => {
             let source = variables
                 .get(&var.name)
@@ -701,6 +1645,15 @@ fn render_argument(
             ports.insert(v.name.clone(), port);
             RenderedArgument::String(number.to_string())
         }
+        Argument::ResourceFieldVariable(var) => RenderedArgument::ValueFrom(EnvVarSource {
+            // Without a container name this refers to the container the variable is set in
+            resource_field_ref: Some(ResourceFieldSelector {
+                container_name: None,
+                divisor: var.divisor.clone().map(Quantity),
+                resource: var.resource.clone(),
+            }),
+            ..Default::default()
+        }),
         Argument::String(v) => RenderedArgument::String(v.clone()),
         Argument::StringVariable(v) => {
             let mut source = EnvVarSource::default();
@@ -708,6 +1661,16 @@ fn render_argument(
                 .get(&v.name)
                 .ok_or_else(|| anyhow!("Variable {} isn't set", v.name))?;
             match variable {
+                VariableSource::ConfigMapKeyRef(v) => {
+                    source.config_map_key_ref = Some(ConfigMapKeySelector {
+                        name: v.name.clone(),
+                        key: v.key.clone(),
+                        optional: None,
+                    });
+                }
+                VariableSource::ExternalSecret(_) => {
+                    bail!("External secret {} should already have been resolved", v.name)
+                }
                 VariableSource::SecretKeyRef(v) => {
                     source.secret_key_ref = Some(SecretKeySelector {
                         name: v.name.clone(),
@@ -737,17 +1700,48 @@ fn render_file_variable(
         .ok_or_else(|| anyhow!("Variable path has no parent"))?
         .to_string_lossy();
 
-    let volume = match source {
+    let (volume, key) = match source {
+        VariableSource::ConfigMapKeyRef(config_map_source) => {
+            let existing_volume = volumes.iter().position(|volume| {
+                volume
+                    .config_map
+                    .as_ref()
+                    .map(|config_map| config_map.name == config_map_source.name)
+                    .unwrap_or(false)
+            });
+            let index = match existing_volume {
+                Some(i) => i,
+                None => {
+                    let mut volume = Volume::default();
+                    volume.name = variable.name.clone();
+                    volume.config_map = Some(ConfigMapVolumeSource {
+                        default_mode: Some(420),
+                        items: Some(Vec::new()),
+                        name: config_map_source.name.clone(),
+                        optional: None,
+                    });
+                    volumes.push(volume);
+                    volumes.len() - 1
+                }
+            };
+            (&mut volumes[index], &config_map_source.key)
+        }
+        VariableSource::ExternalSecret(_) => {
+            bail!(
+                "External secret {} should already have been resolved",
+                variable.name
+            );
+        }
         VariableSource::SecretKeyRef(secret_source) => {
-            let existing_volume = volumes.iter_mut().find(|volume| {
+            let existing_volume = volumes.iter().position(|volume| {
                 volume
                     .secret
                     .as_ref()
                     .map(|secret| secret.secret_name.as_ref() == Some(&secret_source.name))
                     .unwrap_or(false)
             });
-            match existing_volume {
-                Some(v) => v,
+            let index = match existing_volume {
+                Some(i) => i,
                 None => {
                     let mut volume = Volume::default();
                     volume.name = variable.name.clone();
@@ -760,55 +1754,38 @@ fn render_file_variable(
                     secret.items = Some(Vec::new());
                     volume.secret = Some(secret);
                     volumes.push(volume);
-                    volumes.last_mut().unwrap()
+                    volumes.len() - 1
                 }
-            }
+            };
+            (&mut volumes[index], &secret_source.key)
         }
     };
 
-    match source {
-        VariableSource::SecretKeyRef(_) => {
-            let existing_mount = volume_mounts
-                .iter()
-                .find(|mount| mount.name == volume.name && mount.mount_path == parent);
-            match existing_mount {
-                Some(m) => m,
-                None => {
-                    // TODO(april): can we mount the same volume multiple times?
-                    let mut mount = VolumeMount::default();
-                    mount.name = volume.name.clone();
-                    mount.read_only = Some(true);
-                    mount.mount_path = String::from(parent);
-                    volume_mounts.push(mount);
-                    volume_mounts.last().unwrap()
-                }
-            }
-        }
-    };
+    let existing_mount = volume_mounts
+        .iter()
+        .any(|mount| mount.name == volume.name && mount.mount_path == parent);
+    if !existing_mount {
+        // TODO(april): can we mount the same volume multiple times?
+        let mut mount = VolumeMount::default();
+        mount.name = volume.name.clone();
+        mount.read_only = Some(true);
+        mount.mount_path = String::from(parent);
+        volume_mounts.push(mount);
+    }
 
-    match source {
-        VariableSource::SecretKeyRef(secret_source) => {
-            let Some(secret) = volume.secret.as_mut() else {
-                unreachable!("Expected secret");
-            };
-            let Some(items) = secret.items.as_mut() else {
-                unreachable!("Expected items");
-            };
-            let existing_item = items
-                .iter()
-                .find(|i| secret_source.key == i.key && filename == i.path);
-            match existing_item {
-                Some(_) => (),
-                None => {
-                    items.push(KeyToPath {
-                        key: secret_source.key.clone(),
-                        mode: None,
-                        path: String::from(filename),
-                    });
-                }
-            }
-        }
+    let items = match (volume.secret.as_mut(), volume.config_map.as_mut()) {
+        (Some(secret), _) => secret.items.get_or_insert_with(Vec::new),
+        (None, Some(config_map)) => config_map.items.get_or_insert_with(Vec::new),
+        (None, None) => unreachable!("Expected a secret or config map volume"),
     };
+    let existing_item = items.iter().any(|i| *key == i.key && filename == i.path);
+    if !existing_item {
+        items.push(KeyToPath {
+            key: key.clone(),
+            mode: None,
+            path: String::from(filename),
+        });
+    }
 
     Ok(RenderedArgument::String(variable.path.clone()))
 }
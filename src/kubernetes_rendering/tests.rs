@@ -1,6 +1,7 @@
 use super::*;
 use crate::config_image::{Port, Protocol};
-use crate::sisyphus_yaml::ServicePort as SisyphusServicePort;
+use crate::kubernetes_io::owner_key;
+use crate::sisyphus_yaml::{DeploymentIngressConfig, ServicePort as SisyphusServicePort};
 
 #[test]
 fn test_process_cronjob_footprint() -> Result<()> {
@@ -12,19 +13,39 @@ fn test_process_cronjob_footprint() -> Result<()> {
             name: "test-cronjob".to_string(),
             labels: BTreeMap::new(),
             annotations: BTreeMap::new(),
+            namespace: None,
         },
         config: CronJobConfig {
             concurrency_policy: None,
             env: "prod".to_string(),
+            failed_jobs_history_limit: None,
             image: "test-image".to_string(),
+            image_pull_secrets: Vec::new(),
             restart_policy: None,
             schedule: "0 0 * * *".to_string(),
+            starting_deadline_seconds: None,
+            successful_jobs_history_limit: None,
+            suspend: None,
             variables: BTreeMap::new(),
         },
         footprint: BTreeMap::from([
-            ("cluster1".to_string(), CronJobFootprintEntry {}),
-            ("cluster2".to_string(), CronJobFootprintEntry {}),
+            (
+                "cluster1".to_string(),
+                CronJobFootprintEntry {
+                    image_pull_secrets: None,
+                    variables: BTreeMap::new(),
+                },
+            ),
+            (
+                "cluster2".to_string(),
+                CronJobFootprintEntry {
+                    image_pull_secrets: None,
+                    variables: BTreeMap::new(),
+                },
+            ),
         ]),
+        defined_in: None,
+        document: None,
     };
 
     let metadata = ObjectMeta {
@@ -41,7 +62,7 @@ fn test_process_cronjob_footprint() -> Result<()> {
     container.name = "test-cronjob".to_string();
     container.image = Some("test-image:latest".to_string());
 
-    let pod_spec = build_pod_spec(container, "OnFailure", Vec::new());
+    let pod_spec = build_pod_spec(vec![container], "OnFailure", Vec::new());
 
     let mut by_key = BTreeMap::new();
 
@@ -81,16 +102,30 @@ fn test_cronjob_spec_structure() -> Result<()> {
             name: "test-cronjob".to_string(),
             labels: BTreeMap::new(),
             annotations: BTreeMap::new(),
+            namespace: None,
         },
         config: CronJobConfig {
             concurrency_policy: None,
             env: "prod".to_string(),
+            failed_jobs_history_limit: None,
             image: "test-image".to_string(),
+            image_pull_secrets: Vec::new(),
             restart_policy: None,
             schedule: "*/5 * * * *".to_string(),
+            starting_deadline_seconds: None,
+            successful_jobs_history_limit: None,
+            suspend: None,
             variables: BTreeMap::new(),
         },
-        footprint: BTreeMap::from([("cluster1".to_string(), CronJobFootprintEntry {})]),
+        footprint: BTreeMap::from([(
+            "cluster1".to_string(),
+            CronJobFootprintEntry {
+                image_pull_secrets: None,
+                variables: BTreeMap::new(),
+            },
+        )]),
+        defined_in: None,
+        document: None,
     };
 
     let metadata = ObjectMeta {
@@ -103,7 +138,7 @@ fn test_cronjob_spec_structure() -> Result<()> {
     container.name = "test-cronjob".to_string();
     container.image = Some("test-image:latest".to_string());
 
-    let pod_spec = build_pod_spec(container, "OnFailure", Vec::new());
+    let pod_spec = build_pod_spec(vec![container], "OnFailure", Vec::new());
 
     let mut by_key = BTreeMap::new();
 
@@ -149,16 +184,30 @@ fn test_cronjob_concurrency_policy() -> Result<()> {
             name: "test-cronjob".to_string(),
             labels: BTreeMap::new(),
             annotations: BTreeMap::new(),
+            namespace: None,
         },
         config: CronJobConfig {
             concurrency_policy: Some("Forbid".to_string()),
             env: "prod".to_string(),
+            failed_jobs_history_limit: None,
             image: "test-image".to_string(),
+            image_pull_secrets: Vec::new(),
             restart_policy: None,
             schedule: "0 * * * *".to_string(),
+            starting_deadline_seconds: None,
+            successful_jobs_history_limit: None,
+            suspend: None,
             variables: BTreeMap::new(),
         },
-        footprint: BTreeMap::from([("cluster1".to_string(), CronJobFootprintEntry {})]),
+        footprint: BTreeMap::from([(
+            "cluster1".to_string(),
+            CronJobFootprintEntry {
+                image_pull_secrets: None,
+                variables: BTreeMap::new(),
+            },
+        )]),
+        defined_in: None,
+        document: None,
     };
 
     let metadata = ObjectMeta {
@@ -171,7 +220,7 @@ fn test_cronjob_concurrency_policy() -> Result<()> {
     container.name = "test-cronjob".to_string();
     container.image = Some("test-image:latest".to_string());
 
-    let pod_spec = build_pod_spec(container, "OnFailure", Vec::new());
+    let pod_spec = build_pod_spec(vec![container], "OnFailure", Vec::new());
 
     let mut by_key = BTreeMap::new();
 
@@ -193,6 +242,254 @@ fn test_cronjob_concurrency_policy() -> Result<()> {
     Ok(())
 }
 
+#[test]
+fn test_cronjob_history_and_deadline() -> Result<()> {
+    use crate::sisyphus_yaml::{CronJobConfig, CronJobFootprintEntry, Metadata, SisyphusCronJob};
+
+    let cronjob = SisyphusCronJob {
+        api_version: "sisyphus/v1".to_string(),
+        metadata: Metadata {
+            name: "test-cronjob".to_string(),
+            labels: BTreeMap::new(),
+            annotations: BTreeMap::new(),
+            namespace: None,
+        },
+        config: CronJobConfig {
+            concurrency_policy: None,
+            env: "prod".to_string(),
+            failed_jobs_history_limit: Some(5),
+            image: "test-image".to_string(),
+            image_pull_secrets: Vec::new(),
+            restart_policy: None,
+            schedule: "0 * * * *".to_string(),
+            starting_deadline_seconds: Some(300),
+            successful_jobs_history_limit: Some(1),
+            suspend: Some(true),
+            variables: BTreeMap::new(),
+        },
+        footprint: BTreeMap::from([(
+            "cluster1".to_string(),
+            CronJobFootprintEntry {
+                image_pull_secrets: None,
+                variables: BTreeMap::new(),
+            },
+        )]),
+        defined_in: None,
+        document: None,
+    };
+
+    let metadata = ObjectMeta {
+        name: Some("test-cronjob".to_string()),
+        namespace: Some("default".to_string()),
+        ..Default::default()
+    };
+
+    let mut container = Container::default();
+    container.name = "test-cronjob".to_string();
+    container.image = Some("test-image:latest".to_string());
+
+    let pod_spec = build_pod_spec(vec![container], "OnFailure", Vec::new());
+
+    let mut by_key = BTreeMap::new();
+
+    process_cronjob_footprint(
+        &cronjob,
+        &metadata,
+        &None,
+        "0 * * * *",
+        &pod_spec,
+        "default",
+        &mut by_key,
+    )?;
+
+    let cronjob_obj = by_key.values().next().unwrap();
+    let spec = cronjob_obj.data.get("spec").unwrap();
+    assert_eq!(spec["failedJobsHistoryLimit"], 5);
+    assert_eq!(spec["startingDeadlineSeconds"], 300);
+    assert_eq!(spec["successfulJobsHistoryLimit"], 1);
+    assert_eq!(spec["suspend"], true);
+
+    Ok(())
+}
+
+#[test]
+fn test_process_job_footprint() -> Result<()> {
+    use crate::sisyphus_yaml::{JobConfig, JobFootprintEntry, Metadata, SisyphusJob};
+
+    let job = SisyphusJob {
+        api_version: "sisyphus/v1".to_string(),
+        metadata: Metadata {
+            name: "test-migration".to_string(),
+            labels: BTreeMap::new(),
+            annotations: BTreeMap::new(),
+            namespace: None,
+        },
+        config: JobConfig {
+            backoff_limit: Some(2),
+            completions: Some(1),
+            env: "prod".to_string(),
+            image: "test-image".to_string(),
+            image_pull_secrets: Vec::new(),
+            restart_policy: None,
+            ttl_seconds_after_finished: Some(3600),
+            variables: BTreeMap::new(),
+        },
+        footprint: BTreeMap::from([
+            (
+                "cluster1".to_string(),
+                JobFootprintEntry {
+                    image_pull_secrets: None,
+                    variables: BTreeMap::new(),
+                },
+            ),
+            (
+                "cluster2".to_string(),
+                JobFootprintEntry {
+                    image_pull_secrets: None,
+                    variables: BTreeMap::new(),
+                },
+            ),
+        ]),
+        defined_in: None,
+        document: None,
+    };
+
+    let metadata = ObjectMeta {
+        name: Some("test-migration".to_string()),
+        namespace: Some("default".to_string()),
+        labels: Some(BTreeMap::from([(
+            NAME_LABEL.to_string(),
+            "test-migration".to_string(),
+        )])),
+        ..Default::default()
+    };
+
+    let mut container = Container::default();
+    container.name = "test-migration".to_string();
+    container.image = Some("test-image:latest".to_string());
+
+    let pod_spec = build_pod_spec(vec![container], "OnFailure", Vec::new());
+
+    let mut by_key = BTreeMap::new();
+
+    process_job_footprint(&job, &metadata, &pod_spec, "default", &mut by_key)?;
+
+    assert_eq!(by_key.len(), 2);
+    let key = by_key.keys().find(|k| k.cluster == "cluster1").unwrap();
+    assert_eq!(key.kind, "Job");
+    assert_eq!(key.api_version, "batch/v1");
+    assert_eq!(key.namespace, Some("default".to_string()));
+
+    let spec = &by_key[key].data["spec"];
+    assert_eq!(spec["backoffLimit"], 2);
+    assert_eq!(spec["completions"], 1);
+    assert_eq!(spec["ttlSecondsAfterFinished"], 3600);
+    assert_eq!(spec["template"]["spec"]["restartPolicy"], "OnFailure");
+    assert_eq!(
+        spec["template"]["metadata"]["labels"][NAME_LABEL],
+        "test-migration"
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_process_job_footprint_image_pull_secrets() -> Result<()> {
+    use crate::sisyphus_yaml::{
+        ImagePullSecret, JobConfig, JobFootprintEntry, Metadata, SisyphusJob,
+    };
+
+    let pull_secret = |name: &str| ImagePullSecret {
+        from_docker_credentials: Vec::new(),
+        name: name.to_string(),
+    };
+    let job = SisyphusJob {
+        api_version: "sisyphus/v1".to_string(),
+        metadata: Metadata {
+            name: "test-migration".to_string(),
+            labels: BTreeMap::new(),
+            annotations: BTreeMap::new(),
+            namespace: None,
+        },
+        config: JobConfig {
+            backoff_limit: None,
+            completions: None,
+            env: "prod".to_string(),
+            image: "test-image".to_string(),
+            image_pull_secrets: vec![pull_secret("regcred")],
+            restart_policy: None,
+            ttl_seconds_after_finished: None,
+            variables: BTreeMap::new(),
+        },
+        footprint: BTreeMap::from([
+            (
+                "cluster1".to_string(),
+                JobFootprintEntry {
+                    image_pull_secrets: None,
+                    variables: BTreeMap::new(),
+                },
+            ),
+            (
+                "cluster2".to_string(),
+                JobFootprintEntry {
+                    image_pull_secrets: Some(vec![pull_secret("cluster2-regcred")]),
+                    variables: BTreeMap::new(),
+                },
+            ),
+        ]),
+        defined_in: None,
+        document: None,
+    };
+
+    let metadata = ObjectMeta {
+        name: Some("test-migration".to_string()),
+        namespace: Some("default".to_string()),
+        ..Default::default()
+    };
+
+    let pod_spec = build_pod_spec(vec![Container::default()], "OnFailure", Vec::new());
+
+    let mut by_key = BTreeMap::new();
+
+    process_job_footprint(&job, &metadata, &pod_spec, "default", &mut by_key)?;
+
+    let pull_secrets = |cluster: &str| {
+        let key = by_key.keys().find(|k| k.cluster == cluster).unwrap();
+        by_key[key].data["spec"]["template"]["spec"]["imagePullSecrets"].clone()
+    };
+    assert_eq!(pull_secrets("cluster1"), json!([{"name": "regcred"}]));
+    assert_eq!(
+        pull_secrets("cluster2"),
+        json!([{"name": "cluster2-regcred"}])
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_insert_rendered_keeps_one_pull_secret() -> Result<()> {
+    let key = crate::kubernetes_io::test_key("cluster1", "v1", "Secret", Some("echo"), "regcred");
+    let rendered = |registries: &[&str], origin: &str| -> Result<_> {
+        let registries: Vec<String> = registries.iter().map(|r| r.to_string()).collect();
+        let secret = build_docker_config_secret("regcred", "echo", &registries);
+        let mut object: DynamicObject = serde_json::from_value(serde_json::to_value(secret)?)?;
+        object
+            .annotations_mut()
+            .insert(ORIGIN_ANNOTATION.to_string(), origin.to_string());
+        Ok(BTreeMap::from([(key.clone(), object)]))
+    };
+
+    let mut by_key = BTreeMap::new();
+    insert_rendered(&mut by_key, rendered(&["ghcr.io"], "api")?)?;
+    insert_rendered(&mut by_key, rendered(&["ghcr.io"], "worker")?)?;
+    assert_eq!(by_key.len(), 1);
+    assert_eq!(by_key[&key].annotations()[ORIGIN_ANNOTATION], "api");
+
+    let error = insert_rendered(&mut by_key, rendered(&["quay.io"], "cron")?).unwrap_err();
+    assert!(error.to_string().contains("is rendered by both"));
+    Ok(())
+}
+
 #[test]
 fn test_process_deployment_footprint() -> Result<()> {
     use crate::sisyphus_yaml::{
@@ -205,23 +502,37 @@ fn test_process_deployment_footprint() -> Result<()> {
             name: "test-deployment".to_string(),
             labels: BTreeMap::new(),
             annotations: BTreeMap::new(),
+            namespace: None,
         },
         config: DeploymentConfig {
+            disruption_budget: None,
             env: "prod".to_string(),
             image: "test-image".to_string(),
+            image_pull_secrets: Vec::new(),
             service: None,
+            spread: None,
             variables: BTreeMap::new(),
         },
         footprint: BTreeMap::from([
             (
                 "cluster1".to_string(),
-                DeploymentFootprintEntry { replicas: 3 },
+                DeploymentFootprintEntry {
+                    image_pull_secrets: None,
+                    replicas: 3,
+                    variables: BTreeMap::new(),
+                },
             ),
             (
                 "cluster2".to_string(),
-                DeploymentFootprintEntry { replicas: 5 },
+                DeploymentFootprintEntry {
+                    image_pull_secrets: None,
+                    replicas: 5,
+                    variables: BTreeMap::new(),
+                },
             ),
         ]),
+        defined_in: None,
+        document: None,
     };
 
     let metadata = ObjectMeta {
@@ -244,7 +555,7 @@ fn test_process_deployment_footprint() -> Result<()> {
         &deployment,
         &metadata,
         &deployment_spec,
-        &None,
+        &DeploymentCompanions::default(),
         "default",
         &mut by_key,
     )?;
@@ -296,17 +607,27 @@ fn test_process_deployment_footprint_with_service() -> Result<()> {
             name: "test-deployment".to_string(),
             labels: BTreeMap::new(),
             annotations: BTreeMap::new(),
+            namespace: None,
         },
         config: DeploymentConfig {
+            disruption_budget: None,
             env: "prod".to_string(),
             image: "test-image".to_string(),
+            image_pull_secrets: Vec::new(),
             service: None,
+            spread: None,
             variables: BTreeMap::new(),
         },
         footprint: BTreeMap::from([(
             "cluster1".to_string(),
-            DeploymentFootprintEntry { replicas: 2 },
+            DeploymentFootprintEntry {
+                image_pull_secrets: None,
+                replicas: 2,
+                variables: BTreeMap::new(),
+            },
         )]),
+        defined_in: None,
+        document: None,
     };
 
     let metadata = ObjectMeta {
@@ -334,7 +655,10 @@ fn test_process_deployment_footprint_with_service() -> Result<()> {
         &deployment,
         &metadata,
         &deployment_spec,
-        &Some(service_spec),
+        &DeploymentCompanions {
+            service: Some(service_spec),
+            ..Default::default()
+        },
         "default",
         &mut by_key,
     )?;
@@ -351,6 +675,120 @@ fn test_process_deployment_footprint_with_service() -> Result<()> {
     assert_eq!(service_keys.len(), 1);
     assert_eq!(service_keys[0].api_version, "v1");
 
+    // Only the Service is marked as belonging to the Deployment
+    let service = &by_key[service_keys[0]];
+    assert_eq!(
+        service.labels().get(PART_OF_LABEL),
+        Some(&"test-deployment".to_string())
+    );
+    assert_eq!(owner_key(service_keys[0], service), Some(deployment_keys[0].clone()));
+    assert_eq!(by_key[deployment_keys[0]].labels().get(PART_OF_LABEL), None);
+
+    Ok(())
+}
+
+#[test]
+fn test_build_service_export() -> Result<()> {
+    let config_service = |export| {
+        Some(DeploymentServiceConfig {
+            export,
+            ingress: None,
+            ports: BTreeMap::new(),
+        })
+    };
+    let with_service = DeploymentCompanions {
+        service: Some(ServiceSpec::default()),
+        ..Default::default()
+    };
+
+    assert!(!build_service_export(&None, &with_service)?);
+    assert!(!build_service_export(
+        &config_service(false),
+        &with_service
+    )?);
+    assert!(build_service_export(&config_service(true), &with_service)?);
+    // There's nothing to export without a Service
+    assert!(build_service_export(&config_service(true), &DeploymentCompanions::default()).is_err());
+
+    Ok(())
+}
+
+#[test]
+fn test_process_deployment_footprint_with_service_export() -> Result<()> {
+    use crate::sisyphus_yaml::{
+        DeploymentConfig, DeploymentFootprintEntry, Metadata, SisyphusDeployment,
+    };
+
+    let deployment = SisyphusDeployment {
+        api_version: "sisyphus/v1".to_string(),
+        metadata: Metadata {
+            name: "echo".to_string(),
+            labels: BTreeMap::new(),
+            annotations: BTreeMap::new(),
+            namespace: None,
+        },
+        config: DeploymentConfig {
+            disruption_budget: None,
+            env: "prod".to_string(),
+            image: "test-image".to_string(),
+            image_pull_secrets: Vec::new(),
+            service: None,
+            spread: None,
+            variables: BTreeMap::new(),
+        },
+        footprint: BTreeMap::from([(
+            "cluster1".to_string(),
+            DeploymentFootprintEntry {
+                image_pull_secrets: None,
+                replicas: 2,
+                variables: BTreeMap::new(),
+            },
+        )]),
+        defined_in: None,
+        document: None,
+    };
+
+    let metadata = ObjectMeta {
+        name: Some("echo".to_string()),
+        namespace: Some("echo".to_string()),
+        ..Default::default()
+    };
+    let labels = BTreeMap::from([(NAME_LABEL.to_string(), "echo".to_string())]);
+    let deployment_spec =
+        build_base_deployment_spec(labels.clone(), labels.clone(), BTreeMap::new());
+    let service_spec = ServiceSpec {
+        ports: Some(vec![ServicePort {
+            port: 80,
+            ..Default::default()
+        }]),
+        selector: Some(labels),
+        ..Default::default()
+    };
+
+    let mut by_key = BTreeMap::new();
+
+    process_deployment_footprint(
+        &deployment,
+        &metadata,
+        &deployment_spec,
+        &DeploymentCompanions {
+            service: Some(service_spec),
+            service_export: true,
+            ..Default::default()
+        },
+        "echo",
+        &mut by_key,
+    )?;
+
+    let key = by_key.keys().find(|k| k.kind == "ServiceExport").unwrap();
+    assert_eq!(key.api_version, "multicluster.x-k8s.io/v1alpha1");
+    assert_eq!(key.name, "echo");
+    assert_eq!(key.namespace, Some("echo".to_string()));
+    assert_eq!(
+        by_key[key].labels().get(PART_OF_LABEL),
+        Some(&"echo".to_string())
+    );
+
     Ok(())
 }
 
@@ -395,7 +833,7 @@ fn test_build_pod_spec() {
     volume.name = "test-volume".to_string();
     let volumes = vec![volume.clone()];
 
-    let pod_spec = build_pod_spec(container.clone(), "Always", volumes.clone());
+    let pod_spec = build_pod_spec(vec![container.clone()], "Always", volumes.clone());
 
     // Verify container
     assert_eq!(pod_spec.containers.len(), 1);
@@ -420,7 +858,7 @@ fn test_build_pod_spec_empty_volumes() {
     let mut container = Container::default();
     container.name = "test-container".to_string();
 
-    let pod_spec = build_pod_spec(container, "Always", Vec::new());
+    let pod_spec = build_pod_spec(vec![container], "Always", Vec::new());
     assert_eq!(pod_spec.volumes, None);
 }
 
@@ -572,6 +1010,8 @@ fn test_build_service_spec_with_ports() -> Result<()> {
     )]);
 
     let config_service = Some(DeploymentServiceConfig {
+        export: false,
+        ingress: None,
         ports: service_ports,
     });
 
@@ -602,50 +1042,308 @@ fn test_build_service_spec_with_ports() -> Result<()> {
     Ok(())
 }
 
+fn ingress_service_config(
+    ports: &[(&str, Option<&str>)],
+    port: Option<&str>,
+    tls_secret: Option<&str>,
+) -> Option<DeploymentServiceConfig> {
+    Some(DeploymentServiceConfig {
+        export: false,
+        ingress: Some(DeploymentIngressConfig {
+            class_name: Some("nginx".to_string()),
+            host: "echo.example.com".to_string(),
+            path: None,
+            port: port.map(|p| p.to_string()),
+            tls_secret: tls_secret.map(|s| s.to_string()),
+        }),
+        ports: ports
+            .iter()
+            .map(|(k, name)| {
+                (
+                    k.to_string(),
+                    SisyphusServicePort {
+                        name: name.map(|n| n.to_string()),
+                        number: 80,
+                    },
+                )
+            })
+            .collect(),
+    })
+}
+
 #[test]
-fn test_render_argument_string() -> Result<()> {
-    let arg = ArgumentValues::Uniform(Argument::String("test-value".to_string()));
-    let selector = "prod";
-    let port_numbers = BTreeMap::new();
-    let mut ports = BTreeMap::new();
-    let variables = BTreeMap::new();
-    let mut volumes = Vec::new();
-    let mut volume_mounts = Vec::new();
+fn test_build_ingress_spec_without_ingress() -> Result<()> {
+    let config_service = Some(DeploymentServiceConfig {
+        export: false,
+        ingress: None,
+        ports: BTreeMap::new(),
+    });
 
-    let result = render_argument(
-        &arg,
-        selector,
-        &port_numbers,
-        &mut ports,
-        &variables,
-        &mut volumes,
-        &mut volume_mounts,
-    )?;
+    assert!(build_ingress_spec(&config_service, "echo")?.is_none());
+    assert!(build_ingress_spec(&None, "echo")?.is_none());
 
-    let Some(RenderedArgument::String(s)) = result else {
-        panic!("Expected String variant");
-    };
-    assert_eq!(s, "test-value");
     Ok(())
 }
 
 #[test]
-fn test_render_argument_port() -> Result<()> {
-    let port = Port {
-        name: "http".to_string(),
-        number: Some(8080),
-        protocol: Protocol::TCP,
-    };
-    let arg = ArgumentValues::Uniform(Argument::Port(port));
-    let selector = "prod";
-    let port_numbers = BTreeMap::from([("http".to_string(), 8080u16)]);
-    let mut ports = BTreeMap::new();
-    let variables = BTreeMap::new();
-    let mut volumes = Vec::new();
-    let mut volume_mounts = Vec::new();
+fn test_build_ingress_spec_single_port() -> Result<()> {
+    let config_service = ingress_service_config(&[("http", Some("web"))], None, Some("echo-tls"));
+
+    let spec = build_ingress_spec(&config_service, "echo")?.unwrap();
+
+    assert_eq!(spec.ingress_class_name, Some("nginx".to_string()));
+    let rules = spec.rules.unwrap();
+    assert_eq!(rules.len(), 1);
+    assert_eq!(rules[0].host, Some("echo.example.com".to_string()));
+    let path = &rules[0].http.as_ref().unwrap().paths[0];
+    assert_eq!(path.path, Some("/".to_string()));
+    assert_eq!(path.path_type, "Prefix");
+    let backend = path.backend.service.as_ref().unwrap();
+    assert_eq!(backend.name, "echo");
+    // The backend uses the service's (renamed) port name
+    assert_eq!(
+        backend.port.as_ref().unwrap().name,
+        Some("web".to_string())
+    );
 
-    let result = render_argument(
-        &arg,
+    let tls = spec.tls.unwrap();
+    assert_eq!(tls[0].secret_name, Some("echo-tls".to_string()));
+    assert_eq!(tls[0].hosts, Some(vec!["echo.example.com".to_string()]));
+
+    Ok(())
+}
+
+#[test]
+fn test_build_ingress_spec_multiple_ports_requires_port() -> Result<()> {
+    let ports = [("http", None), ("grpc", None)];
+
+    assert!(build_ingress_spec(&ingress_service_config(&ports, None, None), "echo").is_err());
+    assert!(
+        build_ingress_spec(&ingress_service_config(&ports, Some("admin"), None), "echo").is_err()
+    );
+
+    let spec = build_ingress_spec(&ingress_service_config(&ports, Some("grpc"), None), "echo")?
+        .unwrap();
+    let rules = spec.rules.unwrap();
+    let path = &rules[0].http.as_ref().unwrap().paths[0];
+    assert_eq!(
+        path.backend.service.as_ref().unwrap().port.as_ref().unwrap().name,
+        Some("grpc".to_string())
+    );
+    assert!(spec.tls.is_none());
+
+    Ok(())
+}
+
+#[test]
+fn test_build_disruption_budget_spec_none() -> Result<()> {
+    let result = build_disruption_budget_spec(&None, BTreeMap::new())?;
+
+    assert!(result.is_none());
+
+    Ok(())
+}
+
+#[test]
+fn test_build_disruption_budget_spec_min_available() -> Result<()> {
+    let config = Some(DisruptionBudgetConfig {
+        max_unavailable: None,
+        min_available: Some(IntOrString::Int(2)),
+    });
+    let selector = BTreeMap::from([(NAME_LABEL.to_string(), "test".to_string())]);
+
+    let spec = build_disruption_budget_spec(&config, selector.clone())?.unwrap();
+
+    assert_eq!(spec.min_available, Some(IntOrString::Int(2)));
+    assert_eq!(spec.max_unavailable, None);
+    assert_eq!(spec.selector.unwrap().match_labels, Some(selector));
+
+    Ok(())
+}
+
+#[test]
+fn test_build_disruption_budget_spec_requires_exactly_one_bound() {
+    let both = Some(DisruptionBudgetConfig {
+        max_unavailable: Some(IntOrString::String("25%".to_string())),
+        min_available: Some(IntOrString::Int(1)),
+    });
+    assert!(build_disruption_budget_spec(&both, BTreeMap::new()).is_err());
+
+    let neither = Some(DisruptionBudgetConfig {
+        max_unavailable: None,
+        min_available: None,
+    });
+    assert!(build_disruption_budget_spec(&neither, BTreeMap::new()).is_err());
+}
+
+#[test]
+fn test_apply_spread() {
+    let selector = BTreeMap::from([(NAME_LABEL.to_string(), "echo".to_string())]);
+    let mut pod_spec = build_pod_spec(vec![Container::default()], "Always", Vec::new());
+
+    apply_spread(&mut pod_spec, Spread::Zone, &selector);
+
+    let constraints = pod_spec.topology_spread_constraints.unwrap();
+    assert_eq!(constraints.len(), 1);
+    assert_eq!(constraints[0].topology_key, "topology.kubernetes.io/zone");
+    assert_eq!(constraints[0].when_unsatisfiable, "ScheduleAnyway");
+    assert_eq!(
+        constraints[0].label_selector.as_ref().unwrap().match_labels,
+        Some(selector.clone())
+    );
+    let preferred = pod_spec
+        .affinity
+        .unwrap()
+        .pod_anti_affinity
+        .unwrap()
+        .preferred_during_scheduling_ignored_during_execution
+        .unwrap();
+    assert_eq!(
+        preferred[0].pod_affinity_term.topology_key,
+        "topology.kubernetes.io/zone"
+    );
+}
+
+#[test]
+fn test_process_deployment_footprint_with_disruption_budget() -> Result<()> {
+    use crate::sisyphus_yaml::{
+        DeploymentConfig, DeploymentFootprintEntry, Metadata, SisyphusDeployment,
+    };
+
+    let deployment = SisyphusDeployment {
+        api_version: "sisyphus/v1".to_string(),
+        metadata: Metadata {
+            name: "test-deployment".to_string(),
+            labels: BTreeMap::new(),
+            annotations: BTreeMap::new(),
+            namespace: None,
+        },
+        config: DeploymentConfig {
+            disruption_budget: None,
+            env: "prod".to_string(),
+            image: "test-image".to_string(),
+            image_pull_secrets: Vec::new(),
+            service: None,
+            spread: None,
+            variables: BTreeMap::new(),
+        },
+        footprint: BTreeMap::from([
+            (
+                "cluster1".to_string(),
+                DeploymentFootprintEntry {
+                    image_pull_secrets: None,
+                    replicas: 2,
+                    variables: BTreeMap::new(),
+                },
+            ),
+            (
+                "cluster2".to_string(),
+                DeploymentFootprintEntry {
+                    image_pull_secrets: None,
+                    replicas: 2,
+                    variables: BTreeMap::new(),
+                },
+            ),
+        ]),
+        defined_in: None,
+        document: None,
+    };
+
+    let metadata = ObjectMeta {
+        name: Some("test-deployment".to_string()),
+        namespace: Some("default".to_string()),
+        ..Default::default()
+    };
+
+    let labels = BTreeMap::from([("app".to_string(), "test-deployment".to_string())]);
+    let deployment_spec =
+        build_base_deployment_spec(labels.clone(), labels.clone(), BTreeMap::new());
+    let disruption_budget_spec = build_disruption_budget_spec(
+        &Some(DisruptionBudgetConfig {
+            max_unavailable: Some(IntOrString::Int(1)),
+            min_available: None,
+        }),
+        labels,
+    )?;
+
+    let mut by_key = BTreeMap::new();
+
+    process_deployment_footprint(
+        &deployment,
+        &metadata,
+        &deployment_spec,
+        &DeploymentCompanions {
+            disruption_budget: disruption_budget_spec,
+            ..Default::default()
+        },
+        "default",
+        &mut by_key,
+    )?;
+
+    // One Deployment and one PodDisruptionBudget per cluster
+    assert_eq!(by_key.len(), 4);
+    let budget_keys: Vec<_> = by_key
+        .keys()
+        .filter(|k| k.kind == "PodDisruptionBudget")
+        .collect();
+    assert_eq!(budget_keys.len(), 2);
+    assert_eq!(budget_keys[0].api_version, "policy/v1");
+    assert_eq!(budget_keys[0].name, "test-deployment");
+
+    let budget = by_key.get(budget_keys[0]).unwrap();
+    assert_eq!(
+        budget.data["spec"]["selector"]["matchLabels"]["app"],
+        "test-deployment"
+    );
+    assert_eq!(budget.data["spec"]["maxUnavailable"], 1);
+
+    Ok(())
+}
+
+#[test]
+fn test_render_argument_string() -> Result<()> {
+    let arg = ArgumentValues::Uniform(Argument::String("test-value".to_string()));
+    let selector = "prod";
+    let port_numbers = BTreeMap::new();
+    let mut ports = BTreeMap::new();
+    let variables = BTreeMap::new();
+    let mut volumes = Vec::new();
+    let mut volume_mounts = Vec::new();
+
+    let result = render_argument(
+        &arg,
+        selector,
+        &port_numbers,
+        &mut ports,
+        &variables,
+        &mut volumes,
+        &mut volume_mounts,
+    )?;
+
+    let Some(RenderedArgument::String(s)) = result else {
+        panic!("Expected String variant");
+    };
+    assert_eq!(s, "test-value");
+    Ok(())
+}
+
+#[test]
+fn test_render_argument_port() -> Result<()> {
+    let port = Port {
+        name: "http".to_string(),
+        number: Some(8080),
+        protocol: Protocol::TCP,
+    };
+    let arg = ArgumentValues::Uniform(Argument::Port(port));
+    let selector = "prod";
+    let port_numbers = BTreeMap::from([("http".to_string(), 8080u16)]);
+    let mut ports = BTreeMap::new();
+    let variables = BTreeMap::new();
+    let mut volumes = Vec::new();
+    let mut volume_mounts = Vec::new();
+
+    let result = render_argument(
+        &arg,
         selector,
         &port_numbers,
         &mut ports,
@@ -729,6 +1427,162 @@ fn test_render_argument_varying_not_found() -> Result<()> {
     Ok(())
 }
 
+#[test]
+fn test_render_argument_downward_api_variables() -> Result<()> {
+    use crate::config_image::{FieldVariable, ResourceFieldVariable};
+
+    let port_numbers = BTreeMap::new();
+    let mut ports = BTreeMap::new();
+    let variables = BTreeMap::new();
+    let mut volumes = Vec::new();
+    let mut volume_mounts = Vec::new();
+
+    let field = ArgumentValues::Uniform(Argument::FieldVariable(FieldVariable {
+        path: "metadata.name".to_string(),
+    }));
+    let result = render_argument(
+        &field,
+        "prod",
+        &port_numbers,
+        &mut ports,
+        &variables,
+        &mut volumes,
+        &mut volume_mounts,
+    )?;
+    let Some(RenderedArgument::ValueFrom(source)) = result else {
+        panic!("Expected ValueFrom variant");
+    };
+    assert_eq!(source.field_ref.unwrap().field_path, "metadata.name");
+
+    let resource = ArgumentValues::Uniform(Argument::ResourceFieldVariable(
+        ResourceFieldVariable {
+            resource: "limits.memory".to_string(),
+            divisor: Some("1Mi".to_string()),
+        },
+    ));
+    let result = render_argument(
+        &resource,
+        "prod",
+        &port_numbers,
+        &mut ports,
+        &variables,
+        &mut volumes,
+        &mut volume_mounts,
+    )?;
+    let Some(RenderedArgument::ValueFrom(source)) = result else {
+        panic!("Expected ValueFrom variant");
+    };
+    assert_eq!(
+        source.resource_field_ref,
+        Some(ResourceFieldSelector {
+            container_name: None,
+            divisor: Some(Quantity("1Mi".to_string())),
+            resource: "limits.memory".to_string(),
+        })
+    );
+    assert!(volumes.is_empty());
+    Ok(())
+}
+
+#[test]
+fn test_render_argument_config_map_variable() -> Result<()> {
+    use crate::config_image::ConfigMapVariable;
+
+    let port_numbers = BTreeMap::new();
+    let mut ports = BTreeMap::new();
+    let variables = BTreeMap::new();
+    let mut volumes = Vec::new();
+    let mut volume_mounts = Vec::new();
+
+    let env = ArgumentValues::Uniform(Argument::ConfigMapVariable(ConfigMapVariable {
+        name: "echo-settings".to_string(),
+        key: "log-level".to_string(),
+        path: None,
+    }));
+    let result = render_argument(
+        &env,
+        "prod",
+        &port_numbers,
+        &mut ports,
+        &variables,
+        &mut volumes,
+        &mut volume_mounts,
+    )?;
+    let Some(RenderedArgument::ValueFrom(source)) = result else {
+        panic!("Expected ValueFrom variant");
+    };
+    let selector = source.config_map_key_ref.unwrap();
+    assert_eq!(selector.name, "echo-settings");
+    assert_eq!(selector.key, "log-level");
+    assert!(volumes.is_empty());
+
+    let file = ArgumentValues::Uniform(Argument::ConfigMapVariable(ConfigMapVariable {
+        name: "echo-settings".to_string(),
+        key: "config.yaml".to_string(),
+        path: Some("/etc/echo/config.yaml".to_string()),
+    }));
+    let result = render_argument(
+        &file,
+        "prod",
+        &port_numbers,
+        &mut ports,
+        &variables,
+        &mut volumes,
+        &mut volume_mounts,
+    )?;
+    let Some(RenderedArgument::String(path)) = result else {
+        panic!("Expected String variant");
+    };
+    assert_eq!(path, "/etc/echo/config.yaml");
+    assert_eq!(volumes.len(), 1);
+    let config_map = volumes[0].config_map.as_ref().unwrap();
+    assert_eq!(config_map.name, "echo-settings");
+    assert_eq!(
+        config_map.items.as_ref().unwrap()[0],
+        KeyToPath {
+            key: "config.yaml".to_string(),
+            mode: None,
+            path: "config.yaml".to_string(),
+        }
+    );
+    assert_eq!(volume_mounts.len(), 1);
+    assert_eq!(volume_mounts[0].mount_path, "/etc/echo");
+    Ok(())
+}
+
+#[test]
+fn test_render_argument_string_variable_from_config_map() -> Result<()> {
+    use crate::config_image::StringVariable;
+
+    let arg = ArgumentValues::Uniform(Argument::StringVariable(StringVariable {
+        name: "log-level".to_string(),
+    }));
+    let variables = BTreeMap::from([(
+        "log-level".to_string(),
+        VariableSource::ConfigMapKeyRef(KubernetesConfigMapKeyRef {
+            name: "echo-settings".to_string(),
+            key: "log-level".to_string(),
+        }),
+    )]);
+
+    let result = render_argument(
+        &arg,
+        "prod",
+        &BTreeMap::new(),
+        &mut BTreeMap::new(),
+        &variables,
+        &mut Vec::new(),
+        &mut Vec::new(),
+    )?;
+
+    let Some(RenderedArgument::ValueFrom(source)) = result else {
+        panic!("Expected ValueFrom variant");
+    };
+    assert_eq!(source.config_map_key_ref.unwrap().name, "echo-settings");
+    assert!(source.secret_key_ref.is_none());
+    Ok(())
+}
+
 #[test]
 fn test_deployment_labels_and_annotations_propagate_to_pods() {
     let labels = BTreeMap::from([
@@ -773,16 +1627,30 @@ fn test_cronjob_labels_and_annotations_propagate_to_jobs_and_pods() -> Result<()
             name: "test-cronjob".to_string(),
             labels: labels.clone(),
             annotations: annotations.clone(),
+            namespace: None,
         },
         config: CronJobConfig {
             concurrency_policy: None,
             env: "prod".to_string(),
+            failed_jobs_history_limit: None,
             image: "test-image".to_string(),
+            image_pull_secrets: Vec::new(),
             restart_policy: None,
             schedule: "0 0 * * *".to_string(),
+            starting_deadline_seconds: None,
+            successful_jobs_history_limit: None,
+            suspend: None,
             variables: BTreeMap::new(),
         },
-        footprint: BTreeMap::from([("cluster1".to_string(), CronJobFootprintEntry {})]),
+        footprint: BTreeMap::from([(
+            "cluster1".to_string(),
+            CronJobFootprintEntry {
+                image_pull_secrets: None,
+                variables: BTreeMap::new(),
+            },
+        )]),
+        defined_in: None,
+        document: None,
     };
 
     let metadata = ObjectMeta {
@@ -797,7 +1665,7 @@ fn test_cronjob_labels_and_annotations_propagate_to_jobs_and_pods() -> Result<()
     container.name = "test-cronjob".to_string();
     container.image = Some("test-image:latest".to_string());
 
-    let pod_spec = build_pod_spec(container, "OnFailure", Vec::new());
+    let pod_spec = build_pod_spec(vec![container], "OnFailure", Vec::new());
 
     let mut by_key = BTreeMap::new();
 
@@ -856,15 +1724,15 @@ fn test_build_pod_spec_restart_policy() {
     container.name = "test-container".to_string();
 
     // Test with OnFailure (typical for Jobs/CronJobs)
-    let pod_spec = build_pod_spec(container.clone(), "OnFailure", Vec::new());
+    let pod_spec = build_pod_spec(vec![container.clone()], "OnFailure", Vec::new());
     assert_eq!(pod_spec.restart_policy, Some("OnFailure".to_string()));
 
     // Test with Never (also valid for Jobs/CronJobs)
-    let pod_spec = build_pod_spec(container.clone(), "Never", Vec::new());
+    let pod_spec = build_pod_spec(vec![container.clone()], "Never", Vec::new());
     assert_eq!(pod_spec.restart_policy, Some("Never".to_string()));
 
     // Test with Always (typical for Deployments)
-    let pod_spec = build_pod_spec(container, "Always", Vec::new());
+    let pod_spec = build_pod_spec(vec![container], "Always", Vec::new());
     assert_eq!(pod_spec.restart_policy, Some("Always".to_string()));
 }
 
@@ -897,6 +1765,7 @@ fn app_with_metadata_port(liveness: Option<Probe>, readiness: Option<Probe>) ->
         liveness,
         readiness,
         resources: crate::config_image::Resources::default(),
+        security_context: None,
         startup: None,
     }
 }
@@ -905,6 +1774,7 @@ fn test_index() -> ConfigImageIndex {
     ConfigImageIndex {
         binary_digest: "sha256:abc".to_string(),
         binary_repository: "repo".to_string(),
+        binaries: BTreeMap::new(),
         config_entrypoint: "config.star".to_string(),
     }
 }
@@ -915,8 +1785,14 @@ fn test_build_container_config_renders_probes() -> Result<()> {
         Some(probe("/healthz", "metadata")),
         Some(probe("/readyz", "metadata")),
     );
-    let (container, _, _) =
-        build_container_config("dep", &test_index(), &app, "prod", &BTreeMap::new())?;
+    let (containers, _, _) = build_containers(
+        "dep",
+        &test_index(),
+        &Applications::Single(app),
+        "prod",
+        &BTreeMap::new(),
+    )?;
+    let container = containers.into_iter().next().unwrap();
 
     let liveness = container.liveness_probe.expect("liveness probe");
     let http_get = liveness.http_get.expect("http get");
@@ -931,7 +1807,13 @@ fn test_build_container_config_renders_probes() -> Result<()> {
 #[test]
 fn test_build_container_config_probe_unknown_port_errors() {
     let app = app_with_metadata_port(Some(probe("/healthz", "nope")), None);
-    let result = build_container_config("dep", &test_index(), &app, "prod", &BTreeMap::new());
+    let result = build_containers(
+        "dep",
+        &test_index(),
+        &Applications::Single(app),
+        "prod",
+        &BTreeMap::new(),
+    );
     let err = result.unwrap_err().to_string();
     assert!(err.contains("does not declare"), "{}", err);
 }
@@ -950,8 +1832,14 @@ fn test_build_container_config_probe_timing_fields() -> Result<()> {
         failure_threshold: Some(6),
     };
     let app = app_with_metadata_port(Some(liveness), None);
-    let (container, _, _) =
-        build_container_config("dep", &test_index(), &app, "prod", &BTreeMap::new())?;
+    let (containers, _, _) = build_containers(
+        "dep",
+        &test_index(),
+        &Applications::Single(app),
+        "prod",
+        &BTreeMap::new(),
+    )?;
+    let container = containers.into_iter().next().unwrap();
 
     let probe = container.liveness_probe.expect("liveness probe");
     assert_eq!(probe.initial_delay_seconds, Some(5));
@@ -961,3 +1849,440 @@ fn test_build_container_config_probe_timing_fields() -> Result<()> {
     assert_eq!(probe.failure_threshold, Some(6));
     Ok(())
 }
+
+#[test]
+fn test_build_containers_multiple() -> Result<()> {
+    use crate::config_image::BinaryImage;
+
+    let mut exporter = app_with_metadata_port(None, None);
+    exporter.env = BTreeMap::from([(
+        "PORT_METRICS".to_string(),
+        ArgumentValues::Uniform(Argument::Port(Port {
+            name: "metrics".to_string(),
+            number: None,
+            protocol: Protocol::TCP,
+        })),
+    )]);
+    let applications = Applications::Named(BTreeMap::from([
+        ("app".to_string(), app_with_metadata_port(None, None)),
+        ("exporter".to_string(), exporter),
+    ]));
+    let mut index = test_index();
+    index.binaries.insert(
+        "exporter".to_string(),
+        BinaryImage {
+            digest: "sha256:def".to_string(),
+            repository: "exporter".to_string(),
+        },
+    );
+
+    let (containers, ports, _) =
+        build_containers("dep", &index, &applications, "prod", &BTreeMap::new())?;
+
+    assert_eq!(containers.len(), 2);
+    assert_eq!(containers[0].name, "app");
+    assert_eq!(containers[0].image, Some("repo@sha256:abc".to_string()));
+    assert_eq!(containers[1].name, "exporter");
+    assert_eq!(containers[1].image, Some("exporter@sha256:def".to_string()));
+    // Containers share the pod's network, so their ports don't collide
+    assert_eq!(ports["metadata"].container_port, 8080);
+    assert_eq!(ports["metrics"].container_port, 8081);
+    Ok(())
+}
+
+#[test]
+fn test_build_containers_security_context() -> Result<()> {
+    let mut app = app_with_metadata_port(None, None);
+    app.security_context = Some(SecurityContext {
+        allow_privilege_escalation: Some(false),
+        drop_capabilities: vec!["ALL".to_string()],
+        fs_group: Some(2000),
+        run_as_non_root: Some(true),
+        seccomp_profile: Some("RuntimeDefault".to_string()),
+        ..Default::default()
+    });
+    let applications = Applications::Single(app);
+
+    let (containers, _, _) =
+        build_containers("dep", &test_index(), &applications, "prod", &BTreeMap::new())?;
+
+    let security_context = containers[0].security_context.clone().unwrap();
+    assert_eq!(security_context.allow_privilege_escalation, Some(false));
+    assert_eq!(
+        security_context.capabilities,
+        Some(Capabilities {
+            add: None,
+            drop: Some(vec!["ALL".to_string()]),
+        })
+    );
+    assert_eq!(security_context.run_as_non_root, Some(true));
+    assert_eq!(
+        security_context.seccomp_profile.unwrap().type_,
+        "RuntimeDefault"
+    );
+    assert_eq!(
+        build_pod_security_context(&applications)?.fs_group,
+        Some(2000)
+    );
+    Ok(())
+}
+
+#[test]
+fn test_build_pod_security_context_conflicting_fs_group_errors() {
+    let with_group = |group| {
+        let mut app = app_with_metadata_port(None, None);
+        app.security_context = Some(SecurityContext {
+            fs_group: Some(group),
+            ..Default::default()
+        });
+        app
+    };
+    let applications = Applications::Named(BTreeMap::from([
+        ("app".to_string(), with_group(1000)),
+        ("exporter".to_string(), with_group(2000)),
+    ]));
+
+    assert!(build_pod_security_context(&applications).is_err());
+}
+
+fn kubernetes_yaml_resource(data: &str) -> SisyphusResource {
+    serde_yaml::from_str(&format!(
+        r#"
+apiVersion: sisyphus/v1
+kind: KubernetesYaml
+metadata:
+  name: config
+clusters:
+  - cluster1
+objects:
+  - apiVersion: v1
+    kind: ConfigMap
+    metadata:
+      name: config
+    data:
+      key: {}
+"#,
+        data
+    ))
+    .unwrap()
+}
+
+#[test]
+fn test_hash_rendering_inputs_tracks_inputs() -> Result<()> {
+    let hash = hash_rendering_inputs(&kubernetes_yaml_resource("a"))?;
+
+    assert_eq!(hash.len(), 12);
+    assert_eq!(hash, hash_rendering_inputs(&kubernetes_yaml_resource("a"))?);
+    assert_ne!(hash, hash_rendering_inputs(&kubernetes_yaml_resource("b"))?);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_render_sisyphus_resource_stamps_inputs_hash() -> Result<()> {
+    let resource = kubernetes_yaml_resource("a");
+    let mut by_key = BTreeMap::new();
+
+    render_sisyphus_resource(
+        &resource,
+        false,
+        &Some("default".to_string()),
+        &mut by_key,
+        &RegistryClients::new(),
+    )
+    .await?;
+
+    assert_eq!(by_key.len(), 1);
+    let object = by_key.values().next().unwrap();
+    let SisyphusResource::KubernetesYaml(yaml) = &resource else {
+        unreachable!();
+    };
+    assert_eq!(
+        crate::kubernetes_io::inputs_hash(object),
+        Some(hash_rendering_inputs(&yaml.objects[0])?.as_str())
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_kubernetes_yaml_objects_are_hashed_separately() -> Result<()> {
+    let render = |second: &str| {
+        let resource: SisyphusResource = serde_yaml::from_str(&format!(
+            r#"
+apiVersion: sisyphus/v1
+kind: KubernetesYaml
+metadata:
+  name: config
+clusters:
+  - cluster1
+objects:
+  - apiVersion: v1
+    kind: ConfigMap
+    metadata:
+      name: first
+    data:
+      key: a
+  - apiVersion: v1
+    kind: ConfigMap
+    metadata:
+      name: second
+    data:
+      key: {}
+"#,
+            second
+        ))
+        .unwrap();
+        async move {
+            let mut by_key = BTreeMap::new();
+            render_sisyphus_resource(
+                &resource,
+                false,
+                &Some("default".to_string()),
+                &mut by_key,
+                &RegistryClients::new(),
+            )
+            .await?;
+            Ok::<_, anyhow::Error>(
+                by_key
+                    .into_iter()
+                    .map(|(k, v)| {
+                        let hash = crate::kubernetes_io::inputs_hash(&v).map(|h| h.to_string());
+                        (k.name, hash)
+                    })
+                    .collect::<BTreeMap<_, _>>(),
+            )
+        }
+    };
+
+    let before = render("b").await?;
+    let after = render("c").await?;
+    assert_eq!(before["first"], after["first"]);
+    assert_ne!(before["second"], after["second"]);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_render_sisyphus_resource_stamps_origin() -> Result<()> {
+    let mut resource = kubernetes_yaml_resource("a");
+    resource.set_defined_in("echo/index.yaml".into());
+    let mut by_key = BTreeMap::new();
+
+    render_sisyphus_resource(
+        &resource,
+        false,
+        &Some("default".to_string()),
+        &mut by_key,
+        &RegistryClients::new(),
+    )
+    .await?;
+
+    let object = by_key.values().next().unwrap();
+    let origin: Origin = serde_json::from_str(&object.annotations()[ORIGIN_ANNOTATION])?;
+    assert_eq!(
+        origin,
+        Origin {
+            resource: format!("KubernetesYaml {}", resource.metadata().name),
+            file: Some("echo/index.yaml".to_string()),
+            ..Default::default()
+        }
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_render_sisyphus_resource_rejects_key_collisions() -> Result<()> {
+    let mut first = kubernetes_yaml_resource("a");
+    first.set_defined_in("global/index.yaml".into());
+    let mut second = kubernetes_yaml_resource("b");
+    second.set_defined_in("default/index.yaml".into());
+    let mut by_key = BTreeMap::new();
+    let namespace = Some("default".to_string());
+    let registries = RegistryClients::new();
+
+    render_sisyphus_resource(&first, false, &namespace, &mut by_key, &registries).await?;
+    let result =
+        render_sisyphus_resource(&second, false, &namespace, &mut by_key, &registries).await;
+
+    assert_eq!(
+        result.unwrap_err().to_string(),
+        "ConfigMap default/config (cluster1) is rendered by both KubernetesYaml config in \
+         global/index.yaml and KubernetesYaml config in default/index.yaml"
+    );
+    assert_eq!(by_key.values().next().unwrap().data["data"]["key"], "a");
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_render_sisyphus_resource_rejects_oversized_objects() -> Result<()> {
+    let resource = kubernetes_yaml_resource(&"a".repeat(MAX_OBJECT_BYTES));
+    let mut by_key = BTreeMap::new();
+
+    let result = render_sisyphus_resource(
+        &resource,
+        false,
+        &Some("default".to_string()),
+        &mut by_key,
+        &RegistryClients::new(),
+    )
+    .await;
+
+    let message = result.unwrap_err().to_string();
+    assert!(message.contains("The largest field is data.key"), "{}", message);
+    assert!(by_key.is_empty());
+    Ok(())
+}
+
+#[test]
+fn test_check_object_size_limits_annotations() {
+    let key = KubernetesKey {
+        api_version: "v1".to_string(),
+        cluster: "cluster1".to_string(),
+        kind: "ConfigMap".to_string(),
+        name: "config".to_string(),
+        namespace: Some("default".to_string()),
+    };
+    let mut object = DynamicObject {
+        types: None,
+        metadata: ObjectMeta {
+            annotations: Some(BTreeMap::from([(
+                "example.com/small".to_string(),
+                "a".to_string(),
+            )])),
+            ..Default::default()
+        },
+        data: json!({}),
+    };
+    assert!(check_object_size(&key, &object).is_ok());
+
+    object.metadata.annotations.as_mut().unwrap().insert(
+        "example.com/large".to_string(),
+        "a".repeat(MAX_ANNOTATIONS_BYTES),
+    );
+    let message = check_object_size(&key, &object).unwrap_err().to_string();
+    assert!(message.contains("metadata.annotations.example.com/large"), "{}", message);
+}
+
+#[test]
+fn test_resolve_external_secrets() -> Result<()> {
+    use crate::sisyphus_yaml::{ExternalSecretRef, KubernetesSecretKeyRef};
+
+    let variables = BTreeMap::from([
+        (
+            "api-token".to_string(),
+            VariableSource::ExternalSecret(ExternalSecretRef {
+                key: "echo/api".to_string(),
+                property: Some("token".to_string()),
+                refresh_interval: None,
+                store: "vault".to_string(),
+                store_kind: Some("ClusterSecretStore".to_string()),
+            }),
+        ),
+        (
+            "password".to_string(),
+            VariableSource::SecretKeyRef(KubernetesSecretKeyRef {
+                name: "passwords".to_string(),
+                key: "echo".to_string(),
+            }),
+        ),
+    ]);
+    let metadata = ObjectMeta {
+        labels: Some(BTreeMap::from([(
+            NAME_LABEL.to_string(),
+            "echo".to_string(),
+        )])),
+        name: Some("echo".to_string()),
+        namespace: Some("default".to_string()),
+        ..Default::default()
+    };
+
+    let (resolved, external_secrets) = resolve_external_secrets("echo", &metadata, &variables)?;
+
+    // The external variable now points at the Secret the ExternalSecret creates
+    let VariableSource::SecretKeyRef(api_token) = &resolved["api-token"] else {
+        panic!("Expected a SecretKeyRef");
+    };
+    assert_eq!(api_token.name, "echo-api-token");
+    assert_eq!(api_token.key, EXTERNAL_SECRET_KEY);
+    let VariableSource::SecretKeyRef(password) = &resolved["password"] else {
+        panic!("Expected a SecretKeyRef");
+    };
+    assert_eq!(password.name, "passwords");
+
+    assert_eq!(external_secrets.len(), 1);
+    let (name, object) = &external_secrets[0];
+    assert_eq!(name, "echo-api-token");
+    assert_eq!(object["kind"], "ExternalSecret");
+    assert_eq!(object["metadata"]["namespace"], "default");
+    assert_eq!(object["spec"]["secretStoreRef"]["kind"], "ClusterSecretStore");
+    assert_eq!(object["spec"]["secretStoreRef"]["name"], "vault");
+    assert_eq!(object["spec"]["target"]["name"], "echo-api-token");
+    assert_eq!(object["spec"]["data"][0]["remoteRef"]["key"], "echo/api");
+    assert_eq!(object["spec"]["data"][0]["remoteRef"]["property"], "token");
+
+    let mut by_key = BTreeMap::new();
+    let clusters = vec!["cluster1".to_string(), "cluster2".to_string()];
+    insert_external_secrets(&external_secrets, clusters.iter(), "default", &mut by_key)?;
+    assert_eq!(by_key.len(), 2);
+    assert!(by_key
+        .keys()
+        .all(|k| k.kind == "ExternalSecret" && k.api_version == "external-secrets.io/v1beta1"));
+
+    Ok(())
+}
+
+#[test]
+fn test_split_by_cluster_merges_footprint_variables() {
+    let resource: SisyphusResource = serde_yaml::from_str(
+        r#"
+apiVersion: sisyphus/v1
+kind: Job
+metadata:
+  name: migrate
+config:
+  env: prod
+  image: test-image
+  variables:
+    db-password:
+      secretKeyRef:
+        name: db
+        key: password
+    region:
+      configMapKeyRef:
+        name: settings
+        key: region
+footprint:
+  cluster1: {}
+  cluster2:
+    variables:
+      db-password:
+        secretKeyRef:
+          name: db-cluster2
+          key: password
+"#,
+    )
+    .unwrap();
+
+    let secret_names: Vec<_> = split_by_cluster(&resource)
+        .into_iter()
+        .map(|(cluster, part)| {
+            let SisyphusResource::SisyphusJob(job) = part else {
+                panic!("Expected a SisyphusJob");
+            };
+            assert_eq!(job.config.variables.len(), 2);
+            let VariableSource::SecretKeyRef(secret) = &job.config.variables["db-password"] else {
+                panic!("Expected a secretKeyRef");
+            };
+            (cluster.unwrap(), secret.name.clone())
+        })
+        .collect();
+
+    assert_eq!(
+        secret_names,
+        vec![
+            ("cluster1".to_string(), "db".to_string()),
+            ("cluster2".to_string(), "db-cluster2".to_string()),
+        ]
+    );
+}
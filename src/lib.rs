@@ -0,0 +1,215 @@
+//! Sisyphus renders Sisyphus resources into Kubernetes objects, compares them with what it last
+//! pushed, and applies the difference. The `sisyphus` binary is a thin wrapper around [`cli::run`].
+//!
+//! Services that want to push without shelling out can call [`plan`] and then [`apply`] with a
+//! database created by the migrations in this repository. Neither asks for confirmation: callers
+//! decide which changes to apply. Connect with [`connect`] to get the same SQLite tuning as the
+//! binary, and as with any `AnyPool`, call `sqlx::any::install_default_drivers()` first.
+//!
+//! Tools that keep their own state can skip the database entirely: [`render_resource`] renders one
+//! [`SisyphusResource`] and [`diff`] compares any two sets of objects.
+//!
+//! Progress, like each object applied, is reported as `tracing` events. Add a [`ProgressLayer`] to
+//! the subscriber to print them the way the binary does.
+
+mod answers;
+mod app_deploy_local;
+mod app_run_config;
+mod app_run_image;
+mod apply_diff;
+mod audit;
+mod blob_cache;
+mod bulk_import;
+mod cloud_credentials;
+pub mod cli;
+mod cluster_identity;
+mod config_image;
+mod cron_schedule;
+mod database;
+mod debug_overlay;
+mod delegation;
+mod deployed_digests;
+mod discovery_cache;
+mod e2e;
+mod environments;
+mod events;
+mod export;
+mod filter;
+mod generate_diff;
+mod git_revision;
+mod globs;
+mod ignore_file;
+mod image_layers;
+mod kubernetes_io;
+mod kubernetes_rendering;
+mod list;
+mod logging;
+mod metrics;
+mod migrations;
+mod notifications;
+mod oci_bundles;
+mod output;
+mod plan;
+mod plugins;
+mod profiles;
+mod provenance;
+mod pull_secrets;
+mod push_lock;
+mod registry_clients;
+mod render_files;
+mod render_progress;
+mod retry;
+mod serve;
+mod show;
+mod sisyphus_yaml;
+mod starlark;
+mod state_store;
+mod status;
+mod tag_policy;
+mod targets;
+mod telemetry;
+mod timings;
+mod validate;
+mod why;
+
+use anyhow::Result;
+use kube::api::DynamicObject;
+use sqlx::AnyPool;
+use std::{collections::BTreeMap, path::Path};
+
+use crate::{
+    apply_diff::apply_diff,
+    delegation::load_delegation,
+    filter::PartialKey,
+    generate_diff::check_protected,
+    kubernetes_rendering::render_sisyphus_resource,
+    plan::{compare, plan_changes},
+    push_lock::lock_database,
+    registry_clients::RegistryClients,
+    render_files::{render_files, resolve_config_image},
+    retry::DEFAULT_MAX_RETRIES,
+    state_store::connect_database,
+    timings::Timings,
+};
+pub use crate::{
+    environments::Environments,
+    generate_diff::{DiffAction, PlannedChange, SecretKeyChanges},
+    kubernetes_io::{KubernetesKey, KubernetesResources},
+    logging::{LogFormat, ProgressLayer},
+    sisyphus_yaml::SisyphusResource,
+    targets::{load_targets, TargetPolicy},
+};
+
+/// Connects to a Sisyphus database. SQLite databases use write-ahead logging, wait up to 30
+/// seconds for other writers instead of failing with `database is locked`, and make writes from
+/// this pool take turns.
+pub async fn connect(database_url: &str) -> Result<AnyPool> {
+    connect_database(database_url).await
+}
+
+/// Renders the resources in `monitor_directory` (or stdin, given `-`) into the Kubernetes
+/// objects they want to exist, without looking at the database or any cluster.
+pub async fn render(
+    monitor_directory: &str,
+    environments: &mut Environments,
+) -> Result<KubernetesResources> {
+    render_files(
+        &[monitor_directory.to_string()],
+        environments,
+        &mut Timings::new(false),
+    )
+    .await
+}
+
+/// Renders a single resource, as read from a monitor directory's YAML, into the objects it wants
+/// in each cluster of its footprint. Config image tags are resolved against their registries
+/// first, so the objects pin digests just like a push would.
+pub async fn render_resource(
+    resource: &SisyphusResource,
+    namespace: &str,
+) -> Result<BTreeMap<KubernetesKey, DynamicObject>> {
+    let registries = RegistryClients::new();
+    let mut resolved = resource.clone();
+    resolve_config_image(&mut resolved, &registries).await?;
+    let mut by_key = BTreeMap::new();
+    render_sisyphus_resource(
+        &resolved,
+        /* allow_any_namespace= */ false,
+        &Some(namespace.to_string()),
+        &mut by_key,
+        &registries,
+    )
+    .await?;
+    Ok(by_key)
+}
+
+/// Returns the changes that would turn `have` into `want`, in the order they must be applied,
+/// without consulting a database or cluster. `have` is usually what was applied last.
+pub fn diff(have: &KubernetesResources, want: &KubernetesResources) -> Result<Vec<PlannedChange>> {
+    compare(have, want, &mut Timings::new(false))
+}
+
+/// Compares `monitor_directory` against what the database says was last pushed and returns the
+/// changes a push would make, in the order they must be applied. Each change carries the same
+/// rendered diff that `sisyphus diff` prints.
+pub async fn plan(
+    monitor_directory: &str,
+    environments: &mut Environments,
+    pool: &AnyPool,
+) -> Result<Vec<PlannedChange>> {
+    plan_changes(
+        &PartialKey::default(),
+        &[monitor_directory.to_string()],
+        environments,
+        pool,
+        &mut Timings::new(false),
+    )
+    .await
+}
+
+/// Applies changes returned by [`plan`], or any subset of them that keeps their order, and records
+/// each one in the database as it goes. Holds the same database lock as `sisyphus push` while it
+/// runs, and stops if another push takes it over.
+///
+/// The same checks as `sisyphus push` run first, failing without changing anything if one of the
+/// changes is refused: `policy` has to allow every change, deleting or recreating a protected
+/// object needs `allow_protected`, and once the database trusts any delegation keys, `delegation`
+/// has to name a signed delegation covering the changes. Deletions outside of the delegation are
+/// dropped. Delegations don't hold back a caller who can write to the database, since that caller
+/// can trust a key of their own.
+pub async fn apply(
+    mut changes: Vec<PlannedChange>,
+    policy: &TargetPolicy,
+    pool: &AnyPool,
+    hash_secret_values: bool,
+    allow_protected: bool,
+    delegation: Option<&Path>,
+) -> Result<()> {
+    if let Some(delegation) = load_delegation(pool, delegation).await? {
+        changes = delegation.restrict(changes)?;
+    }
+    policy.check(
+        &changes
+            .iter()
+            .map(|c| (&c.key, &c.action))
+            .collect::<Vec<_>>(),
+    )?;
+    if !allow_protected {
+        check_protected(&changes)?;
+    }
+    let changed = changes.into_iter().map(|c| (c.key, c.action)).collect();
+    let lock = lock_database(pool, "apply", /* force_unlock= */ false).await?;
+    let result = apply_diff(
+        changed,
+        policy,
+        pool,
+        &lock,
+        hash_secret_values,
+        /* annotate_provenance= */ false,
+        DEFAULT_MAX_RETRIES,
+        /* keep_going= */ false,
+    )
+    .await;
+    lock.release().await?;
+    result
+}
@@ -0,0 +1,147 @@
+use anyhow::Result;
+use clap::ValueEnum;
+use serde_json::Value as JsonValue;
+use sqlx::AnyPool;
+
+use crate::{
+    database::{get_objects_from_database, get_tracked_objects, TrackedObject},
+    filter::{key_matches_filter, object_matches_filter, PartialKey},
+};
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq, ValueEnum)]
+pub(crate) enum ListFormat {
+    Table,
+    Json,
+    Yaml,
+}
+
+// Prints what the database tracks, without touching files or clusters
+pub(crate) async fn list(filter: &PartialKey, pool: &AnyPool, format: ListFormat) -> Result<()> {
+    // Which app rendered an object is only recorded on the object itself
+    let objects = match filter.needs_objects() {
+        true => Some(get_objects_from_database(pool).await?),
+        false => None,
+    };
+    let mut tracked: Vec<_> = get_tracked_objects(pool)
+        .await?
+        .into_iter()
+        .filter(|t| match &objects {
+            Some(objects) => objects
+                .by_key
+                .get(&t.key)
+                .map_or(false, |o| object_matches_filter(&t.key, o, filter)),
+            None => key_matches_filter(&t.key, filter),
+        })
+        .collect();
+    tracked.sort_by(|a, b| sort_key(a).cmp(&sort_key(b)));
+
+    match format {
+        ListFormat::Table => print!("{}", format_table(&tracked)),
+        ListFormat::Json => {
+            let as_json: Vec<_> = tracked.iter().map(to_json).collect();
+            println!("{}", serde_json::to_string_pretty(&as_json)?);
+        }
+        ListFormat::Yaml => {
+            let as_json: Vec<_> = tracked.iter().map(to_json).collect();
+            print!("{}", serde_yaml::to_string(&as_json)?);
+        }
+    }
+    Ok(())
+}
+
+// Groups the listing the same way the plan summary is grouped
+fn sort_key(tracked: &TrackedObject) -> (&str, &Option<String>, &str, &str, &str) {
+    let k = &tracked.key;
+    (&k.cluster, &k.namespace, &k.kind, &k.name, &k.api_version)
+}
+
+fn to_json(tracked: &TrackedObject) -> JsonValue {
+    serde_json::json!({
+        "apiVersion": tracked.key.api_version,
+        "cluster": tracked.key.cluster,
+        "kind": tracked.key.kind,
+        "name": tracked.key.name,
+        "namespace": tracked.key.namespace,
+        "created": tracked.created.to_string(),
+        "lastUpdated": tracked.last_updated.to_string(),
+    })
+}
+
+fn format_table(tracked: &[TrackedObject]) -> String {
+    let header = [
+        "CLUSTER",
+        "NAMESPACE",
+        "API VERSION",
+        "KIND",
+        "NAME",
+        "CREATED",
+        "LAST UPDATED",
+    ]
+    .map(|h| h.to_string());
+    let rows: Vec<[String; 7]> = std::iter::once(header)
+        .chain(tracked.iter().map(|t| {
+            [
+                t.key.cluster.clone(),
+                t.key.namespace.clone().unwrap_or_else(|| "-".to_string()),
+                t.key.api_version.clone(),
+                t.key.kind.clone(),
+                t.key.name.clone(),
+                t.created.to_string(),
+                t.last_updated.to_string(),
+            ]
+        }))
+        .collect();
+    let mut widths = [0; 7];
+    for row in &rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.len());
+        }
+    }
+
+    let mut table = String::new();
+    for row in &rows {
+        let cells: Vec<_> = row
+            .iter()
+            .zip(widths)
+            .map(|(cell, width)| format!("{:<width$}", cell, width = width))
+            .collect();
+        table.push_str(cells.join("  ").trim_end());
+        table.push('\n');
+    }
+    table
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{database::DecodableOffsetDateTime, kubernetes_io::test_key};
+    use time::OffsetDateTime;
+
+    #[test]
+    fn test_format_table_aligns_columns() {
+        // 2025-03-26T02:09:18Z
+        let time =
+            DecodableOffsetDateTime(OffsetDateTime::from_unix_timestamp(1742954958).unwrap());
+        let tracked = [
+            TrackedObject {
+                key: test_key("prod", "apps/v1", "Deployment", Some("echo"), "echo"),
+                created: time,
+                last_updated: time,
+            },
+            TrackedObject {
+                key: test_key("prod", "v1", "Namespace", None, "echo"),
+                created: time,
+                last_updated: time,
+            },
+        ];
+
+        assert_eq!(
+            format_table(&tracked),
+            "\
+CLUSTER  NAMESPACE  API VERSION  KIND        NAME  CREATED               LAST UPDATED
+prod     echo       apps/v1      Deployment  echo  2025-03-26T02:09:18Z  2025-03-26T02:09:18Z
+prod     -          v1           Namespace   echo  2025-03-26T02:09:18Z  2025-03-26T02:09:18Z
+"
+        );
+    }
+}
@@ -0,0 +1,144 @@
+use clap::ValueEnum;
+use serde_json::{Map, Value as JsonValue};
+use std::fmt;
+use time::{format_description::well_known::Rfc3339, OffsetDateTime};
+use tracing::{
+    field::{Field, Visit},
+    Event, Level, Subscriber,
+};
+use tracing_subscriber::{layer::Context, Layer};
+
+// How progress messages, like each object applied, are written
+#[derive(Clone, Copy, Debug, Eq, PartialEq, ValueEnum)]
+pub enum LogFormat {
+    // Plain lines for people
+    Text,
+    // One JSON object per line with the level, time, and fields of each message, for log pipelines
+    Json,
+}
+
+// Writes Sisyphus's own info, warn, and error events. Everything other crates emit is left to
+// telemetry, since kube alone would drown out the progress.
+pub struct ProgressLayer {
+    format: LogFormat,
+}
+
+impl ProgressLayer {
+    pub fn new(format: LogFormat) -> Self {
+        ProgressLayer { format }
+    }
+}
+
+impl<S: Subscriber> Layer<S> for ProgressLayer {
+    fn on_event(&self, event: &Event<'_>, _: Context<'_, S>) {
+        let metadata = event.metadata();
+        if !metadata.target().starts_with("sisyphus") || *metadata.level() > Level::INFO {
+            return;
+        }
+        let mut fields = Fields::default();
+        event.record(&mut fields);
+        match self.format {
+            LogFormat::Text => {
+                let message = fields.message.unwrap_or_default();
+                match *metadata.level() {
+                    Level::INFO => println!("{}", message),
+                    _ => eprintln!("Warning: {}", message),
+                }
+            }
+            LogFormat::Json => println!("{}", json_line(*metadata.level(), fields)),
+        }
+    }
+}
+
+#[derive(Default)]
+struct Fields {
+    message: Option<String>,
+    values: Map<String, JsonValue>,
+}
+
+impl Visit for Fields {
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        self.record(field, JsonValue::from(format!("{:?}", value)));
+    }
+
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.record(field, JsonValue::from(value));
+    }
+
+    fn record_i64(&mut self, field: &Field, value: i64) {
+        self.record(field, JsonValue::from(value));
+    }
+
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        self.record(field, JsonValue::from(value));
+    }
+
+    fn record_f64(&mut self, field: &Field, value: f64) {
+        self.record(field, JsonValue::from(value));
+    }
+
+    fn record_bool(&mut self, field: &Field, value: bool) {
+        self.record(field, JsonValue::from(value));
+    }
+}
+
+impl Fields {
+    fn record(&mut self, field: &Field, value: JsonValue) {
+        match (field.name(), value) {
+            ("message", JsonValue::String(message)) => self.message = Some(message),
+            (name, value) => {
+                self.values.insert(name.to_string(), value);
+            }
+        }
+    }
+}
+
+fn json_line(level: Level, fields: Fields) -> JsonValue {
+    let mut line = fields.values;
+    line.insert(
+        "time".to_string(),
+        JsonValue::from(
+            OffsetDateTime::now_utc()
+                .format(&Rfc3339)
+                .unwrap_or_default(),
+        ),
+    );
+    line.insert(
+        "level".to_string(),
+        JsonValue::from(level.as_str().to_lowercase()),
+    );
+    line.insert(
+        "message".to_string(),
+        JsonValue::from(fields.message.unwrap_or_default()),
+    );
+    JsonValue::Object(line)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_json_line() {
+        let fields = Fields {
+            message: Some("Created Deployment echo/echo (prod)".to_string()),
+            values: Map::from_iter([
+                ("object".to_string(), json!("Deployment echo/echo (prod)")),
+                ("verb".to_string(), json!("create")),
+            ]),
+        };
+        let mut line = json_line(Level::INFO, fields);
+        assert!(line["time"].is_string());
+        line.as_object_mut().unwrap().remove("time");
+        assert_eq!(
+            line,
+            json!({
+                "level": "info",
+                "message": "Created Deployment echo/echo (prod)",
+                "object": "Deployment echo/echo (prod)",
+                "verb": "create",
+            })
+        );
+    }
+}
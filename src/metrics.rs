@@ -0,0 +1,212 @@
+use anyhow::{bail, Context, Result};
+use std::{
+    collections::BTreeMap,
+    fmt::Write as _,
+    sync::{Mutex, OnceLock},
+    time::Duration,
+};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpListener,
+};
+use tracing::warn;
+
+use crate::timings::Phase;
+
+// Counts of what a run did, kept for Prometheus to scrape from --metrics-listen or for pushing to
+// a Pushgateway when the command finishes
+#[derive(Default)]
+struct Metrics {
+    // How many objects the database tracked when it was last loaded
+    objects_tracked: Option<usize>,
+    // Planned changes by verb
+    diffs: BTreeMap<&'static str, u64>,
+    // Applied changes by verb and whether they succeeded
+    applies: BTreeMap<(&'static str, bool), u64>,
+    // The total seconds and number of times each phase ran
+    phases: BTreeMap<Phase, (f64, u64)>,
+}
+
+static METRICS: Mutex<Metrics> = Mutex::new(Metrics {
+    objects_tracked: None,
+    diffs: BTreeMap::new(),
+    applies: BTreeMap::new(),
+    phases: BTreeMap::new(),
+});
+
+static PUSHGATEWAY: OnceLock<String> = OnceLock::new();
+
+// Serves the metrics at http://<listen>/metrics while the command runs, and remembers the
+// Pushgateway to send them to once it's done
+pub(crate) fn configure_metrics(listen: Option<&str>, pushgateway: Option<&str>) -> Result<()> {
+    if let Some(pushgateway) = pushgateway {
+        if !pushgateway.starts_with("http://") && !pushgateway.starts_with("https://") {
+            bail!(
+                "Expected an http:// or https:// Pushgateway URL but got {}",
+                pushgateway
+            );
+        }
+        let _ = PUSHGATEWAY.set(pushgateway.trim_end_matches('/').to_string());
+    }
+    if let Some(listen) = listen {
+        let listener = std::net::TcpListener::bind(listen)
+            .with_context(|| format!("while listening for metrics on {}", listen))?;
+        listener.set_nonblocking(true)?;
+        tokio::spawn(serve(TcpListener::from_std(listener)?));
+    }
+    Ok(())
+}
+
+pub(crate) fn objects_tracked(count: usize) {
+    METRICS.lock().unwrap().objects_tracked = Some(count);
+}
+
+pub(crate) fn diff_detected(verb: &'static str) {
+    *METRICS.lock().unwrap().diffs.entry(verb).or_default() += 1;
+}
+
+pub(crate) fn apply_finished(verb: &'static str, succeeded: bool) {
+    *METRICS
+        .lock()
+        .unwrap()
+        .applies
+        .entry((verb, succeeded))
+        .or_default() += 1;
+}
+
+pub(crate) fn phase_finished(phase: Phase, elapsed: Duration) {
+    let mut metrics = METRICS.lock().unwrap();
+    let (seconds, count) = metrics.phases.entry(phase).or_default();
+    *seconds += elapsed.as_secs_f64();
+    *count += 1;
+}
+
+// Sends the metrics to the Pushgateway, grouped by command so that a refresh doesn't replace what
+// the last push reported. Like events, failing to only warns.
+pub(crate) async fn push_metrics(command: &str) {
+    let Some(pushgateway) = PUSHGATEWAY.get() else {
+        return;
+    };
+    let body = exposition(&METRICS.lock().unwrap());
+    let sent = reqwest::Client::new()
+        .put(format!(
+            "{}/metrics/job/sisyphus/command/{}",
+            pushgateway, command
+        ))
+        .header("content-type", "text/plain; version=0.0.4")
+        .body(body)
+        .send()
+        .await;
+    match sent {
+        Ok(response) if response.status().is_success() => {}
+        Ok(response) => warn!("Unable to push metrics, got {}", response.status()),
+        Err(e) => warn!(error = %e, "Unable to push metrics: {}", e),
+    }
+}
+
+// Answers every request with the metrics, which is all Prometheus asks for
+async fn serve(listener: TcpListener) {
+    loop {
+        let Ok((mut stream, _)) = listener.accept().await else {
+            continue;
+        };
+        let body = exposition(&METRICS.lock().unwrap());
+        tokio::spawn(async move {
+            let mut request = [0; 4096];
+            let _ = stream.read(&mut request).await;
+            let response = format!(
+                "HTTP/1.1 200 OK\r\ncontent-type: text/plain; version=0.0.4\r\ncontent-length: \
+                 {}\r\nconnection: close\r\n\r\n{}",
+                body.len(),
+                body
+            );
+            let _ = stream.write_all(response.as_bytes()).await;
+        });
+    }
+}
+
+fn phase_label(phase: Phase) -> &'static str {
+    match phase {
+        Phase::Parse => "parse",
+        Phase::Resolve => "resolve",
+        Phase::Render => "render",
+        Phase::DatabaseLoad => "database_load",
+        Phase::Diff => "diff",
+        Phase::Apply => "apply",
+    }
+}
+
+// The Prometheus text format
+fn exposition(metrics: &Metrics) -> String {
+    let mut text = String::new();
+    if let Some(count) = metrics.objects_tracked {
+        text.push_str("# HELP sisyphus_objects_tracked Objects recorded in the database.\n");
+        text.push_str("# TYPE sisyphus_objects_tracked gauge\n");
+        let _ = writeln!(text, "sisyphus_objects_tracked {}", count);
+    }
+    text.push_str("# HELP sisyphus_diffs_detected_total Changes planned, by verb.\n");
+    text.push_str("# TYPE sisyphus_diffs_detected_total counter\n");
+    for (verb, count) in &metrics.diffs {
+        let _ = writeln!(
+            text,
+            "sisyphus_diffs_detected_total{{verb=\"{}\"}} {}",
+            verb, count
+        );
+    }
+    text.push_str("# HELP sisyphus_applies_total Changes applied, by verb and result.\n");
+    text.push_str("# TYPE sisyphus_applies_total counter\n");
+    for ((verb, succeeded), count) in &metrics.applies {
+        let _ = writeln!(
+            text,
+            "sisyphus_applies_total{{verb=\"{}\",result=\"{}\"}} {}",
+            verb,
+            if *succeeded { "succeeded" } else { "failed" },
+            count
+        );
+    }
+    text.push_str(
+        "# HELP sisyphus_phase_duration_seconds Time spent parsing, resolving images in \
+         registries, rendering, loading the database, diffing, and applying.\n",
+    );
+    text.push_str("# TYPE sisyphus_phase_duration_seconds summary\n");
+    for (phase, (seconds, count)) in &metrics.phases {
+        let label = phase_label(*phase);
+        let _ = writeln!(
+            text,
+            "sisyphus_phase_duration_seconds_sum{{phase=\"{}\"}} {}",
+            label, seconds
+        );
+        let _ = writeln!(
+            text,
+            "sisyphus_phase_duration_seconds_count{{phase=\"{}\"}} {}",
+            label, count
+        );
+    }
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_exposition() {
+        let metrics = Metrics {
+            objects_tracked: Some(12),
+            diffs: BTreeMap::from([("patch", 2)]),
+            applies: BTreeMap::from([(("patch", false), 1), (("patch", true), 1)]),
+            phases: BTreeMap::from([(Phase::Resolve, (1.5, 3))]),
+        };
+        let text = exposition(&metrics);
+        assert!(text.contains("\nsisyphus_objects_tracked 12\n"));
+        assert!(text.contains("\nsisyphus_diffs_detected_total{verb=\"patch\"} 2\n"));
+        assert!(text.contains(
+            "\nsisyphus_applies_total{verb=\"patch\",result=\"failed\"} 1\n\
+             sisyphus_applies_total{verb=\"patch\",result=\"succeeded\"} 1\n"
+        ));
+        assert!(text.contains(
+            "\nsisyphus_phase_duration_seconds_sum{phase=\"resolve\"} 1.5\n\
+             sisyphus_phase_duration_seconds_count{phase=\"resolve\"} 3\n"
+        ));
+    }
+}
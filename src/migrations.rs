@@ -0,0 +1,86 @@
+use anyhow::{bail, Context, Result};
+use sqlx::{migrate::Migrator, AnyPool};
+use tracing::info;
+
+// The schema for each database Sisyphus can create itself. sqlx records which of these ran in
+// _sqlx_migrations, so new files only need to describe what changed.
+static POSTGRES: Migrator = sqlx::migrate!("migrations/postgres");
+static SQLITE: Migrator = sqlx::migrate!("migrations/sqlite");
+
+// Creates the tables Sisyphus needs or brings them up to date
+pub(crate) async fn migrate(pool: &AnyPool) -> Result<()> {
+    let backend = pool.acquire().await?.backend_name().to_string();
+    let migrator = match backend.as_str() {
+        "PostgreSQL" => &POSTGRES,
+        "SQLite" => &SQLITE,
+        "MySQL" => bail!(
+            "Can't migrate MySQL databases, create the tables by hand as the README's database \
+             setup describes"
+        ),
+        other => bail!(
+            "Can't migrate {} databases, run the statements in migrations/postgres by hand instead",
+            other
+        ),
+    };
+    migrator
+        .run(pool)
+        .await
+        .with_context(|| format!("while migrating the {} database", backend))?;
+    if let Some(latest) = migrator.iter().last() {
+        info!(
+            "Database is up to date at {} ({})",
+            latest.version, latest.description
+        );
+    }
+    Ok(())
+}
+
+// The tests run against a throwaway SQLite database
+#[cfg(all(test, feature = "sqlite"))]
+mod tests {
+    use super::*;
+    use crate::state_store::connect_database;
+    use sqlx::Row;
+    use tempfile::TempDir;
+
+    #[tokio::test]
+    async fn test_sqlite_migrations_keep_history() -> Result<()> {
+        sqlx::any::install_default_drivers();
+        let directory = TempDir::new()?;
+        let url = format!(
+            "sqlite://{}?mode=rwc",
+            directory.path().join("sisyphus.db").display()
+        );
+        let pool = connect_database(&url).await?;
+        migrate(&pool).await?;
+        // Running again is how every upgrade starts, so it must be harmless
+        migrate(&pool).await?;
+
+        sqlx::query(
+            r#"
+            INSERT INTO kubernetes_objects (api_version, cluster, kind, name, namespace, yaml, provenance)
+            VALUES ('v1', 'prod', 'ConfigMap', 'echo', 'echo', '{}', '{"version":"1"}')
+            "#,
+        )
+        .execute(&pool)
+        .await?;
+        sqlx::query("DELETE FROM kubernetes_objects")
+            .execute(&pool)
+            .await?;
+
+        let rows = sqlx::query(
+            "SELECT action, old_data, new_data FROM kubernetes_objects_audit ORDER BY rowid",
+        )
+        .fetch_all(&pool)
+        .await?;
+        let actions: Vec<String> = rows.iter().map(|r| r.get("action")).collect();
+        assert_eq!(actions, vec!["INSERT", "DELETE"]);
+        let deleted: serde_json::Value = serde_json::from_str(rows[1].get("old_data"))?;
+        assert_eq!(deleted["provenance"], "{\"version\":\"1\"}");
+
+        sqlx::query("SELECT overlay FROM debug_overlays")
+            .fetch_all(&pool)
+            .await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,195 @@
+use anyhow::{bail, Result};
+use serde_json::{json, Value as JsonValue};
+use std::{collections::BTreeMap, sync::OnceLock};
+use tracing::{instrument, warn};
+
+use crate::{kubernetes_io::KubernetesKey, push_lock::whoami};
+
+// Where a summary of each finished push and refresh is posted, so a team channel hears about
+// deployments without watching terminals
+#[derive(Clone, Debug, Eq, PartialEq)]
+enum Notifier {
+    // A Slack incoming webhook, which wants a message rather than arbitrary JSON
+    Slack(String),
+    // Any other URL, which gets the summary as JSON
+    Webhook(String),
+}
+
+static NOTIFIER: OnceLock<Notifier> = OnceLock::new();
+
+// Slack messages get unwieldy well before its size limit, so long plans are cut short
+const MAX_LISTED_CHANGES: usize = 20;
+
+// Sets where summaries go from a URL like https://hooks.slack.com/services/... or
+// https://deploys.example.com/hook. Prefix slack+ to get Slack messages at any other address.
+pub(crate) fn configure_notifications(url: Option<&str>) -> Result<()> {
+    if let Some(url) = url {
+        let _ = NOTIFIER.set(parse_notifier(url)?);
+    }
+    Ok(())
+}
+
+fn parse_notifier(url: &str) -> Result<Notifier> {
+    let (slack, url) = match url.strip_prefix("slack+") {
+        Some(rest) => (true, rest),
+        None => (url.starts_with("https://hooks.slack.com/"), url),
+    };
+    if !url.starts_with("http://") && !url.starts_with("https://") {
+        bail!(
+            "Expected an http:// or https:// URL to notify but got {}",
+            url
+        );
+    }
+    Ok(match slack {
+        true => Notifier::Slack(url.to_string()),
+        false => Notifier::Webhook(url.to_string()),
+    })
+}
+
+// Reports what `command` (push or refresh) changed and whether it worked. Like events, a failure
+// to notify only warns since the changes already happened.
+#[instrument(skip_all)]
+pub(crate) async fn notify_finished(
+    command: &str,
+    changes: &[(KubernetesKey, &str)],
+    error: Option<&anyhow::Error>,
+) {
+    let Some(notifier) = NOTIFIER.get() else {
+        return;
+    };
+    let summary = summarize(command, &whoami(), changes, error);
+    let (url, body) = match notifier {
+        Notifier::Slack(url) => (url, json!({"text": slack_text(&summary)})),
+        Notifier::Webhook(url) => (url, summary),
+    };
+    let sent = reqwest::Client::new()
+        .post(url)
+        .header("content-type", "application/json")
+        .body(body.to_string())
+        .send()
+        .await;
+    match sent {
+        Ok(response) if response.status().is_success() => {}
+        Ok(response) => warn!("Unable to notify, got {}", response.status()),
+        Err(e) => warn!(error = %e, "Unable to notify: {}", e),
+    }
+}
+
+fn summarize(
+    command: &str,
+    user: &str,
+    changes: &[(KubernetesKey, &str)],
+    error: Option<&anyhow::Error>,
+) -> JsonValue {
+    let mut counts: BTreeMap<&str, usize> = BTreeMap::new();
+    for (_, verb) in changes {
+        *counts.entry(*verb).or_default() += 1;
+    }
+    json!({
+        "command": command,
+        "user": user,
+        "succeeded": error.is_none(),
+        "error": error.map(|e| format!("{:#}", e)),
+        "counts": counts,
+        "changes": changes
+            .iter()
+            .map(|(key, verb)| json!({"verb": verb, "object": key.to_string()}))
+            .collect::<Vec<_>>(),
+    })
+}
+
+fn slack_text(summary: &JsonValue) -> String {
+    let counts: Vec<_> = summary["counts"]
+        .as_object()
+        .into_iter()
+        .flatten()
+        .map(|(verb, count)| format!("{} {}", count, verb))
+        .collect();
+    let mut text = match summary["error"].as_str() {
+        None => format!(
+            ":white_check_mark: {} by {} finished: {}",
+            summary["command"].as_str().unwrap_or_default(),
+            summary["user"].as_str().unwrap_or_default(),
+            counts.join(", ")
+        ),
+        Some(error) => format!(
+            ":x: {} by {} failed: {}\nAttempted {}",
+            summary["command"].as_str().unwrap_or_default(),
+            summary["user"].as_str().unwrap_or_default(),
+            error,
+            counts.join(", ")
+        ),
+    };
+    let changes = summary["changes"]
+        .as_array()
+        .map_or(&[][..], |c| c.as_slice());
+    for change in changes.iter().take(MAX_LISTED_CHANGES) {
+        text.push_str(&format!(
+            "\n• {} {}",
+            change["verb"].as_str().unwrap_or_default(),
+            change["object"].as_str().unwrap_or_default()
+        ));
+    }
+    if changes.len() > MAX_LISTED_CHANGES {
+        text.push_str(&format!(
+            "\n…and {} more",
+            changes.len() - MAX_LISTED_CHANGES
+        ));
+    }
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::kubernetes_io::test_key;
+    use anyhow::anyhow;
+
+    #[test]
+    fn test_parse_notifier() -> Result<()> {
+        assert_eq!(
+            parse_notifier("https://hooks.slack.com/services/T0/B0/x")?,
+            Notifier::Slack("https://hooks.slack.com/services/T0/B0/x".to_string())
+        );
+        assert_eq!(
+            parse_notifier("slack+https://proxy.test/slack")?,
+            Notifier::Slack("https://proxy.test/slack".to_string())
+        );
+        assert_eq!(
+            parse_notifier("https://deploys.test/hook")?,
+            Notifier::Webhook("https://deploys.test/hook".to_string())
+        );
+        assert!(parse_notifier("hooks.slack.com/services/T0").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_slack_text() {
+        let changes = [
+            (
+                test_key("prod", "apps/v1", "Deployment", Some("echo"), "echo"),
+                "patch",
+            ),
+            (
+                test_key("prod", "apps/v1", "Deployment", Some("echo"), "web"),
+                "create",
+            ),
+        ];
+        let summary = summarize("push", "alice@laptop", &changes, None);
+        assert_eq!(summary["counts"], json!({"create": 1, "patch": 1}));
+        assert_eq!(
+            slack_text(&summary),
+            ":white_check_mark: push by alice@laptop finished: 1 create, 1 patch\n• patch \
+             Deployment echo/echo (prod)\n• create Deployment echo/web (prod)"
+        );
+
+        let error = anyhow!("Forbidden");
+        let summary = summarize("push", "alice@laptop", &changes[..1], Some(&error));
+        assert_eq!(summary["succeeded"], false);
+        assert_eq!(
+            slack_text(&summary),
+            ":x: push by alice@laptop failed: Forbidden\nAttempted 1 patch\n• patch Deployment \
+             echo/echo (prod)"
+        );
+    }
+}
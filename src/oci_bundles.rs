@@ -0,0 +1,87 @@
+use anyhow::Result;
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    path::{Path, PathBuf},
+};
+use tempfile::TempDir;
+
+use crate::registry_clients::{pull_image, RegistryClients};
+
+// SisyphusYaml sources starting with this name an OCI artifact holding an index.yaml, such as
+// oci://registry.example.com/echo-config:v3
+pub(crate) const OCI_SCHEME: &str = "oci://";
+
+// Bundles pulled so far and the ones the files referenced before they were. Reading files is
+// synchronous, so files are read once, the bundles they name are pulled, and they're read again
+// until no new bundles turn up.
+#[derive(Debug, Default)]
+pub(crate) struct OciBundles {
+    pulled: BTreeMap<String, TempDir>,
+    wanted: BTreeSet<String>,
+}
+
+impl OciBundles {
+    // Where the bundle for `reference` was unpacked, or None after noting that it needs pulling
+    pub(crate) fn directory(&mut self, reference: &str) -> Option<&Path> {
+        match self.pulled.get(reference) {
+            Some(directory) => Some(directory.path()),
+            None => {
+                self.wanted.insert(reference.to_string());
+                None
+            }
+        }
+    }
+
+    // Pulls every bundle referenced since the last call, returning whether there were any
+    pub(crate) async fn pull_wanted(&mut self, registries: &RegistryClients) -> Result<bool> {
+        let wanted = std::mem::take(&mut self.wanted);
+        for reference in &wanted {
+            let image = reference.trim_start_matches(OCI_SCHEME).to_string();
+            let directory = TempDir::new()?;
+            pull_image(&image, registries, directory.path()).await?;
+            self.pulled.insert(reference.clone(), directory);
+        }
+        Ok(!wanted.is_empty())
+    }
+
+    // Names a file inside a pulled bundle by its reference rather than the temporary directory it
+    // was unpacked into
+    pub(crate) fn describe(&self, path: &Path) -> Option<PathBuf> {
+        self.pulled.iter().find_map(|(reference, directory)| {
+            path.strip_prefix(directory.path())
+                .ok()
+                .map(|relative| Path::new(reference).join(relative))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bundles_are_wanted_until_pulled() -> Result<()> {
+        let mut bundles = OciBundles::default();
+        let reference = "oci://registry.test/echo-config:v3";
+        assert!(bundles.directory(reference).is_none());
+        assert_eq!(bundles.wanted.iter().collect::<Vec<_>>(), vec![reference]);
+
+        // Stands in for pull_wanted, which needs a registry
+        bundles.wanted.clear();
+        bundles
+            .pulled
+            .insert(reference.to_string(), TempDir::new()?);
+        let directory = bundles
+            .directory(reference)
+            .map(|d| d.to_path_buf())
+            .unwrap();
+        assert!(bundles.wanted.is_empty());
+        assert_eq!(
+            bundles.describe(&directory.join("index.yaml")),
+            Some(PathBuf::from(
+                "oci://registry.test/echo-config:v3/index.yaml"
+            ))
+        );
+        Ok(())
+    }
+}
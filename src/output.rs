@@ -0,0 +1,111 @@
+use clap::ValueEnum;
+use console::Style;
+use std::sync::OnceLock;
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq, ValueEnum)]
+pub(crate) enum ColorChoice {
+    Auto,
+    Always,
+    Never,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq, ValueEnum)]
+pub(crate) enum ThemeName {
+    Default,
+    Monochrome,
+}
+
+// How diffs, verbs, and progress bars are drawn.
+#[derive(Clone, Debug)]
+pub(crate) struct Theme {
+    pub create: Style,
+    pub delete: Style,
+    pub patch: Style,
+    pub inserted_line: Style,
+    pub deleted_line: Style,
+    pub progress_template: &'static str,
+}
+
+impl Theme {
+    fn new(name: ThemeName) -> Self {
+        match name {
+            ThemeName::Default => Theme {
+                create: Style::new().green(),
+                delete: Style::new().red(),
+                patch: Style::new().yellow(),
+                inserted_line: Style::new().green(),
+                deleted_line: Style::new().red(),
+                progress_template:
+                    "{msg} {wide_bar:.magenta/dim} {pos:>7}/{len:7} {elapsed}/{duration}",
+            },
+            ThemeName::Monochrome => Theme {
+                create: Style::new().bold(),
+                delete: Style::new().bold(),
+                patch: Style::new().bold(),
+                inserted_line: Style::new(),
+                deleted_line: Style::new(),
+                progress_template: "{msg} {wide_bar} {pos:>7}/{len:7} {elapsed}/{duration}",
+            },
+        }
+    }
+}
+
+static THEME: OnceLock<Theme> = OnceLock::new();
+
+// Sets up colors and the theme for the rest of the process. Must run before anything is printed.
+pub(crate) fn configure_output(color: ColorChoice, theme: ThemeName) {
+    let enabled = match color {
+        ColorChoice::Always => true,
+        ColorChoice::Never => false,
+        ColorChoice::Auto => {
+            auto_colors_enabled(std::env::var_os("NO_COLOR"), std::env::var_os("CI"))
+                && console::colors_enabled()
+        }
+    };
+    console::set_colors_enabled(enabled);
+    console::set_colors_enabled_stderr(enabled);
+    let _ = THEME.set(Theme::new(theme));
+}
+
+pub(crate) fn theme() -> &'static Theme {
+    THEME.get_or_init(|| Theme::new(ThemeName::Default))
+}
+
+// NO_COLOR (https://no-color.org) disables color when set to anything non-empty. CI systems set CI
+// and usually collect output into logs that render escape codes as garbage.
+fn auto_colors_enabled(
+    no_color: Option<std::ffi::OsString>,
+    ci: Option<std::ffi::OsString>,
+) -> bool {
+    if no_color.map_or(false, |v| !v.is_empty()) {
+        return false;
+    }
+    if ci.map_or(false, |v| !v.is_empty() && v != "false" && v != "0") {
+        return false;
+    }
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::ffi::OsString;
+
+    #[test]
+    fn test_auto_colors_enabled_by_default() {
+        assert!(auto_colors_enabled(None, None));
+    }
+
+    #[test]
+    fn test_auto_colors_disabled_by_no_color() {
+        assert!(!auto_colors_enabled(Some(OsString::from("1")), None));
+        // An empty NO_COLOR doesn't count
+        assert!(auto_colors_enabled(Some(OsString::new()), None));
+    }
+
+    #[test]
+    fn test_auto_colors_disabled_in_ci() {
+        assert!(!auto_colors_enabled(None, Some(OsString::from("true"))));
+        assert!(auto_colors_enabled(None, Some(OsString::from("false"))));
+    }
+}
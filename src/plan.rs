@@ -0,0 +1,114 @@
+use anyhow::Result;
+use clap::ValueEnum;
+use sqlx::AnyPool;
+use std::{collections::HashSet, time::Instant};
+
+use crate::{
+    database::get_objects_from_database,
+    debug_overlay::apply_active_overlays,
+    environments::Environments,
+    filter::{
+        key_retained, namespace_key_retained, object_matches_filter, required_namespace_identities,
+        PartialKey,
+    },
+    generate_diff::{generate_plan, PlannedChange},
+    kubernetes_io::{make_comparable, munge_secrets, KubernetesResources},
+    metrics::{diff_detected, objects_tracked},
+    render_files::render_files,
+    timings::{Phase, Timings},
+};
+
+// What `diff` compares the files with
+#[derive(Clone, Copy, Debug, Eq, PartialEq, ValueEnum)]
+pub(crate) enum DiffAgainst {
+    // Only what the database recorded at the last push or refresh
+    Database,
+    // The database and also the live objects in the clusters, to show drift alongside the changes
+    Cluster,
+}
+
+// Returns what the database recorded and what the files want, both narrowed to the filter.
+pub(crate) async fn load_database_and_files(
+    filter: &PartialKey,
+    monitor_directory: &[String],
+    environments: &mut Environments,
+    pool: &AnyPool,
+    timings: &mut Timings,
+) -> Result<(KubernetesResources, KubernetesResources)> {
+    let mut from_files = render_files(monitor_directory, environments, timings).await?;
+    apply_active_overlays(&mut from_files, pool).await?;
+
+    let started = Instant::now();
+    let mut from_database = get_objects_from_database(&pool).await?;
+    timings.record(Phase::DatabaseLoad, started);
+    objects_tracked(from_database.by_key.len());
+    for (k, to) in &mut from_files.by_key {
+        let from = from_database.by_key.get(&k);
+        if let Some(f) = from {
+            to.metadata.resource_version = f.metadata.resource_version.clone();
+            to.metadata.uid = f.metadata.uid.clone();
+        }
+        munge_secrets(from, to)?;
+    }
+
+    let retained_owners: HashSet<_> = from_files
+        .by_key
+        .iter()
+        .chain(from_database.by_key.iter())
+        .filter(|(k, v)| object_matches_filter(k, v, filter))
+        .map(|(k, _)| k.clone())
+        .collect();
+    from_files
+        .by_key
+        .retain(|k, v| key_retained(k, v, filter, &retained_owners));
+    from_database
+        .by_key
+        .retain(|k, v| key_retained(k, v, filter, &retained_owners));
+
+    // Keep the namespaces holding any resource we're pushing, even when the
+    // filter (e.g. `--name`) doesn't match the Namespace object itself.
+    // Otherwise a scoped push into a not-yet-created namespace drops the
+    // namespace and the resource fails to create.
+    let required_namespaces =
+        required_namespace_identities(from_files.by_key.keys().chain(from_database.by_key.keys()));
+    from_files
+        .namespaces
+        .retain(|k, _| namespace_key_retained(k, filter, &required_namespaces));
+    from_database
+        .namespaces
+        .retain(|k, _| namespace_key_retained(k, filter, &required_namespaces));
+    Ok((from_database, from_files))
+}
+
+// Renders the files and compares them against what the database says was last pushed, returning
+// the changes in the order they should be applied.
+pub(crate) async fn plan_changes(
+    filter: &PartialKey,
+    monitor_directory: &[String],
+    environments: &mut Environments,
+    pool: &AnyPool,
+    timings: &mut Timings,
+) -> Result<Vec<PlannedChange>> {
+    let (from_database, from_files) =
+        load_database_and_files(filter, monitor_directory, environments, pool, timings).await?;
+    compare(&from_database, &from_files, timings)
+}
+
+// The changes that would make what the database recorded match what the files want
+pub(crate) fn compare(
+    from_database: &KubernetesResources,
+    from_files: &KubernetesResources,
+    timings: &mut Timings,
+) -> Result<Vec<PlannedChange>> {
+    let started = Instant::now();
+    let changed = tracing::info_span!("generate_diff").in_scope(|| {
+        let (comparable_database, comparable_files) =
+            make_comparable(from_database.clone(), from_files.clone())?;
+        generate_plan(comparable_database, comparable_files)
+    })?;
+    timings.record(Phase::Diff, started);
+    for change in &changed {
+        diff_detected(change.action.verb());
+    }
+    Ok(changed)
+}
@@ -0,0 +1,200 @@
+use anyhow::{anyhow, bail, Context, Result};
+use kube::api::DynamicObject;
+use serde::Deserialize;
+use serde_json::{json, Value as JsonValue};
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+    process::Stdio,
+    sync::OnceLock,
+};
+use tokio::{io::AsyncWriteExt, process::Command};
+
+use crate::sisyphus_yaml::{PluginResource, SisyphusResource};
+
+// The kinds Sisyphus renders itself, which plugins can't take over
+const BUILT_IN_KINDS: [&str; 5] = [
+    "CronJob",
+    "Deployment",
+    "Job",
+    "KubernetesYaml",
+    "SisyphusYaml",
+];
+
+// Which executable renders each plugin kind
+static PLUGINS: OnceLock<BTreeMap<String, PathBuf>> = OnceLock::new();
+
+// What a plugin prints for one cluster
+#[derive(Deserialize)]
+struct PluginOutput {
+    objects: Vec<DynamicObject>,
+}
+
+// Registers plugins from entries like KafkaTopic=/usr/local/bin/sisyphus-kafka-topic
+pub(crate) fn configure_plugins(plugins: &[String]) -> Result<()> {
+    let mut by_kind = BTreeMap::new();
+    for plugin in plugins {
+        let (kind, executable) = parse_plugin(plugin)?;
+        if by_kind.insert(kind.clone(), executable).is_some() {
+            bail!("Plugin kind {} is given more than once", kind);
+        }
+    }
+    let _ = PLUGINS.set(by_kind);
+    Ok(())
+}
+
+fn parse_plugin(plugin: &str) -> Result<(String, PathBuf)> {
+    let Some((kind, executable)) = plugin.split_once('=') else {
+        bail!(
+            "Expected a plugin like KafkaTopic=/path/to/plugin but got {}",
+            plugin
+        );
+    };
+    if kind.is_empty() || executable.is_empty() {
+        bail!(
+            "Expected a plugin like KafkaTopic=/path/to/plugin but got {}",
+            plugin
+        );
+    }
+    if BUILT_IN_KINDS.contains(&kind) {
+        bail!("{} is built in and can't be rendered by a plugin", kind);
+    }
+    Ok((kind.to_string(), PathBuf::from(executable)))
+}
+
+// Reads one YAML document as a resource, giving kinds that a plugin renders to that plugin
+pub(crate) fn read_resource(document: serde_yaml::Value) -> Result<SisyphusResource> {
+    let plugins = PLUGINS.get();
+    let is_plugin = document
+        .get("kind")
+        .and_then(|k| k.as_str())
+        .map_or(false, |k| plugins.map_or(false, |p| p.contains_key(k)));
+    Ok(if is_plugin {
+        SisyphusResource::Plugin(PluginResource::deserialize(document)?)
+    } else {
+        SisyphusResource::deserialize(document)?
+    })
+}
+
+// Runs the resource's plugin for one cluster. The plugin reads
+//
+//   {"resource": <the resource as written>, "cluster": "prod-east", "namespace": "echo"}
+//
+// on stdin and prints {"objects": [...]} on stdout, exiting non-zero with a message on stderr if
+// it can't.
+pub(crate) async fn render_plugin(
+    resource: &PluginResource,
+    cluster: &str,
+    namespace: &Option<String>,
+) -> Result<Vec<DynamicObject>> {
+    let executable = PLUGINS
+        .get()
+        .and_then(|p| p.get(&resource.kind))
+        .ok_or_else(|| anyhow!("No plugin renders {}", resource.kind))?;
+    run_plugin(executable, resource, cluster, namespace)
+        .await
+        .with_context(|| {
+            format!(
+                "while rendering {} {} with {}",
+                resource.kind,
+                resource.metadata.name,
+                executable.display()
+            )
+        })
+}
+
+async fn run_plugin(
+    executable: &Path,
+    resource: &PluginResource,
+    cluster: &str,
+    namespace: &Option<String>,
+) -> Result<Vec<DynamicObject>> {
+    let mut written = serde_json::to_value(resource)?;
+    if let Some(fields) = written.as_object_mut() {
+        fields.remove("pluginKind");
+        fields.insert("kind".to_string(), JsonValue::from(resource.kind.as_str()));
+    }
+    let input = json!({
+        "resource": written,
+        "cluster": cluster,
+        "namespace": namespace,
+    });
+    let mut child = Command::new(executable)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .context("Unable to start the plugin")?;
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin.write_all(input.to_string().as_bytes()).await?;
+    }
+    let output = child.wait_with_output().await?;
+    if !output.status.success() {
+        bail!(
+            "The plugin failed with {}: {}",
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    let output: PluginOutput =
+        serde_json::from_slice(&output.stdout).context("The plugin printed invalid output")?;
+    Ok(output.objects)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::PermissionsExt;
+
+    #[test]
+    fn test_parse_plugin() -> Result<()> {
+        assert_eq!(
+            parse_plugin("KafkaTopic=/usr/local/bin/kafka-topic")?,
+            (
+                "KafkaTopic".to_string(),
+                PathBuf::from("/usr/local/bin/kafka-topic")
+            )
+        );
+        assert!(parse_plugin("KafkaTopic").is_err());
+        assert!(parse_plugin("=/usr/local/bin/kafka-topic").is_err());
+        assert!(parse_plugin("Deployment=/usr/local/bin/deployment").is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_run_plugin() -> Result<()> {
+        let directory = tempfile::TempDir::new()?;
+        let executable = directory.path().join("plugin");
+        // Echoes the cluster and the resource's partitions back in a ConfigMap
+        std::fs::write(
+            &executable,
+            r#"#!/bin/sh
+input=$(cat)
+case "$input" in
+  *'"kind":"KafkaTopic"'*'"partitions":3'*) ;;
+  *) echo "unexpected input $input" >&2; exit 1 ;;
+esac
+cluster=$(echo "$input" | sed 's/.*"cluster":"\([^"]*\)".*/\1/')
+echo '{"objects": [{"apiVersion": "v1", "kind": "ConfigMap", "metadata": {"name": "orders"}, "data": {"cluster": "'$cluster'"}}]}'
+"#,
+        )?;
+        std::fs::set_permissions(&executable, std::fs::Permissions::from_mode(0o755))?;
+        let resource: PluginResource = serde_yaml::from_str(
+            "apiVersion: sisyphus/v1\nkind: KafkaTopic\nmetadata:\n  name: orders\nclusters: [prod]\n\
+             partitions: 3\n",
+        )?;
+
+        let objects = run_plugin(&executable, &resource, "prod", &Some("echo".to_string())).await?;
+        assert_eq!(objects.len(), 1);
+        assert_eq!(objects[0].data["data"]["cluster"], "prod");
+
+        let mut broken = resource.clone();
+        broken
+            .fields
+            .insert("partitions".to_string(), JsonValue::from(4));
+        assert!(run_plugin(&executable, &broken, "prod", &None)
+            .await
+            .is_err());
+        Ok(())
+    }
+}
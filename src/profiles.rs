@@ -0,0 +1,319 @@
+use anyhow::{anyhow, bail, Context, Result};
+use clap::{Args, Command};
+use serde::Deserialize;
+use std::{collections::BTreeMap, ffi::OsString, fs::File, path::PathBuf};
+
+// Read by apply_profile before clap runs. Declared so that clap accepts and documents them.
+#[allow(dead_code)]
+#[derive(Args, Debug)]
+pub(crate) struct ProfileArgs {
+    // A named bundle of settings (database, monitor directory, filters, ...) from the profiles file
+    #[arg(long, global = true, env = "SISYPHUS_PROFILE")]
+    profile: Option<String>,
+
+    // Where profiles are read from, defaulting to ~/.config/sisyphus/profiles.yaml
+    #[arg(long, global = true, env = "SISYPHUS_PROFILES_FILE")]
+    profiles_file: Option<String>,
+}
+
+// A named bundle of settings from the profiles file, so operators who manage several Sisyphus
+// installations pick one with `--profile` instead of juggling environment variables.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Profile {
+    audit_log: Option<String>,
+    database_url: Option<String>,
+    environments: Option<String>,
+    #[serde(default)]
+    filter: ProfileFilter,
+    // Accepted only to explain that it isn't supported, rather than failing as an unknown setting
+    label_namespace: Option<String>,
+    management_context: Option<String>,
+    monitor_directory: Option<String>,
+    notify: Option<String>,
+}
+
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ProfileFilter {
+    api_version: Option<String>,
+    app: Option<String>,
+    cluster: Option<String>,
+    kind: Option<String>,
+    name: Option<String>,
+    namespace: Option<String>,
+}
+
+impl Profile {
+    // The environment variables that the matching flags read from
+    fn variables(&self) -> Vec<(&'static str, String)> {
+        [
+            ("DATABASE_URL", &self.database_url),
+            ("MONITOR_DIRECTORY", &self.monitor_directory),
+            ("SISYPHUS_AUDIT_LOG", &self.audit_log),
+            ("SISYPHUS_ENVIRONMENTS", &self.environments),
+            ("SISYPHUS_MANAGEMENT_CONTEXT", &self.management_context),
+            ("SISYPHUS_NOTIFY", &self.notify),
+        ]
+        .into_iter()
+        .filter_map(|(k, v)| v.clone().map(|v| (k, v)))
+        .collect()
+    }
+
+    fn check_supported(&self, name: &str) -> Result<()> {
+        if self.label_namespace.is_some() {
+            bail!(
+                "Profile {} sets label_namespace, which isn't supported: every installation \
+                 labels and annotates objects under sisyphus.april.dev",
+                name
+            );
+        }
+        Ok(())
+    }
+
+    // The filter flags the profile sets, by their argument IDs
+    fn filters(&self) -> Vec<(&'static str, String)> {
+        [
+            ("api_version", &self.filter.api_version),
+            ("app", &self.filter.app),
+            ("cluster", &self.filter.cluster),
+            ("kind", &self.filter.kind),
+            ("name", &self.filter.name),
+            ("namespace", &self.filter.namespace),
+        ]
+        .into_iter()
+        .filter_map(|(k, v)| v.clone().map(|v| (k, v)))
+        .collect()
+    }
+}
+
+// The profile in use, so that it and its filters can be logged once logging is set up
+pub(crate) struct ActiveProfile {
+    pub name: String,
+    pub filters: Vec<(&'static str, String)>,
+}
+
+impl ActiveProfile {
+    // Like `--cluster staging-1 --kind Deployment`
+    pub(crate) fn describe_filters(&self) -> String {
+        self.filters
+            .iter()
+            .map(|(id, value)| format!("--{} {}", id.replace('_', "-"), value))
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+}
+
+// Applies the profile named by `--profile` (or SISYPHUS_PROFILE) by setting the environment
+// variables its flags fall back to. This runs before the arguments are parsed so that explicit
+// flags still win, while the profile wins over whatever happens to be in the environment. Filters
+// have no environment variables, so they're returned for with_profile_filters instead.
+pub(crate) fn apply_profile() -> Result<Option<ActiveProfile>> {
+    let args: Vec<OsString> = std::env::args_os().collect();
+    let Some(name) = flag_value(&args, "--profile").or_else(|| env_value("SISYPHUS_PROFILE"))
+    else {
+        return Ok(None);
+    };
+    let path = match flag_value(&args, "--profiles-file")
+        .or_else(|| env_value("SISYPHUS_PROFILES_FILE"))
+    {
+        Some(path) => PathBuf::from(path),
+        None => default_profiles_path()?,
+    };
+    let file = File::open(&path).with_context(|| format!("while opening {}", path.display()))?;
+    let mut profiles: BTreeMap<String, Profile> = serde_yaml::from_reader(file)
+        .with_context(|| format!("while parsing profiles from {}", path.display()))?;
+    let profile = profiles
+        .remove(&name)
+        .ok_or_else(|| anyhow!("No profile named {} in {}", name, path.display()))?;
+    profile.check_supported(&name)?;
+    for (key, value) in profile.variables() {
+        std::env::set_var(key, value);
+    }
+    Ok(Some(ActiveProfile {
+        filters: profile.filters(),
+        name,
+    }))
+}
+
+// Makes the profile's filters the defaults of every command that takes filters, so that flags
+// passed on the command line still win.
+pub(crate) fn with_profile_filters(
+    mut command: Command,
+    filters: &[(&'static str, String)],
+) -> Command {
+    // Only PartialKey has --exact, while other arguments named like filters, such as the key that
+    // forget takes, mean something else
+    if command.get_arguments().any(|a| a.get_id() == "exact") {
+        for (id, value) in filters {
+            command = command.mut_arg(*id, |arg| arg.default_value(value.clone()));
+        }
+    }
+    let subcommands: Vec<String> = command
+        .get_subcommands()
+        .map(|s| s.get_name().to_string())
+        .collect();
+    for name in subcommands {
+        command = command.mut_subcommand(name, |s| with_profile_filters(s, filters));
+    }
+    command
+}
+
+fn default_profiles_path() -> Result<PathBuf> {
+    let config = match env_value("XDG_CONFIG_HOME") {
+        Some(v) => PathBuf::from(v),
+        None => PathBuf::from(
+            env_value("HOME").ok_or_else(|| anyhow!("Unable to find the profiles file"))?,
+        )
+        .join(".config"),
+    };
+    Ok(config.join("sisyphus").join("profiles.yaml"))
+}
+
+fn env_value(key: &str) -> Option<String> {
+    std::env::var(key).ok().filter(|v| !v.is_empty())
+}
+
+// Finds `--flag value` or `--flag=value` before clap gets a chance to.
+fn flag_value(args: &[OsString], flag: &str) -> Option<String> {
+    let mut args = args.iter().map(|a| a.to_string_lossy());
+    while let Some(arg) = args.next() {
+        if arg == "--" {
+            return None;
+        }
+        if arg == flag {
+            return args.next().map(|v| v.to_string());
+        }
+        if let Some(value) = arg.strip_prefix(flag).and_then(|v| v.strip_prefix('=')) {
+            return Some(value.to_string());
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(values: &[&str]) -> Vec<OsString> {
+        values.iter().map(OsString::from).collect()
+    }
+
+    #[test]
+    fn test_flag_value() {
+        assert_eq!(
+            flag_value(&args(&["sisyphus", "push", "--profile", "staging"]), "--profile"),
+            Some("staging".to_string())
+        );
+        assert_eq!(
+            flag_value(&args(&["sisyphus", "--profile=staging", "diff"]), "--profile"),
+            Some("staging".to_string())
+        );
+        assert_eq!(
+            flag_value(&args(&["sisyphus", "--profiles-file", "p.yaml"]), "--profile"),
+            None
+        );
+    }
+
+    #[test]
+    fn test_profile_variables() {
+        let profiles: BTreeMap<String, Profile> = serde_yaml::from_str(
+            r#"
+staging:
+  database_url: sqlite://staging.db
+  monitor_directory: staging
+  filter:
+    cluster: staging-1
+"#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            profiles["staging"].variables(),
+            vec![
+                ("DATABASE_URL", "sqlite://staging.db".to_string()),
+                ("MONITOR_DIRECTORY", "staging".to_string()),
+            ]
+        );
+        assert_eq!(
+            profiles["staging"].filters(),
+            vec![("cluster", "staging-1".to_string())]
+        );
+    }
+
+    #[test]
+    fn test_profile_filters_are_defaults() {
+        #[derive(clap::Parser)]
+        struct Cli {
+            #[command(subcommand)]
+            command: Commands,
+        }
+
+        #[derive(clap::Subcommand)]
+        enum Commands {
+            Diff {
+                #[command(flatten)]
+                filter: crate::filter::PartialKey,
+            },
+            Forget {
+                #[arg(long)]
+                cluster: Option<String>,
+            },
+        }
+
+        let filters = vec![("cluster", "staging-1".to_string())];
+        let command = with_profile_filters(<Cli as clap::CommandFactory>::command(), &filters);
+        let matches = command.clone().get_matches_from(["sisyphus", "diff"]);
+        let (_, diff) = matches.subcommand().unwrap();
+        assert_eq!(
+            diff.get_raw("cluster")
+                .and_then(|mut v| v.next())
+                .and_then(|v| v.to_str()),
+            Some("staging-1")
+        );
+        let matches =
+            command
+                .clone()
+                .get_matches_from(["sisyphus", "diff", "--cluster", "staging-2"]);
+        let (_, diff) = matches.subcommand().unwrap();
+        assert_eq!(
+            diff.get_raw("cluster")
+                .and_then(|mut v| v.next())
+                .and_then(|v| v.to_str()),
+            Some("staging-2")
+        );
+        let matches = command.get_matches_from(["sisyphus", "forget"]);
+        let (_, forget) = matches.subcommand().unwrap();
+        assert!(forget.get_raw("cluster").is_none());
+    }
+
+    #[test]
+    fn test_describe_filters() {
+        let profile = ActiveProfile {
+            name: "staging".to_string(),
+            filters: vec![
+                ("api_version", "apps/v1".to_string()),
+                ("cluster", "staging-1".to_string()),
+            ],
+        };
+        assert_eq!(
+            profile.describe_filters(),
+            "--api-version apps/v1 --cluster staging-1"
+        );
+    }
+
+    #[test]
+    fn test_label_namespace_is_rejected() {
+        let profiles: BTreeMap<String, Profile> =
+            serde_yaml::from_str("staging:\n  label_namespace: example.com\n").unwrap();
+        assert!(profiles["staging"].check_supported("staging").is_err());
+        assert!(Profile::default().check_supported("staging").is_ok());
+    }
+
+    #[test]
+    fn test_profile_rejects_unknown_settings() {
+        let result: serde_yaml::Result<BTreeMap<String, Profile>> =
+            serde_yaml::from_str("staging:\n  database: sqlite://staging.db\n");
+        assert!(result.is_err());
+    }
+}
@@ -0,0 +1,192 @@
+use clap::{ArgMatches, Command};
+use json_patch::{jsonptr::PointerBuf, AddOperation, PatchOperation};
+use kube::{api::DynamicObject, ResourceExt};
+use serde_json::Value as JsonValue;
+use sha2::{Digest, Sha256};
+use std::{collections::BTreeMap, sync::OnceLock};
+
+// Set on objects pushed with --annotate-provenance. It changes with every release and flag, so it's
+// never a reason to push an object again.
+pub(crate) const PROVENANCE_ANNOTATION: &str = "sisyphus.april.dev/provenance";
+
+// Flags whose values may carry credentials
+const REDACTED_FLAGS: [&str; 1] = ["database_url"];
+
+const LOCKFILE: &[u8] = include_bytes!("../Cargo.lock");
+
+static FLAGS: OnceLock<BTreeMap<String, String>> = OnceLock::new();
+static SOURCE_COMMIT: OnceLock<String> = OnceLock::new();
+static DELEGATED_TEAM: OnceLock<String> = OnceLock::new();
+static PROVENANCE: OnceLock<String> = OnceLock::new();
+
+// Records the flags this process runs with, wherever their values came from (the command line, the
+// environment, a profile, or a default)
+pub(crate) fn configure_provenance(command: &Command, matches: &ArgMatches) {
+    let mut flags = BTreeMap::new();
+    effective_flags(command, matches, &mut flags);
+    let _ = FLAGS.set(flags);
+}
+
+// Records the commit the files were rendered from when they were read from git. Must be called
+// before anything asks for the provenance.
+pub(crate) fn record_source_commit(commit: &str) {
+    let _ = SOURCE_COMMIT.set(commit.to_string());
+}
+
+// Records the team whose signed delegation a push runs under, so its changes can be told apart
+// from fleet-wide ones. Like record_source_commit, must be called before anything asks for the
+// provenance.
+pub(crate) fn record_delegation(team: &str) {
+    let _ = DELEGATED_TEAM.set(team.to_string());
+}
+
+// A JSON object naming the Sisyphus version, the dependencies it was built with, its flags, the
+// commit it rendered, and the team it pushed for, so a live object can be traced back to the tool
+// that produced it
+pub(crate) fn provenance() -> &'static str {
+    PROVENANCE.get_or_init(|| {
+        describe(
+            FLAGS.get().cloned().unwrap_or_default(),
+            SOURCE_COMMIT.get().map(|c| c.as_str()),
+            DELEGATED_TEAM.get().map(|t| t.as_str()),
+        )
+    })
+}
+
+fn describe(
+    flags: BTreeMap<String, String>,
+    commit: Option<&str>,
+    delegated_team: Option<&str>,
+) -> String {
+    let lockfile = format!("{:x}", Sha256::digest(LOCKFILE));
+    let mut description = serde_json::json!({
+        "version": env!("CARGO_PKG_VERSION"),
+        "lockfile": &lockfile[..16],
+        "flags": flags,
+    });
+    if let Some(commit) = commit {
+        description["commit"] = JsonValue::String(commit.to_string());
+    }
+    if let Some(team) = delegated_team {
+        description["delegatedTeam"] = JsonValue::String(team.to_string());
+    }
+    description.to_string()
+}
+
+fn effective_flags(command: &Command, matches: &ArgMatches, flags: &mut BTreeMap<String, String>) {
+    for arg in command.get_arguments() {
+        let id = arg.get_id().as_str();
+        let Ok(Some(values)) = matches.try_get_raw(id) else {
+            continue;
+        };
+        let value = if REDACTED_FLAGS.contains(&id) {
+            "<redacted>".to_string()
+        } else {
+            values
+                .map(|v| v.to_string_lossy().into_owned())
+                .collect::<Vec<_>>()
+                .join(",")
+        };
+        flags.insert(arg.get_long().unwrap_or(id).to_string(), value);
+    }
+    if let Some((name, matches)) = matches.subcommand() {
+        if let Some(subcommand) = command.find_subcommand(name) {
+            effective_flags(subcommand, matches, flags);
+        }
+    }
+}
+
+pub(crate) fn stamp_provenance(object: &mut DynamicObject) {
+    object
+        .annotations_mut()
+        .insert(PROVENANCE_ANNOTATION.to_string(), provenance().to_string());
+}
+
+// The same as stamp_provenance but for an object that's being patched
+pub(crate) fn provenance_patch() -> PatchOperation {
+    PatchOperation::Add(AddOperation {
+        path: PointerBuf::from_tokens(["metadata", "annotations", PROVENANCE_ANNOTATION]),
+        value: JsonValue::String(provenance().to_string()),
+    })
+}
+
+// Makes `want` carry whatever provenance `have` was pushed with, so comparing them ignores it
+pub(crate) fn keep_provenance(have: &DynamicObject, want: &mut DynamicObject) {
+    match have.annotations().get(PROVENANCE_ANNOTATION) {
+        Some(v) => {
+            want.annotations_mut()
+                .insert(PROVENANCE_ANNOTATION.to_string(), v.clone());
+        }
+        None => {
+            if let Some(annotations) = want.metadata.annotations.as_mut() {
+                annotations.remove(PROVENANCE_ANNOTATION);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Arg;
+
+    #[test]
+    fn test_effective_flags_include_subcommands_and_redact() {
+        let command = Command::new("sisyphus")
+            .arg(Arg::new("theme").long("theme").default_value("default"))
+            .subcommand(
+                Command::new("push")
+                    .arg(Arg::new("database_url").long("database-url"))
+                    .arg(Arg::new("monitor_directory").long("monitor-directory")),
+            );
+        let matches = command.clone().get_matches_from([
+            "sisyphus",
+            "push",
+            "--database-url",
+            "postgres://user:password@db/sisyphus",
+            "--monitor-directory",
+            "./production",
+        ]);
+
+        let mut flags = BTreeMap::new();
+        effective_flags(&command, &matches, &mut flags);
+
+        assert_eq!(
+            flags,
+            BTreeMap::from([
+                ("database-url".to_string(), "<redacted>".to_string()),
+                ("monitor-directory".to_string(), "./production".to_string()),
+                ("theme".to_string(), "default".to_string()),
+            ])
+        );
+    }
+
+    #[test]
+    fn test_keep_provenance() {
+        let object = |annotations: &[(&str, &str)]| DynamicObject {
+            types: None,
+            metadata: kube::api::ObjectMeta {
+                annotations: Some(
+                    annotations
+                        .iter()
+                        .map(|(k, v)| (k.to_string(), v.to_string()))
+                        .collect(),
+                ),
+                ..Default::default()
+            },
+            data: serde_json::json!({}),
+        };
+        let pushed = object(&[("a", "b"), (PROVENANCE_ANNOTATION, "{}")]);
+
+        let mut want = object(&[("a", "b")]);
+        keep_provenance(&pushed, &mut want);
+        assert_eq!(want.metadata.annotations, pushed.metadata.annotations);
+
+        let mut want = object(&[("a", "b"), (PROVENANCE_ANNOTATION, "{}")]);
+        keep_provenance(&object(&[("a", "b")]), &mut want);
+        assert_eq!(
+            want.metadata.annotations,
+            object(&[("a", "b")]).metadata.annotations
+        );
+    }
+}
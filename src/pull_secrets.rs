@@ -0,0 +1,172 @@
+use anyhow::{bail, Result};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use docker_credential::{CredentialRetrievalError, DockerCredential};
+use json_patch::{jsonptr::PointerBuf, AddOperation, PatchOperation};
+use k8s_openapi::api::core::v1::Secret;
+use kube::{
+    api::{DynamicObject, ObjectMeta},
+    ResourceExt,
+};
+use serde_json::{json, Value as JsonValue};
+use std::collections::BTreeMap;
+
+// Lists the registries whose credentials a rendered pull Secret holds. The credentials themselves
+// are read from whoever runs push only as the Secret is applied, so rendering doesn't depend on who
+// runs it and the database never sees them.
+pub(crate) const DOCKER_CREDENTIALS_ANNOTATION: &str = "sisyphus.april.dev/docker-credentials";
+
+const DOCKER_CONFIG_KEY: &str = ".dockerconfigjson";
+
+// Renders a pull Secret without its credentials. Nothing about the resource asking for it goes in,
+// so every resource in a namespace naming the same Secret renders exactly the same object.
+pub(crate) fn build_docker_config_secret(
+    name: &str,
+    namespace: &str,
+    registries: &[String],
+) -> Secret {
+    let mut registries = registries.to_vec();
+    registries.sort();
+    registries.dedup();
+    Secret {
+        metadata: ObjectMeta {
+            annotations: Some(BTreeMap::from([(
+                DOCKER_CREDENTIALS_ANNOTATION.to_string(),
+                registries.join(","),
+            )])),
+            name: Some(name.to_string()),
+            namespace: Some(namespace.to_string()),
+            ..Default::default()
+        },
+        type_: Some("kubernetes.io/dockerconfigjson".to_string()),
+        ..Default::default()
+    }
+}
+
+fn registries(object: &DynamicObject) -> Option<Vec<&str>> {
+    object
+        .annotations()
+        .get(DOCKER_CREDENTIALS_ANNOTATION)
+        .map(|r| r.split(',').filter(|r| !r.is_empty()).collect())
+}
+
+// Fills in a rendered pull Secret from the local docker credentials just before it's applied
+pub(crate) fn fill_docker_credentials(object: &mut DynamicObject) -> Result<()> {
+    let Some(data) = docker_config_data(object)? else {
+        return Ok(());
+    };
+    object.data["data"] = data;
+    Ok(())
+}
+
+// Patches carry the credentials too, since have and want both leave them out
+pub(crate) fn docker_credentials_patch(object: &DynamicObject) -> Result<Option<PatchOperation>> {
+    Ok(docker_config_data(object)?.map(|value| {
+        PatchOperation::Add(AddOperation {
+            path: PointerBuf::from_tokens(["data"]),
+            value,
+        })
+    }))
+}
+
+// Drops the credentials the API server hands back so they're never recorded or compared
+pub(crate) fn forget_docker_credentials(object: &mut DynamicObject) {
+    if registries(object).is_some() {
+        if let Some(o) = object.data.as_object_mut() {
+            o.remove("data");
+        }
+    }
+}
+
+fn docker_config_data(object: &DynamicObject) -> Result<Option<JsonValue>> {
+    let Some(registries) = registries(object) else {
+        return Ok(None);
+    };
+    let mut auths = serde_json::Map::new();
+    for registry in registries {
+        let (username, password) = docker_credentials(registry)?;
+        auths.insert(
+            registry.to_string(),
+            json!({ "username": username, "password": password }),
+        );
+    }
+    let config = serde_json::to_vec(&json!({ "auths": auths }))?;
+    Ok(Some(json!({ DOCKER_CONFIG_KEY: STANDARD.encode(config) })))
+}
+
+fn docker_credentials(registry: &str) -> Result<(String, String)> {
+    match docker_credential::get_credential(registry) {
+        Ok(DockerCredential::UsernamePassword(u, p)) => Ok((u, p)),
+        Ok(DockerCredential::IdentityToken(_)) => {
+            bail!("Cannot put the identity token for {} in a Secret", registry)
+        }
+        Err(CredentialRetrievalError::NoCredentialConfigured) => {
+            bail!("No docker credentials are configured for {}", registry)
+        }
+        Err(e) => bail!("Error fetching credential for {}: {}", registry, e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rendered(registries: &[&str]) -> Result<DynamicObject> {
+        let registries: Vec<String> = registries.iter().map(|r| r.to_string()).collect();
+        let secret = build_docker_config_secret("regcred", "echo", &registries);
+        Ok(serde_json::from_value(serde_json::to_value(secret)?)?)
+    }
+
+    #[test]
+    fn test_build_docker_config_secret() -> Result<()> {
+        let secret = build_docker_config_secret(
+            "regcred",
+            "echo",
+            &["us-docker.pkg.dev".to_string(), "ghcr.io".to_string()],
+        );
+
+        assert_eq!(secret.metadata.name, Some("regcred".to_string()));
+        assert_eq!(secret.metadata.namespace, Some("echo".to_string()));
+        assert_eq!(
+            secret.type_,
+            Some("kubernetes.io/dockerconfigjson".to_string())
+        );
+        assert_eq!(secret.data, None);
+        assert_eq!(
+            secret.metadata.annotations.unwrap()[DOCKER_CREDENTIALS_ANNOTATION],
+            "ghcr.io,us-docker.pkg.dev"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_forget_docker_credentials() -> Result<()> {
+        let mut live = rendered(&["ghcr.io"])?;
+        live.data["data"] = json!({ DOCKER_CONFIG_KEY: "c2VjcmV0" });
+        forget_docker_credentials(&mut live);
+        assert_eq!(live, rendered(&["ghcr.io"])?);
+
+        let mut other: DynamicObject = serde_json::from_value(json!({
+            "apiVersion": "v1",
+            "kind": "Secret",
+            "metadata": {"name": "token"},
+            "data": {"token": "c2VjcmV0"},
+        }))?;
+        let before = other.clone();
+        forget_docker_credentials(&mut other);
+        assert_eq!(other, before);
+        Ok(())
+    }
+
+    #[test]
+    fn test_nothing_to_fill_without_the_annotation() -> Result<()> {
+        let mut secret: DynamicObject = serde_json::from_value(json!({
+            "apiVersion": "v1",
+            "kind": "Secret",
+            "metadata": {"name": "token"},
+        }))?;
+        fill_docker_credentials(&mut secret)?;
+        assert_eq!(secret.data.get("data"), None);
+        assert!(docker_credentials_patch(&secret)?.is_none());
+        Ok(())
+    }
+}
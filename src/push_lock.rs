@@ -0,0 +1,197 @@
+use anyhow::{bail, Context, Result};
+use sqlx::AnyPool;
+use std::{
+    fs,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+use time::{format_description::well_known::Rfc3339, OffsetDateTime};
+use tokio::{task::JoinHandle, time::sleep};
+use tracing::warn;
+
+use crate::state_store::{delete_lock, extend_lock, find_lock, insert_lock, LockRow};
+
+// Everything that changes clusters shares one lock per database, since a plan covers every cluster
+const LOCK_NAME: &str = "push";
+// How long a lock outlives its holder's last heartbeat, after which anyone may take it
+const LEASE: Duration = Duration::from_secs(120);
+const HEARTBEAT: Duration = Duration::from_secs(30);
+
+pub(crate) struct PushLock {
+    pool: AnyPool,
+    token: String,
+    heartbeat: JoinHandle<()>,
+    lost: Arc<AtomicBool>,
+}
+
+impl PushLock {
+    // Whether someone else took the lock, after which nothing more should be applied
+    pub(crate) fn is_lost(&self) -> bool {
+        self.lost.load(Ordering::SeqCst)
+    }
+
+    pub(crate) async fn release(self) -> Result<()> {
+        self.heartbeat.abort();
+        delete_lock(&self.pool, LOCK_NAME, &self.token).await
+    }
+}
+
+struct Holder {
+    holder: String,
+    token: String,
+    acquired: String,
+    expires: OffsetDateTime,
+}
+
+// Takes the database's lock for `command`, failing if another push, refresh, or import holds it.
+// `force_unlock` first removes whatever lock is there, for when its holder died and the lease
+// hasn't run out yet.
+pub(crate) async fn lock_database(
+    pool: &AnyPool,
+    command: &str,
+    force_unlock: bool,
+) -> Result<PushLock> {
+    if let Some(current) = current_holder(pool).await? {
+        if force_unlock || current.expires <= OffsetDateTime::now_utc() {
+            if force_unlock {
+                warn!(holder = %current.holder, "Removing the lock held by {}", current.holder);
+            }
+            delete_lock(pool, LOCK_NAME, &current.token).await?;
+        } else {
+            bail!(
+                "{} has held the database since {}. Wait for it to finish, or pass --force-unlock \
+                 if it died.",
+                current.holder,
+                current.acquired
+            );
+        }
+    }
+
+    let token = format!("{:016x}", rand::random::<u64>());
+    let now = OffsetDateTime::now_utc().replace_nanosecond(0)?;
+    let row = LockRow {
+        holder: describe_holder(command),
+        token: token.clone(),
+        acquired: now.format(&Rfc3339)?,
+        expires: (now + LEASE).format(&Rfc3339)?,
+    };
+    let inserted = insert_lock(pool, LOCK_NAME, &row).await;
+    if let Err(e) = inserted {
+        // Someone else got there between the check and the insert
+        if let Some(current) = current_holder(pool).await? {
+            bail!("{} locked the database first", current.holder);
+        }
+        return Err(e).context("while locking the database");
+    }
+
+    let lost = Arc::new(AtomicBool::new(false));
+    let heartbeat = tokio::spawn(keep_alive(pool.clone(), token.clone(), lost.clone()));
+    Ok(PushLock {
+        pool: pool.clone(),
+        token,
+        heartbeat,
+        lost,
+    })
+}
+
+async fn current_holder(pool: &AnyPool) -> Result<Option<Holder>> {
+    let Some(row) = find_lock(pool, LOCK_NAME).await? else {
+        return Ok(None);
+    };
+    Ok(Some(Holder {
+        expires: OffsetDateTime::parse(&row.expires, &Rfc3339)
+            .context("Invalid expiry on the database lock")?,
+        holder: row.holder,
+        token: row.token,
+        acquired: row.acquired,
+    }))
+}
+
+// Extends the lease until the lock is released, which aborts this task, or until someone else
+// takes it
+async fn keep_alive(pool: AnyPool, token: String, lost: Arc<AtomicBool>) {
+    loop {
+        sleep(HEARTBEAT).await;
+        match extend_lease(&pool, &token).await {
+            Ok(0) => {
+                warn!("Lost the database lock, someone passed --force-unlock");
+                lost.store(true, Ordering::SeqCst);
+                return;
+            }
+            Ok(_) => {}
+            Err(e) => warn!(error = %e, "Couldn't extend the database lock: {}", e),
+        }
+    }
+}
+
+async fn extend_lease(pool: &AnyPool, token: &str) -> Result<u64> {
+    let now = OffsetDateTime::now_utc().replace_nanosecond(0)?;
+    extend_lock(
+        pool,
+        LOCK_NAME,
+        token,
+        &now.format(&Rfc3339)?,
+        &(now + LEASE).format(&Rfc3339)?,
+    )
+    .await
+}
+
+fn describe_holder(command: &str) -> String {
+    format!("{} (pid {}, {})", whoami(), std::process::id(), command)
+}
+
+// Like user@host, naming who is running Sisyphus
+pub(crate) fn whoami() -> String {
+    let user = std::env::var("USER").unwrap_or_else(|_| "unknown".to_string());
+    let host = fs::read_to_string("/etc/hostname")
+        .map(|h| h.trim().to_string())
+        .unwrap_or_else(|_| "unknown".to_string());
+    format!("{}@{}", user, host)
+}
+
+// The tests run against a throwaway SQLite database
+#[cfg(all(test, feature = "sqlite"))]
+mod tests {
+    use super::*;
+    use crate::{migrations::migrate, state_store::connect_database};
+    use tempfile::TempDir;
+
+    #[tokio::test]
+    async fn test_lock_database() -> Result<()> {
+        sqlx::any::install_default_drivers();
+        let directory = TempDir::new()?;
+        let url = format!(
+            "sqlite://{}?mode=rwc",
+            directory.path().join("sisyphus.db").display()
+        );
+        let pool = connect_database(&url).await?;
+        migrate(&pool).await?;
+
+        let lock = lock_database(&pool, "push", false).await?;
+        assert!(lock_database(&pool, "refresh", false).await.is_err());
+        lock.release().await?;
+
+        // A holder that died leaves its lock behind until the lease runs out
+        let abandoned = lock_database(&pool, "push", false).await?;
+        abandoned.heartbeat.abort();
+        assert!(lock_database(&pool, "push", false).await.is_err());
+        let forced = lock_database(&pool, "push", true).await?;
+        assert_eq!(extend_lease(&pool, &abandoned.token).await?, 0);
+        assert!(!forced.is_lost());
+        forced.release().await?;
+
+        let expired = lock_database(&pool, "push", false).await?;
+        expired.heartbeat.abort();
+        sqlx::query("UPDATE locks SET expires = '2000-01-01T00:00:00Z'")
+            .execute(&pool)
+            .await?;
+        lock_database(&pool, "import", false)
+            .await?
+            .release()
+            .await?;
+        Ok(())
+    }
+}
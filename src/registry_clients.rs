@@ -4,94 +4,332 @@ use docker_registry::{
     reference::{Reference as RegistryReference, Version as RegistryVersion},
     v2::Client as RegistryClient,
 };
+use futures::{future::try_join_all, TryStreamExt};
+use serde_json::Value as JsonValue;
+use sha2::{Digest, Sha256};
 use std::collections::HashMap;
+use std::path::Path;
 use std::str::FromStr;
+use std::sync::{Arc, Mutex, OnceLock};
+use tokio::sync::OnceCell;
+use tracing::instrument;
+
+use crate::{
+    blob_cache::{read_cached, write_cached},
+    cloud_credentials::cloud_credential,
+    image_layers::{unpack_layers, verify_digest},
+    render_progress::{image_stage, Stage},
+    tag_policy::split_tag_policy,
+};
 
 #[cfg(test)]
 mod tests;
 
+// Config images hold the same files for every platform, so any one of a list will do
+const DEFAULT_PLATFORM: &str = "linux/amd64";
+
+static PLATFORM: OnceLock<String> = OnceLock::new();
+
+// Pins multi-platform images to the manifest for one os/architecture[/variant], like linux/arm64,
+// instead of to the list
+pub(crate) fn configure_platform(platform: Option<String>) {
+    if let Some(platform) = platform {
+        let _ = PLATFORM.set(platform);
+    }
+}
+
+// Registry clients keyed by registry host. Safe to share between tasks (wrap it in an `Arc` to move
+// it into spawned ones): the map is only locked long enough to find a registry's slot, and each
+// registry is connected and authenticated at most once even when many tasks ask for it at once.
 pub(crate) struct RegistryClients {
-    clients: HashMap<String, RegistryClient>,
+    clients: Mutex<HashMap<String, Arc<OnceCell<Arc<RegistryClient>>>>>,
 }
 
 impl RegistryClients {
     pub(crate) fn new() -> Self {
         return RegistryClients {
-            clients: HashMap::new(),
+            clients: Mutex::new(HashMap::new()),
         };
     }
 
-    pub(crate) async fn get_reference_and_registry<'a, 'b: 'a>(
-        self: &'b mut Self,
+    pub(crate) async fn get_reference_and_registry(
+        &self,
         registry: &String,
-    ) -> Result<(RegistryReference, &'a mut RegistryClient)> {
-        let (secure, schemaless) = if registry.starts_with("http://") {
-            (false, registry.strip_prefix("http://").unwrap())
-        } else if registry.starts_with("https://") {
-            (true, registry.strip_prefix("https://").unwrap())
-        } else {
-            (true, registry.as_str())
-        };
-
-        let reference = RegistryReference::from_str(schemaless)
-            .map_err(|e| anyhow!("Unable to parse image url: {}", e))?;
+    ) -> Result<(RegistryReference, Arc<RegistryClient>)> {
+        let (reference, secure) = parse_image(registry)?;
         let registry = self.get_client(&reference.registry(), secure).await?;
         Ok((reference, registry))
     }
 
-    async fn get_client<'a, 'b: 'a>(
-        self: &'b mut Self,
-        registry: &String,
-        secure: bool,
-    ) -> Result<&'a mut RegistryClient> {
-        if !self.clients.contains_key(registry) {
-            let credential = match docker_credential::get_credential(registry.as_ref()) {
-                Ok(DockerCredential::UsernamePassword(u, p)) => Some((u, p)),
-                Ok(DockerCredential::IdentityToken(_)) => bail!("Cannot handle tokens"),
-                Err(CredentialRetrievalError::NoCredentialConfigured) => None,
-                Err(e) => bail!("Error fetching credential: {}", e),
-            };
+    async fn get_client(&self, registry: &String, secure: bool) -> Result<Arc<RegistryClient>> {
+        let cell = self
+            .clients
+            .lock()
+            .map_err(|_| anyhow!("Registry clients lock is poisoned"))?
+            .entry(registry.to_string())
+            .or_default()
+            .clone();
+        let client = cell
+            .get_or_try_init(|| async { connect(registry, secure).await.map(Arc::new) })
+            .await?;
+        Ok(client.clone())
+    }
+}
 
-            let builder = RegistryClient::configure().registry(&registry);
-            let builder2 = match secure {
-                true => builder,
-                false => builder.insecure_registry(true),
-            };
-            let builder3 = if let Some((u, p)) = &credential {
-                builder2.username(Some(u.clone())).password(Some(p.clone()))
-            } else {
-                builder2
-            };
-            let builder4 = builder3.build()?;
-            let v = if let Some(_) = credential {
-                builder4.authenticate(&[]).await?
-            } else {
-                builder4
-            };
-            self.clients.insert(registry.to_string(), v);
-        }
+// Parses an image reference, returning whether its registry is reached over TLS
+fn parse_image(image: &str) -> Result<(RegistryReference, bool)> {
+    let (secure, schemaless) = if let Some(rest) = image.strip_prefix("http://") {
+        (false, rest)
+    } else if let Some(rest) = image.strip_prefix("https://") {
+        (true, rest)
+    } else {
+        (true, image)
+    };
+    let reference = RegistryReference::from_str(schemaless)
+        .map_err(|e| anyhow!("Unable to parse image url: {}", e))?;
+    Ok((reference, secure))
+}
+
+// Identity tokens are OAuth refresh tokens, which the registries that hand them out (such as ACR
+// after `az acr login`) accept as the password of this placeholder user when issuing access tokens
+const IDENTITY_TOKEN_USERNAME: &str = "00000000-0000-0000-0000-000000000000";
+
+#[instrument]
+async fn connect(registry: &str, secure: bool) -> Result<RegistryClient> {
+    let credential = match registry_credential(registry)? {
+        Some(credential) => Some(credential),
+        None => cloud_credential(registry).await?,
+    };
+
+    let builder = RegistryClient::configure().registry(registry);
+    let builder2 = match secure {
+        true => builder,
+        false => builder.insecure_registry(true),
+    };
+    let builder3 = if let Some((u, p)) = &credential {
+        builder2.username(Some(u.clone())).password(Some(p.clone()))
+    } else {
+        builder2
+    };
+    let builder4 = builder3.build()?;
+    let v = if let Some(_) = credential {
+        builder4.authenticate(&[]).await?
+    } else {
+        builder4
+    };
+    Ok(v)
+}
+
+// Finds the credential for `registry` in the docker config, running the credential helper it names
+// for registries like ECR and GAR, and then in podman's. Registries without one use credentials
+// exchanged from the environment's cloud credentials if possible, and otherwise are used
+// anonymously.
+fn registry_credential(registry: &str) -> Result<Option<(String, String)>> {
+    let credential = match docker_credential::get_credential(registry) {
+        Err(e) if is_unconfigured(&e) => docker_credential::get_podman_credential(registry),
+        found => found,
+    };
+    match credential {
+        Ok(credential) => Ok(Some(basic_credential(credential))),
+        Err(e) if is_unconfigured(&e) => Ok(None),
+        Err(CredentialRetrievalError::HelperCommunicationError) => bail!(
+            "Unable to run the credential helper for {}, is its docker-credential-* binary on the \
+             PATH?",
+            registry
+        ),
+        Err(e) => bail!("Error fetching the credential for {}: {}", registry, e),
+    }
+}
+
+fn is_unconfigured(error: &CredentialRetrievalError) -> bool {
+    matches!(
+        error,
+        CredentialRetrievalError::ConfigNotFound
+            | CredentialRetrievalError::ConfigReadError
+            | CredentialRetrievalError::NoCredentialConfigured
+    )
+}
 
-        self.clients
-            .get_mut(registry)
-            .ok_or_else(|| anyhow!("Unable to get client"))
+fn basic_credential(credential: DockerCredential) -> (String, String) {
+    match credential {
+        DockerCredential::UsernamePassword(username, password) => (username, password),
+        DockerCredential::IdentityToken(token) => (IDENTITY_TOKEN_USERNAME.to_string(), token),
     }
 }
 
+// Pins `image` to a digest, first picking the newest allowed tag if it names a tag policy like ~1.2
+#[instrument(skip(registries))]
 pub(crate) async fn resolve_image_tag(
     image: &String,
-    registries: &mut RegistryClients,
+    registries: &RegistryClients,
 ) -> Result<RegistryReference> {
-    let (image, registry) = registries.get_reference_and_registry(image).await?;
-    let manifest = registry
-        .get_manifest(image.repository().as_ref(), image.version().as_ref())
+    let image = match split_tag_policy(image)? {
+        (repository, Some(policy)) => {
+            let (reference, registry) = registries.get_reference_and_registry(&repository).await?;
+            let tags: Vec<String> = registry
+                .get_tags(&reference.repository(), None)
+                .try_collect()
+                .await
+                .with_context(|| format!("while listing the tags of {}", repository))?;
+            let Some(tag) = policy.newest(tags.iter().map(|t| t.as_str())) else {
+                bail!("No tag of {} matches {}", repository, image);
+            };
+            format!("{}:{}", repository, tag)
+        }
+        (_, None) => image.clone(),
+    };
+    let (image, registry) = registries.get_reference_and_registry(&image).await?;
+    let (raw, _, digest) = registry
+        .get_raw_manifest_and_metadata(image.repository().as_ref(), image.version().as_ref())
         .await
         .with_context(|| format!("while resolving {}", image))?;
-    let digests = manifest.layers_digests(None)?;
+    let digest = digest.unwrap_or_else(|| format!("sha256:{:x}", Sha256::digest(&raw)));
+    // The digest of a list lets each node pull its own platform
+    let digest = match PLATFORM.get() {
+        Some(platform) => platform_manifest(&raw, platform)
+            .with_context(|| format!("while resolving {}", image))?
+            .unwrap_or(digest),
+        None => digest,
+    };
     Ok(RegistryReference::new(
         Some(image.registry()),
         image.repository(),
-        Some(RegistryVersion::from_str(
-            format!("@{}", digests[0]).as_ref(),
-        )?),
+        Some(RegistryVersion::from_str(format!("@{}", digest).as_ref())?),
     ))
 }
+
+// The digest of the manifest for `platform` if `raw` is a manifest list or OCI index, or None if
+// it's already a single image's manifest
+fn platform_manifest(raw: &[u8], platform: &str) -> Result<Option<String>> {
+    let manifest: JsonValue = serde_json::from_slice(raw).context("Invalid manifest")?;
+    let Some(entries) = manifest["manifests"].as_array() else {
+        return Ok(None);
+    };
+    let mut wanted = platform.split('/');
+    let (os, architecture, variant) = (wanted.next(), wanted.next(), wanted.next());
+    let mut available = Vec::new();
+    for entry in entries {
+        let p = &entry["platform"];
+        let matches = p["os"].as_str() == os
+            && p["architecture"].as_str() == architecture
+            && (variant.is_none() || p["variant"].as_str() == variant);
+        if matches {
+            if let Some(digest) = entry["digest"].as_str() {
+                return Ok(Some(digest.to_string()));
+            }
+        }
+        available.push(
+            [&p["os"], &p["architecture"], &p["variant"]]
+                .iter()
+                .filter_map(|v| v.as_str())
+                .collect::<Vec<_>>()
+                .join("/"),
+        );
+    }
+    bail!(
+        "No manifest for {}, only for {}",
+        platform,
+        available.join(", ")
+    )
+}
+
+// Fails unless the registry has a manifest for `image`, which should be pinned to a digest. Only
+// asks whether the manifest exists rather than downloading it.
+#[instrument(skip(registries))]
+pub(crate) async fn verify_image_exists(
+    image: &String,
+    registries: &RegistryClients,
+) -> Result<()> {
+    let (reference, registry) = registries.get_reference_and_registry(image).await?;
+    let found = registry
+        .has_manifest(
+            reference.repository().as_ref(),
+            reference.version().as_ref(),
+            None,
+        )
+        .await
+        .with_context(|| format!("while looking up {}", image))?;
+    if found.is_none() {
+        bail!("{} isn't in the registry", image);
+    }
+    Ok(())
+}
+
+// Downloads every layer of `image`, checks each against its digest, and unpacks them in order into
+// `target`. Manifests and layers named by digest come from the local cache when they can, in which
+// case the registry isn't contacted at all.
+#[instrument(skip(registries))]
+pub(crate) async fn pull_image(
+    image: &String,
+    registries: &RegistryClients,
+    target: &Path,
+) -> Result<()> {
+    let (reference, _) = parse_image(image)?;
+    let mut manifest = raw_manifest(image, registries, reference.version().as_ref()).await?;
+    let platform = PLATFORM.get().map_or(DEFAULT_PLATFORM, |p| p.as_str());
+    if let Some(digest) = platform_manifest(&manifest, platform)? {
+        manifest = raw_manifest(image, registries, &digest).await?;
+    }
+    let layers_digests = manifest_layers(&manifest).with_context(|| format!("in {}", image))?;
+    image_stage(image, Stage::Downloading);
+    let blobs = try_join_all(
+        layers_digests
+            .iter()
+            .map(|digest| layer(image, registries, digest)),
+    )
+    .await?;
+    image_stage(image, Stage::Unpacking);
+    unpack_layers(&blobs, target).with_context(|| format!("in {}", image))?;
+    Ok(())
+}
+
+async fn raw_manifest(
+    image: &String,
+    registries: &RegistryClients,
+    version: &str,
+) -> Result<Vec<u8>> {
+    if let Some(cached) = read_cached(version) {
+        return Ok(cached);
+    }
+    let (reference, registry) = registries.get_reference_and_registry(image).await?;
+    let (raw, _, _) = registry
+        .get_raw_manifest_and_metadata(&reference.repository(), version)
+        .await
+        .with_context(|| format!("while pulling {}", image))?;
+    // Only a manifest fetched by digest can be cached under it
+    if verify_digest(version, &raw).is_ok() {
+        write_cached(version, &raw);
+    }
+    Ok(raw)
+}
+
+async fn layer(image: &String, registries: &RegistryClients, digest: &str) -> Result<Vec<u8>> {
+    if let Some(cached) = read_cached(digest) {
+        return Ok(cached);
+    }
+    let (reference, registry) = registries.get_reference_and_registry(image).await?;
+    let blob = registry
+        .get_blob(&reference.repository(), digest)
+        .await
+        .with_context(|| format!("while pulling {}", image))?;
+    verify_digest(digest, &blob).with_context(|| format!("in {}", image))?;
+    write_cached(digest, &blob);
+    Ok(blob)
+}
+
+// The layer digests of a Docker schema 2 or OCI image manifest, in order
+fn manifest_layers(raw: &[u8]) -> Result<Vec<String>> {
+    let manifest: JsonValue = serde_json::from_slice(raw).context("Invalid manifest")?;
+    let Some(layers) = manifest["layers"].as_array() else {
+        bail!("Expected a manifest with layers");
+    };
+    layers
+        .iter()
+        .map(|layer| {
+            layer["digest"]
+                .as_str()
+                .map(|d| d.to_string())
+                .ok_or_else(|| anyhow!("Expected every layer to have a digest"))
+        })
+        .collect()
+}
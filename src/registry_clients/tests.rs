@@ -4,5 +4,85 @@ use super::*;
 fn test_registry_clients_new() {
     let clients = RegistryClients::new();
     // Verify that a new instance has no clients initially
-    assert_eq!(clients.clients.len(), 0);
+    assert_eq!(clients.clients.lock().unwrap().len(), 0);
+}
+
+#[test]
+fn test_registry_clients_is_shareable() {
+    fn assert_send_sync<T: Send + Sync>() {}
+    assert_send_sync::<RegistryClients>();
+}
+
+#[test]
+fn test_identity_tokens_become_basic_credentials() -> Result<()> {
+    let config = r#"{
+        "auths": {
+            "acme.azurecr.io": {"identitytoken": "refresh-token"},
+            "registry.test": {"auth": "dXNlcjpwYXNzd29yZA=="}
+        }
+    }"#;
+    let token = docker_credential::get_credential_from_reader(config.as_bytes(), "acme.azurecr.io")
+        .map_err(|e| anyhow!("{}", e))?;
+    assert_eq!(
+        basic_credential(token),
+        (
+            IDENTITY_TOKEN_USERNAME.to_string(),
+            "refresh-token".to_string()
+        )
+    );
+    let password =
+        docker_credential::get_credential_from_reader(config.as_bytes(), "registry.test")
+            .map_err(|e| anyhow!("{}", e))?;
+    assert_eq!(
+        basic_credential(password),
+        ("user".to_string(), "password".to_string())
+    );
+    assert!(is_unconfigured(
+        &docker_credential::get_credential_from_reader(config.as_bytes(), "other.test")
+            .unwrap_err()
+    ));
+    Ok(())
+}
+
+#[test]
+fn test_platform_manifest() -> Result<()> {
+    let list = br#"{
+        "schemaVersion": 2,
+        "mediaType": "application/vnd.oci.image.index.v1+json",
+        "manifests": [
+            {"digest": "sha256:amd", "platform": {"os": "linux", "architecture": "amd64"}},
+            {
+                "digest": "sha256:arm",
+                "platform": {"os": "linux", "architecture": "arm64", "variant": "v8"}
+            }
+        ]
+    }"#;
+    assert_eq!(
+        platform_manifest(list, "linux/arm64")?,
+        Some("sha256:arm".to_string())
+    );
+    assert_eq!(
+        platform_manifest(list, "linux/amd64")?,
+        Some("sha256:amd".to_string())
+    );
+    assert!(platform_manifest(list, "windows/amd64").is_err());
+
+    let single = br#"{"schemaVersion": 2, "layers": []}"#;
+    assert_eq!(platform_manifest(single, "linux/arm64")?, None);
+    Ok(())
+}
+
+#[test]
+fn test_manifest_layers() -> Result<()> {
+    let manifest = br#"{
+        "schemaVersion": 2,
+        "config": {"digest": "sha256:config"},
+        "layers": [{"digest": "sha256:first"}, {"digest": "sha256:second"}]
+    }"#;
+    assert_eq!(
+        manifest_layers(manifest)?,
+        vec!["sha256:first".to_string(), "sha256:second".to_string()]
+    );
+    assert!(manifest_layers(br#"{"manifests": []}"#).is_err());
+    Ok(())
 }
@@ -0,0 +1,1065 @@
+use anyhow::{anyhow, bail, Context, Result};
+use futures::{future::try_join_all, stream, StreamExt, TryStreamExt};
+use k8s_openapi::api::core::v1::{LimitRange, Namespace, ResourceQuota};
+use kube::{
+    api::{DynamicObject, ObjectMeta},
+    ResourceExt,
+};
+use serde::Deserialize;
+use std::{
+    collections::{btree_map::Entry, BTreeMap, BTreeSet, HashMap, HashSet},
+    fs::{self, File},
+    io::Read,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        OnceLock,
+    },
+    time::Instant,
+};
+use tracing::{instrument, warn, Instrument};
+
+use crate::{
+    environments::Environments,
+    globs::{expand_glob, has_wildcards},
+    ignore_file::IgnoreRules,
+    kubernetes_io::{KubernetesKey, KubernetesResources},
+    kubernetes_rendering::{insert_rendered, render_sisyphus_resource},
+    oci_bundles::{OciBundles, OCI_SCHEME},
+    plugins::read_resource,
+    registry_clients::{resolve_image_tag, RegistryClients},
+    render_progress::{resource_rendered, start_render_progress},
+    sisyphus_yaml::{HasConfigImage, HasKind, NamespaceSettings, SisyphusResource},
+    timings::{Phase, Timings},
+};
+
+// How many resources render at once
+const RENDER_CONCURRENCY: usize = 8;
+
+// Set once resources have been read from stdin, after which prompts must read from the terminal
+pub(crate) static STDIN_CONSUMED: AtomicBool = AtomicBool::new(false);
+
+// Renders the monitor directories into the objects they want to exist.
+#[instrument(skip_all)]
+pub(crate) async fn render_files(
+    monitor_directories: &[String],
+    environments: &mut Environments,
+    timings: &mut Timings,
+) -> Result<KubernetesResources> {
+    let registries = RegistryClients::new();
+    let mut from_files = KubernetesResources {
+        by_key: BTreeMap::new(),
+        namespaces: BTreeMap::new(),
+    };
+    {
+        let started = Instant::now();
+        let mut bundles = OciBundles::default();
+        let mut resources = loop {
+            let resources = get_sisyphus_resources(monitor_directories, &mut bundles)?;
+            if !bundles.pull_wanted(&registries).await? {
+                break resources;
+            }
+        };
+        for resource in resources
+            .global_by_key
+            .values_mut()
+            .chain(resources.by_namespace_by_key.values_mut().flat_map(|v| v.values_mut()))
+        {
+            environments.expand_resource(resource)?;
+        }
+        environments.print_expansions();
+        let explicit_namespaces = explicit_namespace_origins(&resources.global_by_key)?;
+        timings.record(Phase::Parse, started);
+        let progress = start_render_progress(
+            resources.global_by_key.len()
+                + resources
+                    .by_namespace_by_key
+                    .values()
+                    .map(|v| v.len())
+                    .sum::<usize>(),
+        );
+        render_sisyphus_resources(
+            &resources.global_by_key,
+            /* allow_any_namespace= */ true,
+            /* maybe_namespace= */ None,
+            &mut from_files.by_key,
+            &registries,
+            timings,
+        )
+        .await?;
+        from_files.by_key.retain(|k, v| {
+            if k.api_version == "v1" && k.kind == "Namespace" {
+                from_files.namespaces.insert(k.clone(), v.clone());
+                false
+            } else {
+                true
+            }
+        });
+        for (namespace, objects) in resources.by_namespace_by_key {
+            render_sisyphus_resources(
+                &objects,
+                /* allow_any_namespace= */ false,
+                Some(namespace.to_string()),
+                &mut from_files.by_key,
+                &registries,
+                timings,
+            )
+            .await?;
+        }
+        drop(progress);
+        add_namespace_limits(&resources.settings_by_namespace, &mut from_files.by_key)?;
+
+        // Namespaces defined in global/ always win over the bare ones generated here
+        let mut generated = BTreeSet::new();
+        for key in from_files.by_key.keys() {
+            let Some(namespace) = key.namespace.clone() else {
+                continue;
+            };
+            let namespace_key = KubernetesKey {
+                name: namespace.clone(),
+                kind: "Namespace".to_string(),
+                api_version: "v1".to_string(),
+                namespace: None,
+                cluster: key.cluster.clone(),
+            };
+            if let Entry::Vacant(entry) = from_files.namespaces.entry(namespace_key) {
+                generated.insert(entry.key().clone());
+                let settings = resources.settings_by_namespace.get(&namespace);
+                entry.insert(bare_namespace(namespace, settings)?);
+            }
+        }
+        for (key, origin) in &explicit_namespaces {
+            let Some(settings) = resources.settings_by_namespace.get(&key.name) else {
+                continue;
+            };
+            if !settings.labels.is_empty() || !settings.annotations.is_empty() {
+                bail!(
+                    "Namespace {} is defined by {}, so its namespace.yaml can't also set labels or \
+                     annotations",
+                    key.name,
+                    origin
+                );
+            }
+        }
+        for conflict in partially_defined_namespaces(&explicit_namespaces, &generated) {
+            warn!("{}", conflict);
+        }
+    }
+    Ok(from_files)
+}
+
+fn bare_namespace(name: String, settings: Option<&NamespaceSettings>) -> Result<DynamicObject> {
+    let mut metadata = ObjectMeta::default();
+    metadata.name = Some(name);
+    if let Some(settings) = settings {
+        if !settings.labels.is_empty() {
+            metadata.labels = Some(settings.labels.clone());
+        }
+        if !settings.annotations.is_empty() {
+            metadata.annotations = Some(settings.annotations.clone());
+        }
+    }
+    let as_namespace = Namespace {
+        metadata,
+        spec: None,
+        status: None,
+    };
+    Ok(serde_yaml::from_str(&serde_yaml::to_string(
+        &as_namespace,
+    )?)?)
+}
+
+// Adds the ResourceQuota and LimitRange that each namespace.yaml asks for to every cluster its
+// namespace has objects in
+fn add_namespace_limits(
+    settings_by_namespace: &HashMap<String, NamespaceSettings>,
+    by_key: &mut BTreeMap<KubernetesKey, DynamicObject>,
+) -> Result<()> {
+    let mut clusters_by_namespace: BTreeMap<&str, BTreeSet<String>> = BTreeMap::new();
+    for key in by_key.keys() {
+        if let Some(namespace) = &key.namespace {
+            if settings_by_namespace.contains_key(namespace) {
+                clusters_by_namespace
+                    .entry(namespace)
+                    .or_default()
+                    .insert(key.cluster.clone());
+            }
+        }
+    }
+
+    let mut added = Vec::new();
+    for (namespace, clusters) in clusters_by_namespace {
+        let settings = &settings_by_namespace[namespace];
+        let metadata = ObjectMeta {
+            name: Some(namespace.to_string()),
+            namespace: Some(namespace.to_string()),
+            ..Default::default()
+        };
+        let mut objects = Vec::new();
+        if let Some(spec) = &settings.resource_quota {
+            objects.push(serde_json::to_value(ResourceQuota {
+                metadata: metadata.clone(),
+                spec: Some(spec.clone()),
+                status: None,
+            })?);
+        }
+        if let Some(spec) = &settings.limit_range {
+            objects.push(serde_json::to_value(LimitRange {
+                metadata: metadata.clone(),
+                spec: Some(spec.clone()),
+            })?);
+        }
+        for object in objects {
+            let object: DynamicObject = serde_json::from_value(object)?;
+            for cluster in &clusters {
+                let key = KubernetesKey {
+                    name: namespace.to_string(),
+                    kind: object
+                        .types
+                        .as_ref()
+                        .map(|t| t.kind.clone())
+                        .unwrap_or_default(),
+                    api_version: "v1".to_string(),
+                    namespace: Some(namespace.to_string()),
+                    cluster: cluster.clone(),
+                };
+                added.push((key, object.clone()));
+            }
+        }
+    }
+    for (key, object) in added {
+        if by_key.contains_key(&key) {
+            bail!(
+                "{} is set by the namespace's namespace.yaml and also defined by a resource",
+                key
+            );
+        }
+        by_key.insert(key, object);
+    }
+    Ok(())
+}
+
+// Finds where each Namespace in global/ is defined, failing if two resources define the same one
+// for the same cluster since which of them wins would be arbitrary.
+fn explicit_namespace_origins(
+    global: &HashMap<SisyphusKey, SisyphusResource>,
+) -> Result<BTreeMap<KubernetesKey, String>> {
+    let mut sorted: Vec<_> = global.iter().collect();
+    sorted.sort_by(|a, b| a.0.cmp(b.0));
+
+    let mut origins = BTreeMap::new();
+    for (resource_key, resource) in sorted {
+        let SisyphusResource::KubernetesYaml(v) = resource else {
+            continue;
+        };
+        let origin = match &v.defined_in {
+            Some(path) => format!("{} in {}", resource_key.name, path.display()),
+            None => resource_key.name.clone(),
+        };
+        for object in &v.objects {
+            let is_namespace = object
+                .types
+                .as_ref()
+                .map_or(false, |t| t.api_version == "v1" && t.kind == "Namespace");
+            if !is_namespace {
+                continue;
+            }
+            for cluster in &v.clusters {
+                let key = KubernetesKey {
+                    name: object.name_any(),
+                    kind: "Namespace".to_string(),
+                    api_version: "v1".to_string(),
+                    namespace: None,
+                    cluster: cluster.clone(),
+                };
+                if let Some(previous) = origins.insert(key, origin.clone()) {
+                    bail!(
+                        "Namespace {} for {} is defined by both {} and {}",
+                        object.name_any(),
+                        cluster,
+                        previous,
+                        origin
+                    );
+                }
+            }
+        }
+    }
+    Ok(origins)
+}
+
+// Describes namespaces that global/ defines for some clusters but that were generated without their
+// labels and annotations for others, which is rarely what was meant.
+fn partially_defined_namespaces(
+    explicit: &BTreeMap<KubernetesKey, String>,
+    generated: &BTreeSet<KubernetesKey>,
+) -> Vec<String> {
+    let mut by_name: BTreeMap<&str, (BTreeSet<&str>, BTreeSet<&str>)> = BTreeMap::new();
+    for (key, origin) in explicit {
+        by_name
+            .entry(key.name.as_str())
+            .or_default()
+            .0
+            .insert(origin);
+    }
+    for key in generated {
+        if let Some((_, clusters)) = by_name.get_mut(key.name.as_str()) {
+            clusters.insert(&key.cluster);
+        }
+    }
+    by_name
+        .into_iter()
+        .filter(|(_, (_, clusters))| !clusters.is_empty())
+        .map(|(name, (origins, clusters))| {
+            format!(
+                "Namespace {} is defined by {} but generated without it for {}",
+                name,
+                origins.into_iter().collect::<Vec<_>>().join(", "),
+                clusters.into_iter().collect::<Vec<_>>().join(", ")
+            )
+        })
+        .collect()
+}
+
+#[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+struct SisyphusKey {
+    pub api_version: String,
+    pub kind: String,
+    pub name: String,
+}
+
+#[derive(Debug, Default)]
+struct SisyphusResources {
+    by_namespace_by_key: HashMap<String, HashMap<SisyphusKey, SisyphusResource>>,
+    global_by_key: HashMap<SisyphusKey, SisyphusResource>,
+    // From the namespace.yaml in each namespace's folder
+    settings_by_namespace: HashMap<String, NamespaceSettings>,
+}
+
+// Reads every monitor directory and merges them, so that platform-owned global config and app
+// teams' repos can be pushed together. Two directories defining the same resource in the same
+// folder fail since neither should silently win.
+fn get_sisyphus_resources(
+    monitor_directories: &[String],
+    bundles: &mut OciBundles,
+) -> Result<SisyphusResources> {
+    let mut merged = SisyphusResources::default();
+    let mut roots: HashMap<(Option<String>, SisyphusKey), &str> = HashMap::new();
+    let mut settings_roots: HashMap<String, &str> = HashMap::new();
+    for root in monitor_directories {
+        let mut resources = if root == "-" {
+            get_sisyphus_resources_from_stdin(bundles)?
+        } else {
+            get_sisyphus_resources_from_files(Path::new(root), bundles)?
+        };
+        // Recorded origins shouldn't depend on where the monitor directory is checked out, but
+        // need the directory's name to tell files from different repos apart
+        let prefix = match monitor_directories.len() {
+            1 => PathBuf::new(),
+            _ => fs::canonicalize(root)
+                .ok()
+                .and_then(|p| p.file_name().map(PathBuf::from))
+                .unwrap_or_default(),
+        };
+        for resource in resources
+            .global_by_key
+            .values_mut()
+            .chain(resources.by_namespace_by_key.values_mut().flat_map(|v| v.values_mut()))
+        {
+            let Some(defined_in) = resource.defined_in() else {
+                continue;
+            };
+            let described = match defined_in.strip_prefix(root) {
+                Ok(relative) => prefix.join(relative),
+                Err(_) => match bundles.describe(defined_in) {
+                    Some(described) => described,
+                    None => continue,
+                },
+            };
+            resource.set_defined_in(described);
+        }
+
+        for (namespace, settings) in resources.settings_by_namespace {
+            if let Some(other) = settings_roots.insert(namespace.clone(), root) {
+                bail!(
+                    "Namespace {} has a namespace.yaml in both {} and {}",
+                    namespace,
+                    other,
+                    root
+                );
+            }
+            merged.settings_by_namespace.insert(namespace, settings);
+        }
+
+        let folders = std::iter::once((None, resources.global_by_key)).chain(
+            resources
+                .by_namespace_by_key
+                .into_iter()
+                .map(|(namespace, by_key)| (Some(namespace), by_key)),
+        );
+        for (namespace, by_key) in folders {
+            let into = match &namespace {
+                Some(n) => merged.by_namespace_by_key.entry(n.clone()).or_default(),
+                None => &mut merged.global_by_key,
+            };
+            for (key, resource) in by_key {
+                if let Some(other) = roots.insert((namespace.clone(), key.clone()), root) {
+                    bail!(
+                        "{} {} in {} is defined in both {} and {}",
+                        key.kind,
+                        key.name,
+                        namespace.as_deref().unwrap_or("global"),
+                        other,
+                        root
+                    );
+                }
+                into.insert(key, resource);
+            }
+        }
+    }
+    Ok(merged)
+}
+
+fn get_sisyphus_resources_from_files(
+    directory: &Path,
+    bundles: &mut OciBundles,
+) -> Result<SisyphusResources> {
+    let mut resources = SisyphusResources::default();
+    let ignored = IgnoreRules::load(directory)?;
+    // Files already read, since a SisyphusYaml may name an index.yaml that's also found by walking
+    let mut loaded = HashSet::new();
+    for entry in fs::read_dir(directory)? {
+        let path = entry?.path();
+        let Some(name) = path.file_name() else {
+            bail!("Path has no filename");
+        };
+        if path.is_dir() && !ignored.is_ignored(Path::new(name), true) {
+            let settings_path = path.join("namespace.yaml");
+            if let Some(namespace) = name.to_str().filter(|n| *n != "global") {
+                if settings_path.exists()
+                    && !ignored.is_ignored(&Path::new(name).join("namespace.yaml"), false)
+                {
+                    let settings: NamespaceSettings =
+                        serde_yaml::from_reader(File::open(&settings_path)?)
+                            .with_context(|| format!("in file {:?}", settings_path))?;
+                    resources
+                        .settings_by_namespace
+                        .insert(namespace.to_string(), settings);
+                }
+            }
+            let (resources, allow_any_namespace, namespace) =
+                match path.file_name().map(|s| s.to_str()).flatten() {
+                    Some("global") => (&mut resources.global_by_key, true, None),
+                    Some(namespace) => (
+                        resources
+                            .by_namespace_by_key
+                            .entry(namespace.to_string())
+                            .or_insert_with(|| HashMap::new()),
+                        false,
+                        Some(namespace.to_string()),
+                    ),
+                    None => bail!("Path has no filename"),
+                };
+            get_objects_from_namespace(
+                &path,
+                Path::new(name),
+                &ignored,
+                resources,
+                allow_any_namespace,
+                &namespace,
+                &mut loaded,
+                bundles,
+            )?;
+        }
+    }
+    Ok(resources)
+}
+
+// Reads the index.yaml in a namespace's folder and in every folder under it, so large namespaces
+// can split their resources up by team
+fn get_objects_from_namespace(
+    directory: &Path,
+    relative: &Path,
+    ignored: &IgnoreRules,
+    resources: &mut HashMap<SisyphusKey, SisyphusResource>,
+    allow_any_namespace: bool,
+    namespace: &Option<String>,
+    loaded: &mut HashSet<PathBuf>,
+    bundles: &mut OciBundles,
+) -> Result<()> {
+    let index_path = directory.join("index.yaml");
+    if index_path.exists() && !ignored.is_ignored(&relative.join("index.yaml"), false) {
+        get_objects_from_file(
+            &index_path,
+            resources,
+            allow_any_namespace,
+            namespace,
+            loaded,
+            bundles,
+        )?;
+    }
+
+    let mut children = Vec::new();
+    for entry in fs::read_dir(directory)? {
+        let path = entry?.path();
+        let Some(name) = path.file_name() else {
+            continue;
+        };
+        let hidden = name.to_string_lossy().starts_with('.');
+        if path.is_dir() && !hidden && !ignored.is_ignored(&relative.join(name), true) {
+            children.push((path.clone(), relative.join(name)));
+        }
+    }
+    children.sort();
+    for (path, relative) in children {
+        get_objects_from_namespace(
+            &path,
+            &relative,
+            ignored,
+            resources,
+            allow_any_namespace,
+            namespace,
+            loaded,
+            bundles,
+        )?;
+    }
+    Ok(())
+}
+
+fn get_objects_from_file(
+    path: &Path,
+    resources: &mut HashMap<SisyphusKey, SisyphusResource>,
+    allow_any_namespace: bool,
+    namespace: &Option<String>,
+    loaded: &mut HashSet<PathBuf>,
+    bundles: &mut OciBundles,
+) -> Result<()> {
+    if !loaded.insert(fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf())) {
+        return Ok(());
+    }
+    let directory = path
+        .parent()
+        .ok_or_else(|| anyhow!("Expected to be in a child folder"))?;
+    let reader = File::open(&path)?;
+    for (i, document) in serde_yaml::Deserializer::from_reader(&reader).enumerate() {
+        let mut object = read_resource(serde_yaml::Value::deserialize(document)?)
+            .with_context(|| format!("in {}:{}", path.display(), i + 1))?;
+        object.set_document(i + 1);
+        if let Some(n) = &object.metadata().namespace {
+            bail!(
+                "{} in {:?} sets namespace {:?} but its folder already determines it",
+                object.metadata().name,
+                path,
+                n
+            );
+        }
+        add_sisyphus_resource(
+            object,
+            path,
+            directory,
+            resources,
+            allow_any_namespace,
+            namespace,
+            loaded,
+            bundles,
+        )?;
+    }
+
+    Ok(())
+}
+
+// Reads a multi-document stream of resources from stdin. Without folders to go by, each resource
+// names its namespace in its metadata and resources that don't are treated as global. Relative
+// source paths resolve against the current directory.
+fn get_sisyphus_resources_from_stdin(bundles: &mut OciBundles) -> Result<SisyphusResources> {
+    // Reading OCI bundles means reading the resources more than once, but stdin only has them once
+    static CONTENT: OnceLock<String> = OnceLock::new();
+    if CONTENT.get().is_none() {
+        let mut content = String::new();
+        std::io::stdin().read_to_string(&mut content)?;
+        STDIN_CONSUMED.store(true, Ordering::SeqCst);
+        let _ = CONTENT.set(content);
+    }
+    let content = CONTENT.get().map(|c| c.as_str()).unwrap_or_default();
+
+    let mut resources = SisyphusResources::default();
+    let path = Path::new("<stdin>");
+    let directory = std::env::current_dir()?;
+    let mut loaded = HashSet::new();
+    for (i, document) in serde_yaml::Deserializer::from_str(content).enumerate() {
+        let mut object = read_resource(serde_yaml::Value::deserialize(document)?)
+            .with_context(|| format!("in <stdin>:{}", i + 1))?;
+        object.set_document(i + 1);
+        let namespace = object.metadata().namespace.clone();
+        let (by_key, allow_any_namespace) = match &namespace {
+            None => (&mut resources.global_by_key, true),
+            Some(n) => (
+                resources
+                    .by_namespace_by_key
+                    .entry(n.clone())
+                    .or_insert_with(|| HashMap::new()),
+                false,
+            ),
+        };
+        add_sisyphus_resource(
+            object,
+            path,
+            &directory,
+            by_key,
+            allow_any_namespace,
+            &namespace,
+            &mut loaded,
+            bundles,
+        )?;
+    }
+    Ok(resources)
+}
+
+fn add_sisyphus_resource(
+    mut object: SisyphusResource,
+    path: &Path,
+    directory: &Path,
+    resources: &mut HashMap<SisyphusKey, SisyphusResource>,
+    allow_any_namespace: bool,
+    namespace: &Option<String>,
+    loaded: &mut HashSet<PathBuf>,
+    bundles: &mut OciBundles,
+) -> Result<()> {
+    object.set_defined_in(path.to_path_buf());
+    if let SisyphusResource::KubernetesYaml(v) = &mut object {
+        let mut extra_objects = Vec::new();
+        for source_path in &v.sources {
+            for file in source_files(directory, source_path, path)? {
+                load_objects_from_kubernetes_yaml(&file, &mut extra_objects)
+                    .with_context(|| format!("reading file {:?} referenced by {:?}", file, path))?;
+            }
+        }
+        v.sources.clear();
+        v.objects.append(&mut extra_objects);
+
+        for object in &mut v.objects {
+            if let Some(namespace) = object.metadata.namespace.as_ref() {
+                if !allow_any_namespace {
+                    let types = object
+                        .types
+                        .as_ref()
+                        .map(|t| format!("{}/{}", t.api_version, t.kind))
+                        .unwrap_or_else(|| "unknown".to_string());
+                    bail!(
+                        "{}/{} referenced by {} in {:?} should not specify namespace {:?}",
+                        types,
+                        object.name_any(),
+                        v.metadata.name,
+                        path,
+                        namespace
+                    );
+                }
+            } else {
+                object.metadata.namespace = namespace.clone();
+            }
+        }
+        insert_sisyphus_resource(object, resources)?;
+    } else if let SisyphusResource::SisyphusYaml(v) = &mut object {
+        for source_path in &v.sources {
+            let files = if source_path.starts_with(OCI_SCHEME) {
+                // Bundles that haven't been pulled yet are read on the next pass
+                bundles
+                    .directory(source_path)
+                    .map(|d| d.join("index.yaml"))
+                    .into_iter()
+                    .collect()
+            } else {
+                source_files(directory, source_path, path)?
+            };
+            for file in files {
+                get_objects_from_file(
+                    &file,
+                    resources,
+                    allow_any_namespace,
+                    namespace,
+                    loaded,
+                    bundles,
+                )?;
+            }
+        }
+    } else {
+        insert_sisyphus_resource(object, resources)?;
+    }
+    Ok(())
+}
+
+// The files a `sources` entry names, which may be a pattern like `team/*.yaml` or `**/*.yaml`
+fn source_files(directory: &Path, source: &str, referenced_by: &Path) -> Result<Vec<PathBuf>> {
+    if !has_wildcards(source) {
+        return Ok(vec![directory.join(source)]);
+    }
+    let files = expand_glob(directory, source)?;
+    if files.is_empty() {
+        bail!(
+            "{:?} referenced by {:?} matches no files",
+            source,
+            referenced_by
+        );
+    }
+    Ok(files)
+}
+
+fn load_objects_from_kubernetes_yaml(path: &Path, into: &mut Vec<DynamicObject>) -> Result<()> {
+    let reader = File::open(&path)?;
+    for document in serde_yaml::Deserializer::from_reader(&reader) {
+        let object: DynamicObject = DynamicObject::deserialize(document)?;
+        if object.types.is_none() && object.metadata == ObjectMeta::default() {
+            // kubectl tolerates these, so we do too
+            continue;
+        }
+        into.push(object);
+    }
+
+    Ok(())
+}
+
+fn insert_sisyphus_resource(
+    object: SisyphusResource,
+    resources: &mut HashMap<SisyphusKey, SisyphusResource>,
+) -> Result<()> {
+    let (api_version, kind, name) = match &object {
+        SisyphusResource::KubernetesYaml(v) => (&v.api_version, v.kind(), &v.metadata.name),
+        SisyphusResource::SisyphusCronJob(v) => (&v.api_version, v.kind(), &v.metadata.name),
+        SisyphusResource::SisyphusDeployment(v) => (&v.api_version, v.kind(), &v.metadata.name),
+        SisyphusResource::SisyphusJob(v) => (&v.api_version, v.kind(), &v.metadata.name),
+        SisyphusResource::Plugin(v) => (&v.api_version, v.kind.as_str(), &v.metadata.name),
+        SisyphusResource::SisyphusYaml(_) => unreachable!("These should already have been loaded"),
+    };
+    let key = SisyphusKey {
+        api_version: api_version.clone(),
+        kind: kind.to_string(),
+        name: name.clone(),
+    };
+    if resources.contains_key(&key) {
+        bail!("Key {:?} already exists", key);
+    }
+    resources.insert(key, object);
+    Ok(())
+}
+
+async fn render_sisyphus_resources(
+    objects: &HashMap<SisyphusKey, SisyphusResource>,
+    allow_any_namespace: bool,
+    maybe_namespace: Option<String>,
+    by_key: &mut BTreeMap<KubernetesKey, DynamicObject>,
+    registries: &RegistryClients,
+    timings: &mut Timings,
+) -> Result<()> {
+    // Tags are resolved all at once since each one is a registry round trip
+    let started = Instant::now();
+    let resolved = try_join_all(objects.iter().map(|(key, object)| async move {
+        let mut copy = object.clone();
+        resolve_config_image(&mut copy, registries).await?;
+        Ok::<_, anyhow::Error>((key, copy))
+    }))
+    .await?;
+    timings.record(Phase::Resolve, started);
+
+    // Rendered a few at a time so that one config image downloads while another is evaluated, but
+    // merged in order so that which of two colliding resources is blamed doesn't vary
+    let started = Instant::now();
+    let maybe_namespace = &maybe_namespace;
+    let rendered: Vec<_> = stream::iter(resolved)
+        .map(|(key, copy)| async move {
+            let mut objects = BTreeMap::new();
+            render_sisyphus_resource(
+                &copy,
+                allow_any_namespace,
+                maybe_namespace,
+                &mut objects,
+                registries,
+            )
+            .instrument(tracing::info_span!("render", resource = ?key))
+            .await
+            .with_context(|| match copy.location() {
+                Some(location) => format!("while rendering {:?} defined in {}", key, location),
+                None => format!("while rendering {:?}", key),
+            })?;
+            resource_rendered();
+            Ok::<_, anyhow::Error>(objects)
+        })
+        .buffered(RENDER_CONCURRENCY)
+        .try_collect()
+        .await?;
+    timings.record(Phase::Render, started);
+    for objects in rendered {
+        insert_rendered(by_key, objects)?;
+    }
+    Ok(())
+}
+
+// Pins the config image of `resource`, if it has one, to a digest
+pub(crate) async fn resolve_config_image(
+    resource: &mut SisyphusResource,
+    registries: &RegistryClients,
+) -> Result<()> {
+    match resource {
+        SisyphusResource::KubernetesYaml(_) => {}
+        SisyphusResource::SisyphusCronJob(v) => {
+            resolve_sisyphus_config_image(v, registries).await?
+        }
+        SisyphusResource::SisyphusDeployment(v) => {
+            resolve_sisyphus_config_image(v, registries).await?
+        }
+        SisyphusResource::SisyphusJob(v) => resolve_sisyphus_config_image(v, registries).await?,
+        SisyphusResource::SisyphusYaml(_) | SisyphusResource::Plugin(_) => {}
+    };
+    Ok(())
+}
+
+async fn resolve_sisyphus_config_image(
+    object: &mut impl HasConfigImage,
+    registries: &RegistryClients,
+) -> Result<()> {
+    let reference = resolve_image_tag(object.config_image(), registries).await?;
+    object.set_config_image(reference.to_string());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::kubernetes_io::test_key;
+
+    fn defines_namespace(name: &str, clusters: &[&str]) -> (SisyphusKey, SisyphusResource) {
+        let resource: SisyphusResource = serde_yaml::from_str(&format!(
+            r#"
+            apiVersion: sisyphus/v1
+            kind: KubernetesYaml
+            metadata:
+              name: {}
+            clusters: [{}]
+            objects:
+              - apiVersion: v1
+                kind: Namespace
+                metadata:
+                  name: echo
+                  labels:
+                    team: frontend
+            "#,
+            name,
+            clusters.join(", ")
+        ))
+        .unwrap();
+        let key = SisyphusKey {
+            api_version: "sisyphus/v1".to_string(),
+            kind: "KubernetesYaml".to_string(),
+            name: name.to_string(),
+        };
+        (key, resource)
+    }
+
+    #[test]
+    fn test_namespace_defined_twice_for_a_cluster_fails() {
+        let global = HashMap::from([
+            defines_namespace("first", &["a", "b"]),
+            defines_namespace("second", &["b"]),
+        ]);
+
+        let error = explicit_namespace_origins(&global).unwrap_err();
+
+        assert_eq!(
+            error.to_string(),
+            "Namespace echo for b is defined by both first and second"
+        );
+    }
+
+    #[test]
+    fn test_namespaces_defined_for_different_clusters() -> Result<()> {
+        let global = HashMap::from([
+            defines_namespace("first", &["a"]),
+            defines_namespace("second", &["b"]),
+        ]);
+
+        let origins = explicit_namespace_origins(&global)?;
+
+        assert_eq!(
+            origins[&test_key("a", "v1", "Namespace", None, "echo")],
+            "first"
+        );
+        assert_eq!(
+            origins[&test_key("b", "v1", "Namespace", None, "echo")],
+            "second"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_partially_defined_namespaces() -> Result<()> {
+        let explicit =
+            explicit_namespace_origins(&HashMap::from([defines_namespace("first", &["a"])]))?;
+        let generated = BTreeSet::from([
+            test_key("b", "v1", "Namespace", None, "echo"),
+            test_key("c", "v1", "Namespace", None, "echo"),
+        ]);
+
+        assert_eq!(
+            partially_defined_namespaces(&explicit, &generated),
+            vec!["Namespace echo is defined by first but generated without it for b, c"]
+        );
+        assert!(partially_defined_namespaces(&explicit, &BTreeSet::new()).is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_add_namespace_limits() -> Result<()> {
+        let settings: NamespaceSettings = serde_yaml::from_str(
+            r#"
+            labels:
+              pod-security.kubernetes.io/enforce: restricted
+            resourceQuota:
+              hard:
+                cpu: "10"
+            "#,
+        )?;
+        let settings_by_namespace = HashMap::from([("echo".to_string(), settings)]);
+        let object: DynamicObject =
+            serde_yaml::from_str("apiVersion: v1\nkind: ConfigMap\nmetadata:\n  name: config\n")?;
+        let mut by_key = BTreeMap::from([
+            (
+                test_key("a", "v1", "ConfigMap", Some("echo"), "config"),
+                object.clone(),
+            ),
+            (
+                test_key("b", "v1", "ConfigMap", Some("echo"), "config"),
+                object.clone(),
+            ),
+        ]);
+
+        add_namespace_limits(&settings_by_namespace, &mut by_key)?;
+        let quotas: Vec<_> = by_key
+            .iter()
+            .filter(|(k, _)| k.kind == "ResourceQuota")
+            .map(|(k, v)| (k.cluster.as_str(), v.data["spec"]["hard"]["cpu"].clone()))
+            .collect();
+        assert_eq!(
+            quotas,
+            vec![
+                ("a", serde_json::json!("10")),
+                ("b", serde_json::json!("10"))
+            ]
+        );
+        assert!(add_namespace_limits(&settings_by_namespace, &mut by_key).is_err());
+
+        let namespace = bare_namespace("echo".to_string(), settings_by_namespace.get("echo"))?;
+        assert_eq!(
+            namespace.labels().get("pod-security.kubernetes.io/enforce"),
+            Some(&"restricted".to_string())
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_ignored_folders_are_skipped() -> Result<()> {
+        let directory = tempfile::TempDir::new()?;
+        let root = directory.path();
+        fs::create_dir_all(root.join("echo"))?;
+        fs::create_dir_all(root.join("experimental"))?;
+        fs::write(root.join("experimental/index.yaml"), "not: [valid")?;
+        assert!(get_sisyphus_resources_from_files(root, &mut OciBundles::default()).is_err());
+
+        fs::write(root.join(".sisyphusignore"), "experimental/\n")?;
+        let resources = get_sisyphus_resources_from_files(root, &mut OciBundles::default())?;
+        assert_eq!(
+            resources.by_namespace_by_key.keys().collect::<Vec<_>>(),
+            vec!["echo"]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_nested_folders_and_glob_sources() -> Result<()> {
+        let directory = tempfile::TempDir::new()?;
+        let root = directory.path();
+        fs::create_dir_all(root.join("echo/team/configs"))?;
+        let config_map = |name: &str| {
+            format!(
+                "apiVersion: v1\nkind: ConfigMap\nmetadata:\n  name: {}\n",
+                name
+            )
+        };
+        fs::write(
+            root.join("echo/index.yaml"),
+            "apiVersion: sisyphus/v1\n\
+             kind: SisyphusYaml\n\
+             metadata:\n  name: includes\n\
+             sources: [team/index.yaml]\n",
+        )?;
+        fs::write(
+            root.join("echo/team/index.yaml"),
+            "apiVersion: sisyphus/v1\n\
+             kind: KubernetesYaml\n\
+             metadata:\n  name: team\n\
+             clusters: [prod]\n\
+             sources: [\"configs/*.yaml\"]\n",
+        )?;
+        fs::write(root.join("echo/team/configs/a.yaml"), config_map("a"))?;
+        fs::write(root.join("echo/team/configs/b.yaml"), config_map("b"))?;
+
+        // team/index.yaml is both named by the SisyphusYaml and found by walking, but is only read
+        // once
+        let resources = get_sisyphus_resources_from_files(root, &mut OciBundles::default())?;
+        let echo = &resources.by_namespace_by_key["echo"];
+        assert_eq!(echo.len(), 1);
+        let Some(SisyphusResource::KubernetesYaml(team)) = echo.values().next() else {
+            panic!("Expected the team's KubernetesYaml");
+        };
+        let names: Vec<_> = team.objects.iter().map(|o| o.name_any()).collect();
+        assert_eq!(names, vec!["a", "b"]);
+
+        fs::write(
+            root.join("echo/team/index.yaml"),
+            "apiVersion: sisyphus/v1\n\
+             kind: KubernetesYaml\n\
+             metadata:\n  name: team\n\
+             clusters: [prod]\n\
+             sources: [\"missing/*.yaml\"]\n",
+        )?;
+        assert!(get_sisyphus_resources_from_files(root, &mut OciBundles::default()).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_monitor_directories_are_merged() -> Result<()> {
+        let directory = tempfile::TempDir::new()?;
+        let platform = directory.path().join("platform");
+        let app = directory.path().join("app");
+        let kubernetes_yaml = |name: &str| {
+            format!(
+                "apiVersion: sisyphus/v1\n\
+                 kind: KubernetesYaml\n\
+                 metadata:\n  name: {}\n\
+                 clusters: [prod]\n\
+                 objects: []\n",
+                name
+            )
+        };
+        fs::create_dir_all(platform.join("global"))?;
+        fs::create_dir_all(app.join("echo"))?;
+        fs::write(platform.join("global/index.yaml"), kubernetes_yaml("base"))?;
+        fs::write(app.join("echo/index.yaml"), kubernetes_yaml("echo"))?;
+        let roots = vec![
+            platform.to_string_lossy().to_string(),
+            app.to_string_lossy().to_string(),
+        ];
+
+        let resources = get_sisyphus_resources(&roots, &mut OciBundles::default())?;
+        assert_eq!(resources.global_by_key.len(), 1);
+        let echo = resources.by_namespace_by_key["echo"]
+            .values()
+            .next()
+            .ok_or_else(|| anyhow!("Expected echo's resource"))?;
+        assert_eq!(echo.defined_in(), Some(Path::new("app/echo/index.yaml")));
+        assert_eq!(echo.location().as_deref(), Some("app/echo/index.yaml:1"));
+
+        // The same resource in the same folder of both is a conflict
+        fs::create_dir_all(platform.join("echo"))?;
+        fs::write(platform.join("echo/index.yaml"), kubernetes_yaml("echo"))?;
+        assert!(get_sisyphus_resources(&roots, &mut OciBundles::default()).is_err());
+        Ok(())
+    }
+}
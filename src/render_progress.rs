@@ -0,0 +1,109 @@
+use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
+use std::{collections::HashMap, sync::Mutex, time::Duration};
+
+use crate::output::theme;
+
+// Bars shown while the files render: one counting resources and one for each config image being
+// pulled or evaluated. Rendering code reports stages without knowing whether anyone is watching, so
+// outside of render_files, like in the library, they go nowhere.
+static ACTIVE: Mutex<Option<RenderProgress>> = Mutex::new(None);
+
+struct RenderProgress {
+    multi: MultiProgress,
+    resources: ProgressBar,
+    images: HashMap<String, ProgressBar>,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub(crate) enum Stage {
+    Downloading,
+    Unpacking,
+    Evaluating,
+}
+
+// Clears the bars when rendering ends, however it ends
+pub(crate) struct ProgressGuard;
+
+impl Drop for ProgressGuard {
+    fn drop(&mut self) {
+        if let Some(progress) = ACTIVE.lock().unwrap().take() {
+            for bar in progress.images.values() {
+                bar.finish_and_clear();
+            }
+            progress.resources.finish_and_clear();
+        }
+    }
+}
+
+pub(crate) fn start_render_progress(resources: usize) -> ProgressGuard {
+    let multi = MultiProgress::new();
+    let bar = ProgressBar::new(resources as u64).with_message("Rendering resources...");
+    if let Ok(style) = ProgressStyle::with_template(theme().progress_template) {
+        bar.set_style(style);
+    }
+    let resources = multi.add(bar);
+    *ACTIVE.lock().unwrap() = Some(RenderProgress {
+        multi,
+        resources,
+        images: HashMap::new(),
+    });
+    ProgressGuard
+}
+
+pub(crate) fn resource_rendered() {
+    if let Some(progress) = ACTIVE.lock().unwrap().as_ref() {
+        progress.resources.inc(1);
+    }
+}
+
+pub(crate) fn image_stage(image: &str, stage: Stage) {
+    let mut active = ACTIVE.lock().unwrap();
+    let Some(progress) = active.as_mut() else {
+        return;
+    };
+    let bar = progress.images.entry(image.to_string()).or_insert_with(|| {
+        let bar = progress.multi.add(ProgressBar::new_spinner());
+        if let Ok(style) = ProgressStyle::with_template("{spinner} {msg} {elapsed}") {
+            bar.set_style(style);
+        }
+        bar.enable_steady_tick(Duration::from_millis(100));
+        bar
+    });
+    bar.set_message(stage_message(image, stage));
+}
+
+pub(crate) fn image_done(image: &str) {
+    if let Some(progress) = ACTIVE.lock().unwrap().as_mut() {
+        if let Some(bar) = progress.images.remove(image) {
+            bar.finish_and_clear();
+        }
+    }
+}
+
+// Digests make every line the same long blur, so only the repository is shown
+fn stage_message(image: &str, stage: Stage) -> String {
+    let repository = image.split_once('@').map_or(image, |(r, _)| r);
+    let verb = match stage {
+        Stage::Downloading => "Downloading",
+        Stage::Unpacking => "Unpacking",
+        Stage::Evaluating => "Evaluating",
+    };
+    format!("{} {}", verb, repository)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_stage_message() {
+        assert_eq!(
+            stage_message("registry.test/echo_config@sha256:0123", Stage::Downloading),
+            "Downloading registry.test/echo_config"
+        );
+        assert_eq!(
+            stage_message("registry.test/echo_config:v1", Stage::Evaluating),
+            "Evaluating registry.test/echo_config:v1"
+        );
+    }
+}
@@ -0,0 +1,141 @@
+use kube::{core::ErrorResponse, Error};
+use rand::Rng;
+use std::{future::Future, time::Duration};
+use tokio::time::sleep;
+use tracing::info;
+
+pub(crate) const DEFAULT_MAX_RETRIES: u32 = 5;
+
+// The first retry waits up to this long, doubling on every attempt until MAX_DELAY
+const BASE_DELAY: Duration = Duration::from_millis(500);
+const MAX_DELAY: Duration = Duration::from_secs(30);
+
+// Calls `f` until it succeeds, fails with an error that retrying won't fix, or has been retried
+// `max_retries` times. Only use this for calls that are safe to repeat.
+pub(crate) async fn with_retries<T, F, Fut>(max_retries: u32, what: &str, f: F) -> kube::Result<T>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = kube::Result<T>>,
+{
+    retry_when(max_retries, what, f, is_transient).await
+}
+
+// Like `with_retries`, but only retries when the server turned the call away without acting on it,
+// for calls like JSON Patches that would do something different the second time
+pub(crate) async fn with_throttling_retries<T, F, Fut>(
+    max_retries: u32,
+    what: &str,
+    f: F,
+) -> kube::Result<T>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = kube::Result<T>>,
+{
+    retry_when(max_retries, what, f, is_throttled).await
+}
+
+async fn retry_when<T, F, Fut>(
+    max_retries: u32,
+    what: &str,
+    mut f: F,
+    retryable: fn(&Error) -> bool,
+) -> kube::Result<T>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = kube::Result<T>>,
+{
+    let mut attempt = 0;
+    loop {
+        match f().await {
+            Err(e) if attempt < max_retries && retryable(&e) => {
+                let delay = backoff(attempt);
+                info!(
+                    what,
+                    attempt = attempt + 1,
+                    error = %e,
+                    "Retrying {} in {:.1}s ({} of {}): {}",
+                    what,
+                    delay.as_secs_f64(),
+                    attempt + 1,
+                    max_retries,
+                    e
+                );
+                sleep(delay).await;
+                attempt += 1;
+            }
+            result => return result,
+        }
+    }
+}
+
+// Throttling, server errors, and dropped connections usually clear up on their own
+fn is_transient(error: &Error) -> bool {
+    match error {
+        Error::Api(ErrorResponse { code, .. }) => matches!(code, 429 | 500 | 502 | 503 | 504),
+        Error::HyperError(_) | Error::Service(_) => true,
+        _ => false,
+    }
+}
+
+// The apiserver's rate limits reject a request before anything else looks at it
+fn is_throttled(error: &Error) -> bool {
+    matches!(error, Error::Api(ErrorResponse { code: 429, .. }))
+}
+
+// Full jitter, so that clients that failed together don't all retry together
+fn backoff(attempt: u32) -> Duration {
+    let ceiling = BASE_DELAY
+        .saturating_mul(2u32.saturating_pow(attempt))
+        .min(MAX_DELAY);
+    rand::thread_rng().gen_range(Duration::ZERO..=ceiling)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn api_error(code: u16) -> Error {
+        Error::Api(ErrorResponse {
+            status: "Failure".to_string(),
+            message: "".to_string(),
+            reason: "".to_string(),
+            code,
+        })
+    }
+
+    #[test]
+    fn test_is_transient() {
+        assert!(is_transient(&api_error(429)));
+        assert!(is_transient(&api_error(503)));
+        assert!(!is_transient(&api_error(404)));
+        assert!(!is_transient(&api_error(422)));
+    }
+
+    #[test]
+    fn test_is_throttled() {
+        assert!(is_throttled(&api_error(429)));
+        assert!(!is_throttled(&api_error(500)));
+        assert!(!is_throttled(&api_error(503)));
+    }
+
+    #[test]
+    fn test_backoff_is_capped() {
+        for attempt in 0..40 {
+            let delay = backoff(attempt);
+            assert!(delay <= MAX_DELAY);
+        }
+        assert!(backoff(0) <= BASE_DELAY);
+    }
+
+    #[tokio::test]
+    async fn test_with_retries_stops_on_permanent_errors() {
+        let mut calls = 0;
+        let result: kube::Result<()> = with_retries(3, "nothing", || {
+            calls += 1;
+            async { Err(api_error(404)) }
+        })
+        .await;
+        assert!(result.is_err());
+        assert_eq!(calls, 1);
+    }
+}
@@ -0,0 +1,658 @@
+use anyhow::{anyhow, bail, Context, Result};
+use clap::Args;
+use serde_json::{json, Value as JsonValue};
+use sha2::{Digest, Sha256};
+use sqlx::AnyPool;
+use std::{
+    collections::{BTreeSet, HashMap},
+    path::PathBuf,
+    time::{Duration, Instant},
+};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+    sync::{mpsc, oneshot},
+    time::timeout,
+};
+use tracing::{info, warn};
+
+use crate::{
+    apply_diff::apply_diff,
+    audit::audit_prompt,
+    delegation::load_delegation,
+    environments::Environments,
+    filter::{object_matches_filter, PartialKey},
+    generate_diff::{check_protected, PlannedChange},
+    kubernetes_io::munge_secrets,
+    notifications::notify_finished,
+    plan::plan_changes,
+    push_lock::{lock_database, PushLock},
+    render_files::render_files,
+    retry::DEFAULT_MAX_RETRIES,
+    state_store::connect_database,
+    targets::{load_targets, TargetPolicy},
+    timings::Timings,
+};
+
+// A plan has to be approved and applied within this long of being made
+const PLAN_TTL: Duration = Duration::from_secs(15 * 60);
+const MAX_HEADERS: usize = 64 * 1024;
+const MAX_BODY: usize = 1024 * 1024;
+// How long a client gets to send its whole request or take the whole response. Each connection is
+// read and written on its own task, so a slow client only holds up itself.
+const READ_TIMEOUT: Duration = Duration::from_secs(10);
+const WRITE_TIMEOUT: Duration = Duration::from_secs(10);
+// How many requests may wait for the one being handled
+const QUEUE: usize = 64;
+
+#[derive(Args, Debug)]
+pub(crate) struct ServeArgs {
+    #[arg(long, env = "DATABASE_URL")]
+    pub database_url: String,
+
+    // The monitor directories, read again for every plan so that a checkout kept up to date by
+    // something else is always current
+    #[arg(
+        long,
+        env = "MONITOR_DIRECTORY",
+        value_delimiter = ',',
+        required = true
+    )]
+    pub monitor_directory: Vec<String>,
+
+    #[arg(long, env = "SISYPHUS_ENVIRONMENTS")]
+    pub environments: Option<String>,
+
+    #[arg(
+        long,
+        env = "SISYPHUS_MANAGEMENT_CONTEXT",
+        conflicts_with = "environments"
+    )]
+    pub management_context: Option<String>,
+
+    // The address to listen on
+    #[arg(long, env = "SISYPHUS_SERVE_LISTEN", default_value = "127.0.0.1:8080")]
+    pub listen: String,
+
+    // The bearer token every request but approvals has to present
+    #[arg(long, env = "SISYPHUS_SERVE_TOKEN")]
+    pub token: String,
+
+    // The bearer token approvals have to present instead, so that whoever makes plans can't also
+    // approve them
+    #[arg(long, env = "SISYPHUS_SERVE_APPROVER_TOKEN")]
+    pub approver_token: String,
+
+    // Plan and apply as the team named in this signed delegation, refusing changes outside of its
+    // clusters and namespaces. Required once the database trusts any delegation keys.
+    #[arg(long, env = "SISYPHUS_DELEGATION")]
+    pub delegation: Option<PathBuf>,
+
+    // Record salted hashes of Secret values instead of the values themselves
+    #[arg(long, env = "SISYPHUS_HASH_SECRETS")]
+    pub hash_secrets: bool,
+
+    // How many times to retry a Kubernetes call that failed with a throttling, server, or
+    // connection error
+    #[arg(long, env = "SISYPHUS_MAX_RETRIES", default_value_t = DEFAULT_MAX_RETRIES)]
+    pub max_retries: u32,
+}
+
+// A plan waiting to be approved and applied. Approval is recorded per change so that a reviewer
+// can leave some out, like skipping changes in a push.
+struct StoredPlan {
+    filter: JsonValue,
+    changes: Vec<PlannedChange>,
+    created: Instant,
+    approved: Option<Approval>,
+}
+
+struct Approval {
+    approver: String,
+    changes: BTreeSet<usize>,
+}
+
+struct Server {
+    args: ServeArgs,
+    pool: AnyPool,
+    environments: Environments,
+    policy: TargetPolicy,
+    plans: HashMap<String, StoredPlan>,
+}
+
+struct Request {
+    method: String,
+    path: String,
+    authorization: Option<String>,
+    content_length: usize,
+    body: Vec<u8>,
+}
+
+// A request waiting for the server, and where to send its status and body
+type Pending = (Request, oneshot::Sender<(u16, JsonValue)>);
+
+// A failed request, answered with `status` and the message
+struct HttpError(u16, String);
+
+impl From<anyhow::Error> for HttpError {
+    fn from(e: anyhow::Error) -> Self {
+        HttpError(500, format!("{:#}", e))
+    }
+}
+
+fn http_error(status: u16, message: impl Into<String>) -> HttpError {
+    HttpError(status, message.into())
+}
+
+// Serves render, plan, approve, and apply over HTTP with JSON bodies. Connections are read and
+// answered concurrently, but requests are handled one at a time, so an apply never races another
+// apply or a plan being made.
+pub(crate) async fn serve(args: ServeArgs) -> Result<()> {
+    if args.token.len() < 16 || args.approver_token.len() < 16 {
+        bail!("--token and --approver-token have to be at least 16 characters");
+    }
+    if args.token == args.approver_token {
+        bail!("--approver-token has to differ from --token");
+    }
+    let pool = connect_database(&args.database_url).await?;
+    // Fails now rather than on the first plan when a delegation is missing or invalid
+    load_delegation(&pool, args.delegation.as_deref()).await?;
+    let (environments, policy) = load_targets(
+        args.environments.as_deref(),
+        args.management_context.as_deref(),
+    )
+    .await?;
+    let listener = TcpListener::bind(&args.listen)
+        .await
+        .with_context(|| format!("while listening on {}", args.listen))?;
+    info!(address = %args.listen, "Listening on {}", args.listen);
+    let mut server = Server {
+        args,
+        pool,
+        environments,
+        policy,
+        plans: HashMap::new(),
+    };
+    let (sender, mut receiver) = mpsc::channel(QUEUE);
+    let accepting = tokio::spawn(accept(listener, sender));
+    while let Some((request, reply)) = receiver.recv().await {
+        let answer = match server.route(request).await {
+            Ok(body) => (200, body),
+            Err(HttpError(status, message)) => (status, json!({"error": message})),
+        };
+        // The client may have hung up in the meantime, which its task already logged
+        let _ = reply.send(answer);
+    }
+    accepting.await?
+}
+
+async fn accept(listener: TcpListener, sender: mpsc::Sender<Pending>) -> Result<()> {
+    loop {
+        let (stream, _) = listener.accept().await?;
+        let sender = sender.clone();
+        tokio::spawn(async move {
+            if let Err(e) = handle(stream, sender).await {
+                warn!(error = %e, "Unable to answer a request: {:#}", e);
+            }
+        });
+    }
+}
+
+// Reads one request, waits for the server to handle it, and writes the answer
+async fn handle(mut stream: TcpStream, sender: mpsc::Sender<Pending>) -> Result<()> {
+    let (status, body) = match timeout(READ_TIMEOUT, read_request(&mut stream)).await {
+        Err(_) => (408, json!({"error": "Timed out reading the request"})),
+        Ok(Err(e)) => (400, json!({"error": format!("{:#}", e)})),
+        Ok(Ok(request)) => {
+            let (reply, answer) = oneshot::channel();
+            sender
+                .send((request, reply))
+                .await
+                .map_err(|_| anyhow!("The server stopped"))?;
+            answer.await?
+        }
+    };
+    let body = body.to_string();
+    let response = format!(
+        "HTTP/1.1 {} {}\r\ncontent-type: application/json\r\ncontent-length: {}\r\nconnection: \
+         close\r\n\r\n{}",
+        status,
+        reason(status),
+        body.len(),
+        body
+    );
+    timeout(WRITE_TIMEOUT, stream.write_all(response.as_bytes()))
+        .await
+        .context("Timed out writing the response")??;
+    Ok(())
+}
+
+impl Server {
+    async fn route(&mut self, request: Request) -> Result<JsonValue, HttpError> {
+        let segments: Vec<_> = request.path.trim_matches('/').split('/').collect();
+        let expected = match (request.method.as_str(), segments.as_slice()) {
+            ("POST", ["plans", _, "approve"]) => &self.args.approver_token,
+            _ => &self.args.token,
+        };
+        let presented = request
+            .authorization
+            .as_deref()
+            .and_then(|a| a.strip_prefix("Bearer "))
+            .unwrap_or_default();
+        if !same_token(presented, expected) {
+            return Err(http_error(401, "Missing or wrong bearer token"));
+        }
+        let body: JsonValue = if request.body.is_empty() {
+            json!({})
+        } else {
+            serde_json::from_slice(&request.body)
+                .map_err(|e| http_error(400, format!("Invalid JSON: {}", e)))?
+        };
+        self.plans.retain(|_, p| p.created.elapsed() < PLAN_TTL);
+        match (request.method.as_str(), segments.as_slice()) {
+            ("POST", ["render"]) => self.render(&body).await,
+            ("POST", ["plans"]) => self.plan(&body).await,
+            ("GET", ["plans", id]) => self.show(id),
+            ("POST", ["plans", id, "approve"]) => self.approve(id, &body),
+            ("POST", ["plans", id, "apply"]) => self.apply(id).await,
+            _ => Err(http_error(
+                404,
+                format!("No {} {}", request.method, request.path),
+            )),
+        }
+    }
+
+    // The objects the files want, narrowed to the filter and the delegation, with secret values
+    // masked like diff and show mask them
+    async fn render(&mut self, body: &JsonValue) -> Result<JsonValue, HttpError> {
+        let filter = read_filter(body)?;
+        let delegation = load_delegation(&self.pool, self.args.delegation.as_deref())
+            .await
+            .map_err(|e| http_error(403, format!("{:#}", e)))?;
+        let mut rendered = render_files(
+            &self.args.monitor_directory,
+            &mut self.environments,
+            &mut Timings::new(false),
+        )
+        .await?;
+        if let Some(delegation) = &delegation {
+            delegation.retain(&mut rendered);
+        }
+        let mut objects = Vec::new();
+        for (k, v) in rendered.namespaces.iter_mut().chain(&mut rendered.by_key) {
+            if !object_matches_filter(k, v, &filter) {
+                continue;
+            }
+            munge_secrets(None, v)?;
+            objects.push(json!({"key": k.to_string(), "object": v}));
+        }
+        Ok(json!({ "objects": objects }))
+    }
+
+    async fn plan(&mut self, body: &JsonValue) -> Result<JsonValue, HttpError> {
+        let filter = body.get("filter").cloned().unwrap_or_else(|| json!({}));
+        let changes = self.make_plan(&filter).await?;
+        let id = format!("{:016x}", rand::random::<u64>());
+        let plan = StoredPlan {
+            filter,
+            changes,
+            created: Instant::now(),
+            approved: None,
+        };
+        let described = describe(&id, &plan);
+        self.plans.insert(id, plan);
+        Ok(described)
+    }
+
+    fn show(&self, id: &str) -> Result<JsonValue, HttpError> {
+        Ok(describe(id, self.stored(id)?))
+    }
+
+    // Records who approved which changes, like answering the prompts of a push. Changes that
+    // delete or recreate protected objects need allow_protected, like push's --allow-protected.
+    fn approve(&mut self, id: &str, body: &JsonValue) -> Result<JsonValue, HttpError> {
+        let Some(approver) = body["approver"].as_str().filter(|a| !a.is_empty()) else {
+            return Err(http_error(400, "Approvals need an approver"));
+        };
+        let plan = self
+            .plans
+            .get_mut(id)
+            .ok_or_else(|| http_error(404, format!("No plan {}", id)))?;
+        let changes: BTreeSet<usize> = match &body["changes"] {
+            JsonValue::Null => (0..plan.changes.len()).collect(),
+            JsonValue::Array(indices) => indices
+                .iter()
+                .map(|i| {
+                    i.as_u64()
+                        .map(|i| i as usize)
+                        .filter(|i| *i < plan.changes.len())
+                        .ok_or_else(|| http_error(400, format!("No change {} in the plan", i)))
+                })
+                .collect::<Result<_, _>>()?,
+            other => {
+                return Err(http_error(
+                    400,
+                    format!("Expected changes to be a list of indices but got {}", other),
+                ))
+            }
+        };
+        if body["allow_protected"].as_bool() != Some(true) {
+            let approved = plan
+                .changes
+                .iter()
+                .enumerate()
+                .filter(|(i, _)| changes.contains(i))
+                .map(|(_, c)| c);
+            check_protected(approved).map_err(|e| http_error(403, format!("{:#}", e)))?;
+        }
+        for (i, change) in plan.changes.iter().enumerate() {
+            let answer = if changes.contains(&i) {
+                format!("apply (approved by {} over HTTP)", approver)
+            } else {
+                format!("skip (approved by {} over HTTP)", approver)
+            };
+            audit_prompt(change.action.verb(), Some(&change.key), &answer);
+        }
+        plan.approved = Some(Approval {
+            approver: approver.to_string(),
+            changes,
+        });
+        Ok(describe(id, plan))
+    }
+
+    // Applies the approved changes under the database lock, but only if planning again gives
+    // exactly the plan that was approved. Otherwise the files or the database changed in between,
+    // and the plan has to be made and approved again.
+    async fn apply(&mut self, id: &str) -> Result<JsonValue, HttpError> {
+        let stored = self.stored(id)?;
+        let Some(approval) = &stored.approved else {
+            return Err(http_error(409, format!("Plan {} hasn't been approved", id)));
+        };
+        let approved = approval.changes.clone();
+        let filter = stored.filter.clone();
+        let lock = lock_database(&self.pool, "serve", /* force_unlock= */ false)
+            .await
+            .map_err(|e| http_error(409, format!("{:#}", e)))?;
+        let result = self.apply_locked(id, &filter, &approved, &lock).await;
+        lock.release().await?;
+        self.plans.remove(id);
+        result
+    }
+
+    async fn apply_locked(
+        &mut self,
+        id: &str,
+        filter: &JsonValue,
+        approved: &BTreeSet<usize>,
+        lock: &PushLock,
+    ) -> Result<JsonValue, HttpError> {
+        let fresh = self.make_plan(filter).await?;
+        if !same_plan(&self.stored(id)?.changes, &fresh) {
+            return Err(http_error(
+                409,
+                format!("Plan {} is out of date, make and approve a new one", id),
+            ));
+        }
+        let chosen: Vec<_> = fresh
+            .into_iter()
+            .enumerate()
+            .filter(|(i, _)| approved.contains(i))
+            .map(|(_, c)| c)
+            .collect();
+        let changed: Vec<_> = chosen.iter().map(|c| (&c.key, &c.action)).collect();
+        self.policy
+            .check(&changed)
+            .map_err(|e| http_error(403, format!("{:#}", e)))?;
+        let summary: Vec<_> = chosen
+            .iter()
+            .map(|c| (c.key.clone(), c.action.verb()))
+            .collect();
+        let result = apply_diff(
+            chosen.into_iter().map(|c| (c.key, c.action)).collect(),
+            &self.policy,
+            &self.pool,
+            lock,
+            self.args.hash_secrets,
+            /* annotate_provenance= */ false,
+            self.args.max_retries,
+            /* keep_going= */ false,
+        )
+        .await;
+        notify_finished("serve", &summary, result.as_ref().err()).await;
+        result?;
+        Ok(json!({
+            "id": id,
+            "applied": summary
+                .iter()
+                .map(|(key, verb)| json!({"verb": verb, "object": key.to_string()}))
+                .collect::<Vec<_>>(),
+        }))
+    }
+
+    // Plans like push does, narrowed to the delegation. The delegation is read again each time so
+    // that trusting a key or a delegation expiring takes effect without a restart.
+    async fn make_plan(&mut self, filter: &JsonValue) -> Result<Vec<PlannedChange>, HttpError> {
+        let filter =
+            PartialKey::from_json(filter).map_err(|e| http_error(400, format!("{:#}", e)))?;
+        let delegation = load_delegation(&self.pool, self.args.delegation.as_deref())
+            .await
+            .map_err(|e| http_error(403, format!("{:#}", e)))?;
+        let plan = plan_changes(
+            &filter,
+            &self.args.monitor_directory,
+            &mut self.environments,
+            &self.pool,
+            &mut Timings::new(false),
+        )
+        .await?;
+        match delegation {
+            Some(delegation) => delegation
+                .restrict(plan)
+                .map_err(|e| http_error(403, format!("{:#}", e))),
+            None => Ok(plan),
+        }
+    }
+
+    fn stored(&self, id: &str) -> Result<&StoredPlan, HttpError> {
+        self.plans
+            .get(id)
+            .ok_or_else(|| http_error(404, format!("No plan {}", id)))
+    }
+}
+
+// Compares SHA-256 digests so the time taken reveals neither the token nor its length
+fn same_token(presented: &str, expected: &str) -> bool {
+    let presented = Sha256::digest(presented.as_bytes());
+    let expected = Sha256::digest(expected.as_bytes());
+    presented
+        .iter()
+        .zip(expected.iter())
+        .fold(0, |differences, (p, e)| differences | (p ^ e))
+        == 0
+}
+
+fn read_filter(body: &JsonValue) -> Result<PartialKey, HttpError> {
+    PartialKey::from_json(body.get("filter").unwrap_or(&json!({})))
+        .map_err(|e| http_error(400, format!("{:#}", e)))
+}
+
+fn same_plan(approved: &[PlannedChange], fresh: &[PlannedChange]) -> bool {
+    approved.len() == fresh.len()
+        && approved.iter().zip(fresh).all(|(a, f)| {
+            a.key == f.key && a.action.verb() == f.action.verb() && a.rendering == f.rendering
+        })
+}
+
+fn describe(id: &str, plan: &StoredPlan) -> JsonValue {
+    let changes: Vec<_> = plan
+        .changes
+        .iter()
+        .enumerate()
+        .map(|(i, c)| {
+            json!({
+                "index": i,
+                "verb": c.action.verb(),
+                "object": c.key.to_string(),
+                "protected": c.protected,
+                "secret_keys": c.secret_keys,
+                "diff": console::strip_ansi_codes(&c.rendering),
+            })
+        })
+        .collect();
+    json!({
+        "id": id,
+        "filter": plan.filter,
+        "expires_in_seconds": PLAN_TTL.saturating_sub(plan.created.elapsed()).as_secs(),
+        "changes": changes,
+        "approved": plan.approved.as_ref().map(|a| json!({
+            "approver": a.approver,
+            "changes": a.changes,
+        })),
+    })
+}
+
+fn reason(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        403 => "Forbidden",
+        404 => "Not Found",
+        408 => "Request Timeout",
+        409 => "Conflict",
+        _ => "Internal Server Error",
+    }
+}
+
+// Reads one HTTP/1.1 request, which is all a client gets per connection
+async fn read_request(stream: &mut TcpStream) -> Result<Request> {
+    let mut buffer = Vec::new();
+    let header_end = loop {
+        if let Some(end) = buffer.windows(4).position(|w| w == b"\r\n\r\n") {
+            break end;
+        }
+        if buffer.len() > MAX_HEADERS {
+            bail!("Headers are too long");
+        }
+        let mut chunk = [0; 4096];
+        let read = stream.read(&mut chunk).await?;
+        if read == 0 {
+            bail!("Connection closed before the request ended");
+        }
+        buffer.extend_from_slice(&chunk[..read]);
+    };
+    let mut request = parse_head(std::str::from_utf8(&buffer[..header_end])?)?;
+    let length = request.content_length;
+    let mut body = buffer[header_end + 4..].to_vec();
+    while body.len() < length {
+        let mut chunk = [0; 4096];
+        let read = stream.read(&mut chunk).await?;
+        if read == 0 {
+            bail!("Connection closed before the body ended");
+        }
+        body.extend_from_slice(&chunk[..read]);
+    }
+    body.truncate(length);
+    request.body = body;
+    Ok(request)
+}
+
+// Parses the request line and headers, leaving the body to be read
+fn parse_head(head: &str) -> Result<Request> {
+    let mut lines = head.split("\r\n");
+    let mut request_line = lines.next().unwrap_or_default().split(' ');
+    let (Some(method), Some(path)) = (request_line.next(), request_line.next()) else {
+        bail!("Invalid request line");
+    };
+    let mut authorization = None;
+    let mut length = 0;
+    for line in lines {
+        let (name, value) = line
+            .split_once(':')
+            .ok_or_else(|| anyhow!("Invalid header {}", line))?;
+        match name.trim().to_ascii_lowercase().as_str() {
+            "authorization" => authorization = Some(value.trim().to_string()),
+            "content-length" => {
+                length = value.trim().parse().context("Invalid content-length")?;
+                if length > MAX_BODY {
+                    bail!("The body is too long");
+                }
+            }
+            _ => {}
+        }
+    }
+    Ok(Request {
+        method: method.to_string(),
+        // Query strings aren't used
+        path: path.split('?').next().unwrap_or_default().to_string(),
+        authorization,
+        content_length: length,
+        body: Vec::new(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        generate_diff::generate_plan,
+        kubernetes_io::{test_key, KubernetesResources},
+    };
+    use std::collections::BTreeMap;
+
+    #[test]
+    fn test_parse_head() -> Result<()> {
+        let request = parse_head(
+            "POST /plans/abc/approve?x=1 HTTP/1.1\r\nHost: localhost\r\nAuthorization: Bearer \
+             secret\r\nContent-Length: 12",
+        )?;
+        assert_eq!(request.method, "POST");
+        assert_eq!(request.path, "/plans/abc/approve");
+        assert_eq!(request.authorization.as_deref(), Some("Bearer secret"));
+        assert_eq!(request.content_length, 12);
+        assert!(parse_head("GET\r\n").is_err());
+        assert!(parse_head("POST /plans HTTP/1.1\r\nContent-Length: 99999999").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_same_token() {
+        assert!(same_token("0123456789abcdef", "0123456789abcdef"));
+        assert!(!same_token("0123456789abcdeg", "0123456789abcdef"));
+        assert!(!same_token("0123", "0123456789abcdef"));
+        assert!(!same_token("", "0123456789abcdef"));
+    }
+
+    #[test]
+    fn test_describe_lists_secret_keys() -> Result<()> {
+        let key = test_key("prod", "v1", "Secret", Some("echo"), "token");
+        let secret = serde_json::from_value(json!({
+            "apiVersion": "v1",
+            "kind": "Secret",
+            "metadata": {"name": "token"},
+            "data": {"token": "YQ=="},
+        }))?;
+        let changes = generate_plan(
+            KubernetesResources {
+                by_key: BTreeMap::new(),
+                namespaces: BTreeMap::new(),
+            },
+            KubernetesResources {
+                by_key: BTreeMap::from([(key, secret)]),
+                namespaces: BTreeMap::new(),
+            },
+        )?;
+        let plan = StoredPlan {
+            filter: json!({}),
+            changes,
+            created: Instant::now(),
+            approved: None,
+        };
+
+        assert_eq!(
+            describe("abc", &plan)["changes"][0]["secret_keys"],
+            json!({"added": ["token"], "removed": [], "changed": []})
+        );
+        Ok(())
+    }
+}
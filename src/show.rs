@@ -0,0 +1,147 @@
+use anyhow::{anyhow, bail, Context, Result};
+use clap::ValueEnum;
+use kube::api::DynamicObject;
+use sqlx::AnyPool;
+
+use crate::{
+    environments::Environments,
+    kubernetes_io::{
+        get_kubernetes_api, get_kubernetes_clients, munge_secrets, KubernetesKey,
+        KubernetesResources,
+    },
+    render_files::render_files,
+    state_store::{connect_database, find_object},
+    timings::Timings,
+};
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq, ValueEnum)]
+pub(crate) enum ObjectSource {
+    // What the configuration files render to, which is what a push would apply
+    #[value(alias = "rendered")]
+    Files,
+    // What Sisyphus recorded when it last pushed the object
+    Db,
+    // What the cluster has right now
+    #[value(alias = "cluster")]
+    Live,
+}
+
+// Where `show` can find each source. Only the one being read needs to be set.
+pub(crate) struct ShowSources<'a> {
+    pub monitor_directory: Option<&'a [String]>,
+    pub environments: &'a mut Environments,
+    pub database_url: Option<&'a str>,
+}
+
+// Prints a single object as YAML, so answering "what exactly would we apply" doesn't need a full
+// diff. Secret values from files and the cluster are replaced like `import` does.
+pub(crate) async fn show(
+    key: &KubernetesKey,
+    source: ObjectSource,
+    sources: ShowSources<'_>,
+) -> Result<()> {
+    let mut object = match source {
+        ObjectSource::Files => {
+            let Some(monitor_directory) = sources.monitor_directory else {
+                bail!("--monitor-directory is required to show an object from files");
+            };
+            let rendered = render_files(
+                monitor_directory,
+                sources.environments,
+                &mut Timings::new(false),
+            )
+            .await?;
+            take_object(rendered, key).ok_or_else(|| anyhow!("The files don't render {}", key))?
+        }
+        ObjectSource::Db => {
+            let Some(database_url) = sources.database_url else {
+                bail!("--database-url is required to show an object from the database");
+            };
+            let pool = connect_database(database_url).await?;
+            return print_recorded(key, &pool).await;
+        }
+        ObjectSource::Live => {
+            let (clients, types) = get_kubernetes_clients([key]).await?;
+            let api = get_kubernetes_api(key, &clients, &types)?;
+            api.get_opt(&key.name)
+                .await
+                .with_context(|| format!("while fetching {}", key))?
+                .ok_or_else(|| anyhow!("{} doesn't exist in the cluster", key))?
+        }
+    };
+    object.metadata.managed_fields = None;
+    munge_secrets(None, &mut object)?;
+    print!("{}", serde_yaml::to_string(&object)?);
+    Ok(())
+}
+
+fn take_object(mut resources: KubernetesResources, key: &KubernetesKey) -> Option<DynamicObject> {
+    resources
+        .by_key
+        .remove(key)
+        .or_else(|| resources.namespaces.remove(key))
+}
+
+// Prints the YAML exactly as it was stored rather than round-tripping it
+async fn print_recorded(key: &KubernetesKey, pool: &AnyPool) -> Result<()> {
+    let (yaml, _) = find_object(pool, key)
+        .await?
+        .ok_or_else(|| anyhow!("{} isn't tracked in the database", key))?;
+    print!("{}", yaml);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::kubernetes_io::test_key;
+    use std::collections::BTreeMap;
+
+    #[test]
+    fn test_take_object_finds_namespaces_too() {
+        let object = DynamicObject {
+            types: None,
+            metadata: Default::default(),
+            data: serde_json::json!({}),
+        };
+        let resources = || KubernetesResources {
+            by_key: BTreeMap::from([(
+                test_key("prod", "v1", "Service", Some("echo"), "echo"),
+                object.clone(),
+            )]),
+            namespaces: BTreeMap::from([(
+                test_key("prod", "v1", "Namespace", None, "echo"),
+                object.clone(),
+            )]),
+        };
+
+        assert!(take_object(
+            resources(),
+            &test_key("prod", "v1", "Service", Some("echo"), "echo")
+        )
+        .is_some());
+        assert!(take_object(
+            resources(),
+            &test_key("prod", "v1", "Namespace", None, "echo")
+        )
+        .is_some());
+        assert!(take_object(
+            resources(),
+            &test_key("prod", "v1", "Service", Some("other"), "echo")
+        )
+        .is_none());
+    }
+
+    #[test]
+    fn test_source_aliases() {
+        assert_eq!(
+            ObjectSource::from_str("rendered", false),
+            Ok(ObjectSource::Files)
+        );
+        assert_eq!(
+            ObjectSource::from_str("cluster", false),
+            Ok(ObjectSource::Live)
+        );
+        assert_eq!(ObjectSource::from_str("db", false), Ok(ObjectSource::Db));
+    }
+}
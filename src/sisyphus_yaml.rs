@@ -1,6 +1,13 @@
+use k8s_openapi::{
+    api::core::v1::{LimitRangeSpec, ResourceQuotaSpec},
+    apimachinery::pkg::util::intstr::IntOrString,
+};
 use kube::api::DynamicObject;
 use serde::{Deserialize, Serialize};
-use std::collections::BTreeMap;
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+};
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
 #[serde(tag = "kind")]
@@ -10,13 +17,84 @@ pub enum SisyphusResource {
     SisyphusCronJob(SisyphusCronJob),
     #[serde(rename = "Deployment")]
     SisyphusDeployment(SisyphusDeployment),
+    #[serde(rename = "Job")]
+    SisyphusJob(SisyphusJob),
     SisyphusYaml(SisyphusYaml),
+    // Only made by plugins::read_resource, since which kinds are plugins isn't known until runtime
+    #[serde(skip_deserializing)]
+    Plugin(PluginResource),
 }
 
 pub trait HasKind {
     fn kind(&self) -> &'static str;
 }
 
+impl SisyphusResource {
+    pub fn metadata(&self) -> &Metadata {
+        match self {
+            SisyphusResource::KubernetesYaml(v) => &v.metadata,
+            SisyphusResource::SisyphusCronJob(v) => &v.metadata,
+            SisyphusResource::SisyphusDeployment(v) => &v.metadata,
+            SisyphusResource::SisyphusJob(v) => &v.metadata,
+            SisyphusResource::SisyphusYaml(v) => &v.metadata,
+            SisyphusResource::Plugin(v) => &v.metadata,
+        }
+    }
+
+    pub fn defined_in(&self) -> Option<&Path> {
+        match self {
+            SisyphusResource::KubernetesYaml(v) => v.defined_in.as_deref(),
+            SisyphusResource::SisyphusCronJob(v) => v.defined_in.as_deref(),
+            SisyphusResource::SisyphusDeployment(v) => v.defined_in.as_deref(),
+            SisyphusResource::SisyphusJob(v) => v.defined_in.as_deref(),
+            SisyphusResource::SisyphusYaml(_) => None,
+            SisyphusResource::Plugin(v) => v.defined_in.as_deref(),
+        }
+    }
+
+    // Like `echo/index.yaml:3`, naming the document within the file when it's known
+    pub fn location(&self) -> Option<String> {
+        let path = self.defined_in()?;
+        Some(match self.document() {
+            Some(document) => format!("{}:{}", path.display(), document),
+            None => path.display().to_string(),
+        })
+    }
+
+    pub fn document(&self) -> Option<usize> {
+        match self {
+            SisyphusResource::KubernetesYaml(v) => v.document,
+            SisyphusResource::SisyphusCronJob(v) => v.document,
+            SisyphusResource::SisyphusDeployment(v) => v.document,
+            SisyphusResource::SisyphusJob(v) => v.document,
+            SisyphusResource::SisyphusYaml(_) => None,
+            SisyphusResource::Plugin(v) => v.document,
+        }
+    }
+
+    pub fn set_document(&mut self, document: usize) {
+        match self {
+            SisyphusResource::KubernetesYaml(v) => v.document = Some(document),
+            SisyphusResource::SisyphusCronJob(v) => v.document = Some(document),
+            SisyphusResource::SisyphusDeployment(v) => v.document = Some(document),
+            SisyphusResource::SisyphusJob(v) => v.document = Some(document),
+            SisyphusResource::SisyphusYaml(_) => {}
+            SisyphusResource::Plugin(v) => v.document = Some(document),
+        }
+    }
+
+    pub fn set_defined_in(&mut self, path: PathBuf) {
+        match self {
+            SisyphusResource::KubernetesYaml(v) => v.defined_in = Some(path),
+            SisyphusResource::SisyphusCronJob(v) => v.defined_in = Some(path),
+            SisyphusResource::SisyphusDeployment(v) => v.defined_in = Some(path),
+            SisyphusResource::SisyphusJob(v) => v.defined_in = Some(path),
+            SisyphusResource::SisyphusYaml(_) => {}
+            SisyphusResource::Plugin(v) => v.defined_in = Some(path),
+        }
+    }
+}
+
 pub trait HasConfigImage {
     fn config_image<'a>(&'a self) -> &'a String;
     fn set_config_image(&mut self, image: String) -> ();
@@ -32,6 +110,12 @@ pub struct KubernetesYaml {
     pub objects: Vec<DynamicObject>,
     #[serde(default)]
     pub sources: Vec<String>,
+    // The file this resource was read from, for error messages
+    #[serde(skip)]
+    pub defined_in: Option<PathBuf>,
+    // Which YAML document in that file, counting from 1
+    #[serde(skip)]
+    pub document: Option<usize>,
 }
 
 impl HasKind for KubernetesYaml {
@@ -47,6 +131,12 @@ pub struct SisyphusCronJob {
     pub metadata: Metadata,
     pub config: CronJobConfig,
     pub footprint: BTreeMap<String, CronJobFootprintEntry>,
+    // The file this resource was read from, relative to the monitor directory
+    #[serde(skip)]
+    pub defined_in: Option<PathBuf>,
+    // Which YAML document in that file, counting from 1
+    #[serde(skip)]
+    pub document: Option<usize>,
 }
 
 impl HasConfigImage for SisyphusCronJob {
@@ -72,6 +162,12 @@ pub struct SisyphusDeployment {
     pub metadata: Metadata,
     pub config: DeploymentConfig,
     pub footprint: BTreeMap<String, DeploymentFootprintEntry>,
+    // The file this resource was read from, relative to the monitor directory
+    #[serde(skip)]
+    pub defined_in: Option<PathBuf>,
+    // Which YAML document in that file, counting from 1
+    #[serde(skip)]
+    pub document: Option<usize>,
 }
 
 impl HasConfigImage for SisyphusDeployment {
@@ -90,6 +186,37 @@ impl HasKind for SisyphusDeployment {
     }
 }
 
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "camelCase")]
+pub struct SisyphusJob {
+    pub api_version: String,
+    pub metadata: Metadata,
+    pub config: JobConfig,
+    pub footprint: BTreeMap<String, JobFootprintEntry>,
+    // The file this resource was read from, relative to the monitor directory
+    #[serde(skip)]
+    pub defined_in: Option<PathBuf>,
+    // Which YAML document in that file, counting from 1
+    #[serde(skip)]
+    pub document: Option<usize>,
+}
+
+impl HasConfigImage for SisyphusJob {
+    fn config_image<'a>(&'a self) -> &'a String {
+        &self.config.image
+    }
+
+    fn set_config_image(&mut self, image: String) -> () {
+        self.config.image = image
+    }
+}
+
+impl HasKind for SisyphusJob {
+    fn kind(&self) -> &'static str {
+        "SisyphusJob"
+    }
+}
+
 #[derive(Clone, Debug, Serialize, Deserialize)]
 #[serde(deny_unknown_fields, rename_all = "camelCase")]
 pub struct SisyphusYaml {
@@ -105,6 +232,41 @@ impl HasKind for SisyphusYaml {
     }
 }
 
+// A resource of a kind that an exec plugin renders. Everything besides the kind, metadata, and
+// clusters is the plugin's to interpret.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PluginResource {
+    // Serialized under another name since SisyphusResource puts its own tag in `kind`
+    #[serde(rename(serialize = "pluginKind", deserialize = "kind"))]
+    pub kind: String,
+    pub api_version: String,
+    pub metadata: Metadata,
+    pub clusters: Vec<String>,
+    #[serde(flatten)]
+    pub fields: BTreeMap<String, serde_json::Value>,
+    // The file this resource was read from, for error messages
+    #[serde(skip)]
+    pub defined_in: Option<PathBuf>,
+    // Which YAML document in that file, counting from 1
+    #[serde(skip)]
+    pub document: Option<usize>,
+}
+
+// What a namespace folder's namespace.yaml sets on the namespace itself. The Namespace object gets
+// the labels and annotations, and a ResourceQuota and LimitRange named after the namespace are
+// added to every cluster it's in.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "camelCase")]
+pub struct NamespaceSettings {
+    #[serde(default)]
+    pub labels: BTreeMap<String, String>,
+    #[serde(default)]
+    pub annotations: BTreeMap<String, String>,
+    pub resource_quota: Option<ResourceQuotaSpec>,
+    pub limit_range: Option<LimitRangeSpec>,
+}
+
 #[derive(Clone, Debug, Serialize, Deserialize)]
 #[serde(deny_unknown_fields, rename_all = "camelCase")]
 pub struct Metadata {
@@ -113,6 +275,8 @@ pub struct Metadata {
     pub name: String,
     #[serde(default)]
     pub labels: BTreeMap<String, String>,
+    // Only allowed when reading resources from stdin, where there's no folder to take it from
+    pub namespace: Option<String>,
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
@@ -120,39 +284,126 @@ pub struct Metadata {
 pub struct CronJobConfig {
     pub concurrency_policy: Option<String>,
     pub env: String,
+    pub failed_jobs_history_limit: Option<i32>,
     pub image: String,
+    #[serde(default)]
+    pub image_pull_secrets: Vec<ImagePullSecret>,
     pub restart_policy: Option<String>,
     pub schedule: String,
+    pub starting_deadline_seconds: Option<i64>,
+    pub successful_jobs_history_limit: Option<i32>,
+    pub suspend: Option<bool>,
     #[serde(default)]
     pub variables: BTreeMap<String, VariableSource>,
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
 #[serde(deny_unknown_fields, rename_all = "camelCase")]
-pub struct CronJobFootprintEntry {}
+pub struct CronJobFootprintEntry {
+    // Replaces the config's imagePullSecrets in this cluster
+    pub image_pull_secrets: Option<Vec<ImagePullSecret>>,
+    // Added to the config's variables in this cluster, replacing any with the same name
+    #[serde(default)]
+    pub variables: BTreeMap<String, VariableSource>,
+}
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
 #[serde(deny_unknown_fields, rename_all = "camelCase")]
 pub struct DeploymentConfig {
+    pub disruption_budget: Option<DisruptionBudgetConfig>,
     pub env: String,
     pub image: String,
+    #[serde(default)]
+    pub image_pull_secrets: Vec<ImagePullSecret>,
     pub service: Option<DeploymentServiceConfig>,
+    pub spread: Option<Spread>,
     #[serde(default)]
     pub variables: BTreeMap<String, VariableSource>,
 }
 
+// Where a Deployment's replicas should be spread out, so one node or zone going down doesn't take
+// all of them with it
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum Spread {
+    Host,
+    Zone,
+}
+
 #[derive(Clone, Debug, Serialize, Deserialize)]
 #[serde(deny_unknown_fields, rename_all = "camelCase")]
 pub struct DeploymentFootprintEntry {
+    // Replaces the config's imagePullSecrets in this cluster
+    pub image_pull_secrets: Option<Vec<ImagePullSecret>>,
     pub replicas: i32,
+    // Added to the config's variables in this cluster, replacing any with the same name
+    #[serde(default)]
+    pub variables: BTreeMap<String, VariableSource>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "camelCase")]
+pub struct DisruptionBudgetConfig {
+    pub max_unavailable: Option<IntOrString>,
+    pub min_available: Option<IntOrString>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "camelCase")]
+pub struct JobConfig {
+    pub backoff_limit: Option<i32>,
+    pub completions: Option<i32>,
+    pub env: String,
+    pub image: String,
+    #[serde(default)]
+    pub image_pull_secrets: Vec<ImagePullSecret>,
+    pub restart_policy: Option<String>,
+    pub ttl_seconds_after_finished: Option<i32>,
+    #[serde(default)]
+    pub variables: BTreeMap<String, VariableSource>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "camelCase")]
+pub struct JobFootprintEntry {
+    // Replaces the config's imagePullSecrets in this cluster
+    pub image_pull_secrets: Option<Vec<ImagePullSecret>>,
+    // Added to the config's variables in this cluster, replacing any with the same name
+    #[serde(default)]
+    pub variables: BTreeMap<String, VariableSource>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "camelCase")]
+pub struct ImagePullSecret {
+    // Registries whose local docker credentials fill in a generated Secret with this name. Leave it
+    // empty to reference a Secret that's managed some other way.
+    #[serde(default)]
+    pub from_docker_credentials: Vec<String>,
+    pub name: String,
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
 #[serde(deny_unknown_fields, rename_all = "camelCase")]
 pub struct DeploymentServiceConfig {
+    // Renders a ServiceExport so the Service is reachable from the other clusters in its
+    // ClusterSet through the multi-cluster services API
+    #[serde(default)]
+    pub export: bool,
+    pub ingress: Option<DeploymentIngressConfig>,
     pub ports: BTreeMap<String, ServicePort>,
 }
 
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "camelCase")]
+pub struct DeploymentIngressConfig {
+    pub class_name: Option<String>,
+    pub host: String,
+    pub path: Option<String>,
+    pub port: Option<String>,
+    pub tls_secret: Option<String>,
+}
+
 #[derive(Clone, Debug, Serialize, Deserialize)]
 #[serde(deny_unknown_fields, rename_all = "camelCase")]
 pub struct ServicePort {
@@ -163,9 +414,28 @@ pub struct ServicePort {
 #[derive(Clone, Debug, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub enum VariableSource {
+    ConfigMapKeyRef(KubernetesConfigMapKeyRef),
+    ExternalSecret(ExternalSecretRef),
     SecretKeyRef(KubernetesSecretKeyRef),
 }
 
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "camelCase")]
+pub struct KubernetesConfigMapKeyRef {
+    pub name: String,
+    pub key: String,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "camelCase")]
+pub struct ExternalSecretRef {
+    pub key: String,
+    pub property: Option<String>,
+    pub refresh_interval: Option<String>,
+    pub store: String,
+    pub store_kind: Option<String>,
+}
+
 #[derive(Clone, Debug, Serialize, Deserialize)]
 #[serde(deny_unknown_fields, rename_all = "camelCase")]
 pub struct KubernetesSecretKeyRef {
@@ -1,4 +1,4 @@
-use crate::config_image::{make_starlark_globals, Application};
+use crate::config_image::{make_starlark_globals, Applications};
 use allocative::Allocative;
 use anyhow::{anyhow, Result};
 use starlark::{
@@ -7,25 +7,28 @@ use starlark::{
     eval::{Evaluator, FileLoader},
     starlark_simple_value,
     syntax::{AstModule, Dialect},
-    values::{starlark_value, NoSerialize, StarlarkValue, ValueLike},
+    values::{starlark_value, NoSerialize, StarlarkValue},
 };
 use std::{
     fmt,
     path::{Path, PathBuf},
 };
 
+// Passed to a config's main function to describe where it's being rendered for.
 #[derive(Allocative, Clone, Debug, NoSerialize, ProvidesStaticType)]
 pub(crate) struct Context {
     pub name: String,
     pub namespace: Option<String>,
+    pub environment: String,
+    pub cluster: Option<String>,
 }
 
 impl fmt::Display for Context {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(
             f,
-            "Context(name={:?}, namespace={:?})",
-            self.name, self.namespace
+            "Context(name={:?}, namespace={:?}, environment={:?}, cluster={:?})",
+            self.name, self.namespace, self.environment, self.cluster
         )
     }
 }
@@ -53,14 +56,25 @@ fn context_methods(builder: &mut starlark::environment::MethodsBuilder) {
             ))
         })
     }
+
+    fn environment(this: &Context) -> starlark::Result<String> {
+        Ok(this.environment.clone())
+    }
+
+    fn cluster(this: &Context) -> starlark::Result<String> {
+        this.cluster.clone().ok_or_else(|| {
+            starlark::Error::new_other(anyhow!(
+                "ctx.cluster() is not available (hint: do you need to pass --cluster?)"
+            ))
+        })
+    }
 }
 
 pub(crate) async fn load_starlark_config(
     root: &Path,
     path: &Path,
-    name: &str,
-    namespace: Option<&str>,
-) -> Result<Application> {
+    context: Context,
+) -> Result<Applications> {
     let content = tokio::fs::read_to_string(path).await?;
     let path_str = path.to_str().unwrap_or("config.star");
 
@@ -90,21 +104,13 @@ pub(crate) async fn load_starlark_config(
         .flatten()
         .map_err(|e| anyhow!("No main function: {:?}", e))?;
 
-    let ctx = module.heap().alloc_simple(Context {
-        name: name.to_string(),
-        namespace: namespace.map(|s| s.to_string()),
-    });
+    let ctx = module.heap().alloc_simple(context);
 
     let result = eval
         .eval_function(main, &[ctx], &[])
         .map_err(|e| anyhow!("Cannot evaluate config: {:?}", e))?;
 
-    let application = result
-        .downcast_ref::<Application>()
-        .ok_or_else(|| anyhow!("Config didn't return an Application"))?
-        .clone();
-
-    Ok(application)
+    Applications::unpack_value(result)
 }
 
 struct ConfigFileLoader {
@@ -119,6 +125,17 @@ impl FileLoader for ConfigFileLoader {
             Some(from_root) => self.root.join(from_root),
             None => self.current_dir.join(path),
         };
+        // Configs are evaluated from an unpacked image, so don't let them read the rest of the disk
+        let within_root = match (resolved.canonicalize(), self.root.canonicalize()) {
+            (Ok(r), Ok(root)) => r.starts_with(root),
+            _ => true, // Reading the file below reports the real problem
+        };
+        if !within_root {
+            return Err(starlark::Error::new_other(anyhow!(
+                "load() target {:?} is outside of the config root",
+                resolved
+            )));
+        }
         let content = std::fs::read_to_string(&resolved).map_err(|e| {
             starlark::Error::new_other(anyhow!("Unable to read load() target {:?}: {}", resolved, e))
         })?;
@@ -152,6 +169,15 @@ mod tests {
     use std::{collections::BTreeMap, fs};
     use tempfile::TempDir;
 
+    fn test_context() -> Context {
+        Context {
+            name: "test-app".to_string(),
+            namespace: None,
+            environment: "dev".to_string(),
+            cluster: None,
+        }
+    }
+
     fn write(root: &Path, rel: &str, content: &str) {
         let path = root.join(rel);
         fs::create_dir_all(path.parent().unwrap()).unwrap();
@@ -210,10 +236,10 @@ def main(ctx):
         );
 
         let application =
-            load_starlark_config(root, &root.join("app/main.star"), "test-app", None).await?;
+            load_starlark_config(root, &root.join("app/main.star"), test_context()).await?;
 
         assert_eq!(
-            application.labels,
+            application.labels(),
             BTreeMap::from([
                 ("root".to_string(), "root".to_string()),
                 ("local".to_string(), "nested".to_string()),
@@ -221,4 +247,111 @@ def main(ctx):
         );
         Ok(())
     }
+
+    #[tokio::test]
+    async fn load_rejects_paths_outside_of_root() -> Result<()> {
+        let tmp = TempDir::new()?;
+        write(
+            tmp.path(),
+            "secret.star",
+            r#"
+def value():
+    return "secret"
+"#,
+        );
+        let root = tmp.path().join("image");
+        write(
+            &root,
+            "main.star",
+            r#"
+load("../secret.star", "value")
+
+def main(ctx):
+    return Application(labels = {"value": value()})
+"#,
+        );
+
+        let result = load_starlark_config(&root, &root.join("main.star"), test_context()).await;
+
+        assert!(result.is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn main_receives_environment_and_cluster() -> Result<()> {
+        let tmp = TempDir::new()?;
+        let root = tmp.path();
+        write(
+            root,
+            "main.star",
+            r#"
+def main(ctx):
+    return Application(labels = {
+        "environment": ctx.environment(),
+        "cluster": ctx.cluster(),
+    })
+"#,
+        );
+
+        let application = load_starlark_config(
+            root,
+            &root.join("main.star"),
+            Context {
+                cluster: Some("us-east".to_string()),
+                ..test_context()
+            },
+        )
+        .await?;
+        assert_eq!(
+            application.labels(),
+            BTreeMap::from([
+                ("cluster".to_string(), "us-east".to_string()),
+                ("environment".to_string(), "dev".to_string()),
+            ])
+        );
+
+        // Without a cluster, asking for one fails
+        assert!(
+            load_starlark_config(root, &root.join("main.star"), test_context())
+                .await
+                .is_err()
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn main_may_return_named_containers() -> Result<()> {
+        let tmp = TempDir::new()?;
+        let root = tmp.path();
+        write(
+            root,
+            "main.star",
+            r#"
+def main(ctx):
+    return struct(
+        app = Application(labels = {"role": "app"}),
+        exporter = Application(labels = {"scraped": "true"}),
+    )
+"#,
+        );
+
+        let applications =
+            load_starlark_config(root, &root.join("main.star"), test_context()).await?;
+
+        let by_container = applications.by_container("echo");
+        assert_eq!(
+            by_container.keys().collect::<Vec<_>>(),
+            vec!["app", "exporter"]
+        );
+        assert_eq!(
+            applications.labels(),
+            BTreeMap::from([
+                ("role".to_string(), "app".to_string()),
+                ("scraped".to_string(), "true".to_string()),
+            ])
+        );
+        assert!(applications.select(None).is_err());
+        assert!(applications.select(Some("exporter")).is_ok());
+        Ok(())
+    }
 }
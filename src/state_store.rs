@@ -0,0 +1,526 @@
+use anyhow::{Context, Result};
+use sqlx::{
+    any::{AnyArguments, AnyPoolOptions, AnyRow},
+    query::Query,
+    Any, AnyPool, Executor, Row,
+};
+use std::{future::Future, time::Duration};
+use tokio::time::sleep;
+
+use crate::{database::TrackedObject, kubernetes_io::KubernetesKey};
+
+// How long a SQLite connection waits for another process's write to finish before failing with
+// "database is locked"
+const SQLITE_BUSY_TIMEOUT: Duration = Duration::from_secs(30);
+// SQLite still gives up early now and then, such as when a WAL reader can't become a writer, so
+// statements that hit a lock are tried again a few times after a short wait
+const BUSY_RETRIES: u32 = 4;
+const BUSY_BACKOFF: Duration = Duration::from_millis(250);
+
+// Connects to the database behind --database-url. SQLite gets write-ahead logging so that a diff
+// can read while a push writes, a busy timeout so that concurrent runs wait their turn instead of
+// failing, and a single connection so that this process's own writes, like those of clusters
+// applied at the same time, go one at a time rather than contending for the file.
+//
+// That single connection is shared with the push lock's heartbeat, which therefore waits behind
+// whatever apply is writing at the time. Each write holds it for milliseconds against a 30 second
+// heartbeat and a two minute lease, so the wait never costs the lock.
+pub(crate) async fn connect_database(database_url: &str) -> Result<AnyPool> {
+    if !is_sqlite(database_url) {
+        return AnyPool::connect(database_url)
+            .await
+            .context("while connecting to the database");
+    }
+    AnyPoolOptions::new()
+        .max_connections(1)
+        .after_connect(|connection, _| {
+            Box::pin(async move {
+                connection
+                    .execute(
+                        format!("PRAGMA busy_timeout = {}", SQLITE_BUSY_TIMEOUT.as_millis())
+                            .as_str(),
+                    )
+                    .await?;
+                connection.execute("PRAGMA journal_mode = WAL").await?;
+                Ok(())
+            })
+        })
+        .connect(database_url)
+        .await
+        .context("while connecting to the database")
+}
+
+fn is_sqlite(database_url: &str) -> bool {
+    database_url.starts_with("sqlite:")
+}
+
+// Runs a statement, trying again with a growing wait while the database reports it's locked
+async fn retry_busy<T, F, Fut>(mut run: F) -> Result<T>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T, sqlx::Error>>,
+{
+    let mut attempt = 0;
+    loop {
+        match run().await {
+            Err(e) if is_busy(&e) && attempt < BUSY_RETRIES => {
+                attempt += 1;
+                sleep(BUSY_BACKOFF * attempt).await;
+            }
+            result => return Ok(result?),
+        }
+    }
+}
+
+// SQLite reports SQLITE_BUSY as "database is locked" and SQLITE_LOCKED as "database table is
+// locked". Other databases wait on locks themselves.
+fn is_busy(error: &sqlx::Error) -> bool {
+    match error {
+        sqlx::Error::Database(e) => {
+            e.message().contains("database is locked")
+                || e.message().contains("database table is locked")
+        }
+        _ => false,
+    }
+}
+
+// Binds `key` as $1 through $5, in the column order every table keyed by an object uses
+fn bind_key<'q>(
+    query: Query<'q, Any, AnyArguments<'q>>,
+    key: &KubernetesKey,
+) -> Query<'q, Any, AnyArguments<'q>> {
+    query
+        .bind(key.api_version.clone())
+        .bind(key.cluster.clone())
+        .bind(key.kind.clone())
+        .bind(key.name.clone())
+        .bind(namespace_or_default(key.namespace.clone()))
+}
+
+// Cluster-scoped objects are stored with an empty namespace rather than NULL so keys stay unique
+fn namespace_or_default(namespace: Option<String>) -> String {
+    namespace.unwrap_or_else(|| "".to_string())
+}
+
+fn key_from_row(row: &AnyRow) -> KubernetesKey {
+    KubernetesKey {
+        name: row.get("name"),
+        kind: row.get("kind"),
+        api_version: row.get("api_version"),
+        namespace: match row.get("namespace") {
+            "" => None,
+            v => Some(v.to_string()),
+        },
+        cluster: row.get("cluster"),
+    }
+}
+
+// The YAML recorded for `key` and who pushed it, if it's tracked
+pub(crate) async fn find_object(
+    pool: &AnyPool,
+    key: &KubernetesKey,
+) -> Result<Option<(String, Option<String>)>> {
+    let row = retry_busy(|| {
+        bind_key(
+            sqlx::query(
+                r#"
+                SELECT yaml, provenance
+                FROM kubernetes_objects
+                WHERE
+                  api_version = $1
+                  AND cluster = $2
+                  AND kind = $3
+                  AND name = $4
+                  AND namespace = $5
+                "#,
+            ),
+            key,
+        )
+        .fetch_optional(pool)
+    })
+    .await?;
+    Ok(row.map(|r| (r.get("yaml"), r.get("provenance"))))
+}
+
+// Every tracked object's key and recorded YAML
+pub(crate) async fn all_objects(pool: &AnyPool) -> Result<Vec<(KubernetesKey, String)>> {
+    let rows = retry_busy(|| {
+        sqlx::query(
+            r#"SELECT api_version, cluster, kind, namespace, name, yaml FROM kubernetes_objects"#,
+        )
+        .fetch_all(pool)
+    })
+    .await?;
+    Ok(rows
+        .iter()
+        .map(|r| (key_from_row(r), r.get("yaml")))
+        .collect())
+}
+
+pub(crate) async fn tracked_objects(pool: &AnyPool) -> Result<Vec<TrackedObject>> {
+    let rows = retry_busy(|| {
+        sqlx::query(
+            r#"
+            SELECT
+              api_version,
+              cluster,
+              kind,
+              namespace,
+              name,
+              CAST(created AS VARCHAR(64)) AS created,
+              CAST(last_updated AS VARCHAR(64)) AS last_updated
+            FROM kubernetes_objects
+            "#,
+        )
+        .fetch_all(pool)
+    })
+    .await?;
+    let mut tracked = Vec::new();
+    for row in rows {
+        let key = key_from_row(&row);
+        tracked.push(TrackedObject {
+            created: row
+                .try_get("created")
+                .with_context(|| format!("Failed to parse created time of {}", key))?,
+            last_updated: row
+                .try_get("last_updated")
+                .with_context(|| format!("Failed to parse last updated time of {}", key))?,
+            key,
+        });
+    }
+    Ok(tracked)
+}
+
+pub(crate) async fn insert_object(
+    pool: &AnyPool,
+    key: &KubernetesKey,
+    yaml: &str,
+    provenance: &str,
+) -> Result<()> {
+    retry_busy(|| {
+        bind_key(
+            sqlx::query(
+                r#"
+                INSERT INTO kubernetes_objects
+                    (api_version, cluster, kind, name, namespace, yaml, provenance)
+                VALUES ($1, $2, $3, $4, $5, $6, $7)
+                "#,
+            ),
+            key,
+        )
+        .bind(yaml.to_string())
+        .bind(provenance.to_string())
+        .execute(pool)
+    })
+    .await?;
+    Ok(())
+}
+
+// Records new YAML for `key`, along with who pushed it unless this is only a refresh
+pub(crate) async fn update_object(
+    pool: &AnyPool,
+    key: &KubernetesKey,
+    yaml: &str,
+    provenance: Option<&str>,
+) -> Result<()> {
+    retry_busy(|| {
+        let query = match provenance {
+            Some(_) => sqlx::query(
+                r#"
+                UPDATE kubernetes_objects
+                SET last_updated = CURRENT_TIMESTAMP, yaml = $6, provenance = $7
+                WHERE
+                    api_version = $1
+                    AND cluster = $2
+                    AND kind = $3
+                    AND name = $4
+                    AND namespace = $5
+                "#,
+            ),
+            None => sqlx::query(
+                r#"
+                UPDATE kubernetes_objects
+                SET last_updated = CURRENT_TIMESTAMP, yaml = $6
+                WHERE
+                    api_version = $1
+                    AND cluster = $2
+                    AND kind = $3
+                    AND name = $4
+                    AND namespace = $5
+                "#,
+            ),
+        };
+        let query = bind_key(query, key).bind(yaml.to_string());
+        let query = match provenance {
+            Some(p) => query.bind(p.to_string()),
+            None => query,
+        };
+        query.execute(pool)
+    })
+    .await?;
+    Ok(())
+}
+
+// Stops tracking `key`, returning how many rows went with it
+pub(crate) async fn delete_object(pool: &AnyPool, key: &KubernetesKey) -> Result<u64> {
+    let result = retry_busy(|| {
+        bind_key(
+            sqlx::query(
+                r#"
+                DELETE FROM kubernetes_objects
+                WHERE
+                    api_version = $1
+                    AND cluster = $2
+                    AND kind = $3
+                    AND name = $4
+                    AND namespace = $5
+                "#,
+            ),
+            key,
+        )
+        .execute(pool)
+    })
+    .await?;
+    Ok(result.rows_affected())
+}
+
+pub(crate) async fn insert_overlay(
+    pool: &AnyPool,
+    key: &KubernetesKey,
+    expires: &str,
+    overlay: &str,
+) -> Result<()> {
+    retry_busy(|| {
+        bind_key(
+            sqlx::query(
+                r#"
+                INSERT INTO debug_overlays
+                    (api_version, cluster, kind, name, namespace, expires, overlay)
+                VALUES ($1, $2, $3, $4, $5, $6, $7)
+                "#,
+            ),
+            key,
+        )
+        .bind(expires.to_string())
+        .bind(overlay.to_string())
+        .execute(pool)
+    })
+    .await?;
+    Ok(())
+}
+
+pub(crate) async fn delete_overlays(pool: &AnyPool, key: &KubernetesKey) -> Result<()> {
+    retry_busy(|| {
+        bind_key(
+            sqlx::query(
+                r#"
+                DELETE FROM debug_overlays
+                WHERE
+                    api_version = $1
+                    AND cluster = $2
+                    AND kind = $3
+                    AND name = $4
+                    AND namespace = $5
+                "#,
+            ),
+            key,
+        )
+        .execute(pool)
+    })
+    .await?;
+    Ok(())
+}
+
+// Every overlay's key, expiry, and JSON in the order they were made
+pub(crate) async fn all_overlays(pool: &AnyPool) -> Result<Vec<(KubernetesKey, String, String)>> {
+    let rows = retry_busy(|| {
+        sqlx::query(
+            r#"
+            SELECT api_version, cluster, kind, name, namespace, expires, overlay
+            FROM debug_overlays
+            ORDER BY created
+            "#,
+        )
+        .fetch_all(pool)
+    })
+    .await?;
+    Ok(rows
+        .iter()
+        .map(|r| (key_from_row(r), r.get("expires"), r.get("overlay")))
+        .collect())
+}
+
+// Each trusted delegation key's name and hex public key
+pub(crate) async fn delegation_keys(pool: &AnyPool) -> Result<Vec<(String, String)>> {
+    let rows = retry_busy(|| {
+        sqlx::query("SELECT name, public_key FROM delegation_keys ORDER BY name").fetch_all(pool)
+    })
+    .await?;
+    Ok(rows
+        .iter()
+        .map(|r| (r.get("name"), r.get("public_key")))
+        .collect())
+}
+
+pub(crate) async fn insert_delegation_key(
+    pool: &AnyPool,
+    name: &str,
+    public_key: &str,
+) -> Result<()> {
+    retry_busy(|| {
+        sqlx::query("INSERT INTO delegation_keys (name, public_key) VALUES ($1, $2)")
+            .bind(name)
+            .bind(public_key)
+            .execute(pool)
+    })
+    .await?;
+    Ok(())
+}
+
+// A row of the locks table, with its times as RFC 3339 text
+pub(crate) struct LockRow {
+    pub holder: String,
+    pub token: String,
+    pub acquired: String,
+    pub expires: String,
+}
+
+pub(crate) async fn find_lock(pool: &AnyPool, name: &str) -> Result<Option<LockRow>> {
+    let row = retry_busy(|| {
+        sqlx::query("SELECT holder, token, acquired, expires FROM locks WHERE name = $1")
+            .bind(name)
+            .fetch_optional(pool)
+    })
+    .await?;
+    Ok(row.map(|r| LockRow {
+        holder: r.get("holder"),
+        token: r.get("token"),
+        acquired: r.get("acquired"),
+        expires: r.get("expires"),
+    }))
+}
+
+// Fails if someone already holds the lock called `name`
+pub(crate) async fn insert_lock(pool: &AnyPool, name: &str, lock: &LockRow) -> Result<()> {
+    retry_busy(|| {
+        sqlx::query(
+            r#"
+            INSERT INTO locks (name, holder, token, acquired, heartbeat, expires)
+            VALUES ($1, $2, $3, $4, $4, $5)
+            "#,
+        )
+        .bind(name)
+        .bind(lock.holder.as_str())
+        .bind(lock.token.as_str())
+        .bind(lock.acquired.as_str())
+        .bind(lock.expires.as_str())
+        .execute(pool)
+    })
+    .await?;
+    Ok(())
+}
+
+pub(crate) async fn delete_lock(pool: &AnyPool, name: &str, token: &str) -> Result<()> {
+    retry_busy(|| {
+        sqlx::query("DELETE FROM locks WHERE name = $1 AND token = $2")
+            .bind(name)
+            .bind(token)
+            .execute(pool)
+    })
+    .await?;
+    Ok(())
+}
+
+// Moves the lock's heartbeat and expiry forward, returning 0 if `token` no longer holds it
+pub(crate) async fn extend_lock(
+    pool: &AnyPool,
+    name: &str,
+    token: &str,
+    heartbeat: &str,
+    expires: &str,
+) -> Result<u64> {
+    let result = retry_busy(|| {
+        sqlx::query("UPDATE locks SET heartbeat = $1, expires = $2 WHERE name = $3 AND token = $4")
+            .bind(heartbeat)
+            .bind(expires)
+            .bind(name)
+            .bind(token)
+            .execute(pool)
+    })
+    .await?;
+    Ok(result.rows_affected())
+}
+
+// The tests run against a throwaway SQLite database
+#[cfg(all(test, feature = "sqlite"))]
+mod tests {
+    use super::*;
+    use crate::{kubernetes_io::test_key, migrations::migrate};
+    use tempfile::TempDir;
+
+    #[tokio::test]
+    async fn test_sqlite_is_tuned() -> Result<()> {
+        sqlx::any::install_default_drivers();
+        let directory = TempDir::new()?;
+        let url = format!(
+            "sqlite://{}?mode=rwc",
+            directory.path().join("sisyphus.db").display()
+        );
+        let pool = connect_database(&url).await?;
+
+        let journal_mode: String = sqlx::query("PRAGMA journal_mode")
+            .fetch_one(&pool)
+            .await?
+            .get(0);
+        assert_eq!(journal_mode, "wal");
+        let busy_timeout: i64 = sqlx::query("PRAGMA busy_timeout")
+            .fetch_one(&pool)
+            .await?
+            .get(0);
+        assert_eq!(busy_timeout, 30000);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_objects_round_trip() -> Result<()> {
+        sqlx::any::install_default_drivers();
+        let directory = TempDir::new()?;
+        let url = format!(
+            "sqlite://{}?mode=rwc",
+            directory.path().join("sisyphus.db").display()
+        );
+        let pool = connect_database(&url).await?;
+        migrate(&pool).await?;
+
+        let namespaced = test_key("prod", "apps/v1", "Deployment", Some("echo"), "echo");
+        let cluster_scoped = test_key("prod", "v1", "Namespace", None, "echo");
+        insert_object(&pool, &namespaced, "kind: Deployment\n", "ci").await?;
+        insert_object(&pool, &cluster_scoped, "kind: Namespace\n", "ci").await?;
+        update_object(&pool, &namespaced, "kind: Deployment\nspec: {}\n", None).await?;
+        assert_eq!(
+            find_object(&pool, &namespaced).await?,
+            Some((
+                "kind: Deployment\nspec: {}\n".to_string(),
+                Some("ci".to_string())
+            ))
+        );
+        let keys: Vec<_> = all_objects(&pool)
+            .await?
+            .into_iter()
+            .map(|(k, _)| k)
+            .collect();
+        assert!(keys.contains(&cluster_scoped));
+
+        assert_eq!(delete_object(&pool, &cluster_scoped).await?, 1);
+        assert_eq!(delete_object(&pool, &cluster_scoped).await?, 0);
+        assert_eq!(find_object(&pool, &cluster_scoped).await?, None);
+        Ok(())
+    }
+
+    #[test]
+    fn test_is_sqlite() {
+        assert!(is_sqlite("sqlite://sisyphus.db"));
+        assert!(is_sqlite("sqlite::memory:"));
+        assert!(!is_sqlite("postgres://user@db/sisyphus"));
+    }
+}
@@ -0,0 +1,164 @@
+use kube::api::DynamicObject;
+use serde_json::Value as JsonValue;
+
+use crate::kubernetes_io::KubernetesKey;
+
+// How one tracked object compares with what's in its cluster
+#[derive(Debug, Eq, PartialEq)]
+pub(crate) struct ObjectStatus {
+    pub exists: bool,
+    pub drifted: bool,
+    pub health: Option<String>,
+}
+
+impl ObjectStatus {
+    pub(crate) fn new(recorded: &DynamicObject, live: Option<&DynamicObject>) -> Self {
+        match live {
+            Some(live) => ObjectStatus {
+                exists: true,
+                drifted: comparable(recorded) != comparable(live),
+                health: health(live),
+            },
+            None => ObjectStatus {
+                exists: false,
+                drifted: true,
+                health: None,
+            },
+        }
+    }
+
+    pub(crate) fn to_json(&self, key: &KubernetesKey) -> JsonValue {
+        serde_json::json!({
+            "apiVersion": key.api_version,
+            "cluster": key.cluster,
+            "kind": key.kind,
+            "name": key.name,
+            "namespace": key.namespace,
+            "exists": self.exists,
+            "drifted": self.drifted,
+            "health": self.health,
+        })
+    }
+}
+
+// Strips what the cluster changes on its own, so only edits count as drift
+fn comparable(object: &DynamicObject) -> JsonValue {
+    let mut value = serde_json::to_value(object).unwrap_or(JsonValue::Null);
+    if let Some(map) = value.as_object_mut() {
+        map.remove("status");
+    }
+    if let Some(metadata) = value.get_mut("metadata").and_then(|m| m.as_object_mut()) {
+        for field in ["generation", "managedFields", "resourceVersion"] {
+            metadata.remove(field);
+        }
+    }
+    value
+}
+
+// A one-line summary of whether the object is doing its job, for the kinds where that's clear
+fn health(object: &DynamicObject) -> Option<String> {
+    let types = object.types.as_ref()?;
+    let status = object.data.get("status");
+    let number = |v: Option<&JsonValue>, pointer: &str| {
+        v.and_then(|v| v.pointer(pointer)).and_then(|v| v.as_i64())
+    };
+    match (types.api_version.as_str(), types.kind.as_str()) {
+        ("apps/v1", "Deployment") | ("apps/v1", "StatefulSet") => {
+            let wanted = number(object.data.get("spec"), "/replicas").unwrap_or(1);
+            let ready = number(status, "/readyReplicas").unwrap_or(0);
+            Some(format!("{}/{} ready", ready, wanted))
+        }
+        ("apps/v1", "DaemonSet") => {
+            let wanted = number(status, "/desiredNumberScheduled").unwrap_or(0);
+            let ready = number(status, "/numberReady").unwrap_or(0);
+            Some(format!("{}/{} ready", ready, wanted))
+        }
+        ("batch/v1", "CronJob") => {
+            let time = |field: &str| status.and_then(|s| s.get(field)).and_then(|v| v.as_str());
+            Some(
+                match (time("lastScheduleTime"), time("lastSuccessfulTime")) {
+                    (None, _) => "never scheduled".to_string(),
+                    (Some(scheduled), None) => format!("last scheduled {}", scheduled),
+                    (Some(scheduled), Some(succeeded)) => {
+                        format!("last scheduled {}, last succeeded {}", scheduled, succeeded)
+                    }
+                },
+            )
+        }
+        ("batch/v1", "Job") => Some(
+            if number(status, "/succeeded").unwrap_or(0) > 0 {
+                "succeeded"
+            } else if number(status, "/failed").unwrap_or(0) > 0 {
+                "failed"
+            } else {
+                "running"
+            }
+            .to_string(),
+        ),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use kube::api::TypeMeta;
+    use serde_json::json;
+
+    fn object(api_version: &str, kind: &str, data: JsonValue) -> DynamicObject {
+        DynamicObject {
+            types: Some(TypeMeta {
+                api_version: api_version.to_string(),
+                kind: kind.to_string(),
+            }),
+            metadata: Default::default(),
+            data,
+        }
+    }
+
+    #[test]
+    fn test_status_ignores_what_the_cluster_changes() {
+        let recorded = object("apps/v1", "Deployment", json!({"spec": {"replicas": 3}}));
+        let mut live = object(
+            "apps/v1",
+            "Deployment",
+            json!({"spec": {"replicas": 3}, "status": {"readyReplicas": 2}}),
+        );
+        live.metadata.resource_version = Some("12345".to_string());
+
+        assert_eq!(
+            ObjectStatus::new(&recorded, Some(&live)),
+            ObjectStatus {
+                exists: true,
+                drifted: false,
+                health: Some("2/3 ready".to_string()),
+            }
+        );
+    }
+
+    #[test]
+    fn test_status_notices_edits_and_deletions() {
+        let recorded = object("apps/v1", "Deployment", json!({"spec": {"replicas": 3}}));
+        let live = object("apps/v1", "Deployment", json!({"spec": {"replicas": 5}}));
+
+        assert!(ObjectStatus::new(&recorded, Some(&live)).drifted);
+        assert!(!ObjectStatus::new(&recorded, None).exists);
+    }
+
+    #[test]
+    fn test_cronjob_health() {
+        let never = object("batch/v1", "CronJob", json!({}));
+        let ran = object(
+            "batch/v1",
+            "CronJob",
+            json!({"status": {"lastScheduleTime": "2024-01-01T00:00:00Z"}}),
+        );
+
+        assert_eq!(health(&never), Some("never scheduled".to_string()));
+        assert_eq!(
+            health(&ran),
+            Some("last scheduled 2024-01-01T00:00:00Z".to_string())
+        );
+        assert_eq!(health(&object("v1", "ConfigMap", json!({}))), None);
+    }
+}
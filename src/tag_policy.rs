@@ -0,0 +1,180 @@
+use anyhow::{bail, Result};
+
+// How to pick a tag from a repository's tag list instead of naming one, written in place of the tag:
+//
+//   echo_config:~1.2     the newest 1.2.x
+//   echo_config:^1.2     the newest 1.x that's at least 1.2.0
+//   echo_config:latest!  the newest version of all
+//
+// Tags that aren't versions, like latest, and prereleases, like 1.3.0-rc.1, are never picked.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub(crate) enum TagPolicy {
+    Tilde(PartialVersion),
+    Caret(PartialVersion),
+    Newest,
+}
+
+#[derive(Clone, Copy, Debug, Eq, Ord, PartialEq, PartialOrd)]
+struct Version {
+    major: u64,
+    minor: u64,
+    patch: u64,
+}
+
+// A version with trailing parts left off, like 1.2
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub(crate) struct PartialVersion {
+    parts: Vec<u64>,
+}
+
+// Splits the tag policy off of an image reference, returning the reference without a tag when
+// there is one
+pub(crate) fn split_tag_policy(image: &str) -> Result<(String, Option<TagPolicy>)> {
+    // A colon before the last slash separates a registry's port instead
+    let name_start = image.rfind('/').map_or(0, |i| i + 1);
+    let Some(colon) = image[name_start..].rfind(':').map(|i| name_start + i) else {
+        return Ok((image.to_string(), None));
+    };
+    let (repository, tag) = (&image[..colon], &image[colon + 1..]);
+    let policy = if tag == "latest!" {
+        TagPolicy::Newest
+    } else if let Some(range) = tag.strip_prefix('~') {
+        TagPolicy::Tilde(parse_partial(range, image)?)
+    } else if let Some(range) = tag.strip_prefix('^') {
+        TagPolicy::Caret(parse_partial(range, image)?)
+    } else {
+        return Ok((image.to_string(), None));
+    };
+    Ok((repository.to_string(), Some(policy)))
+}
+
+fn parse_partial(range: &str, image: &str) -> Result<PartialVersion> {
+    let parts: Option<Vec<u64>> = range.split('.').map(|p| p.parse().ok()).collect();
+    match parts {
+        Some(parts) if !parts.is_empty() && parts.len() <= 3 => Ok(PartialVersion { parts }),
+        _ => bail!(
+            "Invalid version range in {}, expected something like ~1.2 or ^1",
+            image
+        ),
+    }
+}
+
+// Reads tags like 1.2.3 and v1.2.3, skipping anything else
+fn parse_version(tag: &str) -> Option<Version> {
+    let tag = tag.strip_prefix('v').unwrap_or(tag);
+    let mut parts = tag.split('.').map(|p| p.parse::<u64>().ok());
+    let version = Version {
+        major: parts.next()??,
+        minor: parts.next()??,
+        patch: parts.next()??,
+    };
+    match parts.next() {
+        Some(_) => None,
+        None => Some(version),
+    }
+}
+
+impl PartialVersion {
+    fn part(&self, i: usize) -> u64 {
+        self.parts.get(i).copied().unwrap_or(0)
+    }
+
+    fn floor(&self) -> Version {
+        Version {
+            major: self.part(0),
+            minor: self.part(1),
+            patch: self.part(2),
+        }
+    }
+}
+
+impl TagPolicy {
+    fn allows(&self, version: &Version) -> bool {
+        match self {
+            TagPolicy::Newest => true,
+            // ~1.2.3 and ~1.2 stay on 1.2, ~1 stays on 1
+            TagPolicy::Tilde(range) => {
+                *version >= range.floor()
+                    && version.major == range.part(0)
+                    && (range.parts.len() < 2 || version.minor == range.part(1))
+            }
+            // ^1.2 stays on 1, but ^0.2 stays on 0.2 since minor versions break things before 1.0
+            TagPolicy::Caret(range) => {
+                *version >= range.floor()
+                    && version.major == range.part(0)
+                    && (range.part(0) > 0
+                        || range.parts.len() < 2
+                        || version.minor == range.part(1))
+            }
+        }
+    }
+
+    // The tag holding the newest version this policy allows. When both 1.2.0 and v1.2.0 exist, the
+    // unprefixed one wins so that the pick doesn't depend on the order the registry lists them.
+    pub(crate) fn newest<'a>(&self, tags: impl IntoIterator<Item = &'a str>) -> Option<&'a str> {
+        tags.into_iter()
+            .filter_map(|tag| parse_version(tag).map(|version| (version, tag)))
+            .filter(|(version, _)| self.allows(version))
+            .max_by_key(|(version, tag)| (*version, !tag.starts_with('v')))
+            .map(|(_, tag)| tag)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TAGS: [&str; 9] = [
+        "latest",
+        "1.1.9",
+        "v1.2.0",
+        "1.2.7",
+        "1.2.10",
+        "1.3.0-rc.1",
+        "1.4.2",
+        "2.0.0",
+        "0.2.5",
+    ];
+
+    fn newest(image: &str) -> Result<Option<&'static str>> {
+        let (_, policy) = split_tag_policy(image)?;
+        Ok(policy.and_then(|p| p.newest(TAGS)))
+    }
+
+    #[test]
+    fn test_split_tag_policy() -> Result<()> {
+        assert_eq!(
+            split_tag_policy("localhost:5000/echo_config:~1.2")?,
+            (
+                "localhost:5000/echo_config".to_string(),
+                Some(TagPolicy::Tilde(PartialVersion { parts: vec![1, 2] }))
+            )
+        );
+        assert_eq!(
+            split_tag_policy("localhost:5000/echo_config")?,
+            ("localhost:5000/echo_config".to_string(), None)
+        );
+        assert_eq!(
+            split_tag_policy("echo_config:latest")?,
+            ("echo_config:latest".to_string(), None)
+        );
+        assert!(split_tag_policy("echo_config:~one").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_newest() -> Result<()> {
+        assert_eq!(newest("echo:~1.2")?, Some("1.2.10"));
+        assert_eq!(newest("echo:~1.2.8")?, Some("1.2.10"));
+        assert_eq!(newest("echo:~1")?, Some("1.4.2"));
+        assert_eq!(newest("echo:^1.2")?, Some("1.4.2"));
+        assert_eq!(newest("echo:^0.2")?, Some("0.2.5"));
+        assert_eq!(newest("echo:latest!")?, Some("2.0.0"));
+        assert_eq!(newest("echo:~3")?, None);
+
+        let both = TagPolicy::Tilde(PartialVersion { parts: vec![1, 2] });
+        assert_eq!(both.newest(["v1.2.0", "1.2.0"]), Some("1.2.0"));
+        assert_eq!(both.newest(["1.2.0", "v1.2.0"]), Some("1.2.0"));
+        Ok(())
+    }
+}
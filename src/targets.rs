@@ -0,0 +1,205 @@
+use anyhow::{bail, Context, Result};
+use kube::{
+    api::{ApiResource, DynamicObject, ListParams},
+    config::KubeConfigOptions,
+    ResourceExt,
+};
+use serde::Deserialize;
+use serde_json::json;
+use std::collections::{BTreeMap, BTreeSet};
+
+use crate::{environments::Environments, generate_diff::DiffAction, kubernetes_io::KubernetesKey};
+
+const TARGET_GROUP: &str = "sisyphus.april.dev";
+const TARGET_VERSION: &str = "v1alpha1";
+const TARGET_KIND: &str = "SisyphusTarget";
+const TARGET_PLURAL: &str = "sisyphustargets";
+
+// One SisyphusTarget describes one cluster that Sisyphus may push to.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "camelCase")]
+struct SisyphusTargetSpec {
+    // The kubeconfig context for the cluster, defaulting to the object's name
+    context: Option<String>,
+    #[serde(default)]
+    environments: Vec<String>,
+    // Nothing may be pushed to a frozen cluster
+    #[serde(default)]
+    frozen: bool,
+    // Nothing may be deleted or recreated in a protected cluster
+    #[serde(default)]
+    protected: bool,
+    // The UID of the cluster's kube-system namespace, checked before pushing so that a context
+    // pointing at the wrong cluster is caught
+    uid: Option<String>,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct TargetPolicy {
+    frozen: BTreeSet<String>,
+    protected: BTreeSet<String>,
+    uids: BTreeMap<String, String>,
+}
+
+impl TargetPolicy {
+    pub(crate) fn check(&self, changed: &[(&KubernetesKey, &DiffAction)]) -> Result<()> {
+        let mut violations = Vec::new();
+        for (key, action) in changed {
+            if self.frozen.contains(&key.cluster) {
+                violations.push(format!("{} {} (cluster is frozen)", action.verb(), key));
+            } else if self.protected.contains(&key.cluster)
+                && matches!(action, DiffAction::Delete | DiffAction::Recreate(_))
+            {
+                violations.push(format!("{} {} (cluster is protected)", action.verb(), key));
+            }
+        }
+        if violations.is_empty() {
+            return Ok(());
+        }
+        bail!("Refusing to push:\n  • {}", violations.join("\n  • "))
+    }
+
+    pub(crate) fn expected_uid(&self, cluster: &str) -> Option<&str> {
+        self.uids.get(cluster).map(|v| v.as_str())
+    }
+}
+
+// Reads environments and policy from SisyphusTarget objects in the management cluster when one is
+// given, and otherwise from the local environments file with no policy.
+pub async fn load_targets(
+    environments_path: Option<&str>,
+    management_context: Option<&str>,
+) -> Result<(Environments, TargetPolicy)> {
+    let Some(context) = management_context else {
+        return Ok((Environments::load(environments_path)?, TargetPolicy::default()));
+    };
+    let config = kube::Config::from_kubeconfig(&KubeConfigOptions {
+        context: Some(context.to_string()),
+        cluster: None,
+        user: None,
+    })
+    .await?;
+    let client = kube::Client::try_from(config)?;
+    let ar = ApiResource {
+        group: TARGET_GROUP.to_string(),
+        version: TARGET_VERSION.to_string(),
+        api_version: format!("{}/{}", TARGET_GROUP, TARGET_VERSION),
+        kind: TARGET_KIND.to_string(),
+        plural: TARGET_PLURAL.to_string(),
+    };
+    let api: kube::Api<DynamicObject> = kube::Api::all_with(client, &ar);
+    let targets = api
+        .list(&ListParams::default())
+        .await
+        .with_context(|| format!("while listing {} objects in {}", TARGET_KIND, context))?;
+    parse_targets(targets.items)
+}
+
+fn parse_targets(targets: Vec<DynamicObject>) -> Result<(Environments, TargetPolicy)> {
+    let mut clusters_by_environment: BTreeMap<String, Vec<String>> = BTreeMap::new();
+    let mut policy = TargetPolicy::default();
+    for target in targets {
+        let name = target.name_any();
+        let spec: SisyphusTargetSpec =
+            serde_json::from_value(target.data.get("spec").cloned().unwrap_or(json!({})))
+                .with_context(|| format!("while parsing {} {}", TARGET_KIND, name))?;
+        let cluster = spec.context.unwrap_or(name);
+        for environment in spec.environments {
+            clusters_by_environment
+                .entry(environment)
+                .or_default()
+                .push(cluster.clone());
+        }
+        if spec.frozen {
+            policy.frozen.insert(cluster.clone());
+        }
+        if spec.protected {
+            policy.protected.insert(cluster.clone());
+        }
+        if let Some(uid) = spec.uid {
+            policy.uids.insert(cluster, uid);
+        }
+    }
+    Ok((Environments::new(clusters_by_environment), policy))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::kubernetes_io::test_key;
+    use kube::api::{ObjectMeta, TypeMeta};
+    use serde_json::Value as JsonValue;
+
+    fn target(name: &str, spec: JsonValue) -> DynamicObject {
+        DynamicObject {
+            types: Some(TypeMeta {
+                api_version: format!("{}/{}", TARGET_GROUP, TARGET_VERSION),
+                kind: TARGET_KIND.to_string(),
+            }),
+            metadata: ObjectMeta {
+                name: Some(name.to_string()),
+                ..Default::default()
+            },
+            data: json!({ "spec": spec }),
+        }
+    }
+
+    #[test]
+    fn test_parse_targets() -> Result<()> {
+        let (mut environments, policy) = parse_targets(vec![
+            target(
+                "us-east",
+                json!({"environments": ["production"], "protected": true, "uid": "1234"}),
+            ),
+            target(
+                "eu-west",
+                json!({"context": "gke_eu-west", "environments": ["production"], "frozen": true}),
+            ),
+        ])?;
+
+        assert_eq!(
+            environments.expand_clusters(&["env:production".to_string()])?,
+            vec!["us-east", "gke_eu-west"]
+        );
+        assert_eq!(policy.frozen, BTreeSet::from(["gke_eu-west".to_string()]));
+        assert_eq!(policy.protected, BTreeSet::from(["us-east".to_string()]));
+        assert_eq!(policy.expected_uid("us-east"), Some("1234"));
+        assert_eq!(policy.expected_uid("gke_eu-west"), None);
+        Ok(())
+    }
+
+    #[test]
+    fn test_policy_check() {
+        let policy = TargetPolicy {
+            frozen: BTreeSet::from(["frozen".to_string()]),
+            protected: BTreeSet::from(["protected".to_string()]),
+            uids: BTreeMap::new(),
+        };
+
+        assert!(policy
+            .check(&[(
+                &test_key("other", "v1", "ConfigMap", Some("echo"), "echo"),
+                &DiffAction::Delete
+            )])
+            .is_ok());
+        assert!(policy
+            .check(&[(
+                &test_key("frozen", "v1", "ConfigMap", Some("echo"), "echo"),
+                &DiffAction::Delete
+            )])
+            .is_err());
+        assert!(policy
+            .check(&[(
+                &test_key("protected", "v1", "ConfigMap", Some("echo"), "echo"),
+                &DiffAction::Delete
+            )])
+            .is_err());
+        let created = target("echo", json!({}));
+        assert!(policy
+            .check(&[(
+                &test_key("protected", "v1", "ConfigMap", Some("echo"), "echo"),
+                &DiffAction::Create(created)
+            )])
+            .is_ok());
+    }
+}
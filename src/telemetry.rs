@@ -0,0 +1,57 @@
+use anyhow::Result;
+use opentelemetry::trace::TracerProvider as _;
+use opentelemetry_sdk::{trace::SdkTracerProvider, Resource};
+use tracing_subscriber::{layer::SubscriberExt, Registry};
+
+use crate::logging::{LogFormat, ProgressLayer};
+
+// Tracing is opt-in: spans are only exported when one of these is set, and they're read by the
+// OTLP exporter along with the rest of the standard OTEL_* variables (headers, timeouts, ...).
+const ENDPOINT_VARIABLES: [&str; 2] = [
+    "OTEL_EXPORTER_OTLP_ENDPOINT",
+    "OTEL_EXPORTER_OTLP_TRACES_ENDPOINT",
+];
+
+pub(crate) struct Telemetry {
+    provider: Option<SdkTracerProvider>,
+}
+
+impl Telemetry {
+    // Flushes spans that are still buffered. Must run before the process exits or the end of the
+    // run (usually the interesting part) is lost.
+    pub(crate) fn shutdown(self) {
+        if let Some(provider) = self.provider {
+            if let Err(e) = provider.shutdown() {
+                eprintln!("Unable to flush traces: {}", e);
+            }
+        }
+    }
+}
+
+// Installs the subscriber that writes progress messages in `log_format` and, when configured,
+// exports spans
+pub(crate) fn init_telemetry(log_format: LogFormat) -> Result<Telemetry> {
+    let progress = ProgressLayer::new(log_format);
+    let enabled = ENDPOINT_VARIABLES
+        .iter()
+        .any(|v| std::env::var_os(v).map_or(false, |v| !v.is_empty()));
+    if !enabled {
+        tracing::subscriber::set_global_default(Registry::default().with(progress))?;
+        return Ok(Telemetry { provider: None });
+    }
+
+    let exporter = opentelemetry_otlp::SpanExporter::builder()
+        .with_http()
+        .build()?;
+    let provider = SdkTracerProvider::builder()
+        .with_batch_exporter(exporter)
+        .with_resource(Resource::builder().with_service_name("sisyphus").build())
+        .build();
+    let layer = tracing_opentelemetry::layer().with_tracer(provider.tracer("sisyphus"));
+    // Not `init()`, which would also route `log` records through tracing and clash with
+    // env_logger
+    tracing::subscriber::set_global_default(Registry::default().with(progress).with(layer))?;
+    Ok(Telemetry {
+        provider: Some(provider),
+    })
+}
@@ -0,0 +1,90 @@
+use std::{
+    collections::BTreeMap,
+    fmt,
+    time::{Duration, Instant},
+};
+
+use crate::metrics::phase_finished;
+
+#[derive(Clone, Copy, Debug, Eq, Ord, PartialEq, PartialOrd)]
+pub(crate) enum Phase {
+    Parse,
+    Resolve,
+    Render,
+    DatabaseLoad,
+    Diff,
+    Apply,
+}
+
+impl fmt::Display for Phase {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}",
+            match self {
+                Phase::Parse => "file parse",
+                Phase::Resolve => "registry resolution",
+                Phase::Render => "render",
+                Phase::DatabaseLoad => "database load",
+                Phase::Diff => "diff",
+                Phase::Apply => "apply",
+            }
+        )
+    }
+}
+
+// Accumulates wall-clock time per phase. Phases may be recorded many times (registry resolution
+// happens once per namespace, for example) and are summed.
+pub(crate) struct Timings {
+    enabled: bool,
+    totals: BTreeMap<Phase, Duration>,
+}
+
+impl Timings {
+    pub(crate) fn new(enabled: bool) -> Self {
+        Timings {
+            enabled,
+            totals: BTreeMap::new(),
+        }
+    }
+
+    pub(crate) fn record(&mut self, phase: Phase, started: Instant) {
+        let elapsed = started.elapsed();
+        *self.totals.entry(phase).or_default() += elapsed;
+        phase_finished(phase, elapsed);
+    }
+
+    pub(crate) fn print(&self) {
+        if !self.enabled || self.totals.is_empty() {
+            return;
+        }
+        eprintln!("Timings:");
+        for (phase, duration) in &self.totals {
+            eprintln!("  {:<20} {:>10.3}s", phase.to_string(), duration.as_secs_f64());
+        }
+        let total: Duration = self.totals.values().sum();
+        eprintln!("  {:<20} {:>10.3}s", "total", total.as_secs_f64());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_record_accumulates_per_phase() {
+        let mut timings = Timings::new(true);
+        let started = Instant::now() - Duration::from_millis(5);
+        timings.record(Phase::Resolve, started);
+        timings.record(Phase::Resolve, started);
+        timings.record(Phase::Parse, Instant::now());
+
+        assert_eq!(timings.totals.len(), 2);
+        assert!(timings.totals[&Phase::Resolve] >= Duration::from_millis(10));
+        // Phases print in pipeline order regardless of the order they were recorded in
+        assert_eq!(
+            timings.totals.keys().copied().collect::<Vec<_>>(),
+            vec![Phase::Parse, Phase::Resolve]
+        );
+    }
+}
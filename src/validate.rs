@@ -0,0 +1,406 @@
+use anyhow::{bail, Context, Result};
+use clap::Args;
+use k8s_openapi::api::{
+    apps::v1::{DaemonSet, Deployment, StatefulSet},
+    autoscaling::v2::HorizontalPodAutoscaler,
+    batch::v1::{CronJob, Job},
+    core::v1::{ConfigMap, Namespace, PersistentVolumeClaim, Secret, Service, ServiceAccount},
+    networking::v1::{Ingress, NetworkPolicy},
+    policy::v1::PodDisruptionBudget,
+    rbac::v1::{ClusterRole, ClusterRoleBinding, Role, RoleBinding},
+};
+use serde::de::DeserializeOwned;
+use serde_json::Value as JsonValue;
+use std::path::{Path, PathBuf};
+use tracing::{info, warn};
+
+use crate::{
+    environments::Environments, git_revision::monitor_directories_at, kubernetes_io::KubernetesKey,
+    render_files::render_files, timings::Timings,
+};
+
+#[derive(Args, Debug)]
+pub(crate) struct ValidateArgs {
+    #[arg(
+        long,
+        env = "MONITOR_DIRECTORY",
+        value_delimiter = ',',
+        required = true
+    )]
+    pub monitor_directory: Vec<String>,
+
+    #[arg(long, env = "SISYPHUS_GIT_REF")]
+    pub git_ref: Option<String>,
+
+    // Only a local environments file, since a management cluster is a cluster
+    #[arg(long, env = "SISYPHUS_ENVIRONMENTS")]
+    pub environments: Option<String>,
+
+    // Directories of JSON schemas named like deployment-apps-v1.json, as kubeconform and
+    // kubernetes-json-schema lay them out. These win over the bundled schemas, and are the only way
+    // to check custom resources.
+    #[arg(long, env = "SISYPHUS_SCHEMA_LOCATION", value_delimiter = ',')]
+    pub schema_location: Vec<PathBuf>,
+
+    // Pass objects with no schema instead of failing on them
+    #[arg(long)]
+    pub ignore_missing_schemas: bool,
+
+    #[arg(long)]
+    pub timings: bool,
+}
+
+// Renders everything and checks each object against its schema, without a database or cluster, so
+// that CI can catch malformed objects before a push does
+pub(crate) async fn validate(args: ValidateArgs) -> Result<()> {
+    let (monitor_directory, _checkout) =
+        monitor_directories_at(args.monitor_directory, args.git_ref.as_deref()).await?;
+    let mut environments = Environments::load(args.environments.as_deref())?;
+    let mut timings = Timings::new(args.timings);
+    let from_files = render_files(&monitor_directory, &mut environments, &mut timings).await?;
+    timings.print();
+
+    let mut problems = Vec::new();
+    let mut unchecked = Vec::new();
+    for (key, object) in from_files.namespaces.iter().chain(from_files.by_key.iter()) {
+        let value = serde_json::to_value(object)?;
+        let errors = match find_schema(&args.schema_location, key)? {
+            Some(schema) => {
+                let mut errors = Vec::new();
+                check_schema(&schema, &value, "", &mut errors);
+                errors
+            }
+            None => match check_built_in(key, value) {
+                Some(errors) => errors,
+                None => {
+                    unchecked.push(key);
+                    continue;
+                }
+            },
+        };
+        problems.extend(errors.into_iter().map(|e| format!("{}: {}", key, e)));
+    }
+
+    for problem in &problems {
+        warn!("{}", problem);
+    }
+    for key in &unchecked {
+        warn!(object = %key, "No schema for {}", key);
+    }
+    let missing = if args.ignore_missing_schemas {
+        0
+    } else {
+        unchecked.len()
+    };
+    if !problems.is_empty() || missing > 0 {
+        bail!(
+            "{} problems and {} objects without a schema",
+            problems.len(),
+            missing
+        );
+    }
+    info!(
+        "{} objects are valid",
+        from_files.namespaces.len() + from_files.by_key.len() - unchecked.len()
+    );
+    Ok(())
+}
+
+// Schemas are named by kind, the first label of the API group, and version, like
+// ingress-networking-v1.json or configmap-v1.json
+fn schema_file_name(key: &KubernetesKey) -> String {
+    let name = match key.api_version.split_once('/') {
+        Some((group, version)) => format!(
+            "{}-{}-{}",
+            key.kind,
+            group.split('.').next().unwrap_or(group),
+            version
+        ),
+        None => format!("{}-{}", key.kind, key.api_version),
+    };
+    format!("{}.json", name.to_lowercase())
+}
+
+fn find_schema(locations: &[PathBuf], key: &KubernetesKey) -> Result<Option<JsonValue>> {
+    let file_name = schema_file_name(key);
+    for location in locations {
+        let path = location.join(&file_name);
+        if path.exists() {
+            return read_schema(&path).map(Some);
+        }
+    }
+    Ok(None)
+}
+
+fn read_schema(path: &Path) -> Result<JsonValue> {
+    let contents = std::fs::read_to_string(path)
+        .with_context(|| format!("while reading {}", path.display()))?;
+    serde_json::from_str(&contents).with_context(|| format!("while parsing {}", path.display()))
+}
+
+// Checks the parts of JSON schema that Kubernetes schemas use: types, required and unknown fields,
+// enums, and oneOf/anyOf for int-or-string fields. Schemas have to be standalone, since $ref
+// isn't followed.
+fn check_schema(schema: &JsonValue, value: &JsonValue, path: &str, errors: &mut Vec<String>) {
+    let at = if path.is_empty() { "(root)" } else { path };
+    if schema == &JsonValue::Bool(false) {
+        errors.push(format!("{} isn't allowed", at));
+        return;
+    }
+    for alternatives in ["oneOf", "anyOf"] {
+        if let Some(options) = schema[alternatives].as_array() {
+            let matches = options.iter().any(|option| {
+                let mut ignored = Vec::new();
+                check_schema(option, value, path, &mut ignored);
+                ignored.is_empty()
+            });
+            if !matches {
+                errors.push(format!("{} matches none of the allowed shapes", at));
+                return;
+            }
+        }
+    }
+    if let Some(types) = schema.get("type") {
+        let allowed: Vec<&str> = match types {
+            JsonValue::String(t) => vec![t.as_str()],
+            JsonValue::Array(ts) => ts.iter().filter_map(|t| t.as_str()).collect(),
+            _ => Vec::new(),
+        };
+        if !allowed.is_empty() && !allowed.iter().any(|t| has_type(value, t)) {
+            errors.push(format!(
+                "{} should be {} but is {}",
+                at,
+                allowed.join(" or "),
+                value
+            ));
+            return;
+        }
+    }
+    if let Some(options) = schema["enum"].as_array() {
+        if !options.contains(value) {
+            let options: Vec<String> = options.iter().map(|o| o.to_string()).collect();
+            errors.push(format!(
+                "{} should be one of {} but is {}",
+                at,
+                options.join(", "),
+                value
+            ));
+        }
+    }
+    match value {
+        JsonValue::Object(fields) => {
+            for required in schema["required"].as_array().into_iter().flatten() {
+                if let Some(required) = required.as_str() {
+                    if !fields.contains_key(required) {
+                        errors.push(format!("{} is missing {}", at, required));
+                    }
+                }
+            }
+            let properties = schema["properties"].as_object();
+            for (field, child) in fields {
+                let child_path = if path.is_empty() {
+                    field.to_string()
+                } else {
+                    format!("{}.{}", path, field)
+                };
+                match properties.and_then(|p| p.get(field)) {
+                    Some(property) => check_schema(property, child, &child_path, errors),
+                    None => match schema.get("additionalProperties") {
+                        Some(JsonValue::Bool(false)) => {
+                            errors.push(format!("{} isn't a known field", child_path))
+                        }
+                        Some(additional @ JsonValue::Object(_)) => {
+                            check_schema(additional, child, &child_path, errors)
+                        }
+                        _ => {}
+                    },
+                }
+            }
+        }
+        JsonValue::Array(items) => {
+            if let Some(item_schema) = schema.get("items") {
+                for (i, item) in items.iter().enumerate() {
+                    check_schema(item_schema, item, &format!("{}[{}]", path, i), errors);
+                }
+            }
+        }
+        _ => {}
+    }
+}
+
+fn has_type(value: &JsonValue, t: &str) -> bool {
+    match t {
+        "object" => value.is_object(),
+        "array" => value.is_array(),
+        "string" => value.is_string(),
+        "integer" => value.is_i64() || value.is_u64(),
+        "number" => value.is_number(),
+        "boolean" => value.is_boolean(),
+        "null" => value.is_null(),
+        _ => true,
+    }
+}
+
+// The schemas bundled with Sisyphus are the k8s-openapi types, which catch wrong types and missing
+// required fields but not unknown ones. Returns None for kinds they don't cover.
+fn check_built_in(key: &KubernetesKey, value: JsonValue) -> Option<Vec<String>> {
+    let parsed = match (key.api_version.as_str(), key.kind.as_str()) {
+        ("apps/v1", "DaemonSet") => parses::<DaemonSet>(value),
+        ("apps/v1", "Deployment") => parses::<Deployment>(value),
+        ("apps/v1", "StatefulSet") => parses::<StatefulSet>(value),
+        ("autoscaling/v2", "HorizontalPodAutoscaler") => parses::<HorizontalPodAutoscaler>(value),
+        ("batch/v1", "CronJob") => parses::<CronJob>(value),
+        ("batch/v1", "Job") => parses::<Job>(value),
+        ("networking.k8s.io/v1", "Ingress") => parses::<Ingress>(value),
+        ("networking.k8s.io/v1", "NetworkPolicy") => parses::<NetworkPolicy>(value),
+        ("policy/v1", "PodDisruptionBudget") => parses::<PodDisruptionBudget>(value),
+        ("rbac.authorization.k8s.io/v1", "ClusterRole") => parses::<ClusterRole>(value),
+        ("rbac.authorization.k8s.io/v1", "ClusterRoleBinding") => {
+            parses::<ClusterRoleBinding>(value)
+        }
+        ("rbac.authorization.k8s.io/v1", "Role") => parses::<Role>(value),
+        ("rbac.authorization.k8s.io/v1", "RoleBinding") => parses::<RoleBinding>(value),
+        ("v1", "ConfigMap") => parses::<ConfigMap>(value),
+        ("v1", "Namespace") => parses::<Namespace>(value),
+        ("v1", "PersistentVolumeClaim") => parses::<PersistentVolumeClaim>(value),
+        ("v1", "Secret") => parses::<Secret>(value),
+        ("v1", "Service") => parses::<Service>(value),
+        ("v1", "ServiceAccount") => parses::<ServiceAccount>(value),
+        _ => return None,
+    };
+    Some(match parsed {
+        Ok(()) => Vec::new(),
+        Err(e) => vec![e.to_string()],
+    })
+}
+
+fn parses<T: DeserializeOwned>(value: JsonValue) -> serde_json::Result<()> {
+    serde_json::from_value::<T>(value).map(|_| ())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::kubernetes_io::test_key;
+    use serde_json::json;
+
+    #[test]
+    fn test_schema_file_name() {
+        assert_eq!(
+            schema_file_name(&test_key(
+                "prod",
+                "apps/v1",
+                "Deployment",
+                Some("echo"),
+                "echo"
+            )),
+            "deployment-apps-v1.json"
+        );
+        assert_eq!(
+            schema_file_name(&test_key(
+                "prod",
+                "networking.k8s.io/v1",
+                "Ingress",
+                Some("echo"),
+                "echo"
+            )),
+            "ingress-networking-v1.json"
+        );
+        assert_eq!(
+            schema_file_name(&test_key("prod", "v1", "ConfigMap", Some("echo"), "echo")),
+            "configmap-v1.json"
+        );
+    }
+
+    #[test]
+    fn test_check_schema() {
+        let schema = json!({
+            "type": "object",
+            "required": ["spec"],
+            "additionalProperties": false,
+            "properties": {
+                "kind": {"type": "string", "enum": ["KafkaTopic"]},
+                "spec": {
+                    "type": "object",
+                    "properties": {
+                        "partitions": {"type": "integer"},
+                        "port": {"oneOf": [{"type": "string"}, {"type": "integer"}]},
+                        "replicas": {"type": "array", "items": {"type": "string"}},
+                    },
+                },
+            },
+        });
+
+        let mut errors = Vec::new();
+        check_schema(
+            &schema,
+            &json!({"kind": "KafkaTopic", "spec": {"partitions": 3, "port": "http"}}),
+            "",
+            &mut errors,
+        );
+        assert_eq!(errors, Vec::<String>::new());
+
+        let mut errors = Vec::new();
+        check_schema(
+            &schema,
+            &json!({
+                "kind": "Topic",
+                "color": "red",
+                "spec": {"partitions": "3", "port": true, "replicas": ["a", 1]},
+            }),
+            "",
+            &mut errors,
+        );
+        assert_eq!(
+            errors,
+            vec![
+                "color isn't a known field",
+                "kind should be one of \"KafkaTopic\" but is \"Topic\"",
+                "spec.partitions should be integer but is \"3\"",
+                "spec.port matches none of the allowed shapes",
+                "spec.replicas[1] should be string but is 1",
+            ]
+        );
+
+        let mut errors = Vec::new();
+        check_schema(&schema, &json!({}), "", &mut errors);
+        assert_eq!(errors, vec!["(root) is missing spec"]);
+    }
+
+    #[test]
+    fn test_check_built_in() {
+        let deployment = test_key("prod", "apps/v1", "Deployment", Some("echo"), "echo");
+        let valid = json!({
+            "apiVersion": "apps/v1",
+            "kind": "Deployment",
+            "metadata": {"name": "echo"},
+            "spec": {
+                "replicas": 2,
+                "selector": {"matchLabels": {"app": "echo"}},
+                "template": {"spec": {"containers": [{"name": "echo"}]}},
+            },
+        });
+        assert_eq!(check_built_in(&deployment, valid.clone()), Some(Vec::new()));
+
+        let mut invalid = valid;
+        invalid["spec"]["replicas"] = json!("two");
+        assert_eq!(
+            check_built_in(&deployment, invalid).map(|e| e.len()),
+            Some(1)
+        );
+
+        assert_eq!(
+            check_built_in(
+                &test_key(
+                    "prod",
+                    "kafka.example.com/v1",
+                    "KafkaTopic",
+                    Some("echo"),
+                    "echo"
+                ),
+                json!({})
+            ),
+            None
+        );
+    }
+}
@@ -0,0 +1,83 @@
+use anyhow::{anyhow, Context, Result};
+use kube::{api::DynamicObject, ResourceExt};
+use sqlx::AnyPool;
+
+use crate::{
+    environments::Environments,
+    kubernetes_io::{KubernetesKey, ORIGIN_ANNOTATION},
+    kubernetes_rendering::Origin,
+    render_files::render_files,
+    state_store::find_object,
+    timings::Timings,
+};
+
+// Explains which file, config image, and Starlark entrypoint produced a tracked object, and which
+// Sisyphus pushed it. Given the monitor directory, also checks whether the files still render it
+// from the same place, which catches a config image tag that has moved since the last push.
+pub(crate) async fn why(
+    key: &KubernetesKey,
+    pool: &AnyPool,
+    monitor_directory: &[String],
+    environments: &mut Environments,
+) -> Result<()> {
+    let (yaml, provenance) = find_object(pool, key)
+        .await?
+        .ok_or_else(|| anyhow!("{} isn't tracked in the database", key))?;
+    let recorded: DynamicObject = serde_yaml::from_str(&yaml)?;
+
+    println!("{}", key);
+    let pushed = origin(&recorded)?;
+    match &pushed {
+        Some(origin) => print_origin(origin),
+        None => println!("  Pushed before Sisyphus recorded where objects come from"),
+    }
+    if let Some(provenance) = provenance {
+        println!("  Pushed by:     {}", provenance);
+    }
+
+    if monitor_directory.is_empty() {
+        return Ok(());
+    }
+    let rendered = render_files(monitor_directory, environments, &mut Timings::new(false)).await?;
+    let Some(object) = rendered
+        .by_key
+        .get(key)
+        .or_else(|| rendered.namespaces.get(key))
+    else {
+        println!("\nThe files no longer render it, so the next push deletes it");
+        return Ok(());
+    };
+    let now = origin(object)?;
+    if now == pushed {
+        println!("\nThe files still render it from the same place");
+        return Ok(());
+    }
+    println!("\nThe files now render it from somewhere else:");
+    if let Some(now) = &now {
+        print_origin(now);
+        if let Some(pushed) = &pushed {
+            if now.config_image != pushed.config_image {
+                println!("  The config image resolves to a different digest than was pushed");
+            }
+        }
+    }
+    Ok(())
+}
+
+fn origin(object: &DynamicObject) -> Result<Option<Origin>> {
+    object
+        .annotations()
+        .get(ORIGIN_ANNOTATION)
+        .map(|v| serde_json::from_str(v).context("Invalid origin annotation"))
+        .transpose()
+}
+
+fn print_origin(origin: &Origin) {
+    println!("  Rendered by:   {}", origin);
+    if let Some(image) = &origin.config_image {
+        println!("  Config image:  {}", image);
+    }
+    if let Some(entrypoint) = &origin.entrypoint {
+        println!("  Entrypoint:    {}", entrypoint);
+    }
+}